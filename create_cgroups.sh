@@ -5,6 +5,19 @@ SUBS="blkio cpuacct memory pids freezer"
 
 sudo -v
 
+if [ -f /sys/fs/cgroup/cgroup.controllers ]
+then
+	# cgroup v2 (unified hierarchy)
+	for cgroup in ${CONTROL_GROUPS}
+	do
+		echo "+memory +cpu +pids" | sudo tee /sys/fs/cgroup/cgroup.subtree_control > /dev/null
+		sudo mkdir /sys/fs/cgroup/$cgroup/
+		echo "+memory +cpu +pids" | sudo tee /sys/fs/cgroup/$cgroup/cgroup.subtree_control > /dev/null
+		sudo chown -R ${USER} /sys/fs/cgroup/$cgroup/
+	done
+	exit 0
+fi
+
 for cgroup in ${CONTROL_GROUPS}
 do
 	for sub in ${SUBS}
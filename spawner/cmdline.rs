@@ -0,0 +1,288 @@
+//! Conversions between argument vectors and single command line strings.
+
+use crate::{Error, Result};
+
+use std::ffi::{OsStr, OsString};
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(windows)]
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+/// Joins the arguments into a Windows command line, quoting them by the rules of the
+/// Microsoft C runtime, so that `CommandLineToArgvW` and the `argv` of a C program give
+/// back the original arguments:
+///
+/// * An argument is quoted if it is empty or contains whitespace or `"`.
+/// * Inside quotes, `"` is escaped as `\"`, and the backslashes that precede a `"`, or the
+///   closing quote, are doubled. Other backslashes are kept as is.
+///
+/// The first argument is expected to be the program name, which is parsed by simpler rules
+/// and should not contain `"`.
+pub fn join_windows<T, U>(args: T) -> String
+where
+    T: IntoIterator<Item = U>,
+    U: AsRef<str>,
+{
+    let mut cmd = Vec::new();
+    for (idx, arg) in args.into_iter().enumerate() {
+        if idx != 0 {
+            cmd.push(b' ');
+        }
+        quote_windows(&mut cmd, arg.as_ref().as_bytes());
+    }
+    // Only ASCII characters are inserted, so the result is valid UTF-8.
+    String::from_utf8(cmd).unwrap()
+}
+
+/// Joins the arguments like [`join_windows`], but accepts the arguments that are not valid
+/// Unicode, e.g. Windows file names with unpaired surrogates, and passes them through as is.
+///
+/// [`join_windows`]: fn.join_windows.html
+pub fn join_windows_os<T, U>(args: T) -> OsString
+where
+    T: IntoIterator<Item = U>,
+    U: AsRef<OsStr>,
+{
+    let separator = os_units(OsStr::new(" "));
+    let mut cmd = Vec::new();
+    for (idx, arg) in args.into_iter().enumerate() {
+        if idx != 0 {
+            cmd.extend_from_slice(&separator);
+        }
+        quote_windows(&mut cmd, &os_units(arg.as_ref()));
+    }
+    os_from_units(cmd)
+}
+
+#[cfg(windows)]
+fn os_units(s: &OsStr) -> Vec<u16> {
+    s.encode_wide().collect()
+}
+
+#[cfg(windows)]
+fn os_from_units(units: Vec<u16>) -> OsString {
+    OsString::from_wide(&units)
+}
+
+#[cfg(unix)]
+fn os_units(s: &OsStr) -> Vec<u8> {
+    s.as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn os_from_units(units: Vec<u8>) -> OsString {
+    OsString::from_vec(units)
+}
+
+/// Quotes an argument given as UTF-8 bytes or UTF-16 code units. Only ASCII characters are
+/// special, and they never occur inside the encoding of other characters.
+fn quote_windows<C>(cmd: &mut Vec<C>, arg: &[C])
+where
+    C: Copy + PartialEq + From<u8>,
+{
+    let is = |c: C, ascii: u8| c == C::from(ascii);
+    let needs_quotes = arg.is_empty()
+        || arg.iter().any(|&c| {
+            [b' ', b'\t', b'\n', b'\x0b', b'"']
+                .iter()
+                .any(|&q| is(c, q))
+        });
+    if !needs_quotes {
+        cmd.extend_from_slice(arg);
+        return;
+    }
+
+    cmd.push(b'"'.into());
+    let mut backslashes = 0;
+    for &c in arg {
+        if is(c, b'\\') {
+            backslashes += 1;
+            continue;
+        } else if is(c, b'"') {
+            push_backslash_units(cmd, backslashes * 2 + 1);
+        } else {
+            push_backslash_units(cmd, backslashes);
+        }
+        backslashes = 0;
+        cmd.push(c);
+    }
+    push_backslash_units(cmd, backslashes * 2);
+    cmd.push(b'"'.into());
+}
+
+fn push_backslash_units<C: From<u8>>(cmd: &mut Vec<C>, n: usize) {
+    for _ in 0..n {
+        cmd.push(b'\\'.into());
+    }
+}
+
+fn push_backslashes(cmd: &mut String, n: usize) {
+    for _ in 0..n {
+        cmd.push('\\');
+    }
+}
+
+/// Splits the command line into the program and its arguments: by [`split_windows`] on
+/// Windows, and by [`split_posix`] elsewhere.
+///
+/// [`split_windows`]: fn.split_windows.html
+/// [`split_posix`]: fn.split_posix.html
+pub fn split(cmd: &str) -> Result<Vec<String>> {
+    if cfg!(windows) {
+        split_windows(cmd)
+    } else {
+        split_posix(cmd)
+    }
+}
+
+/// Splits a Windows command line the way `CommandLineToArgvW` does, except that leading
+/// whitespace is skipped and a command line that ends inside quotes is an error:
+///
+/// * The program name ends at the first whitespace, or, if it starts with `"`, at the next
+///   `"`. Backslashes in it are not escapes.
+/// * Arguments are separated by spaces and tabs outside of quotes.
+/// * `2n` backslashes followed by `"` give `n` backslashes and a quote that opens or closes
+///   the quoted part, `2n + 1` backslashes followed by `"` give `n` backslashes and a
+///   literal `"`. Other backslashes are literal.
+/// * Inside quotes, `""` gives a literal `"` and ends the quoted part.
+pub fn split_windows(cmd: &str) -> Result<Vec<String>> {
+    let mut chars = cmd.trim_start_matches([' ', '\t']).chars().peekable();
+    let mut program = String::new();
+    if chars.peek() == Some(&'"') {
+        chars.next();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some(c) => program.push(c),
+                None => return Err(unbalanced_quotes()),
+            }
+        }
+    } else {
+        while let Some(&c) = chars.peek() {
+            if c == ' ' || c == '\t' {
+                break;
+            }
+            program.push(c);
+            chars.next();
+        }
+    }
+    if program.is_empty() {
+        return Err(Error::from("Empty command line"));
+    }
+
+    let mut args = vec![program];
+    loop {
+        while chars.peek() == Some(&' ') || chars.peek() == Some(&'\t') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut arg = String::new();
+        let mut in_quotes = false;
+        let mut backslashes = 0;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    backslashes += 1;
+                    continue;
+                }
+                '"' => {
+                    push_backslashes(&mut arg, backslashes / 2);
+                    if backslashes % 2 == 1 {
+                        arg.push('"');
+                    } else if in_quotes && chars.peek() == Some(&'"') {
+                        chars.next();
+                        arg.push('"');
+                        in_quotes = false;
+                    } else {
+                        in_quotes = !in_quotes;
+                    }
+                }
+                ' ' | '\t' if !in_quotes => {
+                    push_backslashes(&mut arg, backslashes);
+                    backslashes = 0;
+                    break;
+                }
+                _ => {
+                    push_backslashes(&mut arg, backslashes);
+                    arg.push(c);
+                }
+            }
+            backslashes = 0;
+        }
+        push_backslashes(&mut arg, backslashes);
+        if in_quotes {
+            return Err(unbalanced_quotes());
+        }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Splits the command line into words like a POSIX shell, but without any expansions,
+/// redirections or comments:
+///
+/// * Words are separated by spaces, tabs and newlines outside of quotes.
+/// * Inside `'...'` every character is literal.
+/// * Inside `"..."` a backslash only escapes `$`, `` ` ``, `"`, `\` and a newline.
+/// * Outside of quotes a backslash escapes any character.
+/// * A backslash followed by a newline is removed.
+pub fn split_posix(cmd: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = cmd.chars();
+    // The word being built, if any. Quotes start a word even if it stays empty.
+    let mut word: Option<String> = None;
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => args.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(unbalanced_quotes()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {}
+                            Some(c @ '$') | Some(c @ '`') | Some(c @ '"') | Some(c @ '\\') => {
+                                word.push(c)
+                            }
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(unbalanced_quotes()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(unbalanced_quotes()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(Error::from("Command line ends with an escape character")),
+            },
+            _ => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(word);
+    if args.is_empty() {
+        return Err(Error::from("Empty command line"));
+    }
+    Ok(args)
+}
+
+fn unbalanced_quotes() -> Error {
+    Error::from("Unbalanced quotes in command line")
+}
@@ -2,8 +2,13 @@ use crate::pipe::{ReadPipe, WritePipe};
 use crate::{Error, Result};
 
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+#[cfg(feature = "async")]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, TryLockError, Weak};
+#[cfg(feature = "async")]
+use std::task::{Context, Waker};
 use std::thread::{self, JoinHandle};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -20,6 +25,7 @@ pub trait SourceReader: Send {
 enum ConnectionKind {
     Pipe(WritePipe),
     File(BufWriter<WritePipe>),
+    Closed,
 }
 
 enum ConnectionState {
@@ -38,11 +44,21 @@ pub struct Destination {
     edges: Vec<SourceId>,
 }
 
+/// A handle that closes a destination without keeping it alive.
+#[derive(Clone)]
+pub struct DestinationHandle(Weak<Mutex<ConnectionKind>>);
+
+/// A handle that tells whether all destinations of a source are gone.
+#[derive(Clone)]
+pub struct SourceHandle(Arc<AtomicBool>);
+
 pub struct Source {
     pipe: ReadPipe,
     connections: Vec<Connection>,
     edges: Vec<DestinationId>,
     reader: Option<Box<dyn SourceReader>>,
+    disconnected: Arc<AtomicBool>,
+    drain_on_disconnect: bool,
 }
 
 #[derive(Default)]
@@ -61,8 +77,23 @@ pub struct TransmitterResults {
 pub struct Transmitter {
     readers: Vec<(SourceId, JoinHandle<Result<ReadPipe>>)>,
     file_dsts: Vec<Destination>,
+    #[cfg(feature = "async")]
+    readers_left: Arc<ReadersLeft>,
+}
+
+// The number of source readers that are still running, and the tasks to wake once there
+// are none, see `Transmitter::poll_finished`.
+#[cfg(feature = "async")]
+#[derive(Default)]
+struct ReadersLeft {
+    count: AtomicUsize,
+    wakers: Mutex<Vec<Waker>>,
 }
 
+// Counts a source reader as finished once dropped, even if the reader panics.
+#[cfg(feature = "async")]
+struct ReaderGuard(Arc<ReadersLeft>);
+
 impl ConnectionKind {
     fn is_file(&self) -> bool {
         matches!(self, ConnectionKind::File(_))
@@ -83,6 +114,7 @@ impl Connection {
             ConnectionState::Alive(ref mut kind) => match *kind.lock().unwrap() {
                 ConnectionKind::Pipe(ref mut p) => p.write_all(data),
                 ConnectionKind::File(ref mut f) => f.write_all(data),
+                ConnectionKind::Closed => Err(io::ErrorKind::BrokenPipe.into()),
             },
             ConnectionState::Dead => return,
         };
@@ -115,12 +147,62 @@ impl Source {
     {
         self.reader = Some(Box::new(reader));
     }
+
+    /// Makes the source keep reading and discarding the data after all of its connections
+    /// are dead, so that the writer does not block on a full pipe. Otherwise the source
+    /// stops reading. Has no effect if the source has a custom reader.
+    pub fn set_drain_on_disconnect(&mut self, drain: bool) {
+        self.drain_on_disconnect = drain;
+    }
+
+    pub fn handle(&self) -> SourceHandle {
+        SourceHandle(self.disconnected.clone())
+    }
+}
+
+impl SourceHandle {
+    /// Returns `true` if the source had connections and all of them are dead, e.g. because
+    /// the reading ends of the destination pipes are closed.
+    pub fn is_disconnected(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
 }
 
 impl Destination {
     pub fn edges(&self) -> &[SourceId] {
         &self.edges
     }
+
+    pub fn handle(&self) -> DestinationHandle {
+        DestinationHandle(Arc::downgrade(&self.connection_kind))
+    }
+}
+
+impl DestinationHandle {
+    /// Closes the destination, so that the reading end of its pipe gets EOF once the
+    /// data sent so far is read. Connections to a closed destination are considered dead.
+    ///
+    /// Returns `false` without blocking if the destination is being written to, in which
+    /// case closing must be retried later.
+    pub fn try_close(&self) -> bool {
+        let kind = match self.0.upgrade() {
+            Some(kind) => kind,
+            // The destination is already dropped, and so is the pipe.
+            None => return true,
+        };
+        let result = match kind.try_lock() {
+            Ok(mut kind) => {
+                *kind = ConnectionKind::Closed;
+                true
+            }
+            Err(TryLockError::WouldBlock) => false,
+            Err(TryLockError::Poisoned(e)) => {
+                *e.into_inner() = ConnectionKind::Closed;
+                true
+            }
+        };
+        result
+    }
 }
 
 impl Graph {
@@ -137,6 +219,8 @@ impl Graph {
                 connections: Vec::new(),
                 edges: Vec::new(),
                 reader: None,
+                disconnected: Arc::new(AtomicBool::new(false)),
+                drain_on_disconnect: false,
             },
         );
         id
@@ -203,6 +287,7 @@ impl Graph {
             {
                 ConnectionKind::Pipe(p) => p,
                 ConnectionKind::File(f) => f.into_inner().unwrap(),
+                ConnectionKind::Closed => unreachable!("Destination is closed before transmission"),
             }
         })
     }
@@ -242,13 +327,29 @@ impl Graph {
                 }
             })
             .collect();
+        #[cfg(feature = "async")]
+        let readers_left = Arc::new(ReadersLeft {
+            count: AtomicUsize::new(self.srcs.len()),
+            wakers: Mutex::new(Vec::new()),
+        });
         Transmitter {
             readers: self
                 .srcs
                 .into_iter()
-                .map(|(id, src)| (id, thread::spawn(move || read_source(src))))
+                .map(|(id, src)| {
+                    #[cfg(feature = "async")]
+                    let guard = ReaderGuard(readers_left.clone());
+                    let reader = thread::spawn(move || {
+                        #[cfg(feature = "async")]
+                        let _guard = guard;
+                        read_source(src)
+                    });
+                    (id, reader)
+                })
                 .collect(),
             file_dsts,
+            #[cfg(feature = "async")]
+            readers_left,
         }
     }
 
@@ -264,6 +365,22 @@ impl Graph {
 }
 
 impl Transmitter {
+    /// Returns `true` if every source has been read to the end, so that `wait` does not
+    /// block. Otherwise the task of `cx` is woken once that happens.
+    #[cfg(feature = "async")]
+    pub fn poll_finished(&self, cx: &mut Context) -> bool {
+        // The count is checked under the lock, so that the last reader, which decrements
+        // the count before taking the lock, cannot miss the registered waker.
+        let mut wakers = self.readers_left.wakers.lock().unwrap();
+        if self.readers_left.count.load(Ordering::Acquire) == 0 {
+            return true;
+        }
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        false
+    }
+
     pub fn wait(self) -> TransmitterResults {
         TransmitterResults {
             sources: self
@@ -283,10 +400,23 @@ impl Transmitter {
     }
 }
 
+#[cfg(feature = "async")]
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            for waker in self.0.wakers.lock().unwrap().drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
 fn read_source(src: Source) -> Result<ReadPipe> {
     let reader = src.reader;
     let mut pipe = src.pipe;
     let mut connections = src.connections;
+    let disconnected = src.disconnected;
+    let drain_on_disconnect = src.drain_on_disconnect;
 
     if let Some(mut reader) = reader {
         return reader.read(&mut pipe, &mut connections).map(|_| pipe);
@@ -307,7 +437,13 @@ fn read_source(src: Source) -> Result<ReadPipe> {
         reader.consume(data_len);
 
         if connections.iter().all(Connection::is_dead) {
-            break;
+            let is_disconnected = !connections.is_empty();
+            if is_disconnected {
+                disconnected.store(true, Ordering::SeqCst);
+            }
+            if !(is_disconnected && drain_on_disconnect) {
+                break;
+            }
         }
     }
 
@@ -0,0 +1,195 @@
+use crate::process::ExitStatus;
+use crate::{Report, TerminationKind};
+
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+/// A report in the text format of the original spawner. The time is in seconds and the
+/// memory is in megabytes.
+///
+/// [`Report::to_legacy`] fills in the results. The header describes how the program was
+/// run, which the report does not know, so it is left for the caller to fill in.
+///
+/// [`Report::to_legacy`]: struct.Report.html#method.to_legacy
+#[derive(Clone, Debug)]
+pub struct LegacyReport {
+    pub application: String,
+    pub parameters: Vec<String>,
+    pub security_level: Option<u32>,
+    pub create_process_method: String,
+    pub username: Option<String>,
+    pub user_time_limit: Option<f64>,
+    pub deadline: Option<f64>,
+    pub memory_limit: Option<f64>,
+    pub write_limit: Option<f64>,
+    pub user_time: f64,
+    pub peak_memory_used: f64,
+    pub written: f64,
+    /// The name of the reason, see [`TerminationKind::legacy_name`].
+    ///
+    /// [`TerminationKind::legacy_name`]: enum.TerminationKind.html#method.legacy_name
+    pub terminate_reason: String,
+    pub exit_code: u32,
+    pub exit_status: String,
+    pub spawner_errors: Vec<String>,
+}
+
+struct NoneOrJoin<T, U>(T)
+where
+    T: IntoIterator<Item = U> + Clone,
+    U: AsRef<str>;
+
+struct MbOrInf(Option<f64>);
+struct FltSecsOrInf(Option<f64>);
+struct Mb(f64);
+struct FltSecs(f64);
+
+impl TerminationKind {
+    /// Returns the name of the reason in the reports of the original spawner. The limits
+    /// it has no counterpart for are reported as `TerminatedByController`.
+    pub fn legacy_name(&self) -> &'static str {
+        use self::TerminationKind::*;
+        match *self {
+            WallClockTimeLimitExceeded | UserTimeLimitExceeded => "TimeLimitExceeded",
+            IdleTimeLimitExceeded => "IdleTimeLimitExceeded",
+            WriteLimitExceeded => "WriteLimitExceeded",
+            ReadLimitExceeded => "ReadLimitExceeded",
+            MemoryLimitExceeded | SwapLimitExceeded => "MemoryLimitExceeded",
+            ProcessLimitExceeded | ProcessCreationRateExceeded => "ProcessesCountLimitExceeded",
+            ActiveProcessLimitExceeded => "ActiveProcessesCountLimitExceeded",
+            ActiveThreadLimitExceeded => "ActiveThreadsCountLimitExceeded",
+            ActiveNetworkConnectionLimitExceeded => "ActiveConnectionCountLimitExceeded",
+            HandleLimitExceeded => "HandlesCountLimitExceeded",
+            NetworkEndpointLimitExceeded
+            | TerminatedByRunner
+            | GracefullyTerminatedByRunner
+            | OutputConsumerGone
+            | DeadlineExceeded
+            | SecurityViolation => "TerminatedByController",
+        }
+    }
+}
+
+impl Report {
+    /// Returns the results in the format of the original spawner, with an empty header.
+    pub fn to_legacy(&self) -> LegacyReport {
+        let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let user_time = self
+            .timers
+            .map_or(Duration::from_secs(0), |t| t.total_user_time);
+        let (terminate_reason, exit_code, exit_status) = match self.exit_status {
+            ExitStatus::Finished(code) => ("ExitProcess", code, code.to_string()),
+            ExitStatus::Crashed(ref cause) => ("AbnormalExitProcess", 0, cause.clone()),
+            ExitStatus::Running => ("ExitProcess", 0, "0".to_string()),
+        };
+        LegacyReport {
+            application: String::new(),
+            parameters: Vec::new(),
+            security_level: None,
+            create_process_method: "CreateProcess".to_string(),
+            username: None,
+            user_time_limit: None,
+            deadline: None,
+            memory_limit: None,
+            write_limit: None,
+            user_time: user_time.as_secs_f64(),
+            peak_memory_used: mb(self.memory.map_or(0, |m| m.max_usage)),
+            written: mb(self.io.map_or(0, |io| io.total_bytes_written)),
+            terminate_reason: self
+                .termination_reason
+                .map_or(terminate_reason, |r| r.kind().legacy_name())
+                .to_string(),
+            exit_code,
+            exit_status,
+            spawner_errors: self.error.iter().cloned().collect(),
+        }
+    }
+
+    /// Same as `self.to_legacy().to_string()`.
+    pub fn to_legacy_string(&self) -> String {
+        self.to_legacy().to_string()
+    }
+}
+
+macro_rules! line {
+    ($f:expr, $name:expr, $val:expr) => {
+        write!($f, "{0: <27}{1}\n", $name, $val)
+    };
+}
+
+impl Display for LegacyReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "--------------- Spawner report ---------------")?;
+        line!(f, "Application:", self.application)?;
+        line!(f, "Parameters:", NoneOrJoin(self.parameters.iter()))?;
+        line!(f, "SecurityLevel:", self.security_level.unwrap_or(0))?;
+        line!(f, "CreateProcessMethod:", self.create_process_method)?;
+        line!(
+            f,
+            "UserName:",
+            self.username.as_ref().unwrap_or(&String::new())
+        )?;
+        line!(f, "UserTimeLimit:", FltSecsOrInf(self.user_time_limit))?;
+        line!(f, "DeadLine:", FltSecsOrInf(self.deadline))?;
+        line!(f, "MemoryLimit:", MbOrInf(self.memory_limit))?;
+        line!(f, "WriteLimit:", MbOrInf(self.write_limit))?;
+        writeln!(f, "----------------------------------------------")?;
+        line!(f, "UserTime:", FltSecs(self.user_time))?;
+        line!(f, "PeakMemoryUsed:", Mb(self.peak_memory_used))?;
+        line!(f, "Written:", Mb(self.written))?;
+        line!(f, "TerminateReason:", self.terminate_reason)?;
+        line!(f, "ExitCode:", self.exit_code)?;
+        line!(f, "ExitStatus:", self.exit_status)?;
+        writeln!(f, "----------------------------------------------")?;
+        line!(f, "SpawnerError:", NoneOrJoin(self.spawner_errors.iter()))
+    }
+}
+
+impl<T, U> Display for NoneOrJoin<T, U>
+where
+    T: IntoIterator<Item = U> + Clone,
+    U: AsRef<str>,
+{
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut empty = true;
+        for i in self.0.clone().into_iter() {
+            empty = false;
+            write!(f, "{} ", i.as_ref())?;
+        }
+        if empty {
+            f.write_str("<none>")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Mb {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:.6} (Mb)", self.0)
+    }
+}
+
+impl Display for FltSecs {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:.6} (sec)", self.0)
+    }
+}
+
+impl Display for MbOrInf {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Some(v) => write!(f, "{}", Mb(v)),
+            None => write!(f, "Infinity"),
+        }
+    }
+}
+
+impl Display for FltSecsOrInf {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Some(v) => write!(f, "{}", FltSecs(v)),
+            None => write!(f, "Infinity"),
+        }
+    }
+}
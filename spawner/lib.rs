@@ -1,4 +1,5 @@
 extern crate cfg_if;
+extern crate rand;
 
 use cfg_if::cfg_if;
 
@@ -12,9 +13,10 @@ cfg_if! {
         }
     } else if #[cfg(unix)] {
         extern crate nix;
-        extern crate rand;
         extern crate cgroups_fs;
         extern crate procfs;
+        #[cfg(feature = "tokio")]
+        extern crate tokio;
 
         pub mod unix {
             pub use sys::process_ext as process;
@@ -22,18 +24,33 @@ cfg_if! {
     }
 }
 
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
+/// Emits a tracing event at the given level. Expands to nothing, and does not evaluate
+/// its arguments, unless the `tracing` feature is enabled.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::event!(::tracing::Level::$level, $($arg)+);
+    };
+}
+
+pub mod cmdline;
 pub mod dataflow;
 pub mod pipe;
 pub mod process;
 
 mod dataflow_analysis;
 mod error;
+mod legacy_report;
 mod limit_checker;
 mod spawner;
 mod supervisor;
 mod sys;
 
 pub use error::*;
+pub use legacy_report::*;
 pub use spawner::*;
 
 pub type Result<T> = std::result::Result<T, self::Error>;
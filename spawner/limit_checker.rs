@@ -1,6 +1,9 @@
-use crate::process::{GroupTimers, ResourceUsage};
-use crate::{ResourceLimits, Result, TerminationReason};
+use crate::process::{
+    GroupIo, GroupMemory, GroupNetwork, GroupPidCounters, GroupTimers, OsLimit, ResourceUsage,
+};
+use crate::{RemainingLimits, ResourceLimits, Result, TerminationKind, TerminationReason};
 
+use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
 pub struct LimitChecker {
@@ -9,9 +12,18 @@ pub struct LimitChecker {
     wall_clock_time: Duration,
     total_user_time: Duration,
     total_idle_time: Duration,
-    average_cpu_load: f64,
-    average_cpu_load_points: usize,
+    cpu_load_samples: VecDeque<f64>,
+    cpu_load_sum: f64,
+    last_cpu_load: f64,
     time_accounting_stopped: bool,
+    peak_open_handles: Option<usize>,
+    limit_usage: Vec<(TerminationKind, f64)>,
+    // The total number of processes created at each check within the window of the process
+    // creation rate limit, starting with the last check before the window. A check adds a
+    // sample only if the total has changed, so there are no more samples than processes
+    // created within the window, which the limit bounds.
+    process_creation_samples: VecDeque<(Instant, usize)>,
+    last_sample: Sample,
 }
 
 struct PrevCheck {
@@ -19,20 +31,25 @@ struct PrevCheck {
     total_user_time: Duration,
 }
 
-const CPU_LOAD_WINDOW_LENGTH: usize = 20;
-const CPU_LOAD_SMOOTHING_FACTOR: f64 = 1.0 - 1.0 / CPU_LOAD_WINDOW_LENGTH as f64;
-
 impl LimitChecker {
     pub fn new(limits: ResourceLimits) -> Self {
         Self {
             limits,
-            prev_check: None,
+            prev_check: Some(PrevCheck {
+                time: Instant::now(),
+                total_user_time: Duration::from_millis(0),
+            }),
             wall_clock_time: Duration::from_millis(0),
             total_user_time: Duration::from_millis(0),
             total_idle_time: Duration::from_millis(0),
-            average_cpu_load: 0.0,
-            average_cpu_load_points: 0,
+            cpu_load_samples: VecDeque::new(),
+            cpu_load_sum: 0.0,
+            last_cpu_load: 0.0,
             time_accounting_stopped: false,
+            peak_open_handles: None,
+            limit_usage: Vec::new(),
+            process_creation_samples: vec![(Instant::now(), 0)].into(),
+            last_sample: Sample::default(),
         }
     }
 
@@ -44,11 +61,101 @@ impl LimitChecker {
         self.time_accounting_stopped = false;
     }
 
+    pub fn is_time_accounting_stopped(&self) -> bool {
+        self.time_accounting_stopped
+    }
+
+    pub fn idle_time(&self) -> Duration {
+        self.total_idle_time
+    }
+
+    pub fn cpu_load(&self) -> f64 {
+        self.last_cpu_load
+    }
+
+    pub fn peak_open_handles(&self) -> Option<usize> {
+        self.peak_open_handles
+    }
+
+    /// Returns the fraction of every set limit that is used, as of the last check. The
+    /// fraction exceeds 1 once the limit is exceeded.
+    pub fn limit_usage(&self) -> &[(TerminationKind, f64)] {
+        &self.limit_usage
+    }
+
+    /// Returns what is left of every set limit, as of the last check.
+    pub fn remaining(&self) -> RemainingLimits {
+        fn left<T: Amount>(used: T, limit: Option<T>) -> Option<T> {
+            limit.map(|l| l.saturating_sub(used))
+        }
+
+        let limits = &self.limits;
+        let sample = &self.last_sample;
+        RemainingLimits {
+            idle_time: left(
+                self.total_idle_time,
+                limits.idle_time.map(|i| i.total_idle_time),
+            ),
+            wall_clock_time: left(self.wall_clock_time, limits.wall_clock_time),
+            total_user_time: left(self.total_user_time, limits.total_user_time),
+            max_memory_usage: left(sample.memory.max_usage, limits.max_memory_usage),
+            max_swap_usage: left(sample.memory.total_swap_bytes, limits.max_swap_usage),
+            total_bytes_written: left(sample.io.total_bytes_written, limits.total_bytes_written),
+            total_bytes_read: left(sample.io.total_bytes_read, limits.total_bytes_read),
+            total_processes_created: left(
+                sample.pid_counters.total_processes,
+                limits.total_processes_created,
+            ),
+            max_process_creation_rate: left(
+                sample.processes_created_in_window,
+                limits.max_process_creation_rate.map(|(n, _)| n),
+            ),
+            active_processes: left(
+                sample.pid_counters.active_processes,
+                limits.active_processes,
+            ),
+            active_threads: left(sample.pid_counters.active_threads, limits.active_threads),
+            active_network_connections: left(
+                sample.network.active_connections,
+                limits.active_network_connections,
+            ),
+            max_open_handles: left(sample.open_handles, limits.max_open_handles),
+            max_distinct_endpoints: left(
+                sample.network.distinct_remote_endpoints,
+                limits.max_distinct_endpoints,
+            ),
+        }
+    }
+
     pub fn reset_time(&mut self) {
         self.wall_clock_time = Duration::from_millis(0);
         self.total_user_time = Duration::from_millis(0);
     }
 
+    /// Forgets the counters that are derived from the counters of the group, once they are
+    /// reset.
+    pub fn reset_counters(&mut self) {
+        self.peak_open_handles = None;
+        self.process_creation_samples = vec![(Instant::now(), 0)].into();
+    }
+
+    /// Returns the time left until the nearest time limit is exceeded. The user time is
+    /// projected using the most recent cpu load.
+    pub fn time_until_limit(&self) -> Option<Duration> {
+        fn left(limit: Option<Duration>, used: Duration) -> Option<Duration> {
+            limit.map(|l| l.checked_sub(used).unwrap_or_default())
+        }
+
+        let wall_clock_time_left = left(self.limits.wall_clock_time, self.wall_clock_time);
+        let user_time_left = left(self.limits.total_user_time, self.total_user_time)
+            // User time can't grow faster than wall clock time on a single cpu.
+            .map(|t| t.div_f64(self.last_cpu_load.max(1.0)));
+        match (wall_clock_time_left, user_time_left) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+
     pub fn check(&mut self, usage: &ResourceUsage) -> Result<Option<TerminationReason>> {
         let timers = usage.timers()?.unwrap_or_default();
         self.update_timers(timers);
@@ -57,12 +164,15 @@ impl LimitChecker {
             total_user_time: timers.total_user_time,
         });
 
-        let limits = &self.limits;
-        let query_memory = limits.max_memory_usage.is_some();
-        let query_io = limits.total_bytes_written.is_some();
-        let query_network = limits.active_network_connections.is_some();
-        let query_pid_counters =
-            limits.active_processes.is_some() || limits.total_processes_created.is_some();
+        let limits = self.limits;
+        let query_memory = limits.max_memory_usage.is_some() || limits.max_swap_usage.is_some();
+        let query_io = limits.total_bytes_written.is_some() || limits.total_bytes_read.is_some();
+        let query_network =
+            limits.active_network_connections.is_some() || limits.max_distinct_endpoints.is_some();
+        let query_pid_counters = limits.active_processes.is_some()
+            || limits.active_threads.is_some()
+            || limits.total_processes_created.is_some()
+            || limits.max_process_creation_rate.is_some();
 
         let memory = if query_memory { usage.memory()? } else { None }.unwrap_or_default();
         let io = if query_io { usage.io()? } else { None }.unwrap_or_default();
@@ -78,36 +188,150 @@ impl LimitChecker {
             None
         }
         .unwrap_or_default();
-
-        fn gr<T: PartialOrd>(stat: T, limit: Option<T>) -> bool {
-            limit.is_some() && stat > limit.unwrap()
+        let open_handles = if limits.max_open_handles.is_some() {
+            usage.open_handles()?
+        } else {
+            None
+        };
+        if let Some(n) = open_handles {
+            let peak = self.peak_open_handles.unwrap_or(0);
+            self.peak_open_handles = Some(std::cmp::max(peak, n));
         }
+        let open_handles = open_handles.unwrap_or_default();
+        let processes_created_in_window = match limits.max_process_creation_rate {
+            Some((_, window)) => {
+                self.processes_created_within(window, pid_counters.total_processes)
+            }
+            None => 0,
+        };
 
-        Ok(Some(if gr(self.wall_clock_time, limits.wall_clock_time) {
-            TerminationReason::WallClockTimeLimitExceeded
-        } else if gr(
-            self.total_idle_time,
-            limits.idle_time.map(|i| i.total_idle_time),
-        ) {
-            TerminationReason::IdleTimeLimitExceeded
-        } else if gr(self.total_user_time, limits.total_user_time) {
-            TerminationReason::UserTimeLimitExceeded
-        } else if gr(io.total_bytes_written, limits.total_bytes_written) {
-            TerminationReason::WriteLimitExceeded
-        } else if gr(memory.max_usage, limits.max_memory_usage) {
-            TerminationReason::MemoryLimitExceeded
-        } else if gr(pid_counters.total_processes, limits.total_processes_created) {
-            TerminationReason::ProcessLimitExceeded
-        } else if gr(pid_counters.active_processes, limits.active_processes) {
-            TerminationReason::ActiveProcessLimitExceeded
-        } else if gr(
-            network.active_connections,
-            limits.active_network_connections,
-        ) {
-            TerminationReason::ActiveNetworkConnectionLimitExceeded
-        } else {
-            return Ok(None);
-        }))
+        use TerminationReason::*;
+        let idle_time_limit = limits.idle_time.map(|i| i.total_idle_time);
+        let stats = [
+            Stat::new(
+                self.wall_clock_time,
+                limits.wall_clock_time,
+                |used, limit| WallClockTimeLimitExceeded { used, limit },
+            ),
+            Stat::new(self.total_idle_time, idle_time_limit, |used, limit| {
+                IdleTimeLimitExceeded { used, limit }
+            }),
+            Stat::new(
+                self.total_user_time,
+                limits.total_user_time,
+                |used, limit| UserTimeLimitExceeded { used, limit },
+            ),
+            Stat::new(
+                io.total_bytes_written,
+                limits.total_bytes_written,
+                |used, limit| WriteLimitExceeded { used, limit },
+            ),
+            Stat::new(
+                io.total_bytes_read,
+                limits.total_bytes_read,
+                |used, limit| ReadLimitExceeded { used, limit },
+            ),
+            Stat::new(memory.max_usage, limits.max_memory_usage, |used, limit| {
+                MemoryLimitExceeded { used, limit }
+            }),
+            Stat::new(
+                memory.total_swap_bytes,
+                limits.max_swap_usage,
+                |used, limit| SwapLimitExceeded { used, limit },
+            ),
+            Stat::new(
+                pid_counters.total_processes,
+                limits.total_processes_created,
+                |used, limit| ProcessLimitExceeded { used, limit },
+            ),
+            Stat::new(
+                processes_created_in_window,
+                limits.max_process_creation_rate.map(|(n, _)| n),
+                |used, limit| ProcessCreationRateExceeded { used, limit },
+            ),
+            Stat::new(
+                pid_counters.active_processes,
+                limits.active_processes,
+                |used, limit| ActiveProcessLimitExceeded { used, limit },
+            ),
+            Stat::new(
+                pid_counters.active_threads,
+                limits.active_threads,
+                |used, limit| ActiveThreadLimitExceeded { used, limit },
+            ),
+            Stat::new(
+                network.active_connections,
+                limits.active_network_connections,
+                |used, limit| ActiveNetworkConnectionLimitExceeded { used, limit },
+            ),
+            Stat::new(open_handles, limits.max_open_handles, |used, limit| {
+                HandleLimitExceeded { used, limit }
+            }),
+            Stat::new(
+                network.distinct_remote_endpoints,
+                limits.max_distinct_endpoints,
+                |used, limit| NetworkEndpointLimitExceeded { used, limit },
+            ),
+        ];
+
+        self.last_sample = Sample {
+            memory,
+            io,
+            pid_counters,
+            network,
+            open_handles,
+            processes_created_in_window,
+        };
+        self.limit_usage.clear();
+        self.limit_usage.extend(
+            stats
+                .iter()
+                .flatten()
+                .map(|s| (s.reason.kind(), s.fraction)),
+        );
+        Ok(stats
+            .iter()
+            .flatten()
+            .find(|s| s.exceeded)
+            .map(|s| s.reason))
+    }
+
+    /// Returns the reason to terminate the group with once the OS has hit `limit`, with the
+    /// usage measured now.
+    pub fn os_limit_exceeded(
+        &self,
+        limit: OsLimit,
+        usage: &ResourceUsage,
+    ) -> Result<TerminationReason> {
+        let limits = &self.limits;
+        Ok(match limit {
+            OsLimit::Memory => TerminationReason::MemoryLimitExceeded {
+                used: usage.memory()?.unwrap_or_default().max_usage,
+                limit: limits.max_memory_usage.unwrap_or(0),
+            },
+            OsLimit::Swap => TerminationReason::SwapLimitExceeded {
+                used: usage.memory()?.unwrap_or_default().total_swap_bytes,
+                limit: limits.max_swap_usage.unwrap_or(0),
+            },
+            OsLimit::ActiveProcess => TerminationReason::ActiveProcessLimitExceeded {
+                used: usage.pid_counters()?.unwrap_or_default().active_processes,
+                limit: limits.active_processes.unwrap_or(0),
+            },
+        })
+    }
+
+    fn processes_created_within(&mut self, window: Duration, total_processes: usize) -> usize {
+        let now = Instant::now();
+        let samples = &mut self.process_creation_samples;
+        // Dropping a sample with the same total as the previous one doesn't change the total
+        // at the start of the window.
+        if samples.back().map(|&(_, total)| total) != Some(total_processes) {
+            samples.push_back((now, total_processes));
+        }
+        while samples.len() > 1 && now.duration_since(samples[1].0) >= window {
+            samples.pop_front();
+        }
+        total_processes.saturating_sub(samples[0].1)
     }
 
     fn update_timers(&mut self, timers: GroupTimers) {
@@ -120,26 +344,140 @@ impl LimitChecker {
             None => return,
         };
         let dt = prev_check.time.elapsed();
-        let d_user = timers.total_user_time - prev_check.total_user_time;
+        // The user time decreases if the counters of the group are reset.
+        let d_user = timers
+            .total_user_time
+            .saturating_sub(prev_check.total_user_time);
         let new_cpu_load = d_user.as_micros() as f64 / dt.as_micros() as f64;
 
         self.wall_clock_time += dt;
         self.total_user_time += d_user;
-        self.average_cpu_load = self.average_cpu_load * CPU_LOAD_SMOOTHING_FACTOR
-            + new_cpu_load * (1.0 - CPU_LOAD_SMOOTHING_FACTOR);
-        self.average_cpu_load_points += 1;
+        self.last_cpu_load = new_cpu_load;
 
         let idle_time_limit = match self.limits.idle_time {
             Some(il) => il,
             None => return,
         };
-        if self.average_cpu_load_points < CPU_LOAD_WINDOW_LENGTH {
+
+        // Keep the last `cpu_load_window_length` samples, so a single dip in the cpu load
+        // does not make the process idle.
+        let window_length = std::cmp::max(idle_time_limit.cpu_load_window_length, 1);
+        self.cpu_load_samples.push_back(new_cpu_load);
+        self.cpu_load_sum += new_cpu_load;
+        while self.cpu_load_samples.len() > window_length {
+            self.cpu_load_sum -= self.cpu_load_samples.pop_front().unwrap();
+        }
+        if self.cpu_load_samples.len() < window_length {
             return;
         }
-        if self.average_cpu_load < idle_time_limit.cpu_load_threshold {
+
+        let average_cpu_load = self.cpu_load_sum / window_length as f64;
+        if average_cpu_load < idle_time_limit.cpu_load_threshold {
             self.total_idle_time += dt;
         } else {
             self.total_idle_time = Duration::from_millis(0);
         }
     }
 }
+
+trait Amount: PartialOrd + Copy {
+    fn as_f64(self) -> f64;
+
+    fn saturating_sub(self, rhs: Self) -> Self;
+}
+
+// The usage seen at the last check, for the limits that are set.
+#[derive(Default)]
+struct Sample {
+    memory: GroupMemory,
+    io: GroupIo,
+    pid_counters: GroupPidCounters,
+    network: GroupNetwork,
+    open_handles: usize,
+    processes_created_in_window: usize,
+}
+
+// A statistic that is checked against its limit.
+struct Stat {
+    // The reason to terminate the group with if the limit is exceeded.
+    reason: TerminationReason,
+    exceeded: bool,
+    // The fraction of the limit that is used.
+    fraction: f64,
+}
+
+impl Stat {
+    // Returns `None` if the limit is not set.
+    fn new<T, F>(stat: T, limit: Option<T>, reason: F) -> Option<Self>
+    where
+        T: Amount,
+        F: FnOnce(T, T) -> TerminationReason,
+    {
+        limit.map(|limit| Self {
+            reason: reason(stat, limit),
+            exceeded: stat > limit,
+            fraction: stat.as_f64() / limit.as_f64(),
+        })
+    }
+}
+
+impl Amount for Duration {
+    fn as_f64(self) -> f64 {
+        self.as_secs_f64()
+    }
+
+    fn saturating_sub(self, rhs: Self) -> Self {
+        Duration::saturating_sub(self, rhs)
+    }
+}
+
+impl Amount for u64 {
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn saturating_sub(self, rhs: Self) -> Self {
+        u64::saturating_sub(self, rhs)
+    }
+}
+
+impl Amount for usize {
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn saturating_sub(self, rhs: Self) -> Self {
+        usize::saturating_sub(self, rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn process_creation_samples_are_bounded() {
+        let window = Duration::from_secs(60);
+        let mut checker = LimitChecker::new(ResourceLimits::default());
+        for _ in 0..1000 {
+            assert_eq!(checker.processes_created_within(window, 3), 3);
+        }
+        assert_eq!(checker.process_creation_samples.len(), 2);
+
+        assert_eq!(checker.processes_created_within(window, 5), 5);
+        assert_eq!(checker.process_creation_samples.len(), 3);
+    }
+
+    #[test]
+    fn processes_created_within_window() {
+        let window = Duration::from_millis(100);
+        let mut checker = LimitChecker::new(ResourceLimits::default());
+        assert_eq!(checker.processes_created_within(window, 2), 2);
+        thread::sleep(Duration::from_millis(150));
+        // The total at the start of the window is kept while the total doesn't change.
+        assert_eq!(checker.processes_created_within(window, 2), 0);
+        assert_eq!(checker.processes_created_within(window, 3), 1);
+    }
+}
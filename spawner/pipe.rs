@@ -2,11 +2,30 @@ use crate::sys::pipe as imp;
 use crate::sys::{FromInner, IntoInner};
 use crate::Result;
 
-use std::io::{self, Read, Write};
+#[cfg(all(unix, feature = "tokio"))]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
 use std::path::Path;
+#[cfg(all(unix, feature = "tokio"))]
+use std::pin::Pin;
+#[cfg(all(unix, feature = "tokio"))]
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 /// A reference to the reading end of a pipe or to the file opened in read mode.
 ///
+/// The underlying descriptor or handle can be borrowed with `AsRawFd` or `AsRawHandle`.
+/// It stays owned by the pipe and is closed when the pipe is dropped. Conversely,
+/// `FromRawFd` and `FromRawHandle` make the pipe take ownership of a descriptor or handle
+/// obtained elsewhere, e.g. a socket; on Linux it is made close-on-exec, like the
+/// descriptors opened by the pipe itself.
+///
 /// [`create`]: fn.create.html
 /// [`ReadPipe::open`]: struct.ReadPipe.html#method.open
 #[derive(Debug)]
@@ -14,11 +33,35 @@ pub struct ReadPipe(imp::ReadPipe);
 
 /// A reference to the writing end of a pipe or to the file opened in write mode.
 ///
+/// The underlying descriptor or handle can be borrowed and adopted as for [`ReadPipe`].
+///
+/// [`ReadPipe`]: struct.ReadPipe.html
 /// [`create`]: fn.create.html
 /// [`WritePipe::open`]: struct.WritePipe.html#method.open
 #[derive(Debug)]
 pub struct WritePipe(imp::WritePipe);
 
+/// A [`ReadPipe`] adapted for reading from a tokio runtime.
+///
+/// The descriptor is switched to non-blocking mode and registered in the reactor of the
+/// current runtime. Its original mode is restored when the adapter is dropped or turned
+/// back into a [`ReadPipe`].
+///
+/// Only available on unix: anonymous pipes on Windows do not support overlapped io.
+///
+/// [`ReadPipe`]: struct.ReadPipe.html
+#[cfg(all(unix, feature = "tokio"))]
+#[derive(Debug)]
+pub struct AsyncReadPipe(imp::AsyncReadPipe);
+
+/// A [`WritePipe`] adapted for writing from a tokio runtime. See [`AsyncReadPipe`].
+///
+/// [`WritePipe`]: struct.WritePipe.html
+/// [`AsyncReadPipe`]: struct.AsyncReadPipe.html
+#[cfg(all(unix, feature = "tokio"))]
+#[derive(Debug)]
+pub struct AsyncWritePipe(imp::AsyncWritePipe);
+
 /// Creates a new pipe returning the [`ReadPipe`] and [`WritePipe`] pair.
 ///
 /// [`ReadPipe`]: struct.ReadPipe.html
@@ -36,6 +79,56 @@ impl ReadPipe {
     pub fn null() -> Result<Self> {
         imp::ReadPipe::null().map(Self)
     }
+
+    /// Creates a new `ReadPipe` that refers to the same pipe or file as this one.
+    pub fn try_clone(&self) -> Result<Self> {
+        self.0.try_clone().map(Self)
+    }
+
+    /// Returns `true` if this pipe refers to a regular file.
+    pub fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    /// Returns `true` if this pipe refers to a pipe rather than to a file or a device.
+    pub fn is_pipe(&self) -> bool {
+        self.0.is_pipe()
+    }
+
+    /// Spawns a thread that reads the pipe until EOF and invokes `f` on every line, without
+    /// the line terminator. Both `\n` and `\r\n` end a line, and the data after the last
+    /// terminator is passed as the last line if it is not empty. Invalid UTF-8 sequences
+    /// are replaced with `U+FFFD`.
+    pub fn for_each_line<F>(self, mut f: F) -> JoinHandle<Result<()>>
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        thread::spawn(move || {
+            let mut reader = BufReader::new(self);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    return Ok(());
+                }
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                f(&String::from_utf8_lossy(&line));
+            }
+        })
+    }
+
+    /// Converts this pipe into an [`AsyncReadPipe`]. Must be called within a tokio runtime.
+    ///
+    /// [`AsyncReadPipe`]: struct.AsyncReadPipe.html
+    #[cfg(all(unix, feature = "tokio"))]
+    pub fn into_async(self) -> Result<AsyncReadPipe> {
+        imp::AsyncReadPipe::new(self.0).map(AsyncReadPipe)
+    }
 }
 
 impl IntoInner<imp::ReadPipe> for ReadPipe {
@@ -56,14 +149,135 @@ impl Read for ReadPipe {
     }
 }
 
+#[cfg(unix)]
+impl AsRawFd for ReadPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawHandle for ReadPipe {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+#[cfg(unix)]
+impl FromRawFd for ReadPipe {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(imp::ReadPipe::from_raw_fd(fd))
+    }
+}
+
+#[cfg(windows)]
+impl FromRawHandle for ReadPipe {
+    unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+        Self(imp::ReadPipe::from_raw_handle(handle))
+    }
+}
+
 impl WritePipe {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         imp::WritePipe::open(path).map(Self)
     }
 
+    /// Opens the file at `path` for appending, creating it if it does not exist. Unlike
+    /// [`open`], the existing contents are kept and every write goes to the end of the file.
+    ///
+    /// [`open`]: struct.WritePipe.html#method.open
+    pub fn open_append<P: AsRef<Path>>(path: P) -> Result<Self> {
+        imp::WritePipe::open_append(path).map(Self)
+    }
+
     pub fn null() -> Result<Self> {
         imp::WritePipe::null().map(Self)
     }
+
+    /// Creates a new `WritePipe` that refers to the standard output of the current process.
+    pub fn stdout() -> Result<Self> {
+        imp::WritePipe::stdout().map(Self)
+    }
+
+    /// Creates a new `WritePipe` that refers to the same pipe or file as this one.
+    pub fn try_clone(&self) -> Result<Self> {
+        self.0.try_clone().map(Self)
+    }
+
+    /// Returns `true` if this pipe refers to a regular file.
+    pub fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    /// Returns `true` if this pipe refers to a pipe rather than to a file or a device.
+    pub fn is_pipe(&self) -> bool {
+        self.0.is_pipe()
+    }
+
+    /// Creates a new pipe whose data is forwarded to `inner`, invoking `f` on each chunk
+    /// before it is written. The data is forwarded by a background thread until all copies
+    /// of the returned pipe are closed or writing to `inner` fails, so the returned pipe can
+    /// be used wherever `inner` could, e.g. as the stdout of a process.
+    ///
+    /// The callback runs on the forwarding thread and should return quickly: the writer
+    /// blocks once the pipe buffer is full.
+    pub fn inspect<F>(inner: WritePipe, f: F) -> Result<Self>
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        let (r, w) = create()?;
+        thread::spawn(move || forward_inspected(r, inner, f));
+        Ok(w)
+    }
+
+    /// Spawns a thread that copies `src` into this pipe at no more than `bytes_per_sec`
+    /// bytes per second, e.g. to feed the stdin of a process gradually. The data is written
+    /// in small chunks with sleeps in between, and the pipe is closed once `src` is
+    /// exhausted. Feeding stops early without an error if the reading end is closed.
+    ///
+    /// The thread returns the number of bytes written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_sec` is zero.
+    pub fn feed_paced<R>(mut self, mut src: R, bytes_per_sec: u64) -> JoinHandle<Result<u64>>
+    where
+        R: Read + Send + 'static,
+    {
+        assert!(bytes_per_sec > 0, "bytes_per_sec must be positive");
+        // Ten chunks per second keep the input smooth without waking up too often.
+        let chunk_len = (bytes_per_sec / 10).clamp(1, 4096) as usize;
+        thread::spawn(move || {
+            let start = Instant::now();
+            let mut buf = vec![0; chunk_len];
+            let mut written = 0;
+            loop {
+                let n = match src.read(&mut buf) {
+                    Ok(0) => return Ok(written),
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                match self.write_all(&buf[..n]) {
+                    Ok(()) => written += n as u64,
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(written),
+                    Err(e) => return Err(e.into()),
+                }
+                let due = Duration::from_secs_f64(written as f64 / bytes_per_sec as f64);
+                if let Some(delay) = due.checked_sub(start.elapsed()) {
+                    thread::sleep(delay);
+                }
+            }
+        })
+    }
+
+    /// Converts this pipe into an [`AsyncWritePipe`]. Must be called within a tokio runtime.
+    ///
+    /// [`AsyncWritePipe`]: struct.AsyncWritePipe.html
+    #[cfg(all(unix, feature = "tokio"))]
+    pub fn into_async(self) -> Result<AsyncWritePipe> {
+        imp::AsyncWritePipe::new(self.0).map(AsyncWritePipe)
+    }
 }
 
 impl IntoInner<imp::WritePipe> for WritePipe {
@@ -87,3 +301,96 @@ impl Write for WritePipe {
         self.0.flush()
     }
 }
+
+#[cfg(unix)]
+impl AsRawFd for WritePipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawHandle for WritePipe {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+#[cfg(unix)]
+impl FromRawFd for WritePipe {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(imp::WritePipe::from_raw_fd(fd))
+    }
+}
+
+#[cfg(windows)]
+impl FromRawHandle for WritePipe {
+    unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+        Self(imp::WritePipe::from_raw_handle(handle))
+    }
+}
+
+fn forward_inspected<F>(mut src: ReadPipe, mut dst: WritePipe, mut f: F)
+where
+    F: FnMut(&[u8]),
+{
+    let mut buf = [0; 4096];
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        f(&buf[..n]);
+        if dst.write_all(&buf[..n]).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "tokio"))]
+impl AsyncReadPipe {
+    /// Restores the original mode of the descriptor and returns the underlying pipe.
+    pub fn into_sync(self) -> ReadPipe {
+        ReadPipe(self.0.into_inner())
+    }
+}
+
+#[cfg(all(unix, feature = "tokio"))]
+impl AsyncRead for AsyncReadPipe {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+#[cfg(all(unix, feature = "tokio"))]
+impl AsyncWritePipe {
+    /// Restores the original mode of the descriptor and returns the underlying pipe.
+    pub fn into_sync(self) -> WritePipe {
+        WritePipe(self.0.into_inner())
+    }
+}
+
+#[cfg(all(unix, feature = "tokio"))]
+impl AsyncWrite for AsyncWritePipe {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
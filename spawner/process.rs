@@ -1,15 +1,108 @@
+use crate::cmdline;
 use crate::pipe::{ReadPipe, WritePipe};
 use crate::sys::process as imp;
 use crate::sys::{AsInnerMut, IntoInner};
 use crate::Result;
 
-use std::time::Duration;
+use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Describes the result of a process after it has terminated.
 #[derive(Clone, Debug, PartialEq)]
 pub enum ExitStatus {
     Crashed(String),
     Finished(u32),
+    /// The process has not terminated yet. Only reported in live snapshots.
+    Running,
+}
+
+/// Describes the environment a process is started with.
+///
+/// On Windows `Clear` and `Set` still keep the variables that are required to start most
+/// programs (e.g. `SystemRoot`), unless they are overridden.
+#[derive(Clone, Debug)]
+pub enum EnvMode {
+    /// Inherit the environment of the current process.
+    Inherit,
+    /// Start with an empty environment.
+    Clear,
+    /// Start with the given set of variables only.
+    Set(HashMap<String, String>),
+}
+
+/// Describes the scheduling priority of a process.
+///
+/// On Windows it maps to a priority class, on Linux to a nice value. Raising the priority
+/// above `Normal` on Linux requires `CAP_SYS_NICE`, without it spawning fails.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Priority {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+}
+
+/// Describes which console a process is attached to. Only has an effect on Windows.
+///
+/// Console control events, and therefore [`Group::interrupt`] and [`Group::signal`], only
+/// reach the processes that share the console of the spawner. A process with a `New` or
+/// `None` console can't be interrupted: `ProgramMessage::GracefulTerminate` terminates it
+/// once the timeout elapses.
+///
+/// [`Group::interrupt`]: struct.Group.html#method.interrupt
+/// [`Group::signal`]: struct.Group.html#method.signal
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConsoleMode {
+    /// Attach to the console of the spawner. This is the default.
+    Inherit,
+    /// Allocate a new console (`CREATE_NEW_CONSOLE`), which is visible for debugging.
+    New,
+    /// Run without a visible console (`CREATE_NO_WINDOW`). Console processes spawned by the
+    /// process still share its hidden console, rather than opening windows of their own.
+    None,
+}
+
+/// Describes the directory a process spawned with [`ProcessInfo::filesystem_sandbox`] can
+/// write to.
+///
+/// [`ProcessInfo::filesystem_sandbox`]: struct.ProcessInfo.html#method.filesystem_sandbox
+#[derive(Clone, Debug, PartialEq)]
+pub struct FsSandbox {
+    /// The only directory the process can write to, which must exist. It becomes the
+    /// working directory, unless one is set by [`ProcessInfo::working_dir`].
+    ///
+    /// [`ProcessInfo::working_dir`]: struct.ProcessInfo.html#method.working_dir
+    pub scratch_dir: String,
+    /// The maximum size of the files in the scratch directory, in bytes. Defaults to half of
+    /// the RAM.
+    pub scratch_size: Option<u64>,
+}
+
+/// Describes what is taken away from a process spawned with
+/// [`ProcessInfo::restrict_token`]. Each field only has an effect on one platform.
+///
+/// [`ProcessInfo::restrict_token`]: struct.ProcessInfo.html#method.restrict_token
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestrictOptions {
+    /// The groups that are made deny-only on Windows, as string SIDs: the token keeps them,
+    /// but they are only matched against the entries that deny access. Defaults to the
+    /// Administrators (`S-1-5-32-544`) and Power Users (`S-1-5-32-547`) groups, and to the
+    /// local accounts that are members of Administrators (`S-1-5-114`).
+    pub deny_only_sids: Vec<String>,
+    /// The privileges that are kept on Windows, by name; all the others are removed from
+    /// the token. Defaults to `SeChangeNotifyPrivilege`, which most programs need to access
+    /// the files in directories they can't list.
+    pub keep_privileges: Vec<String>,
+    /// The capabilities that are kept on Linux, by number, e.g. 10 for
+    /// `CAP_NET_BIND_SERVICE`. Only the capabilities held by the spawner can be kept. Empty
+    /// by default.
+    pub keep_capabilities: Vec<u32>,
 }
 
 /// Describes the standard I/O streams of a process.
@@ -19,15 +112,47 @@ pub struct Stdio {
     pub stderr: WritePipe,
 }
 
+/// A builder for [`Stdio`]. Streams that are not set are redirected to the null device.
+///
+/// [`Stdio`]: struct.Stdio.html
+#[derive(Default)]
+pub struct StdioBuilder {
+    stdin: Option<ReadPipe>,
+    stdout: Option<WritePipe>,
+    stderr: Option<WritePipe>,
+    inherit_stdout: bool,
+}
+
 /// Represents the set of parameters to use to spawn a process.
+#[derive(Clone)]
 pub struct ProcessInfo(imp::ProcessInfo);
 
 /// Handle to a process.
 pub struct Process(imp::Process);
 
+/// Interrupts [`Process::wait_or_wake`] from another thread.
+///
+/// [`Process::wait_or_wake`]: struct.Process.html#method.wait_or_wake
+pub(crate) struct WakeEvent(imp::WakeEvent);
+
 #[derive(Copy, Clone, Debug)]
 pub struct GroupMemory {
+    /// The peak memory usage, in bytes. This is the value that
+    /// [`ResourceLimits::max_memory_usage`] is checked against.
+    ///
+    /// [`ResourceLimits::max_memory_usage`]: ../struct.ResourceLimits.html#structfield.max_memory_usage
     pub max_usage: u64,
+    /// The memory that is currently resident, in bytes. On Linux this excludes the page
+    /// cache that can be reclaimed.
+    pub working_set_bytes: u64,
+    /// The memory that is currently private to the group, in bytes: anonymous memory on
+    /// Linux, private commit on Windows.
+    pub private_bytes: u64,
+    /// The total number of page faults.
+    pub page_fault_count: u64,
+    /// The memory that is currently swapped out, in bytes. On Windows this is the
+    /// committed memory of the job, i.e. the memory charged against the page file.
+    pub total_swap_bytes: u64,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -39,39 +164,163 @@ pub struct GroupTimers {
 #[derive(Copy, Clone, Debug)]
 pub struct GroupIo {
     pub total_bytes_written: u64,
+    pub total_bytes_read: u64,
+    /// The number of read operations. On Linux these are the read syscalls counted by
+    /// `syscr` in `/proc/<pid>/io`, on Windows the read operations of the job object.
+    pub read_operations: u64,
+    /// The number of write operations, counted as `syscw` on Linux.
+    pub write_operations: u64,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct GroupPidCounters {
     pub active_processes: usize,
+    pub active_threads: usize,
     pub total_processes: usize,
 }
 
+/// A process of a group, as seen by [`Group::process_nodes`].
+///
+/// [`Group::process_nodes`]: struct.Group.html#method.process_nodes
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessNode {
+    pub pid: u32,
+    pub parent_pid: u32,
+    /// When the process started, in clock ticks since boot on Linux and in 100-nanosecond
+    /// intervals since 1601 on Windows. Tells the process apart from a later one reusing
+    /// its pid.
+    pub start_time: u64,
+    /// The name of the executable without the directory. On Linux it is the `comm` name of
+    /// the process, which is truncated to 15 bytes.
+    pub image_name: String,
+    /// The exit code of the process, if it has already exited.
+    pub exit_code: Option<u32>,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct GroupNetwork {
+    /// The number of TCP and UDP sockets (both IPv4 and IPv6) owned by the
+    /// processes of the group. TCP sockets are counted in every state, including
+    /// listening ones. On Linux a socket belongs to the group if one of its
+    /// processes holds a file descriptor for it; on Windows the owning pid is
+    /// taken from the system TCP/UDP tables.
     pub active_connections: usize,
+    /// The number of distinct remote addresses and ports the processes of the group have
+    /// been connected to over TCP, as seen by the queries of the resource usage, see
+    /// [`ResourceUsage::remote_endpoints`]. Listening sockets are not counted.
+    ///
+    /// [`ResourceUsage::remote_endpoints`]: struct.ResourceUsage.html#method.remote_endpoints
+    pub distinct_remote_endpoints: usize,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum OsLimit {
     Memory,
+    /// Unlike other limits, setting it fails if the limit can't be checked at all.
+    Swap,
     ActiveProcess,
 }
 
-pub struct ResourceUsage<'a>(imp::ResourceUsage<'a>);
+/// Reads the resource usage of a [`Group`]. The cumulative counters are accounted since the
+/// last [`Group::reset_counters`] that was seen by [`update`], and the peak memory usage
+/// since the last [`Group::reset_peak_memory`] too.
+///
+/// [`Group`]: struct.Group.html
+/// [`Group::reset_counters`]: struct.Group.html#method.reset_counters
+/// [`Group::reset_peak_memory`]: struct.Group.html#method.reset_peak_memory
+/// [`update`]: struct.ResourceUsage.html#method.update
+pub struct ResourceUsage<'a> {
+    inner: imp::ResourceUsage<'a>,
+    group: &'a Group,
+    counter_resets_seen: usize,
+    baseline: Option<Baseline>,
+    peak_memory_resets_seen: usize,
+    // The peak working set since the reset, if the OS could not reset the peak memory usage.
+    // Sampled on every update, so that it is the peak seen by the monitoring.
+    peak_memory: Option<u64>,
+    // The remote endpoints seen since the last reset of the counters.
+    remote_endpoints: RefCell<HashSet<SocketAddr>>,
+}
+
+// The cumulative counters at the last reset, which are subtracted from the current ones.
+struct Baseline {
+    time: Instant,
+    timers: GroupTimers,
+    io: GroupIo,
+    total_processes: usize,
+    page_fault_count: u64,
+}
 
 /// Describes a group of processes.
-pub struct Group(imp::Group);
+pub struct Group {
+    inner: imp::Group,
+    counter_resets: Mutex<CounterResets>,
+    // The time of the last `cpu_load` call and the total cpu time at that moment.
+    cpu_load_sample: Mutex<(Instant, Duration)>,
+}
+
+#[derive(Copy, Clone, Default)]
+struct CounterResets {
+    count: usize,
+    // Resets of the peak memory usage, including the ones by `reset_counters`.
+    peak_memory_count: usize,
+    // Whether the OS has reset the peak memory usage on the last reset.
+    peak_memory_reset: bool,
+}
 
 impl ProcessInfo {
     pub fn new<T: AsRef<str>>(app: T) -> Self {
         Self(imp::ProcessInfo::new(app))
     }
 
+    /// Creates the process info from a command line, which is split into the program and
+    /// the arguments by [`cmdline::split`].
+    ///
+    /// [`cmdline::split`]: ../cmdline/fn.split.html
+    pub fn from_command_line<T: AsRef<str>>(cmd: T) -> Result<Self> {
+        let mut args = cmdline::split(cmd.as_ref())?.into_iter();
+        let mut info = Self::new(args.next().unwrap());
+        info.args(args);
+        Ok(info)
+    }
+
+    /// Adds an argument. Arguments are passed to the process as is: on Linux the vector is
+    /// passed to `exec` directly, and on Windows the command line is assembled by
+    /// [`cmdline::join_windows`].
+    ///
+    /// [`cmdline::join_windows`]: ../cmdline/fn.join_windows.html
+    pub fn arg<T: AsRef<str>>(&mut self, arg: T) -> &mut Self {
+        self.arg_os(arg.as_ref())
+    }
+
+    /// Adds multiple arguments, see [`arg`].
+    ///
+    /// [`arg`]: struct.ProcessInfo.html#method.arg
     pub fn args<T, U>(&mut self, args: T) -> &mut Self
     where
         T: IntoIterator<Item = U>,
         U: AsRef<str>,
+    {
+        self.args_os(args.into_iter().map(|arg| arg.as_ref().to_owned()))
+    }
+
+    /// Adds an argument that is not necessarily valid Unicode, such as a file name with
+    /// unpaired surrogates on Windows. It is passed to the process without a lossy
+    /// conversion, on Windows through [`cmdline::join_windows_os`].
+    ///
+    /// [`cmdline::join_windows_os`]: ../cmdline/fn.join_windows_os.html
+    pub fn arg_os<T: AsRef<OsStr>>(&mut self, arg: T) -> &mut Self {
+        self.0.args(std::iter::once(arg));
+        self
+    }
+
+    /// Adds multiple arguments, see [`arg_os`].
+    ///
+    /// [`arg_os`]: struct.ProcessInfo.html#method.arg_os
+    pub fn args_os<T, U>(&mut self, args: T) -> &mut Self
+    where
+        T: IntoIterator<Item = U>,
+        U: AsRef<OsStr>,
     {
         self.0.args(args);
         self
@@ -87,11 +336,37 @@ impl ProcessInfo {
         self
     }
 
+    /// Sets a single variable on top of the environment selected by [`env`].
+    /// On Windows an existing variable is replaced regardless of the case of its name.
+    ///
+    /// [`env`]: struct.ProcessInfo.html#method.env
+    pub fn env_override<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.0.envs(std::iter::once((key, value)));
+        self
+    }
+
     pub fn working_dir<T: AsRef<str>>(&mut self, dir: T) -> &mut Self {
         self.0.working_dir(dir);
         self
     }
 
+    /// Leaves the process suspended after it is spawned, before it executes any of its own
+    /// code: on Linux the child stops right before `exec`, on Windows the process is
+    /// created with `CREATE_SUSPENDED`. It starts running after [`Process::resume`], or
+    /// after [`ProgramMessage::Resume`] if it is run by a session.
+    ///
+    /// [`Process::spawn_in_group`] adds the process to the group, whose OS limits are
+    /// already set, while it is suspended, so the limits apply from its first instruction.
+    /// Processes that are not suspended are resumed right after that step. Note that the
+    /// wall clock time of the program keeps running while it is suspended.
+    ///
+    /// [`Process::resume`]: struct.Process.html#method.resume
+    /// [`Process::spawn_in_group`]: struct.Process.html#method.spawn_in_group
+    /// [`ProgramMessage::Resume`]: ../enum.ProgramMessage.html#variant.Resume
     pub fn suspended(&mut self, v: bool) -> &mut Self {
         self.0.suspended(v);
         self
@@ -102,14 +377,17 @@ impl ProcessInfo {
         self
     }
 
-    pub fn env_clear(&mut self) -> &mut Self {
-        self.0.env_clear();
+    pub fn env(&mut self, mode: EnvMode) -> &mut Self {
+        self.0.env(mode);
         self
     }
 
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env(EnvMode::Clear)
+    }
+
     pub fn env_inherit(&mut self) -> &mut Self {
-        self.0.env_inherit();
-        self
+        self.env(EnvMode::Inherit)
     }
 
     pub fn user<T, U>(&mut self, username: T, password: Option<U>) -> &mut Self
@@ -120,13 +398,191 @@ impl ProcessInfo {
         self.0.user(username, password);
         self
     }
+
+    /// Restricts the process and its children to the cores set in `mask`, where bit `i`
+    /// stands for core `i`. Spawning fails if the mask is empty or refers to cores that
+    /// do not exist. On Linux this overrides the cpuset set by `ProcessInfoExt::cpuset`.
+    pub fn cpu_affinity(&mut self, mask: u64) -> &mut Self {
+        self.0.cpu_affinity(mask);
+        self
+    }
+
+    /// Checks that the user can be impersonated and the cpu affinity mask is valid,
+    /// without spawning the process.
+    pub fn validate(&self) -> Result<()> {
+        self.0.validate()
+    }
+
+    /// Makes the windows of the process visible, they are hidden by default. Only has an
+    /// effect on Windows.
+    pub fn show_window(&mut self, show: bool) -> &mut Self {
+        self.0.show_window(show);
+        self
+    }
+
+    /// Makes the process the leader of a new console process group, so that
+    /// [`Group::interrupt`] and [`Group::signal`] reach it, which is needed for
+    /// `ProgramMessage::GracefulTerminate` to give the process a chance to exit. Only has an
+    /// effect on Windows, where it is disabled by default: the process group stops the
+    /// `CTRL_C_EVENT` of the console from reaching the process.
+    ///
+    /// [`Group::interrupt`]: struct.Group.html#method.interrupt
+    /// [`Group::signal`]: struct.Group.html#method.signal
+    pub fn interruptible(&mut self, v: bool) -> &mut Self {
+        self.0.interruptible(v);
+        self
+    }
+
+    /// Sets the console the process is attached to, see [`ConsoleMode`].
+    ///
+    /// [`ConsoleMode`]: enum.ConsoleMode.html
+    pub fn console(&mut self, mode: ConsoleMode) -> &mut Self {
+        self.0.console(mode);
+        self
+    }
+
+    /// Sets whether the processes of the group are terminated when the supervisor goes
+    /// away while they are running: when the monitoring fails or the supervisor panics,
+    /// and, on Windows, when the job object of the group is closed, which happens once the
+    /// group is dropped. Enabled by default.
+    ///
+    /// If disabled, the report is still collected, but the process and its descendants may
+    /// outlive the supervisor, and then run without any limits enforced. On Windows the
+    /// setting applies to the whole group the process is spawned in.
+    pub fn kill_on_drop(&mut self, v: bool) -> &mut Self {
+        self.0.kill_on_drop(v);
+        self
+    }
+
+    pub(crate) fn is_kill_on_drop(&self) -> bool {
+        self.0.is_kill_on_drop()
+    }
+
+    /// Sets the priority of the process. Child processes inherit it.
+    pub fn priority(&mut self, priority: Priority) -> &mut Self {
+        self.0.priority(priority);
+        self
+    }
+
+    /// Attaches the process to a new pseudo terminal of `cols` x `rows` characters, so
+    /// that it sees a real console, e.g. to run interactive programs. The input of the
+    /// terminal is read from the stdin of the process and its output, including stderr, is
+    /// written to the stdout; the stderr pipe is closed.
+    ///
+    /// On Windows the terminal is a ConPTY (`CreatePseudoConsole`, Windows 10 1809 or
+    /// later), and the [`ConsoleMode`] is ignored. On Linux it is opened with `openpty`
+    /// and becomes the controlling terminal of the process, and a pair of threads copies
+    /// the data between the pipes and the terminal. The threads stop once the exit of the
+    /// process is seen, after reading the output left in the terminal.
+    ///
+    /// [`ConsoleMode`]: enum.ConsoleMode.html
+    pub fn pseudo_console(&mut self, cols: u16, rows: u16) -> &mut Self {
+        self.0.pseudo_console(cols, rows);
+        self
+    }
+
+    /// Spawns the process with fewer privileges than the account it runs under, the
+    /// current one or the one set by [`user`], e.g. to run untrusted code. The children of
+    /// the process inherit the restrictions.
+    ///
+    /// On Windows the process gets a token created by `CreateRestrictedToken`: the groups
+    /// in [`RestrictOptions::deny_only_sids`] become deny-only, and the privileges not in
+    /// [`RestrictOptions::keep_privileges`] are removed. On Linux the capabilities not in
+    /// [`RestrictOptions::keep_capabilities`] are dropped from every capability set, and
+    /// `PR_SET_NO_NEW_PRIVS` is set, so that set-user-ID programs and file capabilities
+    /// can't bring them back. The bounding set is only changed if the spawner has
+    /// `CAP_SETPCAP`; without it the process has no capabilities to drop anyway.
+    ///
+    /// [`user`]: struct.ProcessInfo.html#method.user
+    /// [`RestrictOptions::deny_only_sids`]: struct.RestrictOptions.html#structfield.deny_only_sids
+    /// [`RestrictOptions::keep_privileges`]: struct.RestrictOptions.html#structfield.keep_privileges
+    /// [`RestrictOptions::keep_capabilities`]: struct.RestrictOptions.html#structfield.keep_capabilities
+    pub fn restrict_token(&mut self, options: RestrictOptions) -> &mut Self {
+        self.0.restrict_token(options);
+        self
+    }
+
+    /// Confines the writes of the process and its children to a scratch directory, see
+    /// [`FsSandbox`].
+    ///
+    /// Only supported on Linux, where the process gets a new mount namespace, which needs
+    /// `CAP_SYS_ADMIN`. Every mount in it is remounted read-only, and an empty tmpfs is
+    /// mounted on the scratch directory, so the files written there are discarded once the
+    /// process and its children exit, and the directory itself is not changed. Reading is
+    /// not restricted. On Windows spawning fails: job objects can't restrict the file
+    /// system, and running in an AppContainer is not supported.
+    ///
+    /// A process with `CAP_SYS_ADMIN` could remount the file system writable, so the
+    /// capability is dropped once the namespace is set up, unless it is kept by
+    /// [`restrict_token`]. The sandbox alone does not hold against a process that is
+    /// privileged otherwise, e.g. one running as root that can write to `/proc/sys` or
+    /// load kernel modules; use it together with [`user`] or [`restrict_token`] for
+    /// untrusted code.
+    ///
+    /// [`FsSandbox`]: struct.FsSandbox.html
+    /// [`user`]: struct.ProcessInfo.html#method.user
+    /// [`restrict_token`]: struct.ProcessInfo.html#method.restrict_token
+    pub fn filesystem_sandbox(&mut self, sandbox: FsSandbox) -> &mut Self {
+        self.0.filesystem_sandbox(sandbox);
+        self
+    }
+
+    /// Cuts the process and its children off the network.
+    ///
+    /// Only supported on Linux, where the process gets a new network namespace. The only
+    /// interface in the namespace is a loopback one which is down, so the process can't
+    /// connect anywhere, including the services listening on the loopback interface of the
+    /// host. Unix domain sockets bound to a path are not affected. On Windows spawning
+    /// fails: job objects can't restrict networking.
+    ///
+    /// Creating the namespace needs `CAP_SYS_ADMIN`. Without it the namespace is created
+    /// together with a new user namespace, in which the process keeps its user and group.
+    /// A process with `CAP_SYS_ADMIN` could join the network namespace of the spawner, so
+    /// the capability is dropped once the namespace is set up, unless it is kept by
+    /// [`restrict_token`]. As with [`filesystem_sandbox`], this does not hold against a
+    /// process that is privileged otherwise; use it together with [`user`] or
+    /// [`restrict_token`] for untrusted code.
+    ///
+    /// [`user`]: struct.ProcessInfo.html#method.user
+    /// [`restrict_token`]: struct.ProcessInfo.html#method.restrict_token
+    /// [`filesystem_sandbox`]: struct.ProcessInfo.html#method.filesystem_sandbox
+    pub fn deny_network(&mut self, v: bool) -> &mut Self {
+        self.0.deny_network(v);
+        self
+    }
 }
 
 impl Process {
+    pub fn pid(&self) -> u32 {
+        self.0.pid()
+    }
+
     pub fn exit_status(&mut self) -> Result<Option<ExitStatus>> {
         self.0.exit_status()
     }
 
+    /// Blocks until the process exits or `timeout` elapses, and returns the exit status
+    /// as [`exit_status`] does. Unlike polling [`exit_status`] in a loop, the thread
+    /// sleeps in the OS wait primitive and wakes up as soon as the process exits. Waits
+    /// indefinitely if `timeout` is `None`.
+    ///
+    /// [`exit_status`]: #method.exit_status
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+        self.0.wait(timeout)
+    }
+
+    /// Like [`wait`], but also returns once `event` is woken. A wake-up that happens
+    /// before the call is not lost, and is consumed by it.
+    ///
+    /// [`wait`]: #method.wait
+    pub(crate) fn wait_or_wake(
+        &mut self,
+        event: &WakeEvent,
+        timeout: Duration,
+    ) -> Result<Option<ExitStatus>> {
+        self.0.wait_or_wake(&event.0, timeout)
+    }
+
     /// Suspends the main thread of a process.
     pub fn suspend(&self) -> Result<()> {
         self.0.suspend()
@@ -137,6 +593,11 @@ impl Process {
         self.0.resume()
     }
 
+    /// Returns whether the main thread of a process is blocked reading stdin.
+    pub fn is_waiting_on_stdin(&self) -> Result<bool> {
+        self.0.is_waiting_on_stdin()
+    }
+
     pub fn terminate(&self) -> Result<()> {
         self.0.terminate()
     }
@@ -149,7 +610,9 @@ impl Process {
         imp::Process::spawn(info.as_mut().as_inner_mut(), stdio.into().into_inner()).map(Self)
     }
 
-    pub fn spawn_in_group<T, U>(mut info: T, stdio: U, group: &mut Group) -> Result<Self>
+    /// Spawns the process and adds it to `group` before it executes any of its own code,
+    /// so the OS limits of the group apply from its first instruction.
+    pub fn spawn_in_group<T, U>(mut info: T, stdio: U, group: &Group) -> Result<Self>
     where
         T: AsMut<ProcessInfo>,
         U: Into<Stdio>,
@@ -157,63 +620,393 @@ impl Process {
         imp::Process::spawn_in_group(
             &mut info.as_mut().0,
             stdio.into().into_inner(),
-            &mut group.0,
+            &group.inner,
         )
         .map(Self)
     }
 }
 
+impl WakeEvent {
+    pub(crate) fn new() -> Result<Self> {
+        imp::WakeEvent::new().map(Self)
+    }
+
+    pub(crate) fn wake(&self) -> Result<()> {
+        self.0.wake()
+    }
+}
+
 impl<'a> ResourceUsage<'a> {
     pub fn new(group: &'a Group) -> Self {
-        Self(imp::ResourceUsage::new(&group.0))
+        Self {
+            inner: imp::ResourceUsage::new(&group.inner),
+            group,
+            counter_resets_seen: 0,
+            baseline: None,
+            peak_memory_resets_seen: 0,
+            peak_memory: None,
+            remote_endpoints: RefCell::new(HashSet::new()),
+        }
     }
 
+    /// Samples the counters. If the counters of the group were reset since the last update,
+    /// the current values become the new baseline.
     pub fn update(&mut self) -> Result<()> {
-        self.0.update()
+        self.inner.update()?;
+        let resets = *self.group.counter_resets.lock().unwrap();
+        if resets.count != self.counter_resets_seen {
+            self.counter_resets_seen = resets.count;
+            self.baseline = Some(self.take_baseline()?);
+            self.remote_endpoints.get_mut().clear();
+        }
+        if resets.peak_memory_count != self.peak_memory_resets_seen {
+            self.peak_memory_resets_seen = resets.peak_memory_count;
+            self.peak_memory = if resets.peak_memory_reset {
+                None
+            } else {
+                Some(0)
+            };
+        }
+        if let Some(peak) = self.peak_memory {
+            let memory = self.inner.memory()?.unwrap_or_default();
+            self.peak_memory = Some(std::cmp::max(peak, memory.working_set_bytes));
+        }
+        Ok(())
+    }
+
+    /// Returns the time the last reset of the counters was seen by [`update`].
+    ///
+    /// [`update`]: struct.ResourceUsage.html#method.update
+    pub fn counters_reset_at(&self) -> Option<Instant> {
+        self.baseline.as_ref().map(|b| b.time)
     }
 
     pub fn timers(&self) -> Result<Option<GroupTimers>> {
-        self.0.timers()
+        Ok(self.inner.timers()?.map(|t| match self.baseline {
+            Some(ref b) => GroupTimers {
+                total_user_time: t.total_user_time.saturating_sub(b.timers.total_user_time),
+                total_kernel_time: t
+                    .total_kernel_time
+                    .saturating_sub(b.timers.total_kernel_time),
+            },
+            None => t,
+        }))
     }
 
     pub fn memory(&self) -> Result<Option<GroupMemory>> {
-        self.0.memory()
+        Ok(self.inner.memory()?.map(|mut m| {
+            if let Some(ref b) = self.baseline {
+                m.page_fault_count = m.page_fault_count.saturating_sub(b.page_fault_count);
+            }
+            if let Some(peak) = self.peak_memory {
+                m.max_usage = std::cmp::max(peak, m.working_set_bytes);
+            }
+            m
+        }))
     }
 
     pub fn io(&self) -> Result<Option<GroupIo>> {
-        self.0.io()
+        Ok(self.inner.io()?.map(|io| match self.baseline {
+            Some(ref b) => GroupIo {
+                total_bytes_written: io
+                    .total_bytes_written
+                    .saturating_sub(b.io.total_bytes_written),
+                total_bytes_read: io.total_bytes_read.saturating_sub(b.io.total_bytes_read),
+                read_operations: io.read_operations.saturating_sub(b.io.read_operations),
+                write_operations: io.write_operations.saturating_sub(b.io.write_operations),
+            },
+            None => io,
+        }))
     }
 
     pub fn pid_counters(&self) -> Result<Option<GroupPidCounters>> {
-        self.0.pid_counters()
+        Ok(self.inner.pid_counters()?.map(|mut c| {
+            if let Some(ref b) = self.baseline {
+                c.total_processes = c.total_processes.saturating_sub(b.total_processes);
+            }
+            c
+        }))
     }
 
     pub fn network(&self) -> Result<Option<GroupNetwork>> {
-        self.0.network()
+        let network = self.inner.network()?;
+        if network.is_none() {
+            return Ok(None);
+        }
+        let distinct_remote_endpoints = self.sample_remote_endpoints()?.len();
+        Ok(network.map(|n| GroupNetwork {
+            distinct_remote_endpoints,
+            ..n
+        }))
+    }
+
+    /// Returns the distinct remote endpoints the group has been connected to over TCP, in
+    /// ascending order. Every query of the network usage records the endpoints the sockets
+    /// of the group are connected to at the moment, so an endpoint is only seen if the
+    /// connection is open during a query. On Linux the sockets are read from
+    /// `/proc/net/tcp` and `/proc/net/tcp6`, on Windows from the system TCP tables.
+    pub fn remote_endpoints(&self) -> Result<Vec<SocketAddr>> {
+        let mut endpoints = self
+            .sample_remote_endpoints()?
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        endpoints.sort();
+        Ok(endpoints)
+    }
+
+    /// Returns the total number of open handles of the processes in the group. On Linux
+    /// these are the file descriptors listed in `/proc/<pid>/fd`, on Windows the handle
+    /// counts reported by `GetProcessHandleCount`.
+    pub fn open_handles(&self) -> Result<Option<usize>> {
+        self.inner.open_handles()
+    }
+
+    fn sample_remote_endpoints(&self) -> Result<Ref<'_, HashSet<SocketAddr>>> {
+        self.remote_endpoints
+            .borrow_mut()
+            .extend(self.inner.remote_endpoints()?);
+        Ok(self.remote_endpoints.borrow())
+    }
+
+    fn take_baseline(&self) -> Result<Baseline> {
+        Ok(Baseline {
+            time: Instant::now(),
+            timers: self.inner.timers()?.unwrap_or_default(),
+            io: self.inner.io()?.unwrap_or_default(),
+            total_processes: self
+                .inner
+                .pid_counters()?
+                .unwrap_or_default()
+                .total_processes,
+            page_fault_count: self.inner.memory()?.unwrap_or_default().page_fault_count,
+        })
     }
 }
 
 impl Group {
     pub fn new() -> Result<Self> {
-        imp::Group::new().map(Self)
+        imp::Group::new().map(|inner| Self {
+            inner,
+            counter_resets: Mutex::new(CounterResets::default()),
+            cpu_load_sample: Mutex::new((Instant::now(), Duration::from_millis(0))),
+        })
     }
 
-    pub fn add(&mut self, ps: &Process) -> Result<()> {
-        self.0.add(&ps.0)
+    pub fn add(&self, ps: &Process) -> Result<()> {
+        self.inner.add(&ps.0)
     }
 
     /// Returns `true` if the limit was set.
-    pub fn set_os_limit(&mut self, limit: OsLimit, value: u64) -> Result<bool> {
-        self.0.set_os_limit(limit, value)
+    pub fn set_os_limit(&self, limit: OsLimit, value: u64) -> Result<bool> {
+        self.inner.set_os_limit(limit, value)
     }
 
     /// Returns `true` if the limit was hit.
     pub fn is_os_limit_hit(&self, limit: OsLimit) -> Result<bool> {
-        self.0.is_os_limit_hit(limit)
+        self.inner.is_os_limit_hit(limit)
+    }
+
+    /// Returns the ids of all live processes in the group.
+    pub fn pids(&self) -> Result<Vec<u32>> {
+        self.inner.pids()
+    }
+
+    /// Returns the processes in the group with their parents. On Linux they are read from
+    /// `/proc`, on Windows from a toolhelp snapshot of the processes in the job. Processes
+    /// that exit while they are listed are skipped.
+    pub fn process_nodes(&self) -> Result<Vec<ProcessNode>> {
+        self.inner.process_nodes()
+    }
+
+    /// Asks the processes in the group to exit. On Linux every process receives
+    /// `SIGTERM`. On Windows `CTRL_BREAK_EVENT` is sent to the console process group of
+    /// every process spawned with [`ProcessInfo::interruptible`], which includes its
+    /// descendants unless they created their own process group. The event is not delivered
+    /// to the other processes, nor to processes with their own console, see
+    /// [`ConsoleMode`].
+    ///
+    /// [`ProcessInfo::interruptible`]: struct.ProcessInfo.html#method.interruptible
+    /// [`ConsoleMode`]: enum.ConsoleMode.html
+    pub fn interrupt(&self) -> Result<()> {
+        self.inner.interrupt()
+    }
+
+    /// Sends `signal` to every process in the group. Unlike termination, the processes may
+    /// handle it and keep running.
+    ///
+    /// On Linux `signal` is a signal number. On Windows it is a console control event, and
+    /// only `CTRL_BREAK_EVENT` is accepted: `CTRL_C_EVENT` can't be sent to a console
+    /// process group, and the rest are sent by the system only. See [`interrupt`] for who
+    /// receives the event.
+    ///
+    /// [`interrupt`]: struct.Group.html#method.interrupt
+    pub fn signal(&self, signal: i32) -> Result<()> {
+        self.inner.signal(signal)
+    }
+
+    /// Returns an error if `signal` can't be sent with [`signal`] on the current platform.
+    ///
+    /// [`signal`]: struct.Group.html#method.signal
+    pub fn validate_signal(signal: i32) -> Result<()> {
+        imp::Group::validate_signal(signal)
+    }
+
+    /// Resets the cumulative counters of the group, so that the resource usage of a phase of
+    /// a run can be measured apart from the previous ones. The new baseline is taken by
+    /// every [`ResourceUsage`] of the group at its next [`update`].
+    ///
+    /// The OS keeps accounting the totals, so the cpu times, the io, the number of created
+    /// processes and the page faults are reset by subtracting their values at the reset.
+    /// The peak memory usage is reset as by [`reset_peak_memory`]. The current values, such
+    /// as the number of active processes, are not affected.
+    ///
+    /// [`ResourceUsage`]: struct.ResourceUsage.html
+    /// [`update`]: struct.ResourceUsage.html#method.update
+    /// [`reset_peak_memory`]: struct.Group.html#method.reset_peak_memory
+    pub fn reset_counters(&self) -> Result<()> {
+        self.reset_peak_memory()?;
+        self.counter_resets.lock().unwrap().count += 1;
+        Ok(())
+    }
+
+    /// Resets the peak memory usage of the group, leaving the other counters as they are,
+    /// e.g. to measure the peak of each phase of a run. As with [`reset_counters`], the
+    /// new peak is tracked by every [`ResourceUsage`] of the group from its next [`update`].
+    ///
+    /// With cgroup v1 `memory.max_usage_in_bytes` and `memory.kmem.max_usage_in_bytes` are
+    /// reset, and when cgroups are not available, the peak tracked from `/proc`. With cgroup
+    /// v2 and on Windows the peak reported by the OS is kept, and the peak of the working set
+    /// sampled by each [`update`] since the reset is reported instead, which misses the
+    /// spikes between the samples.
+    ///
+    /// [`reset_counters`]: struct.Group.html#method.reset_counters
+    /// [`ResourceUsage`]: struct.ResourceUsage.html
+    /// [`update`]: struct.ResourceUsage.html#method.update
+    pub fn reset_peak_memory(&self) -> Result<()> {
+        let peak_memory_reset = self.inner.reset_peak_memory()?;
+        let mut resets = self.counter_resets.lock().unwrap();
+        resets.peak_memory_count += 1;
+        resets.peak_memory_reset = peak_memory_reset;
+        Ok(())
+    }
+
+    /// Returns the cpu load of the group since the previous call, or since the group was
+    /// created: the user and kernel time used by its processes divided by the wall clock
+    /// time. A load of 1.0 is one core used in full, so the load can reach the number of
+    /// cores. Unlike the counters in [`ResourceUsage`], it is not affected by
+    /// [`reset_counters`].
+    ///
+    /// The load is only meaningful if the method is called periodically, e.g. on every
+    /// check of a custom idle policy: calls that are too close return noisy values, since
+    /// the cpu time is updated at the resolution of the OS scheduler, and calls that are
+    /// too far apart average out the bursts of activity.
+    ///
+    /// [`ResourceUsage`]: struct.ResourceUsage.html
+    /// [`reset_counters`]: struct.Group.html#method.reset_counters
+    pub fn cpu_load(&self) -> Result<f64> {
+        let timers = imp::ResourceUsage::new(&self.inner)
+            .timers()?
+            .unwrap_or_default();
+        let cpu_time = timers.total_user_time + timers.total_kernel_time;
+        let now = Instant::now();
+        let mut sample = self.cpu_load_sample.lock().unwrap();
+        let (prev_time, prev_cpu_time) = *sample;
+        *sample = (now, cpu_time);
+        let dt = now.duration_since(prev_time).as_secs_f64();
+        if dt == 0.0 {
+            return Ok(0.0);
+        }
+        Ok(cpu_time.saturating_sub(prev_cpu_time).as_secs_f64() / dt)
     }
 
+    /// Terminates the processes in the group without waiting for them to exit.
     pub fn terminate(&self) -> Result<()> {
-        self.0.terminate()
+        self.inner.terminate()
+    }
+
+    /// Terminates the processes in the group and waits until none of them is alive or
+    /// `timeout` elapses. Returns `true` if all processes have exited.
+    pub fn terminate_and_wait(&self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        self.terminate()?;
+        loop {
+            if self.pids()?.is_empty() {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Stdio {
+    /// Creates `Stdio` with all streams redirected to the null device.
+    pub fn null() -> Result<Self> {
+        StdioBuilder::new().build()
+    }
+
+    /// Creates `Stdio` with stderr redirected into stdout, so the process writes both
+    /// streams into the same pipe (like `2>&1`).
+    pub fn merged(stdin: ReadPipe, stdout: WritePipe) -> Result<Self> {
+        let stderr = stdout.try_clone()?;
+        Ok(Self {
+            stdin,
+            stdout,
+            stderr,
+        })
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            stdin: self.stdin.try_clone()?,
+            stdout: self.stdout.try_clone()?,
+            stderr: self.stderr.try_clone()?,
+        })
+    }
+}
+
+impl StdioBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stdin(mut self, stdin: ReadPipe) -> Self {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    pub fn stdout(mut self, stdout: WritePipe) -> Self {
+        self.stdout = Some(stdout);
+        self.inherit_stdout = false;
+        self
+    }
+
+    pub fn stderr(mut self, stderr: WritePipe) -> Self {
+        self.stderr = Some(stderr);
+        self
+    }
+
+    /// Connects stdout of the process to the stdout of the current process.
+    pub fn inherit_stdout(mut self) -> Self {
+        self.stdout = None;
+        self.inherit_stdout = true;
+        self
+    }
+
+    pub fn build(self) -> Result<Stdio> {
+        let stdout = match self.stdout {
+            Some(stdout) => stdout,
+            None if self.inherit_stdout => WritePipe::stdout()?,
+            None => WritePipe::null()?,
+        };
+        Ok(Stdio {
+            stdin: self.stdin.map_or_else(ReadPipe::null, Ok)?,
+            stdout,
+            stderr: self.stderr.map_or_else(WritePipe::null, Ok)?,
+        })
     }
 }
 
@@ -235,7 +1028,7 @@ impl AsInnerMut<imp::ProcessInfo> for ProcessInfo {
 
 impl AsInnerMut<imp::Group> for Group {
     fn as_inner_mut(&mut self) -> &mut imp::Group {
-        &mut self.0
+        &mut self.inner
     }
 }
 
@@ -245,17 +1038,49 @@ impl AsMut<ProcessInfo> for ProcessInfo {
     }
 }
 
+impl FsSandbox {
+    pub fn new<T: AsRef<str>>(scratch_dir: T) -> Self {
+        Self {
+            scratch_dir: scratch_dir.as_ref().to_string(),
+            scratch_size: None,
+        }
+    }
+}
+
+impl Default for RestrictOptions {
+    fn default() -> Self {
+        Self {
+            deny_only_sids: vec![
+                "S-1-5-32-544".to_string(),
+                "S-1-5-32-547".to_string(),
+                "S-1-5-114".to_string(),
+            ],
+            keep_privileges: vec!["SeChangeNotifyPrivilege".to_string()],
+            keep_capabilities: Vec::new(),
+        }
+    }
+}
+
 impl Default for GroupIo {
     fn default() -> Self {
         Self {
             total_bytes_written: 0,
+            total_bytes_read: 0,
+            read_operations: 0,
+            write_operations: 0,
         }
     }
 }
 
 impl Default for GroupMemory {
     fn default() -> Self {
-        Self { max_usage: 0 }
+        Self {
+            max_usage: 0,
+            working_set_bytes: 0,
+            private_bytes: 0,
+            page_fault_count: 0,
+            total_swap_bytes: 0,
+        }
     }
 }
 
@@ -263,6 +1088,7 @@ impl Default for GroupNetwork {
     fn default() -> Self {
         Self {
             active_connections: 0,
+            distinct_remote_endpoints: 0,
         }
     }
 }
@@ -271,6 +1097,7 @@ impl Default for GroupPidCounters {
     fn default() -> Self {
         Self {
             active_processes: 0,
+            active_threads: 0,
             total_processes: 0,
         }
     }
@@ -1,39 +1,200 @@
-use crate::dataflow::{DestinationId, Graph, SourceId, Transmitter, TransmitterResults};
-use crate::dataflow_analysis::DataflowOptimizer;
-use crate::pipe;
+use crate::dataflow::{
+    Destination, DestinationHandle, DestinationId, Graph, Source, SourceHandle, SourceId,
+    Transmitter, TransmitterResults,
+};
+use crate::dataflow_analysis::{
+    DataflowAnalyzer, DataflowOptimizer, DestinationOptimization, SourceOptimization,
+};
+use crate::pipe::{self, ReadPipe, WritePipe};
 use crate::process::{
-    ExitStatus, Group, GroupIo, GroupMemory, GroupNetwork, GroupPidCounters, GroupTimers,
-    ProcessInfo, Stdio,
+    ExitStatus, Group, GroupIo, GroupMemory, GroupNetwork, GroupPidCounters, GroupTimers, OsLimit,
+    Process, ProcessInfo, ProcessNode, Stdio, WakeEvent,
 };
 use crate::supervisor::Supervisor;
 use crate::{Error, Result};
 
 use std::collections::HashSet;
 use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+#[cfg(feature = "async")]
+use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::Receiver;
-use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll, Waker};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// The interval between the checks of the limits unless [`Program::monitor_interval`] is
+/// set. A shorter interval enforces the limits more precisely and samples the usage more
+/// often, at the cost of the cpu time the monitor spends on every check; a longer one is
+/// cheaper, but a limit may be exceeded by up to the interval before it is noticed. It is
+/// also the shortest interval, since the monitor never sleeps for less.
+///
+/// [`Program::monitor_interval`]: struct.Program.html#method.monitor_interval
+pub const DEFAULT_MONITOR_INTERVAL: Duration = Duration::from_millis(1);
+
+// How often the thread that forwards the messages of `Program::msg_receiver` checks whether
+// the supervisor has finished, in case the sender of the program is never dropped.
+const FORWARDING_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
-/// Describes the termination reason for a process.
+/// Describes the termination reason for a process. The reasons of exceeded limits carry the
+/// value that was measured and the limit it exceeded.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TerminationReason {
+    WallClockTimeLimitExceeded {
+        used: Duration,
+        limit: Duration,
+    },
+    IdleTimeLimitExceeded {
+        used: Duration,
+        limit: Duration,
+    },
+    UserTimeLimitExceeded {
+        used: Duration,
+        limit: Duration,
+    },
+    WriteLimitExceeded {
+        used: u64,
+        limit: u64,
+    },
+    ReadLimitExceeded {
+        used: u64,
+        limit: u64,
+    },
+    MemoryLimitExceeded {
+        used: u64,
+        limit: u64,
+    },
+    SwapLimitExceeded {
+        used: u64,
+        limit: u64,
+    },
+    ProcessLimitExceeded {
+        used: usize,
+        limit: usize,
+    },
+    /// More processes were created within a window than
+    /// [`ResourceLimits::max_process_creation_rate`] allows.
+    ///
+    /// [`ResourceLimits::max_process_creation_rate`]: struct.ResourceLimits.html#structfield.max_process_creation_rate
+    ProcessCreationRateExceeded {
+        used: usize,
+        limit: usize,
+    },
+    ActiveProcessLimitExceeded {
+        used: usize,
+        limit: usize,
+    },
+    ActiveThreadLimitExceeded {
+        used: usize,
+        limit: usize,
+    },
+    ActiveNetworkConnectionLimitExceeded {
+        used: usize,
+        limit: usize,
+    },
+    HandleLimitExceeded {
+        used: usize,
+        limit: usize,
+    },
+    /// More distinct remote endpoints were contacted than
+    /// [`ResourceLimits::max_distinct_endpoints`] allows.
+    ///
+    /// [`ResourceLimits::max_distinct_endpoints`]: struct.ResourceLimits.html#structfield.max_distinct_endpoints
+    NetworkEndpointLimitExceeded {
+        used: usize,
+        limit: usize,
+    },
+    TerminatedByRunner,
+    /// The process exited on its own after [`ProgramMessage::GracefulTerminate`].
+    ///
+    /// [`ProgramMessage::GracefulTerminate`]: enum.ProgramMessage.html#variant.GracefulTerminate
+    GracefullyTerminatedByRunner,
+    /// All readers of the program's output are gone, see [`BrokenPipe::Terminate`].
+    ///
+    /// [`BrokenPipe::Terminate`]: enum.BrokenPipe.html#variant.Terminate
+    OutputConsumerGone,
+    /// The program ran past [`Program::max_runtime`] or [`Program::absolute_deadline`].
+    ///
+    /// [`Program::max_runtime`]: struct.Program.html#method.max_runtime
+    /// [`Program::absolute_deadline`]: struct.Program.html#method.absolute_deadline
+    DeadlineExceeded,
+    /// The [`SecurityPolicy`] of the program vetoed it, the reason is in
+    /// [`Report::security_violation`].
+    ///
+    /// [`SecurityPolicy`]: trait.SecurityPolicy.html
+    /// [`Report::security_violation`]: struct.Report.html#structfield.security_violation
+    SecurityViolation,
+}
+
+/// The kind of a [`TerminationReason`], without the measured values.
+///
+/// [`TerminationReason`]: enum.TerminationReason.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TerminationKind {
     WallClockTimeLimitExceeded,
     IdleTimeLimitExceeded,
     UserTimeLimitExceeded,
     WriteLimitExceeded,
+    ReadLimitExceeded,
     MemoryLimitExceeded,
+    SwapLimitExceeded,
     ProcessLimitExceeded,
+    ProcessCreationRateExceeded,
     ActiveProcessLimitExceeded,
+    ActiveThreadLimitExceeded,
     ActiveNetworkConnectionLimitExceeded,
+    HandleLimitExceeded,
+    NetworkEndpointLimitExceeded,
     TerminatedByRunner,
+    GracefullyTerminatedByRunner,
+    OutputConsumerGone,
+    DeadlineExceeded,
+    SecurityViolation,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct IdleTimeLimit {
     pub total_idle_time: Duration,
     pub cpu_load_threshold: f64,
+    /// The number of recent cpu load samples that are averaged before comparing
+    /// against `cpu_load_threshold`.
+    pub cpu_load_window_length: usize,
+}
+
+/// Describes whether the supervisor respawns a program after it exits. A program is not
+/// restarted if it was terminated, either by the runner or because of a limit.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Restarts the program at most `max` times if it crashes or exits with a non-zero code.
+    OnFailure { max: usize },
+    /// Restarts the program at most `max` times regardless of how it exits.
+    Always { max: usize },
+}
+
+/// Describes what happens to a program when all the destinations of its stdout or stderr
+/// are gone, e.g. when the programs that read its output exit. The destinations are only
+/// tracked by the dataflow graph of a [`Session`], so the policy is set per program with
+/// [`Program::on_broken_pipe`] rather than on a [`Stdio`], which is just a set of pipe ends.
+///
+/// [`Session`]: struct.Session.html
+/// [`Program::on_broken_pipe`]: struct.Program.html#method.on_broken_pipe
+/// [`Stdio`]: process/struct.Stdio.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BrokenPipe {
+    /// Terminates the program with [`TerminationReason::OutputConsumerGone`].
+    ///
+    /// [`TerminationReason::OutputConsumerGone`]: enum.TerminationReason.html#variant.OutputConsumerGone
+    Terminate,
+    /// Keeps reading the output and discards it, as if it was redirected to the null device.
+    Discard,
 }
 
 /// The limits that are imposed on a process group.
@@ -44,25 +205,153 @@ pub struct ResourceLimits {
     pub wall_clock_time: Option<Duration>,
     /// The maximum allowed amount of user-mode execution time for a process group.
     pub total_user_time: Option<Duration>,
-    /// The maximum allowed memory usage, in bytes.
+    /// The maximum allowed memory usage, in bytes. Checked against [`GroupMemory::max_usage`],
+    /// which is the peak memory charged to the cgroup (including page cache and kernel
+    /// memory) on Linux, or the sum of resident set sizes if cgroups are unavailable, and the
+    /// peak committed memory of the job object on Windows. The same limit is also set on the
+    /// cgroup or the job object.
+    ///
+    /// [`GroupMemory::max_usage`]: process/struct.GroupMemory.html#structfield.max_usage
     pub max_memory_usage: Option<u64>,
+    /// The maximum allowed swap usage, in bytes, checked against
+    /// [`GroupMemory::total_swap_bytes`]. Running a program with this limit fails if swap
+    /// accounting is not available.
+    ///
+    /// [`GroupMemory::total_swap_bytes`]: process/struct.GroupMemory.html#structfield.total_swap_bytes
+    pub max_swap_usage: Option<u64>,
     /// The maximum allowed amount of bytes written by a process group.
     pub total_bytes_written: Option<u64>,
+    /// The maximum allowed amount of bytes read by a process group.
+    pub total_bytes_read: Option<u64>,
     /// The maximum allowed number of processes created.
     pub total_processes_created: Option<usize>,
+    /// The maximum allowed number of processes created within any window of the given
+    /// length, e.g. `(10, Duration::from_secs(1))` for at most 10 processes per second. It
+    /// catches fork bombs that create and destroy processes quickly. The creation is
+    /// detected by the monitor, so processes that live shorter than the check interval may
+    /// be missed.
+    pub max_process_creation_rate: Option<(usize, Duration)>,
     /// The maximum allowed number of active processes.
     pub active_processes: Option<usize>,
-    /// The maximum allowed number of active network connections.
+    /// The maximum allowed number of active threads.
+    pub active_threads: Option<usize>,
+    /// The maximum allowed number of active network connections, see
+    /// `GroupNetwork::active_connections` for how they are counted. The group
+    /// is terminated once the limit is exceeded at any check.
+    pub active_network_connections: Option<usize>,
+    /// The maximum allowed total number of open handles (file descriptors on Linux) of the
+    /// processes in a group, see [`ResourceUsage::open_handles`].
+    ///
+    /// [`ResourceUsage::open_handles`]: process/struct.ResourceUsage.html#method.open_handles
+    pub max_open_handles: Option<usize>,
+    /// The maximum allowed number of distinct remote endpoints the group has connected to
+    /// over TCP, see [`GroupNetwork::distinct_remote_endpoints`]. Connections are only
+    /// seen at the checks, so a short connection may be missed.
+    ///
+    /// [`GroupNetwork::distinct_remote_endpoints`]: process/struct.GroupNetwork.html#structfield.distinct_remote_endpoints
+    pub max_distinct_endpoints: Option<usize>,
+}
+
+/// What is left of every limit of [`ResourceLimits`] as of the last check of the monitor,
+/// see [`Run::remaining`]. The limits that are not set are `None`, and the exceeded ones
+/// are zero.
+///
+/// [`ResourceLimits`]: struct.ResourceLimits.html
+/// [`Run::remaining`]: struct.Run.html#method.remaining
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RemainingLimits {
+    pub idle_time: Option<Duration>,
+    pub wall_clock_time: Option<Duration>,
+    pub total_user_time: Option<Duration>,
+    pub max_memory_usage: Option<u64>,
+    pub max_swap_usage: Option<u64>,
+    pub total_bytes_written: Option<u64>,
+    pub total_bytes_read: Option<u64>,
+    pub total_processes_created: Option<usize>,
+    /// The number of processes that can be created within the current window.
+    pub max_process_creation_rate: Option<usize>,
+    pub active_processes: Option<usize>,
+    pub active_threads: Option<usize>,
     pub active_network_connections: Option<usize>,
+    pub max_open_handles: Option<usize>,
+    pub max_distinct_endpoints: Option<usize>,
+}
+
+/// Builds [`ResourceLimits`], setting only the limits it is given.
+///
+/// ```
+/// # use spawner::ResourceLimitsBuilder;
+/// # use std::time::Duration;
+/// let limits = ResourceLimitsBuilder::new()
+///     .wall_clock(Duration::from_secs(10))
+///     .memory(64 * 1024 * 1024)
+///     .build();
+/// assert_eq!(limits.total_user_time, None);
+/// ```
+///
+/// [`ResourceLimits`]: struct.ResourceLimits.html
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ResourceLimitsBuilder {
+    limits: ResourceLimits,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ProgramMessage {
     Terminate,
+    /// Asks the process group to exit (see [`Group::interrupt`]) and terminates it if it is
+    /// still running after `timeout`. The termination reason is
+    /// [`TerminationReason::GracefullyTerminatedByRunner`] if the process exits in time,
+    /// and [`TerminationReason::TerminatedByRunner`] otherwise. On Windows the process must
+    /// be spawned with [`ProcessInfo::interruptible`] to receive the request, otherwise it
+    /// is terminated once the timeout elapses.
+    ///
+    /// [`Group::interrupt`]: process/struct.Group.html#method.interrupt
+    /// [`ProcessInfo::interruptible`]: process/struct.ProcessInfo.html#method.interruptible
+    /// [`TerminationReason::GracefullyTerminatedByRunner`]: enum.TerminationReason.html#variant.GracefullyTerminatedByRunner
+    /// [`TerminationReason::TerminatedByRunner`]: enum.TerminationReason.html#variant.TerminatedByRunner
+    GracefulTerminate {
+        timeout: Duration,
+    },
+    /// Sends a signal to the process group, see [`Group::signal`]. Use
+    /// [`ProgramMessage::signal`] to check the signal before sending: an invalid signal is
+    /// ignored, apart from a tracing event.
+    ///
+    /// [`Group::signal`]: process/struct.Group.html#method.signal
+    /// [`ProgramMessage::signal`]: enum.ProgramMessage.html#method.signal
+    Signal(i32),
+    /// Closes the write end of the program's stdin, so that the program reads EOF after the
+    /// data already written to it. Has no effect if stdin is not transmitted through the
+    /// dataflow graph, e.g. if it was redirected directly to a file or another program.
+    CloseStdin,
+    /// Suspends the program's process, see [`Process::suspend`]. Ignored if it has exited.
+    ///
+    /// [`Process::suspend`]: process/struct.Process.html#method.suspend
     Suspend,
+    /// Resumes the program's process, including a process that was spawned suspended, see
+    /// [`ProcessInfo::suspended`]. Ignored if it has exited.
+    ///
+    /// [`ProcessInfo::suspended`]: process/struct.ProcessInfo.html#method.suspended
     Resume,
     StopTimeAccounting,
     ResumeTimeAccounting,
+    /// Resets the wall clock time and the user time accounted against the limits. Also
+    /// resets the counters of the process group if enabled by
+    /// [`Program::reset_counters_on_reset_time`]. As with [`ProgramMessage::ResetPeakMemory`],
+    /// if resetting the counters fails, they are kept and the monitoring goes on.
+    ///
+    /// [`Program::reset_counters_on_reset_time`]: struct.Program.html#method.reset_counters_on_reset_time
+    /// [`ProgramMessage::ResetPeakMemory`]: enum.ProgramMessage.html#variant.ResetPeakMemory
     ResetTime,
+    /// Resets the peak memory usage of the process group, which is then checked against
+    /// [`ResourceLimits::max_memory_usage`] and reported. Unlike
+    /// [`Program::reset_counters_on_reset_time`], the other counters are kept. See
+    /// [`Group::reset_peak_memory`] for what is reset on each platform. If the reset fails,
+    /// the peak is kept and the monitoring goes on.
+    ///
+    /// [`ResourceLimits::max_memory_usage`]: struct.ResourceLimits.html#structfield.max_memory_usage
+    /// [`Program::reset_counters_on_reset_time`]: struct.Program.html#method.reset_counters_on_reset_time
+    /// [`Group::reset_peak_memory`]: process/struct.Group.html#method.reset_peak_memory
+    ResetPeakMemory,
 }
 
 /// Summary information about process's execution.
@@ -76,6 +365,123 @@ pub struct Report {
     pub network: Option<GroupNetwork>,
     pub exit_status: ExitStatus,
     pub termination_reason: Option<TerminationReason>,
+    /// The error that stopped the monitoring of the process. If set, the report is
+    /// incomplete: counters that could not be read are `None`, and `exit_status` is
+    /// `ExitStatus::Running` if the process had not exited yet.
+    pub error: Option<String>,
+    /// The number of times the program was restarted according to its [`RestartPolicy`].
+    ///
+    /// [`RestartPolicy`]: enum.RestartPolicy.html
+    pub restarts: usize,
+    /// The number of iterations of the monitoring loop, each of which samples the counters.
+    pub samples_taken: usize,
+    /// The time spent in the monitoring loop apart from sleeping between the samples: reading
+    /// the counters, checking the limits, handling the messages and invoking the callback.
+    pub sampling_time: Duration,
+    /// The largest total number of open handles seen by the limit checks. Only sampled if
+    /// [`ResourceLimits::max_open_handles`] is set.
+    ///
+    /// [`ResourceLimits::max_open_handles`]: struct.ResourceLimits.html#structfield.max_open_handles
+    pub peak_open_handles: Option<usize>,
+    /// The time the process group has been idle for, as compared against
+    /// [`IdleTimeLimit::total_idle_time`]. It is reset once the cpu load rises above the
+    /// threshold, and is only accounted if the idle time limit is set.
+    ///
+    /// [`IdleTimeLimit::total_idle_time`]: struct.IdleTimeLimit.html#structfield.total_idle_time
+    pub idle_time: Duration,
+    /// The cpu load of the process group measured at the last limit check, that is the
+    /// user time it used divided by the wall clock time since the previous check.
+    pub cpu_load: f64,
+    /// The message of the [`SecurityPolicy`] that terminated the program.
+    ///
+    /// [`SecurityPolicy`]: trait.SecurityPolicy.html
+    pub security_violation: Option<String>,
+    /// Every process seen in the group while the program ran, if enabled by
+    /// [`Program::record_process_tree`]. The spawned process gets its exit code once it
+    /// exits.
+    ///
+    /// [`Program::record_process_tree`]: struct.Program.html#method.record_process_tree
+    pub process_tree: Option<Vec<ProcessNode>>,
+    /// The distinct remote endpoints the group has connected to over TCP, if enabled by
+    /// [`Program::record_remote_endpoints`].
+    ///
+    /// [`Program::record_remote_endpoints`]: struct.Program.html#method.record_remote_endpoints
+    pub remote_endpoints: Option<Vec<SocketAddr>>,
+    /// The exit status of the process if it exited on its own after a limit was exceeded,
+    /// within the grace window of [`Program::capture_natural_exit`]. `None` if the process
+    /// was terminated, in which case `exit_status` is caused by the termination.
+    ///
+    /// [`Program::capture_natural_exit`]: struct.Program.html#method.capture_natural_exit
+    pub natural_exit_status: Option<ExitStatus>,
+    /// The wall clock time at which the counters of the process group were last reset, see
+    /// [`Group::reset_counters`]. The resource usage in the report is accounted since then.
+    ///
+    /// [`Group::reset_counters`]: process/struct.Group.html#method.reset_counters
+    pub counters_reset_at: Option<Duration>,
+}
+
+/// How much the measurements of two reports may differ for [`Report::compare`] to consider
+/// them equal. The default allows 50ms of time and 1MB of memory, the bytes read and written
+/// must match exactly.
+///
+/// [`Report::compare`]: struct.Report.html#method.compare
+#[derive(Copy, Clone, Debug)]
+pub struct ReportTolerances {
+    /// Applies to the wall clock, user and kernel time.
+    pub time: Duration,
+    /// Applies to the peak memory usage, in bytes.
+    pub memory: u64,
+    /// Applies to the total bytes read and written.
+    pub io: u64,
+}
+
+/// A field that differs between two reports beyond the [`ReportTolerances`], as returned
+/// by [`Report::compare`].
+///
+/// [`ReportTolerances`]: struct.ReportTolerances.html
+/// [`Report::compare`]: struct.Report.html#method.compare
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReportDiff {
+    WallClockTime {
+        expected: Duration,
+        actual: Duration,
+    },
+    UserTime {
+        expected: Duration,
+        actual: Duration,
+    },
+    KernelTime {
+        expected: Duration,
+        actual: Duration,
+    },
+    PeakMemory {
+        expected: u64,
+        actual: u64,
+    },
+    BytesWritten {
+        expected: u64,
+        actual: u64,
+    },
+    BytesRead {
+        expected: u64,
+        actual: u64,
+    },
+    TotalProcesses {
+        expected: usize,
+        actual: usize,
+    },
+    Restarts {
+        expected: usize,
+        actual: usize,
+    },
+    ExitStatus {
+        expected: ExitStatus,
+        actual: ExitStatus,
+    },
+    TerminationReason {
+        expected: Option<TerminationKind>,
+        actual: Option<TerminationKind>,
+    },
 }
 
 #[derive(Debug)]
@@ -85,13 +491,63 @@ pub struct ProgramErrors {
 
 pub type ProgramResult = std::result::Result<Report, ProgramErrors>;
 
+/// A callback that is invoked on every monitor tick with a live snapshot of the report.
+/// The `exit_status` of the snapshot is `ExitStatus::Running`.
+pub trait OnMonitorTick: Send {
+    fn on_tick(&mut self, report: &Report);
+}
+
+/// A custom rule that is checked on every monitor tick against a live snapshot of the
+/// report, like [`OnMonitorTick`]. Returning a message terminates the process group with
+/// [`TerminationReason::SecurityViolation`]. A policy that panics is treated as violated.
+///
+/// [`OnMonitorTick`]: trait.OnMonitorTick.html
+/// [`TerminationReason::SecurityViolation`]: enum.TerminationReason.html#variant.SecurityViolation
+pub trait SecurityPolicy: Send {
+    fn check(&mut self, report: &Report) -> Option<String>;
+}
+
+/// A callback that is invoked when the usage of a resource crosses the configured fraction
+/// of its limit, with a live snapshot of the report, like [`OnMonitorTick`], and the kind of
+/// the reason the program will be terminated with once the limit is exceeded.
+///
+/// [`OnMonitorTick`]: trait.OnMonitorTick.html
+pub trait OnThreshold: Send {
+    fn on_threshold(&mut self, report: &Report, kind: TerminationKind);
+}
+
 pub struct Program {
+    pub(crate) info: ProcessInfo,
+    pub(crate) group: Option<Arc<Group>>,
+    pub(crate) resource_limits: Option<ResourceLimits>,
+    pub(crate) msg_receiver: Option<Receiver<ProgramMessage>>,
+    pub(crate) monitor_interval: Option<Duration>,
+    pub(crate) adaptive_interval: Option<(Duration, Duration)>,
+    pub(crate) wait_for_children: Option<bool>,
+    pub(crate) pause_time_on_stdin_wait: bool,
+    pub(crate) max_messages_per_tick: usize,
+    pub(crate) restart_policy: RestartPolicy,
+    pub(crate) reset_time_on_restart: bool,
+    pub(crate) reset_counters_on_reset_time: bool,
+    pub(crate) on_tick: Option<Box<dyn OnMonitorTick>>,
+    pub(crate) security_policy: Option<Box<dyn SecurityPolicy>>,
+    pub(crate) on_threshold: Option<(f64, Box<dyn OnThreshold>)>,
+    pub(crate) record_process_tree: bool,
+    pub(crate) record_remote_endpoints: bool,
+    pub(crate) natural_exit_grace: Option<Duration>,
+    pub(crate) max_runtime: Option<Duration>,
+    pub(crate) absolute_deadline: Option<Instant>,
+    pub(crate) event_driven_monitor: bool,
+    merge_stderr: bool,
+    on_broken_pipe: Option<BrokenPipe>,
+}
+
+/// Creates programs with the same settings, e.g. to run several identical workers. The
+/// settings are applied by a callback, so that every program gets its own copies of the
+/// settings that can't be shared, like callbacks. Each program gets its own message channel.
+pub struct ProgramTemplate {
     info: ProcessInfo,
-    group: Option<Group>,
-    resource_limits: Option<ResourceLimits>,
-    msg_receiver: Option<Receiver<ProgramMessage>>,
-    monitor_interval: Duration,
-    wait_for_children: bool,
+    configure: Box<dyn Fn(&mut Program) + Send>,
 }
 
 #[derive(Copy, Clone)]
@@ -106,8 +562,29 @@ struct ProgramExt {
     stdio: Stdio,
 }
 
+/// The settings that are applied to every program of a session that does not set them.
+#[derive(Copy, Clone, Default)]
+struct ProgramDefaults {
+    resource_limits: Option<ResourceLimits>,
+    monitor_interval: Option<Duration>,
+    wait_for_children: Option<bool>,
+    max_runtime: Option<Duration>,
+}
+
+/// Creates a [`Session`] with default settings for its programs. A program keeps the
+/// settings it sets itself, e.g. a program with its own [`Program::resource_limits`]
+/// ignores the default limits entirely.
+///
+/// [`Session`]: struct.Session.html
+/// [`Program::resource_limits`]: struct.Program.html#method.resource_limits
+#[derive(Default)]
+pub struct SessionBuilder {
+    defaults: ProgramDefaults,
+}
+
 #[derive(Default)]
 pub struct Session {
+    defaults: ProgramDefaults,
     progs: Vec<ProgramExt>,
     mappings: Vec<StdioMapping>,
     graph: Graph,
@@ -115,17 +592,68 @@ pub struct Session {
     ignored_dsts: HashSet<DestinationId>,
 }
 
-struct FlagGuard(Arc<AtomicBool>);
+#[derive(Default)]
+struct Completion {
+    is_finished: AtomicBool,
+    #[cfg(feature = "async")]
+    wakers: Mutex<Vec<Waker>>,
+}
+
+struct FlagGuard {
+    completion: Arc<Completion>,
+    idx: usize,
+    finished_sender: Sender<usize>,
+}
 
 struct SupervisorThread {
-    handle: JoinHandle<Result<Report>>,
-    is_finished: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Result<Report>>>,
+    result: Option<Result<Report>>,
+    completion: Arc<Completion>,
+    control_sender: Sender<ProgramMessage>,
+    // Wakes up the event-driven monitor once a message is sent.
+    wake_event: Option<Arc<WakeEvent>>,
+    // Updated by the supervisor at every check of the limits, see `Run::remaining`.
+    remaining: Arc<Mutex<Option<RemainingLimits>>>,
 }
 
 pub struct Run {
     supervisors: Vec<SupervisorThread>,
     mappings: Vec<StdioMapping>,
     transmitter: Transmitter,
+    // Receives the index of every program whose supervisor has finished.
+    finished_receiver: Receiver<usize>,
+}
+
+/// A process started by [`spawn_detached`], in a group of its own.
+///
+/// [`spawn_detached`]: fn.spawn_detached.html
+pub struct DetachedProcess {
+    process: Process,
+    group: Group,
+}
+
+/// Future returned by [`Run::finished`].
+///
+/// [`Run::finished`]: struct.Run.html#method.finished
+#[cfg(feature = "async")]
+pub struct Finished(Arc<Completion>);
+
+/// Future returned by [`Run::wait_async`].
+///
+/// [`Run::wait_async`]: struct.Run.html#method.wait_async
+#[cfg(feature = "async")]
+pub struct WaitAsync {
+    run: Option<Run>,
+    pending: Vec<Arc<Completion>>,
+}
+
+impl ProgramMessage {
+    /// Creates a [`ProgramMessage::Signal`] if the signal is valid on the current platform.
+    ///
+    /// [`ProgramMessage::Signal`]: enum.ProgramMessage.html#variant.Signal
+    pub fn signal(signal: i32) -> Result<Self> {
+        Group::validate_signal(signal).map(|_| ProgramMessage::Signal(signal))
+    }
 }
 
 impl Default for ResourceLimits {
@@ -135,11 +663,271 @@ impl Default for ResourceLimits {
             idle_time: None,
             total_user_time: None,
             max_memory_usage: None,
+            max_swap_usage: None,
             total_bytes_written: None,
+            total_bytes_read: None,
             total_processes_created: None,
+            max_process_creation_rate: None,
             active_processes: None,
+            active_threads: None,
             active_network_connections: None,
+            max_open_handles: None,
+            max_distinct_endpoints: None,
+        }
+    }
+}
+
+impl Default for ReportTolerances {
+    fn default() -> Self {
+        Self {
+            time: Duration::from_millis(50),
+            memory: 1024 * 1024,
+            io: 0,
+        }
+    }
+}
+
+impl TerminationReason {
+    pub fn kind(&self) -> TerminationKind {
+        use self::TerminationReason::*;
+        match *self {
+            WallClockTimeLimitExceeded { .. } => TerminationKind::WallClockTimeLimitExceeded,
+            IdleTimeLimitExceeded { .. } => TerminationKind::IdleTimeLimitExceeded,
+            UserTimeLimitExceeded { .. } => TerminationKind::UserTimeLimitExceeded,
+            WriteLimitExceeded { .. } => TerminationKind::WriteLimitExceeded,
+            ReadLimitExceeded { .. } => TerminationKind::ReadLimitExceeded,
+            MemoryLimitExceeded { .. } => TerminationKind::MemoryLimitExceeded,
+            SwapLimitExceeded { .. } => TerminationKind::SwapLimitExceeded,
+            ProcessLimitExceeded { .. } => TerminationKind::ProcessLimitExceeded,
+            ProcessCreationRateExceeded { .. } => TerminationKind::ProcessCreationRateExceeded,
+            ActiveProcessLimitExceeded { .. } => TerminationKind::ActiveProcessLimitExceeded,
+            ActiveThreadLimitExceeded { .. } => TerminationKind::ActiveThreadLimitExceeded,
+            ActiveNetworkConnectionLimitExceeded { .. } => {
+                TerminationKind::ActiveNetworkConnectionLimitExceeded
+            }
+            HandleLimitExceeded { .. } => TerminationKind::HandleLimitExceeded,
+            NetworkEndpointLimitExceeded { .. } => TerminationKind::NetworkEndpointLimitExceeded,
+            TerminatedByRunner => TerminationKind::TerminatedByRunner,
+            GracefullyTerminatedByRunner => TerminationKind::GracefullyTerminatedByRunner,
+            OutputConsumerGone => TerminationKind::OutputConsumerGone,
+            DeadlineExceeded => TerminationKind::DeadlineExceeded,
+            SecurityViolation => TerminationKind::SecurityViolation,
+        }
+    }
+}
+
+impl Report {
+    /// Compares the report against the `expected` one, e.g. of a previous run, and returns
+    /// the fields that differ beyond the `tolerances`. Termination reasons are compared by
+    /// their [`TerminationKind`], since the measured values they carry vary between runs.
+    /// The counters that are missing from either report are not compared.
+    ///
+    /// [`TerminationKind`]: enum.TerminationKind.html
+    pub fn compare(&self, expected: &Report, tolerances: ReportTolerances) -> Vec<ReportDiff> {
+        fn differ<T: Copy + Ord + std::ops::Sub<Output = T>>(a: T, b: T, tolerance: T) -> bool {
+            let delta = if a > b { a - b } else { b - a };
+            delta > tolerance
+        }
+
+        let mut diffs = Vec::new();
+        let (e, a) = (expected.wall_clock_time, self.wall_clock_time);
+        if differ(e, a, tolerances.time) {
+            diffs.push(ReportDiff::WallClockTime {
+                expected: e,
+                actual: a,
+            });
+        }
+        if let (Some(e), Some(a)) = (expected.timers, self.timers) {
+            let (e_user, a_user) = (e.total_user_time, a.total_user_time);
+            if differ(e_user, a_user, tolerances.time) {
+                diffs.push(ReportDiff::UserTime {
+                    expected: e_user,
+                    actual: a_user,
+                });
+            }
+            let (e_kernel, a_kernel) = (e.total_kernel_time, a.total_kernel_time);
+            if differ(e_kernel, a_kernel, tolerances.time) {
+                diffs.push(ReportDiff::KernelTime {
+                    expected: e_kernel,
+                    actual: a_kernel,
+                });
+            }
+        }
+        if let (Some(e), Some(a)) = (expected.memory, self.memory) {
+            if differ(e.max_usage, a.max_usage, tolerances.memory) {
+                diffs.push(ReportDiff::PeakMemory {
+                    expected: e.max_usage,
+                    actual: a.max_usage,
+                });
+            }
+        }
+        if let (Some(e), Some(a)) = (expected.io, self.io) {
+            if differ(e.total_bytes_written, a.total_bytes_written, tolerances.io) {
+                diffs.push(ReportDiff::BytesWritten {
+                    expected: e.total_bytes_written,
+                    actual: a.total_bytes_written,
+                });
+            }
+            if differ(e.total_bytes_read, a.total_bytes_read, tolerances.io) {
+                diffs.push(ReportDiff::BytesRead {
+                    expected: e.total_bytes_read,
+                    actual: a.total_bytes_read,
+                });
+            }
+        }
+        if let (Some(e), Some(a)) = (expected.pid_counters, self.pid_counters) {
+            if e.total_processes != a.total_processes {
+                diffs.push(ReportDiff::TotalProcesses {
+                    expected: e.total_processes,
+                    actual: a.total_processes,
+                });
+            }
+        }
+        if expected.restarts != self.restarts {
+            diffs.push(ReportDiff::Restarts {
+                expected: expected.restarts,
+                actual: self.restarts,
+            });
+        }
+        if expected.exit_status != self.exit_status {
+            diffs.push(ReportDiff::ExitStatus {
+                expected: expected.exit_status.clone(),
+                actual: self.exit_status.clone(),
+            });
+        }
+        let (e, a) = (
+            expected.termination_reason.map(|r| r.kind()),
+            self.termination_reason.map(|r| r.kind()),
+        );
+        if e != a {
+            diffs.push(ReportDiff::TerminationReason {
+                expected: e,
+                actual: a,
+            });
+        }
+        diffs
+    }
+}
+
+impl ResourceLimitsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn idle_time(mut self, limit: IdleTimeLimit) -> Self {
+        self.limits.idle_time = Some(limit);
+        self
+    }
+
+    pub fn wall_clock(mut self, time: Duration) -> Self {
+        self.limits.wall_clock_time = Some(time);
+        self
+    }
+
+    pub fn user_time(mut self, time: Duration) -> Self {
+        self.limits.total_user_time = Some(time);
+        self
+    }
+
+    pub fn memory(mut self, bytes: u64) -> Self {
+        self.limits.max_memory_usage = Some(bytes);
+        self
+    }
+
+    pub fn swap(mut self, bytes: u64) -> Self {
+        self.limits.max_swap_usage = Some(bytes);
+        self
+    }
+
+    pub fn bytes_written(mut self, bytes: u64) -> Self {
+        self.limits.total_bytes_written = Some(bytes);
+        self
+    }
+
+    pub fn bytes_read(mut self, bytes: u64) -> Self {
+        self.limits.total_bytes_read = Some(bytes);
+        self
+    }
+
+    pub fn processes_created(mut self, n: usize) -> Self {
+        self.limits.total_processes_created = Some(n);
+        self
+    }
+
+    pub fn process_creation_rate(mut self, n: usize, window: Duration) -> Self {
+        self.limits.max_process_creation_rate = Some((n, window));
+        self
+    }
+
+    pub fn active_processes(mut self, n: usize) -> Self {
+        self.limits.active_processes = Some(n);
+        self
+    }
+
+    pub fn active_threads(mut self, n: usize) -> Self {
+        self.limits.active_threads = Some(n);
+        self
+    }
+
+    pub fn active_network_connections(mut self, n: usize) -> Self {
+        self.limits.active_network_connections = Some(n);
+        self
+    }
+
+    pub fn open_handles(mut self, n: usize) -> Self {
+        self.limits.max_open_handles = Some(n);
+        self
+    }
+
+    pub fn distinct_endpoints(mut self, n: usize) -> Self {
+        self.limits.max_distinct_endpoints = Some(n);
+        self
+    }
+
+    pub fn build(self) -> ResourceLimits {
+        self.limits
+    }
+}
+
+impl ResourceLimits {
+    pub(crate) fn set_os_limits(&self, group: &Group) -> Result<()> {
+        self.try_set_os_limits(group).map(|_| ())
+    }
+
+    /// Same as `set_os_limits`, but fails if one of the limits is not enforced by the OS,
+    /// since without a supervisor nothing checks it in software.
+    pub(crate) fn set_enforced_os_limits(&self, group: &Group) -> Result<()> {
+        let name = match self.try_set_os_limits(group)?.first() {
+            Some(OsLimit::Memory) => "memory",
+            Some(OsLimit::Swap) => "swap",
+            Some(OsLimit::ActiveProcess) => "active process",
+            None => return Ok(()),
+        };
+        Err(Error::from(format!(
+            "The {} limit can't be enforced by the OS",
+            name
+        )))
+    }
+
+    // Returns the limits that are not enforced by the OS.
+    fn try_set_os_limits(&self, group: &Group) -> Result<Vec<OsLimit>> {
+        let limits = [
+            (OsLimit::Memory, self.max_memory_usage),
+            (OsLimit::Swap, self.max_swap_usage),
+            (
+                OsLimit::ActiveProcess,
+                self.active_processes.map(|n| n as u64),
+            ),
+        ];
+        let mut not_enforced = Vec::new();
+        for (limit, value) in limits.iter() {
+            if let Some(value) = *value {
+                if !group.set_os_limit(*limit, value)? {
+                    not_enforced.push(*limit);
+                }
+            }
         }
+        Ok(not_enforced)
     }
 }
 
@@ -154,6 +942,33 @@ impl fmt::Display for ProgramErrors {
     }
 }
 
+impl<F> OnMonitorTick for F
+where
+    F: FnMut(&Report) + Send,
+{
+    fn on_tick(&mut self, report: &Report) {
+        self(report)
+    }
+}
+
+impl<F> SecurityPolicy for F
+where
+    F: FnMut(&Report) -> Option<String> + Send,
+{
+    fn check(&mut self, report: &Report) -> Option<String> {
+        self(report)
+    }
+}
+
+impl<F> OnThreshold for F
+where
+    F: FnMut(&Report, TerminationKind) + Send,
+{
+    fn on_threshold(&mut self, report: &Report, kind: TerminationKind) {
+        self(report, kind)
+    }
+}
+
 impl Program {
     pub fn new(info: ProcessInfo) -> Self {
         Self {
@@ -161,9 +976,26 @@ impl Program {
             group: None,
             resource_limits: None,
             // stdio: None,
-            monitor_interval: Duration::from_millis(1),
-            wait_for_children: false,
+            monitor_interval: None,
+            adaptive_interval: None,
+            wait_for_children: None,
+            pause_time_on_stdin_wait: false,
+            max_messages_per_tick: 10,
+            restart_policy: RestartPolicy::default(),
+            reset_time_on_restart: false,
+            reset_counters_on_reset_time: false,
+            merge_stderr: false,
+            on_broken_pipe: None,
             msg_receiver: None,
+            on_tick: None,
+            security_policy: None,
+            on_threshold: None,
+            record_process_tree: false,
+            record_remote_endpoints: false,
+            natural_exit_grace: None,
+            max_runtime: None,
+            absolute_deadline: None,
+            event_driven_monitor: false,
         }
     }
 
@@ -177,6 +1009,39 @@ impl Program {
     }
 
     pub fn group(&mut self, group: Group) -> &mut Self {
+        self.shared_group(Arc::new(group))
+    }
+
+    /// Spawns the program into a group that may be shared with other programs. The
+    /// resource usage of a shared group is the total of all its processes, so resource
+    /// limits of each program are checked against these totals, and a program that exceeds
+    /// them terminates the whole group. The limits enforced by the OS, that is the memory,
+    /// swap and active process limits, are set on the group once by [`Session::run`], to
+    /// the strictest of the programs that share it.
+    ///
+    /// ```no_run
+    /// # use spawner::process::{Group, ProcessInfo};
+    /// # use spawner::{Program, ResourceLimits, Session};
+    /// # use std::sync::Arc;
+    /// # fn main() -> spawner::Result<()> {
+    /// let group = Arc::new(Group::new()?);
+    /// let limits = ResourceLimits {
+    ///     max_memory_usage: Some(64 * 1024 * 1024),
+    ///     ..Default::default()
+    /// };
+    /// let mut sess = Session::new();
+    /// for app in &["a", "b"] {
+    ///     sess.add_program(Program::new_with(ProcessInfo::new(app), |p| {
+    ///         p.shared_group(group.clone()).resource_limits(limits);
+    ///     }))?;
+    /// }
+    /// sess.run()?.wait();
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Session::run`]: struct.Session.html#method.run
+    pub fn shared_group(&mut self, group: Arc<Group>) -> &mut Self {
         self.group = Some(group);
         self
     }
@@ -186,22 +1051,321 @@ impl Program {
         self
     }
 
+    /// Sets the interval between the checks of the limits, [`DEFAULT_MONITOR_INTERVAL`] by
+    /// default. Shorter intervals, including zero, are raised to it.
+    ///
+    /// [`DEFAULT_MONITOR_INTERVAL`]: constant.DEFAULT_MONITOR_INTERVAL.html
     pub fn monitor_interval(&mut self, monitor_interval: Duration) -> &mut Self {
-        self.monitor_interval = monitor_interval;
+        self.monitor_interval = Some(std::cmp::max(monitor_interval, DEFAULT_MONITOR_INTERVAL));
+        self
+    }
+
+    /// Makes the monitor sleep until the process exits, a message is sent through the
+    /// [`Run`] or [`msg_receiver`], or the next check of the limits is due, instead of
+    /// waking up every millisecond. This saves cpu time on long runs with a long
+    /// `monitor_interval`. The other events, like the exit of child processes, are noticed
+    /// on the next check.
+    ///
+    /// [`Run`]: struct.Run.html
+    /// [`msg_receiver`]: struct.Program.html#method.msg_receiver
+    pub fn event_driven_monitor(&mut self, enable: bool) -> &mut Self {
+        self.event_driven_monitor = enable;
+        self
+    }
+
+    /// Makes the monitor interval adaptive. The interval starts at `min` and is doubled up
+    /// to `max` while the resource usage of the program does not change. Overrides
+    /// `monitor_interval`.
+    pub fn adaptive_interval(&mut self, min: Duration, max: Duration) -> &mut Self {
+        let min = std::cmp::max(min, DEFAULT_MONITOR_INTERVAL);
+        self.adaptive_interval = Some((min, std::cmp::max(min, max)));
         self
     }
 
     pub fn wait_for_children(&mut self, wait: bool) -> &mut Self {
-        self.wait_for_children = wait;
+        self.wait_for_children = Some(wait);
+        self
+    }
+
+    /// Stops time accounting while the main process is blocked reading its stdin and
+    /// resumes it once the process continues, as if `StopTimeAccounting` and
+    /// `ResumeTimeAccounting` were sent.
+    ///
+    /// The state is sampled once per monitor interval, so up to one interval around each
+    /// wait may be misattributed. Only the main thread of the main process is inspected.
+    /// On Linux the process must be sleeping in `read` or `readv` on fd 0, which is read
+    /// from `/proc/<pid>/syscall` and is not available for processes of other users.
+    /// On Windows any pending I/O of the main thread is treated as a wait on stdin.
+    pub fn pause_time_on_stdin_wait(&mut self, pause: bool) -> &mut Self {
+        self.pause_time_on_stdin_wait = pause;
+        self
+    }
+
+    /// Sets the maximum number of messages handled per monitor tick, 10 by default. The
+    /// rest stay queued until the next ticks. [`ProgramMessage::Terminate`] is not subject
+    /// to the limit and is handled as soon as it is received. At least one message is
+    /// handled per tick, so 0 is treated as 1.
+    ///
+    /// [`ProgramMessage::Terminate`]: enum.ProgramMessage.html#variant.Terminate
+    pub fn max_messages_per_tick(&mut self, max: usize) -> &mut Self {
+        self.max_messages_per_tick = std::cmp::max(max, 1);
+        self
+    }
+
+    /// Sets when the program is respawned after it exits, [`RestartPolicy::Never`] by
+    /// default. The new process is spawned in the same group with the same stdio, so the
+    /// resource usage and the limits span all restarts.
+    ///
+    /// [`RestartPolicy::Never`]: enum.RestartPolicy.html#variant.Never
+    pub fn restart_policy(&mut self, policy: RestartPolicy) -> &mut Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Resets the wall clock time and the user time accounted against the limits on every
+    /// restart, as if [`ProgramMessage::ResetTime`] was received. The other limits are
+    /// checked against the usage of the whole group and are never reset.
+    ///
+    /// [`ProgramMessage::ResetTime`]: enum.ProgramMessage.html#variant.ResetTime
+    pub fn reset_time_on_restart(&mut self, reset: bool) -> &mut Self {
+        self.reset_time_on_restart = reset;
+        self
+    }
+
+    /// Makes [`ProgramMessage::ResetTime`] also reset the counters of the process group, so
+    /// that the limits and the report apply to the usage since the reset. See
+    /// [`Group::reset_counters`] for the counters that can be reset on each platform.
+    ///
+    /// [`ProgramMessage::ResetTime`]: enum.ProgramMessage.html#variant.ResetTime
+    /// [`Group::reset_counters`]: process/struct.Group.html#method.reset_counters
+    pub fn reset_counters_on_reset_time(&mut self, reset: bool) -> &mut Self {
+        self.reset_counters_on_reset_time = reset;
+        self
+    }
+
+    /// Redirects stderr of the program into its stdout. The stderr source of the program
+    /// stays in the graph, but no data is ever written to it.
+    pub fn merge_stderr(&mut self, merge: bool) -> &mut Self {
+        self.merge_stderr = merge;
+        self
+    }
+
+    /// Sets what happens when the destinations of the program's output are gone. By default
+    /// the output stops being read, so the program blocks once the pipe is full. With a
+    /// policy set, the output is always transmitted through the dataflow graph rather than
+    /// being connected directly to the destination. Outputs read by a custom
+    /// [`SourceReader`] are not affected.
+    ///
+    /// [`SourceReader`]: dataflow/trait.SourceReader.html
+    pub fn on_broken_pipe(&mut self, policy: BrokenPipe) -> &mut Self {
+        self.on_broken_pipe = Some(policy);
+        self
+    }
+
+    /// Sets the callback that is invoked on every monitor tick. If the callback panics,
+    /// it is not invoked anymore.
+    pub fn on_tick<T>(&mut self, on_tick: T) -> &mut Self
+    where
+        T: OnMonitorTick + 'static,
+    {
+        self.on_tick = Some(Box::new(on_tick));
+        self
+    }
+
+    /// Sets the policy that is checked on every monitor tick, see [`SecurityPolicy`].
+    ///
+    /// [`SecurityPolicy`]: trait.SecurityPolicy.html
+    pub fn security_policy<T>(&mut self, policy: T) -> &mut Self
+    where
+        T: SecurityPolicy + 'static,
+    {
+        self.security_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Sets the callback that is invoked once for every limit whose usage crosses `fraction`
+    /// of it, e.g. `0.8` for a warning at 80% of the memory or time budget. Limits set by
+    /// the OS are checked on their own and are not reported. If the callback panics, it is
+    /// not invoked anymore. `fraction` must be within (0, 1], otherwise adding the program to
+    /// a [`Session`] fails.
+    ///
+    /// [`Session`]: struct.Session.html
+    pub fn on_threshold<T>(&mut self, fraction: f64, on_threshold: T) -> &mut Self
+    where
+        T: OnThreshold + 'static,
+    {
+        self.on_threshold = Some((fraction, Box::new(on_threshold)));
+        self
+    }
+
+    /// Records the processes of the group with their parents in [`Report::process_tree`].
+    /// The pids of the group are checked on every monitor tick, and the processes are
+    /// listed whenever the pids change, which is expensive, so it is disabled by default.
+    /// A process that starts and exits between two ticks is missed.
+    ///
+    /// [`Report::process_tree`]: struct.Report.html#structfield.process_tree
+    pub fn record_process_tree(&mut self, record: bool) -> &mut Self {
+        self.record_process_tree = record;
+        self
+    }
+
+    /// Records the remote endpoints the group connects to in [`Report::remote_endpoints`].
+    /// Like [`record_process_tree`], the connections are listed on every monitor tick, and
+    /// a connection that is opened and closed between two ticks is missed.
+    ///
+    /// [`Report::remote_endpoints`]: struct.Report.html#structfield.remote_endpoints
+    /// [`record_process_tree`]: struct.Program.html#method.record_process_tree
+    pub fn record_remote_endpoints(&mut self, record: bool) -> &mut Self {
+        self.record_remote_endpoints = record;
+        self
+    }
+
+    /// Delays the termination of the group by `grace` once a limit is exceeded, so that a
+    /// process about to exit can do so on its own. The termination reason is reported
+    /// either way, and the exit status of a process that exits within the window is kept
+    /// in [`Report::natural_exit_status`]. The OS-enforced limits, such as the memory
+    /// limit, can not be delayed.
+    ///
+    /// [`Report::natural_exit_status`]: struct.Report.html#structfield.natural_exit_status
+    pub fn capture_natural_exit(&mut self, grace: Duration) -> &mut Self {
+        self.natural_exit_grace = Some(grace);
+        self
+    }
+
+    /// Sets the hard maximum of the wall clock time of the program, including restarts. Unlike
+    /// [`ResourceLimits::wall_clock_time`], it is not affected by the time accounting, e.g.
+    /// [`ProgramMessage::StopTimeAccounting`], and it is enforced even after another limit is
+    /// exceeded. The program is terminated with [`TerminationReason::DeadlineExceeded`].
+    /// Unlimited by default.
+    ///
+    /// [`ResourceLimits::wall_clock_time`]: struct.ResourceLimits.html#structfield.wall_clock_time
+    /// [`ProgramMessage::StopTimeAccounting`]: enum.ProgramMessage.html#variant.StopTimeAccounting
+    /// [`TerminationReason::DeadlineExceeded`]: enum.TerminationReason.html#variant.DeadlineExceeded
+    pub fn max_runtime(&mut self, max: Duration) -> &mut Self {
+        self.max_runtime = Some(max);
+        self
+    }
+
+    /// Terminates the program at `deadline` the same way as [`max_runtime`]. If both are
+    /// set, the earlier one wins.
+    ///
+    /// [`max_runtime`]: struct.Program.html#method.max_runtime
+    pub fn absolute_deadline(&mut self, deadline: Instant) -> &mut Self {
+        self.absolute_deadline = Some(deadline);
         self
     }
 
+    /// Checks the program without spawning it: the process info and the options are
+    /// validated, and the OS limits are set on a temporary group. Returns the first failure.
+    /// Stdio is checked by [`Session::validate`], since it is set up by the session.
+    ///
+    /// [`Session::validate`]: struct.Session.html#method.validate
+    pub fn validate(&self) -> Result<()> {
+        self.info.validate()?;
+        self.validate_options()?;
+        let group = Group::new()?;
+        self.resource_limits
+            .unwrap_or_default()
+            .set_os_limits(&group)
+    }
+
+    fn validate_options(&self) -> Result<()> {
+        match self.on_threshold {
+            Some((fraction, _)) if !(fraction > 0.0 && fraction <= 1.0) => Err(Error::from(
+                format!("The threshold fraction {} is not within (0, 1]", fraction),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the channel the program receives messages from, in addition to the ones sent
+    /// through the [`Run`]. The messages wake the monitor up like those of the [`Run`].
+    ///
+    /// [`Run`]: struct.Run.html
     pub fn msg_receiver(&mut self, receiver: Receiver<ProgramMessage>) -> &mut Self {
         self.msg_receiver = Some(receiver);
         self
     }
 }
 
+impl ProgramTemplate {
+    pub fn new<F>(info: ProcessInfo, configure: F) -> Self
+    where
+        F: Fn(&mut Program) + Send + 'static,
+    {
+        Self {
+            info,
+            configure: Box::new(configure),
+        }
+    }
+
+    /// Creates a new program and returns it with the sender of its messages.
+    pub fn instantiate(&self) -> (Program, Sender<ProgramMessage>) {
+        let (sender, receiver) = channel();
+        let mut prog = Program::new(self.info.clone());
+        (self.configure)(&mut prog);
+        prog.msg_receiver(receiver);
+        (prog, sender)
+    }
+}
+
+impl ProgramDefaults {
+    fn apply(&self, prog: &mut Program) {
+        if prog.resource_limits.is_none() {
+            prog.resource_limits = self.resource_limits;
+        }
+        if prog.monitor_interval.is_none() {
+            prog.monitor_interval = self.monitor_interval;
+        }
+        if prog.wait_for_children.is_none() {
+            prog.wait_for_children = self.wait_for_children;
+        }
+        if prog.max_runtime.is_none() {
+            prog.max_runtime = self.max_runtime;
+        }
+    }
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.defaults.resource_limits = Some(resource_limits);
+        self
+    }
+
+    /// Sets the default of [`Program::monitor_interval`].
+    ///
+    /// [`Program::monitor_interval`]: struct.Program.html#method.monitor_interval
+    pub fn monitor_interval(mut self, monitor_interval: Duration) -> Self {
+        self.defaults.monitor_interval =
+            Some(std::cmp::max(monitor_interval, DEFAULT_MONITOR_INTERVAL));
+        self
+    }
+
+    pub fn wait_for_children(mut self, wait: bool) -> Self {
+        self.defaults.wait_for_children = Some(wait);
+        self
+    }
+
+    /// Sets the default of [`Program::max_runtime`].
+    ///
+    /// [`Program::max_runtime`]: struct.Program.html#method.max_runtime
+    pub fn max_runtime(mut self, max: Duration) -> Self {
+        self.defaults.max_runtime = Some(max);
+        self
+    }
+
+    pub fn build(self) -> Session {
+        Session {
+            defaults: self.defaults,
+            ..Session::default()
+        }
+    }
+}
+
 impl Session {
     pub fn new() -> Self {
         Self::default()
@@ -211,26 +1375,49 @@ impl Session {
     where
         P: Into<Program>,
     {
+        let mut prog = p.into();
+        prog.validate_options()?;
+        self.defaults.apply(&mut prog);
         let (stdin_r, stdin_w) = pipe::create()?;
         let (stdout_r, stdout_w) = pipe::create()?;
         let (stderr_r, stderr_w) = pipe::create()?;
+        let stdio = if prog.merge_stderr {
+            Stdio::merged(stdin_r, stdout_w)?
+        } else {
+            Stdio {
+                stdin: stdin_r,
+                stdout: stdout_w,
+                stderr: stderr_w,
+            }
+        };
         let mapping = StdioMapping {
             stdin: self.graph.add_destination(stdin_w),
             stdout: self.graph.add_source(stdout_r),
             stderr: self.graph.add_source(stderr_r),
         };
-        self.progs.push(ProgramExt {
-            prog: p.into(),
-            stdio: Stdio {
-                stdin: stdin_r,
-                stdout: stdout_w,
-                stderr: stderr_w,
-            },
-        });
+        self.progs.push(ProgramExt { prog, stdio });
         self.mappings.push(mapping);
         Ok(mapping)
     }
 
+    /// Adds the programs as stages of a pipeline, connecting stdout of each stage to stdin
+    /// of the next one. Stdin of the first stage and stdout of the last stage are left
+    /// unconnected. Each stage receives EOF on stdin once the previous stage closes its stdout.
+    pub fn pipeline<I, P>(&mut self, programs: I) -> Result<Vec<StdioMapping>>
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<Program>,
+    {
+        let mappings = programs
+            .into_iter()
+            .map(|p| self.add_program(p))
+            .collect::<Result<Vec<_>>>()?;
+        for stages in mappings.windows(2) {
+            self.graph.connect(stages[0].stdout, stages[1].stdin);
+        }
+        Ok(mappings)
+    }
+
     pub fn disable_source_optimization(&mut self, src: SourceId) {
         self.ignored_srcs.insert(src);
     }
@@ -247,26 +1434,144 @@ impl Session {
         &self.graph
     }
 
+    /// Checks the session without spawning anything: every program is validated as by
+    /// [`Program::validate`], and stdio is checked the way [`run`] sets it up. Returns the
+    /// first failure.
+    ///
+    /// [`Program::validate`]: struct.Program.html#method.validate
+    /// [`run`]: struct.Session.html#method.run
+    pub fn validate(&self) -> Result<()> {
+        for prog in self.progs.iter() {
+            prog.prog.validate()?;
+        }
+        self.check_stdio()
+    }
+
     pub fn run(mut self) -> Result<Run> {
-        self.optimize_io().map(|_| Run {
+        self.optimize_io()?;
+        self.set_group_limits()?;
+        let graph = self.graph;
+        let (finished_sender, finished_receiver) = channel();
+        Ok(Run {
             supervisors: self
                 .progs
                 .into_iter()
-                .map(|p| SupervisorThread::spawn(p.prog, p.stdio))
+                .zip(self.mappings.iter())
+                .enumerate()
+                .map(|(idx, (p, mapping))| {
+                    let stdin = graph.destination(mapping.stdin).map(Destination::handle);
+                    let outputs = match p.prog.on_broken_pipe {
+                        Some(BrokenPipe::Terminate) => [mapping.stdout, mapping.stderr]
+                            .iter()
+                            .filter_map(|&id| graph.source(id))
+                            .map(Source::handle)
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    let guard = FlagGuard {
+                        completion: Arc::new(Completion::default()),
+                        idx,
+                        finished_sender: finished_sender.clone(),
+                    };
+                    SupervisorThread::spawn(p.prog, p.stdio, stdin, outputs, guard)
+                })
                 .collect(),
-            transmitter: self.graph.transmit_data(),
+            transmitter: graph.transmit_data(),
             mappings: self.mappings,
+            finished_receiver,
         })
     }
 
+    // Sets the OS limits of the groups given to the programs once, since a group may be
+    // shared, and the limits of one program would overwrite the ones of another. The
+    // supervisors only set the limits of the groups they create.
+    fn set_group_limits(&self) -> Result<()> {
+        fn strictest<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        let mut groups: Vec<(&Arc<Group>, ResourceLimits)> = Vec::new();
+        for prog in self.progs.iter().map(|p| &p.prog) {
+            let group = match prog.group {
+                Some(ref group) => group,
+                None => continue,
+            };
+            let limits = prog.resource_limits.unwrap_or_default();
+            match groups.iter_mut().find(|(g, _)| Arc::ptr_eq(g, group)) {
+                Some((_, shared)) => {
+                    shared.max_memory_usage =
+                        strictest(shared.max_memory_usage, limits.max_memory_usage);
+                    shared.max_swap_usage = strictest(shared.max_swap_usage, limits.max_swap_usage);
+                    shared.active_processes =
+                        strictest(shared.active_processes, limits.active_processes);
+                }
+                None => groups.push((group, limits)),
+            }
+        }
+        groups
+            .iter()
+            .try_for_each(|(group, limits)| limits.set_os_limits(group))
+    }
+
     fn optimize_io(&mut self) -> Result<()> {
+        for (mapping, prog) in self.mappings.iter().zip(self.progs.iter()) {
+            let policy = match prog.prog.on_broken_pipe {
+                Some(policy) => policy,
+                None => continue,
+            };
+            for &id in [mapping.stdout, mapping.stderr].iter() {
+                let src = match self.graph.source_mut(id) {
+                    // An unconnected source is replaced with the null device, so it can't break.
+                    Some(src) if !src.edges().is_empty() => src,
+                    _ => continue,
+                };
+                src.set_drain_on_disconnect(policy == BrokenPipe::Discard);
+                // Broken connections are only detected by the graph.
+                self.ignored_srcs.insert(id);
+            }
+        }
+
         let mut optimizer =
             DataflowOptimizer::new(&mut self.graph, &self.ignored_srcs, &self.ignored_dsts);
         for (mapping, prog) in self.mappings.iter().zip(self.progs.iter_mut()) {
             let stdio = &mut prog.stdio;
             optimizer.optimize_destination(mapping.stdin, &mut stdio.stdin)?;
             optimizer.optimize_source(mapping.stdout, &mut stdio.stdout)?;
-            optimizer.optimize_source(mapping.stderr, &mut stdio.stderr)?;
+            if prog.prog.merge_stderr {
+                // Stdout may have been replaced, so stderr has to follow it.
+                stdio.stderr = stdio.stdout.try_clone()?;
+            } else {
+                optimizer.optimize_source(mapping.stderr, &mut stdio.stderr)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Performs the steps of `optimize_io` that can fail, without changing the session.
+    fn check_stdio(&self) -> Result<()> {
+        let analyzer = DataflowAnalyzer::new(&self.graph);
+        for (mapping, prog) in self.mappings.iter().zip(self.progs.iter()) {
+            if !self.ignored_dsts.contains(&mapping.stdin) {
+                if let DestinationOptimization::ReplaceWithNull =
+                    analyzer.analyze_destination(mapping.stdin)
+                {
+                    ReadPipe::null()?;
+                }
+            }
+            for &id in [mapping.stdout, mapping.stderr].iter() {
+                if self.ignored_srcs.contains(&id) {
+                    continue;
+                }
+                if let SourceOptimization::ReplaceWithNull = analyzer.analyze_source(id) {
+                    WritePipe::null()?;
+                }
+            }
+            if prog.prog.merge_stderr {
+                prog.stdio.stdout.try_clone()?;
+            }
         }
         Ok(())
     }
@@ -274,34 +1579,100 @@ impl Session {
 
 impl Drop for FlagGuard {
     fn drop(&mut self) {
-        self.0.store(true, Ordering::Release);
+        self.completion.is_finished.store(true, Ordering::Release);
+        #[cfg(feature = "async")]
+        for waker in self.completion.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+        // The run may have been dropped already.
+        let _ = self.finished_sender.send(self.idx);
+    }
+}
+
+impl Completion {
+    fn is_finished(&self) -> bool {
+        self.is_finished.load(Ordering::Acquire)
+    }
+
+    #[cfg(feature = "async")]
+    fn poll_finished(&self, cx: &mut Context) -> bool {
+        // The flag is checked under the lock so that the supervisor thread, which sets the
+        // flag before taking the lock, cannot miss the registered waker.
+        let mut wakers = self.wakers.lock().unwrap();
+        if self.is_finished() {
+            return true;
+        }
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        false
     }
 }
 
 impl SupervisorThread {
-    fn spawn(p: Program, stdio: Stdio) -> Self {
-        let is_finished = Arc::new(AtomicBool::new(false));
+    fn spawn(
+        mut p: Program,
+        stdio: Stdio,
+        stdin: Option<DestinationHandle>,
+        outputs: Vec<SourceHandle>,
+        guard: FlagGuard,
+    ) -> Self {
+        let (control_sender, control_receiver) = channel();
+        // Falls back to the regular monitor if the event can't be created.
+        let wake_event = if p.event_driven_monitor {
+            WakeEvent::new().ok().map(Arc::new)
+        } else {
+            None
+        };
+        let monitor_wake_event = wake_event.clone();
+        let remaining = Arc::new(Mutex::new(None));
+        let monitor_remaining = remaining.clone();
+        // The supervisor waits on a single channel, so the messages of the program are
+        // forwarded to it. The thread exits once the sender of the program is dropped or the
+        // supervisor has finished. Since the sender may outlive the run, the receiver is
+        // waited on with a timeout, after which the completion of the supervisor is checked.
+        if let Some(receiver) = p.msg_receiver.take() {
+            let sender = control_sender.clone();
+            let wake_event = wake_event.clone();
+            let completion = guard.completion.clone();
+            thread::spawn(move || loop {
+                match receiver.recv_timeout(FORWARDING_CHECK_INTERVAL) {
+                    Ok(msg) => {
+                        if !send_message(&sender, wake_event.as_deref(), msg) {
+                            break;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) if !completion.is_finished() => {}
+                    Err(_) => break,
+                }
+            });
+        }
         Self {
-            is_finished: is_finished.clone(),
-            handle: thread::spawn(|| {
-                let _guard = FlagGuard(is_finished);
+            completion: guard.completion.clone(),
+            handle: Some(thread::spawn(|| {
+                let _guard = guard;
                 Supervisor::start_monitoring(
-                    p.info,
+                    p,
                     stdio,
-                    match p.group {
-                        Some(g) => g,
-                        None => Group::new()?,
-                    },
-                    p.resource_limits.unwrap_or_default(),
-                    p.monitor_interval,
-                    p.msg_receiver,
-                    p.wait_for_children,
+                    stdin,
+                    outputs,
+                    control_receiver,
+                    monitor_wake_event,
+                    monitor_remaining,
                 )
-            }),
+            })),
+            result: None,
+            control_sender,
+            wake_event,
+            remaining,
         }
     }
 
-    fn wait(self, mapping: StdioMapping, results: &mut TransmitterResults) -> ProgramResult {
+    fn send(&self, msg: ProgramMessage) -> bool {
+        send_message(&self.control_sender, self.wake_event.as_deref(), msg)
+    }
+
+    fn wait(mut self, mapping: StdioMapping, results: &mut TransmitterResults) -> ProgramResult {
         // Collect io errors for this program.
         let mut errs = [mapping.stdout, mapping.stderr]
             .iter()
@@ -315,9 +1686,7 @@ impl SupervisorThread {
             .collect::<Vec<_>>();
 
         let result = self
-            .handle
-            .join()
-            .unwrap_or_else(|_| Err(Error::from("Supervisor thread panicked")))
+            .take_result()
             .map_err(|e| {
                 errs.push(e);
             })
@@ -329,11 +1698,45 @@ impl SupervisorThread {
         }
     }
 
+    fn take_result(&mut self) -> Result<Report> {
+        self.result
+            .take()
+            .unwrap_or_else(|| join(self.handle.take().unwrap()))
+    }
+
+    fn try_result(&mut self) -> Option<&Result<Report>> {
+        if self.result.is_none() && self.is_finished() {
+            self.result = Some(join(self.handle.take().unwrap()));
+        }
+        self.result.as_ref()
+    }
+
     fn is_finished(&self) -> bool {
-        self.is_finished.load(Ordering::Acquire)
+        self.completion.is_finished()
     }
 }
 
+// Returns whether the message is sent, i.e. the supervisor is still running.
+fn send_message(
+    sender: &Sender<ProgramMessage>,
+    wake_event: Option<&WakeEvent>,
+    msg: ProgramMessage,
+) -> bool {
+    if sender.send(msg).is_err() {
+        return false;
+    }
+    if let Some(event) = wake_event {
+        let _ = event.wake();
+    }
+    true
+}
+
+fn join(handle: JoinHandle<Result<Report>>) -> Result<Report> {
+    handle
+        .join()
+        .unwrap_or_else(|_| Err(Error::from("Supervisor thread panicked")))
+}
+
 impl Run {
     pub fn wait(self) -> Vec<ProgramResult> {
         let mut transmitter_results = self.transmitter.wait();
@@ -344,7 +1747,458 @@ impl Run {
             .collect::<Vec<_>>()
     }
 
+    /// Waits at most `timeout` for every supervisor to finish. If all of them finish in
+    /// time, returns the same results as [`wait`]. Otherwise the run is returned back as is:
+    /// unfinished programs keep running, and the results of the finished ones are available
+    /// through [`try_report`]. The returned run can be waited for again.
+    ///
+    /// [`wait`]: struct.Run.html#method.wait
+    /// [`try_report`]: struct.Run.html#method.try_report
+    pub fn wait_timeout(self, timeout: Duration) -> std::result::Result<Vec<ProgramResult>, Run> {
+        let deadline = Instant::now() + timeout;
+        while !self.all_finished() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(self);
+            }
+            // Every supervisor sends its index once finished, which wakes this up.
+            match self.finished_receiver.recv_timeout(deadline - now) {
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                // Every supervisor has finished.
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(self.wait())
+    }
+
+    /// Waits for every supervisor to finish, calling `f` with the index and the result of
+    /// each program as soon as its supervisor finishes, so the results come in completion
+    /// order rather than in the order of programs. The programs that have already finished
+    /// come first, in the order of programs. Unlike [`wait`], io errors of the programs are
+    /// not reported.
+    ///
+    /// [`wait`]: struct.Run.html#method.wait
+    pub fn wait_each<F>(mut self, mut f: F)
+    where
+        F: FnMut(usize, Result<Report>),
+    {
+        let mut reported = vec![false; self.supervisors.len()];
+        // The indices of the programs that have finished during `wait_timeout` are no longer
+        // in the channel, so those programs are found by their flags.
+        for (idx, supervisor) in self.supervisors.iter_mut().enumerate() {
+            if supervisor.is_finished() {
+                reported[idx] = true;
+                f(idx, supervisor.take_result());
+            }
+        }
+        while reported.contains(&false) {
+            let idx = match self.finished_receiver.recv() {
+                Ok(idx) => idx,
+                Err(_) => break,
+            };
+            if !reported[idx] {
+                reported[idx] = true;
+                f(idx, self.supervisors[idx].take_result());
+            }
+        }
+        self.transmitter.wait();
+    }
+
+    /// Returns a future that resolves once the supervisor of the program at `idx` has
+    /// finished. The result itself can then be taken with [`try_report`]. The future does
+    /// not depend on any particular executor: the supervisor thread wakes it on exit.
+    ///
+    /// [`try_report`]: struct.Run.html#method.try_report
+    #[cfg(feature = "async")]
+    pub fn finished(&self, idx: usize) -> Finished {
+        Finished(self.supervisors[idx].completion.clone())
+    }
+
+    /// Async version of [`wait`]. The returned future resolves once every supervisor has
+    /// finished and the output of every program has been read, after which the results are
+    /// collected the same way [`wait`] does. Polling it never blocks.
+    ///
+    /// [`wait`]: struct.Run.html#method.wait
+    #[cfg(feature = "async")]
+    pub fn wait_async(self) -> WaitAsync {
+        WaitAsync {
+            pending: self
+                .supervisors
+                .iter()
+                .map(|s| s.completion.clone())
+                .collect(),
+            run: Some(self),
+        }
+    }
+
     pub fn all_finished(&self) -> bool {
         self.supervisors.iter().all(SupervisorThread::is_finished)
     }
+
+    /// Returns the result of the program at `idx` if its supervisor has finished, without
+    /// blocking. Once available, the same result is returned on every call. Io errors of the
+    /// program are reported only by [`wait`].
+    ///
+    /// [`wait`]: struct.Run.html#method.wait
+    pub fn try_report(&mut self, idx: usize) -> Option<&Result<Report>> {
+        self.supervisors[idx].try_result()
+    }
+
+    /// Returns what is left of every limit of the program at `idx` as of the last check of
+    /// its monitor, without waiting for the next one. Returns `None` until the limits are
+    /// checked for the first time. Once the program has finished, the result of the last
+    /// check is returned.
+    pub fn remaining(&self, idx: usize) -> Option<RemainingLimits> {
+        *self.supervisors[idx].remaining.lock().unwrap()
+    }
+
+    /// Sends `msg` to every program. Returns the indices of the programs that have
+    /// already finished and did not receive the message.
+    pub fn broadcast(&self, msg: ProgramMessage) -> Vec<usize> {
+        self.supervisors
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.send(msg))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Terminates every program. See [`broadcast`].
+    ///
+    /// [`broadcast`]: struct.Run.html#method.broadcast
+    pub fn terminate_all(&self) -> Vec<usize> {
+        self.broadcast(ProgramMessage::Terminate)
+    }
+}
+
+impl DetachedProcess {
+    pub fn pid(&self) -> u32 {
+        self.process.pid()
+    }
+
+    pub fn group(&self) -> &Group {
+        &self.group
+    }
+
+    /// Terminates the group of the process.
+    pub fn terminate(&self) -> Result<()> {
+        self.group.terminate()
+    }
+
+    /// Blocks until the process exits or `timeout` elapses, see [`Process::wait`].
+    ///
+    /// [`Process::wait`]: process/struct.Process.html#method.wait
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+        self.process.wait(timeout)
+    }
+}
+
+/// Runs a single program to completion, feeding it `stdin` and capturing its stdout and
+/// stderr. The output is decoded as UTF-8, with invalid sequences replaced. Without `stdin`
+/// the program reads from the null device.
+pub fn run_capture(
+    info: ProcessInfo,
+    stdin: Option<&[u8]>,
+    limits: ResourceLimits,
+) -> Result<(Report, String, String)> {
+    let mut sess = Session::new();
+    let mapping = sess.add_program(Program::new_with(info, |p| {
+        p.resource_limits(limits);
+    }))?;
+
+    let stdin_writer = match stdin {
+        Some(data) => {
+            let (r, mut w) = pipe::create()?;
+            let src = sess.graph_mut().add_source(r);
+            sess.graph_mut().connect(src, mapping.stdin);
+            let data = data.to_vec();
+            // The program may exit without reading everything, so the write error is ignored.
+            Some(thread::spawn(move || {
+                let _ = w.write_all(&data);
+            }))
+        }
+        None => None,
+    };
+    let stdout = capture_source(&mut sess, mapping.stdout)?;
+    let stderr = capture_source(&mut sess, mapping.stderr)?;
+
+    let result = sess.run()?.wait().pop().unwrap();
+    if let Some(writer) = stdin_writer {
+        let _ = writer.join();
+    }
+    let stdout = join_capture(stdout)?;
+    let stderr = join_capture(stderr)?;
+    result
+        .map(|report| (report, stdout, stderr))
+        .map_err(|e| Error::from(e.to_string()))
+}
+
+/// Spawns a process in a new group with only the limits that the OS enforces, that is the
+/// memory, swap and active process limits, and returns without monitoring it. The other
+/// limits are ignored. Fails if the OS can't enforce one of these limits, e.g. on Linux
+/// without control groups. On Windows the process is killed once the handle is dropped,
+/// unless [`ProcessInfo::kill_on_drop`] is disabled.
+///
+/// [`ProcessInfo::kill_on_drop`]: process/struct.ProcessInfo.html#method.kill_on_drop
+pub fn spawn_detached(
+    mut info: ProcessInfo,
+    stdio: Stdio,
+    limits: ResourceLimits,
+) -> Result<DetachedProcess> {
+    let group = Group::new()?;
+    limits.set_enforced_os_limits(&group)?;
+    let process = Process::spawn_in_group(&mut info, stdio, &group)?;
+    Ok(DetachedProcess { process, group })
+}
+
+fn capture_source(sess: &mut Session, src: SourceId) -> Result<JoinHandle<io::Result<Vec<u8>>>> {
+    let (mut r, w) = pipe::create()?;
+    let dst = sess.graph_mut().add_destination(w);
+    sess.graph_mut().connect(src, dst);
+    Ok(thread::spawn(move || {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).map(|_| data)
+    }))
+}
+
+fn join_capture(handle: JoinHandle<io::Result<Vec<u8>>>) -> Result<String> {
+    let data = handle
+        .join()
+        .map_err(|_| Error::from("Output reader panicked"))??;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+#[cfg(feature = "async")]
+impl Future for Finished {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0.poll_finished(cx) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Future for WaitAsync {
+    type Output = Vec<ProgramResult>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.pending.retain(|c| !c.poll_finished(cx));
+        if !self.pending.is_empty() {
+            return Poll::Pending;
+        }
+        // The output of a program may still be read after its supervisor has finished,
+        // e.g. while a child process keeps the pipe open.
+        let run = self
+            .run
+            .as_ref()
+            .expect("WaitAsync polled after completion");
+        if !run.transmitter.poll_finished(cx) {
+            return Poll::Pending;
+        }
+        // Every thread has finished its work, so collecting the results does not block.
+        let run = self.run.take().unwrap();
+        Poll::Ready(run.wait())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    const TOLERANCES: ReportTolerances = ReportTolerances {
+        time: Duration::from_millis(50),
+        memory: MB,
+        io: 10,
+    };
+
+    fn report() -> Report {
+        Report {
+            wall_clock_time: Duration::from_secs(1),
+            memory: Some(GroupMemory {
+                max_usage: 10 * MB,
+                ..Default::default()
+            }),
+            io: Some(GroupIo {
+                total_bytes_written: 100,
+                total_bytes_read: 100,
+                ..Default::default()
+            }),
+            timers: Some(GroupTimers {
+                total_user_time: Duration::from_millis(500),
+                total_kernel_time: Duration::from_millis(100),
+            }),
+            pid_counters: Some(GroupPidCounters {
+                total_processes: 2,
+                ..Default::default()
+            }),
+            network: None,
+            exit_status: ExitStatus::Finished(0),
+            termination_reason: None,
+            error: None,
+            restarts: 0,
+            samples_taken: 0,
+            sampling_time: Duration::from_millis(0),
+            peak_open_handles: None,
+            idle_time: Duration::from_millis(0),
+            cpu_load: 0.0,
+            security_violation: None,
+            process_tree: None,
+            remote_endpoints: None,
+            natural_exit_status: None,
+            counters_reset_at: None,
+        }
+    }
+
+    fn with_timers(user: Duration, kernel: Duration) -> Report {
+        let mut r = report();
+        r.timers = Some(GroupTimers {
+            total_user_time: user,
+            total_kernel_time: kernel,
+        });
+        r
+    }
+
+    fn with_io(written: u64, read: u64) -> Report {
+        let mut r = report();
+        r.io = Some(GroupIo {
+            total_bytes_written: written,
+            total_bytes_read: read,
+            ..Default::default()
+        });
+        r
+    }
+
+    #[test]
+    fn compare_equal_reports() {
+        assert_eq!(report().compare(&report(), TOLERANCES), []);
+    }
+
+    #[test]
+    fn compare_time() {
+        let expected = report();
+        let ms = Duration::from_millis;
+
+        let actual = with_timers(ms(550), ms(150));
+        assert_eq!(actual.compare(&expected, TOLERANCES), []);
+
+        let actual = with_timers(ms(551), ms(49));
+        assert_eq!(
+            actual.compare(&expected, TOLERANCES),
+            [
+                ReportDiff::UserTime {
+                    expected: ms(500),
+                    actual: ms(551),
+                },
+                ReportDiff::KernelTime {
+                    expected: ms(100),
+                    actual: ms(49),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_peak_memory() {
+        let expected = report();
+        let mut actual = report();
+        actual.memory.as_mut().unwrap().max_usage = 11 * MB;
+        assert_eq!(actual.compare(&expected, TOLERANCES), []);
+
+        actual.memory.as_mut().unwrap().max_usage = 11 * MB + 1;
+        assert_eq!(
+            actual.compare(&expected, TOLERANCES),
+            [ReportDiff::PeakMemory {
+                expected: 10 * MB,
+                actual: 11 * MB + 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn compare_io() {
+        let expected = report();
+        assert_eq!(with_io(110, 90).compare(&expected, TOLERANCES), []);
+        assert_eq!(
+            with_io(111, 89).compare(&expected, TOLERANCES),
+            [
+                ReportDiff::BytesWritten {
+                    expected: 100,
+                    actual: 111,
+                },
+                ReportDiff::BytesRead {
+                    expected: 100,
+                    actual: 89,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_process_count() {
+        let mut actual = report();
+        actual.pid_counters.as_mut().unwrap().total_processes = 3;
+        assert_eq!(
+            actual.compare(&report(), TOLERANCES),
+            [ReportDiff::TotalProcesses {
+                expected: 2,
+                actual: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn compare_missing_counters() {
+        let mut actual = with_timers(Duration::from_secs(5), Duration::from_secs(5));
+        actual.memory.as_mut().unwrap().max_usage = 100 * MB;
+        actual.pid_counters.as_mut().unwrap().total_processes = 10;
+        actual.io = Some(GroupIo::default());
+
+        let mut expected = report();
+        expected.timers = None;
+        expected.memory = None;
+        expected.io = None;
+        expected.pid_counters = None;
+        assert_eq!(actual.compare(&expected, TOLERANCES), []);
+        assert_eq!(expected.compare(&actual, TOLERANCES), []);
+    }
+
+    #[test]
+    fn threshold_fraction() {
+        let check = |fraction: f64| {
+            let mut prog = Program::new(ProcessInfo::new("app"));
+            prog.on_threshold(fraction, |_: &Report, _: TerminationKind| {});
+            prog.validate_options().is_ok()
+        };
+        assert!(check(0.8));
+        assert!(check(1.0));
+        assert!(!check(0.0));
+        assert!(!check(-0.5));
+        assert!(!check(1.5));
+        assert!(!check(f64::NAN));
+    }
+
+    #[test]
+    fn zero_monitor_interval_is_raised_to_default() {
+        let zero = Duration::from_millis(0);
+        let mut prog = Program::new(ProcessInfo::new("app"));
+        prog.monitor_interval(zero);
+        assert_eq!(prog.monitor_interval, Some(DEFAULT_MONITOR_INTERVAL));
+        prog.adaptive_interval(zero, zero);
+        assert_eq!(
+            prog.adaptive_interval,
+            Some((DEFAULT_MONITOR_INTERVAL, DEFAULT_MONITOR_INTERVAL))
+        );
+
+        let builder = SessionBuilder::new().monitor_interval(zero);
+        assert_eq!(
+            builder.defaults.monitor_interval,
+            Some(DEFAULT_MONITOR_INTERVAL)
+        );
+    }
 }
@@ -1,83 +1,475 @@
+use crate::dataflow::{DestinationHandle, SourceHandle};
 use crate::limit_checker::LimitChecker;
-use crate::process::{Group, OsLimit, Process, ProcessInfo, ResourceUsage, Stdio};
-use crate::{ProgramMessage, Report, ResourceLimits, Result, TerminationReason};
+use crate::process::{
+    ExitStatus, Group, GroupIo, GroupTimers, OsLimit, Process, ProcessInfo, ProcessNode,
+    ResourceUsage, Stdio, WakeEvent,
+};
+use crate::{
+    Error, OnMonitorTick, OnThreshold, Program, ProgramMessage, RemainingLimits, Report,
+    RestartPolicy, Result, SecurityPolicy, TerminationKind, TerminationReason,
+    DEFAULT_MONITOR_INTERVAL,
+};
 
-use std::sync::mpsc::Receiver;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+// How long the supervisor waits for the group to die after a failure.
+const TERMINATION_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Supervisor {
     limit_checker: LimitChecker,
     process: Process,
+    info: ProcessInfo,
     creation_time: Instant,
     term_reason: Option<TerminationReason>,
-    msg_receiver: Option<Receiver<ProgramMessage>>,
+    control_receiver: Receiver<ProgramMessage>,
+    // Set if the monitor sleeps until an event instead of polling, see
+    // `Program::event_driven_monitor`.
+    wake_event: Option<Arc<WakeEvent>>,
+    // Received messages that are not handled yet.
+    pending_msgs: VecDeque<ProgramMessage>,
+    max_messages_per_tick: usize,
+    // What is left of the limits as of the last check, read by `Run::remaining`.
+    remaining: Arc<Mutex<Option<RemainingLimits>>>,
     monitor_interval: Duration,
+    adaptive_interval: Option<AdaptiveInterval>,
     wait_for_children: bool,
+    stdin_wait: Option<StdinWait>,
+    // The time by which the process must exit after `ProgramMessage::GracefulTerminate`.
+    graceful_termination_deadline: Option<Instant>,
+    // The stdin destination in the dataflow graph, if the data is transmitted to it.
+    stdin: Option<DestinationHandle>,
+    // Whether `ProgramMessage::CloseStdin` is received, but stdin is not closed yet.
+    stdin_close_pending: bool,
+    // The stdout and stderr sources, if the program is terminated once they are disconnected.
+    outputs: Vec<SourceHandle>,
+    restart_policy: RestartPolicy,
+    reset_time_on_restart: bool,
+    reset_counters_on_reset_time: bool,
+    // The time the counters of the group were last reset, as seen by the resource usage.
+    counters_reset_at: Option<Instant>,
+    // The stdio that restarted processes are spawned with. Only kept if the program can be
+    // restarted, since the copies of the pipes delay EOF for the readers.
+    restart_stdio: Option<Stdio>,
+    restarts: usize,
+    samples_taken: usize,
+    sampling_time: Duration,
+    on_tick: Option<Box<dyn OnMonitorTick>>,
+    security_policy: Option<Box<dyn SecurityPolicy>>,
+    // The message of the security policy that terminated the program.
+    security_violation: Option<String>,
+    on_threshold: Option<(f64, Box<dyn OnThreshold>)>,
+    // The limits whose usage has crossed the threshold.
+    crossed_thresholds: Vec<TerminationKind>,
+    // Every process seen in the group, if recorded.
+    process_tree: Option<Vec<ProcessNode>>,
+    // The sorted pids of the group when the process tree was last updated.
+    process_tree_pids: Vec<u32>,
+    // Whether the remote endpoints are recorded.
+    record_remote_endpoints: bool,
+    natural_exit_grace: Option<Duration>,
+    // The time by which the process must exit after a limit is exceeded, if the termination
+    // is delayed by `natural_exit_grace`.
+    natural_exit_deadline: Option<Instant>,
+    // The exit status of the process that exited on its own after a limit was exceeded.
+    natural_exit_status: Option<ExitStatus>,
+    // The time the group is terminated at no matter what, see `Program::max_runtime`. Reset
+    // once the group is terminated.
+    deadline: Option<Instant>,
+}
+
+// Terminates the group if the supervisor panics, so that the processes do not keep running
+// with no limits enforced. On Windows the job object also kills them once it is closed.
+// Both are disabled by `ProcessInfo::kill_on_drop`.
+struct TerminateOnPanic<'a>(&'a Group);
+
+struct StdinWait {
+    // Whether time accounting was stopped by the supervisor, rather than by a message.
+    time_accounting_stopped: bool,
+}
+
+struct AdaptiveInterval {
+    min: Duration,
+    max: Duration,
+    last_activity: Option<(GroupTimers, GroupIo)>,
 }
 
 impl Supervisor {
     pub fn start_monitoring(
-        info: ProcessInfo,
+        prog: Program,
         stdio: Stdio,
-        mut group: Group,
-        limits: ResourceLimits,
-        monitor_interval: Duration,
-        receiver: Option<Receiver<ProgramMessage>>,
-        wait_for_children: bool,
+        stdin: Option<DestinationHandle>,
+        outputs: Vec<SourceHandle>,
+        control_receiver: Receiver<ProgramMessage>,
+        wake_event: Option<Arc<WakeEvent>>,
+        remaining: Arc<Mutex<Option<RemainingLimits>>>,
     ) -> Result<Report> {
-        if let Some(mem_limit) = limits.max_memory_usage {
-            group.set_os_limit(OsLimit::Memory, mem_limit)?;
-        }
-        if let Some(num) = limits.active_processes {
-            group.set_os_limit(OsLimit::ActiveProcess, num as u64)?;
-        }
+        let Program {
+            mut info,
+            group,
+            resource_limits,
+            monitor_interval,
+            adaptive_interval,
+            wait_for_children,
+            pause_time_on_stdin_wait,
+            max_messages_per_tick,
+            restart_policy,
+            reset_time_on_restart,
+            reset_counters_on_reset_time,
+            on_tick,
+            security_policy,
+            on_threshold,
+            record_process_tree,
+            record_remote_endpoints,
+            natural_exit_grace,
+            max_runtime,
+            absolute_deadline,
+            ..
+        } = prog;
+        let limits = resource_limits.unwrap_or_default();
+        // The limits of a given group are set by `Session::run`, since it may be shared.
+        let group = match group {
+            Some(g) => g,
+            None => {
+                let group = Group::new()?;
+                limits.set_os_limits(&group)?;
+                Arc::new(group)
+            }
+        };
+        let restart_stdio = match restart_policy {
+            RestartPolicy::Never => None,
+            _ => Some(stdio.try_clone()?),
+        };
 
-        Process::spawn_in_group(info, stdio, &mut group)
+        let deadline = match (max_runtime.map(|t| Instant::now() + t), absolute_deadline) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+
+        Process::spawn_in_group(&mut info, stdio, &group)
             .map(|ps| Self {
                 limit_checker: LimitChecker::new(limits),
                 process: ps,
+                info,
                 creation_time: Instant::now(),
                 term_reason: None,
-                msg_receiver: receiver,
-                monitor_interval,
-                wait_for_children,
+                control_receiver,
+                wake_event,
+                pending_msgs: VecDeque::new(),
+                max_messages_per_tick,
+                remaining,
+                monitor_interval: match adaptive_interval {
+                    Some((min, _)) => min,
+                    None => monitor_interval.unwrap_or(DEFAULT_MONITOR_INTERVAL),
+                },
+                adaptive_interval: adaptive_interval.map(|(min, max)| AdaptiveInterval {
+                    min,
+                    max,
+                    last_activity: None,
+                }),
+                wait_for_children: wait_for_children.unwrap_or(false),
+                stdin_wait: if pause_time_on_stdin_wait {
+                    Some(StdinWait {
+                        time_accounting_stopped: false,
+                    })
+                } else {
+                    None
+                },
+                graceful_termination_deadline: None,
+                stdin,
+                stdin_close_pending: false,
+                outputs,
+                restart_policy,
+                reset_time_on_restart,
+                reset_counters_on_reset_time,
+                counters_reset_at: None,
+                restart_stdio,
+                restarts: 0,
+                samples_taken: 0,
+                sampling_time: Duration::from_millis(0),
+                on_tick,
+                security_policy,
+                security_violation: None,
+                on_threshold,
+                crossed_thresholds: Vec::new(),
+                process_tree: if record_process_tree {
+                    Some(Vec::new())
+                } else {
+                    None
+                },
+                process_tree_pids: Vec::new(),
+                record_remote_endpoints,
+                natural_exit_grace,
+                natural_exit_deadline: None,
+                natural_exit_status: None,
+                deadline,
             })
             .and_then(|pm| pm.monitoring_loop(group))
     }
 
-    fn monitoring_loop(mut self, group: Group) -> Result<Report> {
+    fn monitoring_loop(mut self, group: Arc<Group>) -> Result<Report> {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!("supervisor", pid = self.process.pid()).entered();
+        trace_event!(DEBUG, "monitoring started");
+
+        let _guard = if self.info.is_kill_on_drop() {
+            Some(TerminateOnPanic(&group))
+        } else {
+            None
+        };
         let mut usage = ResourceUsage::new(&group);
+        self.monitor(&group, &mut usage).or_else(|e| {
+            trace_event!(
+                WARN,
+                elapsed = ?self.creation_time.elapsed(),
+                error = %e,
+                "monitoring failed"
+            );
+            // Limits can't be enforced anymore, so the group must not outlive the supervisor.
+            if self.info.is_kill_on_drop() {
+                let _ = group.terminate_and_wait(TERMINATION_TIMEOUT);
+            }
+            Ok(self.partial_report(&usage, e))
+        })
+    }
+
+    fn monitor(&mut self, group: &Group, usage: &mut ResourceUsage) -> Result<Report> {
         let mut last_check_time = Instant::now();
         loop {
+            let sample_start = Instant::now();
+            self.samples_taken += 1;
             usage.update()?;
-            if let Some(report) = self.get_report(&group, &usage)? {
+            self.check_counters_reset(usage);
+            self.update_process_tree(group)?;
+            if self.record_remote_endpoints {
+                usage.remote_endpoints()?;
+            }
+            if let Some(mut report) = self.get_report(group, usage)? {
+                self.sampling_time += sample_start.elapsed();
+                report.sampling_time = self.sampling_time;
                 return Ok(report);
             }
+            self.check_graceful_termination(group)?;
+            self.check_natural_exit_deadline(group)?;
+            self.check_deadline(group)?;
+            self.check_outputs(group)?;
+            self.close_stdin_if_pending();
 
-            if last_check_time.elapsed() > self.monitor_interval {
+            if last_check_time.elapsed() > self.check_interval() {
                 last_check_time = Instant::now();
-                if let Some(tr) = self.check_limits(&group, &usage)? {
-                    group.terminate()?;
-                    self.term_reason = Some(tr);
+                self.check_stdin_wait()?;
+                if self.term_reason.is_none() {
+                    if let Some(tr) = self.check_limits(group, usage)? {
+                        self.trace_termination(tr);
+                        match self.natural_exit_grace {
+                            Some(grace) => {
+                                self.natural_exit_deadline = Some(Instant::now() + grace)
+                            }
+                            None => group.terminate()?,
+                        }
+                        self.term_reason = Some(tr);
+                    }
+                    *self.remaining.lock().unwrap() = Some(self.limit_checker.remaining());
                 }
+                self.check_security_policy(group, usage)?;
+                self.check_thresholds(usage);
+                self.notify_tick(usage);
+                self.adapt_interval(usage)?;
             }
 
-            self.handle_messages(&group)?;
-            thread::sleep(Duration::from_millis(1));
+            self.handle_messages(group)?;
+            self.sampling_time += sample_start.elapsed();
+            self.wait_for_message()?;
         }
     }
 
+    /// Sleeps for the monitor interval, waking up early if a message arrives, so that a
+    /// backed-off interval does not delay termination. The messages of
+    /// `Program::msg_receiver` are forwarded to the control channel, so both are waited on.
+    /// The event-driven monitor also wakes up once the main process exits.
+    fn wait_for_message(&mut self) -> Result<()> {
+        let sleep_duration = self.sleep_duration();
+        if let Some(ref event) = self.wake_event {
+            // Once the main process has exited, e.g. while its children are waited for,
+            // only the messages can wake the monitor up.
+            if self.process.exit_status()?.is_none() {
+                self.process.wait_or_wake(event, sleep_duration)?;
+                self.pending_msgs.extend(self.control_receiver.try_iter());
+                return Ok(());
+            }
+        }
+        match self.control_receiver.recv_timeout(sleep_duration) {
+            Ok(msg) => self.pending_msgs.push_back(msg),
+            Err(RecvTimeoutError::Timeout) => {}
+            // The run was dropped without being waited for, so no message can arrive and
+            // only the exit of the main process ends the sleep early.
+            Err(RecvTimeoutError::Disconnected) => {
+                if self.process.exit_status()?.is_none() {
+                    self.process.wait(Some(sleep_duration))?;
+                } else {
+                    thread::sleep(sleep_duration);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_counters_reset(&mut self, usage: &ResourceUsage) {
+        if usage.counters_reset_at() != self.counters_reset_at {
+            self.counters_reset_at = usage.counters_reset_at();
+            self.limit_checker.reset_counters();
+        }
+    }
+
+    fn check_graceful_termination(&mut self, group: &Group) -> Result<()> {
+        match self.graceful_termination_deadline {
+            Some(deadline) if self.term_reason.is_none() && Instant::now() >= deadline => {
+                self.trace_termination(TerminationReason::TerminatedByRunner);
+                group.terminate()?;
+                self.term_reason = Some(TerminationReason::TerminatedByRunner);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn check_natural_exit_deadline(&mut self, group: &Group) -> Result<()> {
+        match self.natural_exit_deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                group.terminate()?;
+                self.natural_exit_deadline = None;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn check_deadline(&mut self, group: &Group) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                // The termination might be delayed or might have failed to kill the group.
+                if self.term_reason.is_none() {
+                    self.trace_termination(TerminationReason::DeadlineExceeded);
+                    self.term_reason = Some(TerminationReason::DeadlineExceeded);
+                }
+                group.terminate()?;
+                self.natural_exit_deadline = None;
+                self.deadline = None;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn check_outputs(&mut self, group: &Group) -> Result<()> {
+        if self.term_reason.is_none() && self.outputs.iter().any(SourceHandle::is_disconnected) {
+            self.trace_termination(TerminationReason::OutputConsumerGone);
+            group.terminate()?;
+            self.term_reason = Some(TerminationReason::OutputConsumerGone);
+        }
+        Ok(())
+    }
+
+    fn close_stdin_if_pending(&mut self) {
+        if !self.stdin_close_pending {
+            return;
+        }
+        // Closing is retried on the next tick if a source is writing to stdin, since the
+        // supervisor must not block on a child that does not read its input.
+        let closed = match self.stdin {
+            Some(ref stdin) => stdin.try_close(),
+            None => true,
+        };
+        if closed {
+            self.stdin = None;
+            self.stdin_close_pending = false;
+        }
+    }
+
+    fn check_stdin_wait(&mut self) -> Result<()> {
+        let stdin_wait = match self.stdin_wait {
+            Some(ref mut w) => w,
+            None => return Ok(()),
+        };
+        if self.process.is_waiting_on_stdin()? {
+            if !self.limit_checker.is_time_accounting_stopped() {
+                self.limit_checker.stop_time_accounting();
+                stdin_wait.time_accounting_stopped = true;
+            }
+        } else if stdin_wait.time_accounting_stopped {
+            self.limit_checker.resume_time_accounting();
+            stdin_wait.time_accounting_stopped = false;
+        }
+        Ok(())
+    }
+
+    fn adapt_interval(&mut self, usage: &ResourceUsage) -> Result<()> {
+        let adaptive_interval = match self.adaptive_interval {
+            Some(ref mut i) => i,
+            None => return Ok(()),
+        };
+        let timers = usage.timers()?.unwrap_or_default();
+        let io = usage.io()?.unwrap_or_default();
+        let is_active = match adaptive_interval.last_activity {
+            Some((last_timers, last_io)) => {
+                timers.total_user_time != last_timers.total_user_time
+                    || timers.total_kernel_time != last_timers.total_kernel_time
+                    || io.total_bytes_written != last_io.total_bytes_written
+                    || io.total_bytes_read != last_io.total_bytes_read
+            }
+            None => true,
+        };
+        adaptive_interval.last_activity = Some((timers, io));
+        self.monitor_interval = if is_active {
+            adaptive_interval.min
+        } else {
+            let next = std::cmp::max(self.monitor_interval * 2, Duration::from_millis(1));
+            std::cmp::min(next, adaptive_interval.max)
+        };
+        Ok(())
+    }
+
+    fn check_interval(&self) -> Duration {
+        // Do not wait past the nearest limit, otherwise it would be enforced too late.
+        match self.limit_checker.time_until_limit() {
+            Some(t) => std::cmp::min(self.monitor_interval, t),
+            None => self.monitor_interval,
+        }
+    }
+
+    fn sleep_duration(&self) -> Duration {
+        let min_sleep = Duration::from_millis(1);
+        // Once terminated, the exit must be noticed as soon as possible.
+        if (self.adaptive_interval.is_none() && self.wake_event.is_none())
+            || self.term_reason.is_some()
+            || self.graceful_termination_deadline.is_some()
+            || self.natural_exit_deadline.is_some()
+        {
+            return min_sleep;
+        }
+        let sleep = match self.deadline {
+            Some(deadline) => std::cmp::min(
+                self.check_interval(),
+                deadline.saturating_duration_since(Instant::now()),
+            ),
+            None => self.check_interval(),
+        };
+        std::cmp::max(sleep, min_sleep)
+    }
+
     fn check_limits(
         &mut self,
         group: &Group,
         usage: &ResourceUsage,
     ) -> Result<Option<TerminationReason>> {
-        if group.is_os_limit_hit(OsLimit::Memory)? {
-            return Ok(Some(TerminationReason::MemoryLimitExceeded));
-        }
-        if group.is_os_limit_hit(OsLimit::ActiveProcess)? {
-            return Ok(Some(TerminationReason::ActiveProcessLimitExceeded));
+        for &limit in [OsLimit::Memory, OsLimit::Swap, OsLimit::ActiveProcess].iter() {
+            if group.is_os_limit_hit(limit)? {
+                return self.limit_checker.os_limit_exceeded(limit, usage).map(Some);
+            }
         }
         self.limit_checker.check(usage)
     }
@@ -96,48 +488,367 @@ impl Supervisor {
             return Ok(None);
         }
 
+        // The process exited before the termination that a limit was delayed for.
+        if self.natural_exit_deadline.take().is_some() {
+            self.natural_exit_status = Some(exit_status.clone());
+        }
         if self.term_reason.is_none() {
             self.term_reason = self.check_limits(group, usage)?;
+            if self.term_reason.is_some() && self.natural_exit_grace.is_some() {
+                self.natural_exit_status = Some(exit_status.clone());
+            }
+            if self.term_reason.is_none() && self.graceful_termination_deadline.is_some() {
+                self.term_reason = Some(TerminationReason::GracefullyTerminatedByRunner);
+            }
+            if self.term_reason.is_none() && self.should_restart(&exit_status) {
+                self.restart(group)?;
+                return Ok(None);
+            }
+            if let Some(tr) = self.term_reason {
+                self.trace_termination(tr);
+            }
+        }
+        trace_event!(
+            DEBUG,
+            elapsed = ?self.creation_time.elapsed(),
+            exit_status = ?exit_status,
+            "process exited"
+        );
+
+        self.report(usage, exit_status).map(Some)
+    }
+
+    fn should_restart(&self, exit_status: &ExitStatus) -> bool {
+        let max = match self.restart_policy {
+            RestartPolicy::Never => return false,
+            RestartPolicy::OnFailure { .. } if *exit_status == ExitStatus::Finished(0) => {
+                return false
+            }
+            RestartPolicy::OnFailure { max } | RestartPolicy::Always { max } => max,
+        };
+        self.restarts < max
+    }
+
+    fn restart(&mut self, group: &Group) -> Result<()> {
+        let stdio = self.restart_stdio.as_ref().unwrap().try_clone()?;
+        self.process = Process::spawn_in_group(&mut self.info, stdio, group)?;
+        self.restarts += 1;
+        if self.reset_time_on_restart {
+            self.limit_checker.reset_time();
         }
+        trace_event!(
+            INFO,
+            elapsed = ?self.creation_time.elapsed(),
+            restarts = self.restarts,
+            "process restarted"
+        );
+        Ok(())
+    }
 
-        Ok(Some(Report {
+    fn report(&self, usage: &ResourceUsage, exit_status: ExitStatus) -> Result<Report> {
+        let process_tree = self.process_tree.clone().map(|mut tree| {
+            if let ExitStatus::Finished(code) = exit_status {
+                let pid = self.process.pid();
+                if let Some(node) = tree.iter_mut().find(|n| n.pid == pid) {
+                    node.exit_code = Some(code);
+                }
+            }
+            tree
+        });
+        Ok(Report {
             wall_clock_time: self.creation_time.elapsed(),
             memory: usage.memory()?,
             io: usage.io()?,
             timers: usage.timers()?,
-            pid_counters,
+            pid_counters: usage.pid_counters()?,
             network: usage.network()?,
             exit_status,
             termination_reason: self.term_reason,
-        }))
+            error: None,
+            restarts: self.restarts,
+            samples_taken: self.samples_taken,
+            sampling_time: self.sampling_time,
+            peak_open_handles: self.limit_checker.peak_open_handles(),
+            idle_time: self.limit_checker.idle_time(),
+            cpu_load: self.limit_checker.cpu_load(),
+            security_violation: self.security_violation.clone(),
+            process_tree,
+            remote_endpoints: if self.record_remote_endpoints {
+                Some(usage.remote_endpoints()?)
+            } else {
+                None
+            },
+            natural_exit_status: self.natural_exit_status.clone(),
+            counters_reset_at: self.counters_reset_at(),
+        })
     }
 
-    fn handle_messages(&mut self, group: &Group) -> Result<()> {
-        let receiver = match &mut self.msg_receiver {
-            Some(r) => r,
+    /// Takes the snapshot passed to the callbacks. If a counter can't be read, the callbacks
+    /// are skipped for the tick rather than ending the monitoring.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn live_report(&self, usage: &ResourceUsage) -> Option<Report> {
+        self.report(usage, ExitStatus::Running)
+            .map_err(|e| {
+                trace_event!(
+                    WARN,
+                    elapsed = ?self.creation_time.elapsed(),
+                    error = %e,
+                    "failed to take a snapshot of the report"
+                );
+            })
+            .ok()
+    }
+
+    fn counters_reset_at(&self) -> Option<Duration> {
+        self.counters_reset_at
+            .map(|t| t.saturating_duration_since(self.creation_time))
+    }
+
+    /// Builds a report from the counters that can still be read after `error`.
+    fn partial_report(&mut self, usage: &ResourceUsage, error: Error) -> Report {
+        Report {
+            wall_clock_time: self.creation_time.elapsed(),
+            memory: usage.memory().unwrap_or(None),
+            io: usage.io().unwrap_or(None),
+            timers: usage.timers().unwrap_or(None),
+            pid_counters: usage.pid_counters().unwrap_or(None),
+            network: usage.network().unwrap_or(None),
+            exit_status: self
+                .process
+                .exit_status()
+                .unwrap_or(None)
+                .unwrap_or(ExitStatus::Running),
+            termination_reason: self.term_reason,
+            error: Some(error.to_string()),
+            restarts: self.restarts,
+            samples_taken: self.samples_taken,
+            sampling_time: self.sampling_time,
+            peak_open_handles: self.limit_checker.peak_open_handles(),
+            idle_time: self.limit_checker.idle_time(),
+            cpu_load: self.limit_checker.cpu_load(),
+            security_violation: self.security_violation.clone(),
+            process_tree: self.process_tree.clone(),
+            remote_endpoints: if self.record_remote_endpoints {
+                usage.remote_endpoints().ok()
+            } else {
+                None
+            },
+            natural_exit_status: None,
+            counters_reset_at: self.counters_reset_at(),
+        }
+    }
+
+    fn update_process_tree(&mut self, group: &Group) -> Result<()> {
+        let tree = match self.process_tree {
+            Some(ref mut tree) => tree,
+            None => return Ok(()),
+        };
+        // Listing the processes is expensive, so they are only listed once the pids change.
+        let mut pids = group.pids()?;
+        pids.sort_unstable();
+        if pids == self.process_tree_pids {
+            return Ok(());
+        }
+        for node in group.process_nodes()? {
+            // A pid may be reused by a process that is not a descendant of the previous one.
+            match tree
+                .iter_mut()
+                .find(|n| n.pid == node.pid && n.start_time == node.start_time)
+            {
+                Some(n) => n.exit_code = n.exit_code.or(node.exit_code),
+                None => tree.push(node),
+            }
+        }
+        self.process_tree_pids = pids;
+        Ok(())
+    }
+
+    fn check_security_policy(&mut self, group: &Group, usage: &ResourceUsage) -> Result<()> {
+        if self.security_policy.is_none() || self.term_reason.is_some() {
+            return Ok(());
+        }
+        let report = match self.live_report(usage) {
+            Some(report) => report,
             None => return Ok(()),
         };
-        for msg in receiver.try_iter().take(10) {
-            match msg {
-                ProgramMessage::Terminate => {
-                    group.terminate()?;
-                    self.term_reason = Some(TerminationReason::TerminatedByRunner);
+        let policy = self.security_policy.as_mut().unwrap();
+        let violation = match panic::catch_unwind(AssertUnwindSafe(|| policy.check(&report))) {
+            Ok(violation) => violation,
+            Err(_) => Some("Security policy panicked".to_string()),
+        };
+        if let Some(msg) = violation {
+            self.trace_termination(TerminationReason::SecurityViolation);
+            group.terminate()?;
+            self.term_reason = Some(TerminationReason::SecurityViolation);
+            self.security_violation = Some(msg);
+        }
+        Ok(())
+    }
+
+    fn notify_tick(&mut self, usage: &ResourceUsage) {
+        if self.on_tick.is_none() {
+            return;
+        }
+        let report = match self.live_report(usage) {
+            Some(report) => report,
+            None => return,
+        };
+        let on_tick = self.on_tick.as_mut().unwrap();
+        // A panicking callback must not take down the supervisor, so it is just dropped.
+        if panic::catch_unwind(AssertUnwindSafe(|| on_tick.on_tick(&report))).is_err() {
+            self.on_tick = None;
+        }
+    }
+
+    fn check_thresholds(&mut self, usage: &ResourceUsage) {
+        let fraction = match self.on_threshold {
+            Some((fraction, _)) if self.term_reason.is_none() => fraction,
+            _ => return,
+        };
+        let crossed = self
+            .limit_checker
+            .limit_usage()
+            .iter()
+            .filter(|&&(kind, f)| f >= fraction && !self.crossed_thresholds.contains(&kind))
+            .map(|&(kind, _)| kind)
+            .collect::<Vec<_>>();
+        if crossed.is_empty() {
+            return;
+        }
+        // Without a snapshot the thresholds are not marked as crossed, so the callback is
+        // retried on the next tick.
+        let report = match self.live_report(usage) {
+            Some(report) => report,
+            None => return,
+        };
+        let on_threshold = &mut self.on_threshold.as_mut().unwrap().1;
+        for &kind in crossed.iter() {
+            if panic::catch_unwind(AssertUnwindSafe(|| {
+                on_threshold.on_threshold(&report, kind)
+            }))
+            .is_err()
+            {
+                self.on_threshold = None;
+                break;
+            }
+        }
+        self.crossed_thresholds.extend(crossed);
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn trace_termination(&self, reason: TerminationReason) {
+        trace_event!(
+            INFO,
+            elapsed = ?self.creation_time.elapsed(),
+            reason = ?reason,
+            "terminating process group"
+        );
+    }
+
+    fn handle_messages(&mut self, group: &Group) -> Result<()> {
+        self.pending_msgs.extend(self.control_receiver.try_iter());
+
+        // The messages queued before termination have no effect anyway.
+        if self
+            .pending_msgs
+            .iter()
+            .any(|msg| matches!(msg, ProgramMessage::Terminate))
+        {
+            self.pending_msgs.clear();
+            return self.handle_message(group, ProgramMessage::Terminate);
+        }
+
+        let count = std::cmp::min(self.max_messages_per_tick, self.pending_msgs.len());
+        let msgs = self.pending_msgs.drain(..count).collect::<Vec<_>>();
+        for msg in msgs {
+            self.handle_message(group, msg)?;
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn handle_message(&mut self, group: &Group, msg: ProgramMessage) -> Result<()> {
+        trace_event!(
+            DEBUG,
+            elapsed = ?self.creation_time.elapsed(),
+            message = ?msg,
+            "message received"
+        );
+        match msg {
+            ProgramMessage::Terminate => {
+                self.trace_termination(TerminationReason::TerminatedByRunner);
+                group.terminate()?;
+                self.term_reason = Some(TerminationReason::TerminatedByRunner);
+            }
+            ProgramMessage::GracefulTerminate { timeout } => {
+                if self.graceful_termination_deadline.is_none() {
+                    group.interrupt()?;
+                    self.graceful_termination_deadline = Some(Instant::now() + timeout);
                 }
-                ProgramMessage::Suspend => {
-                    if self.process.exit_status()?.is_none() {
-                        self.process.suspend()?;
-                    }
+            }
+            ProgramMessage::Signal(signal) => {
+                // An invalid signal must not end the monitoring, see `ProgramMessage::signal`.
+                if let Err(e) = group.signal(signal) {
+                    trace_event!(
+                        WARN,
+                        elapsed = ?self.creation_time.elapsed(),
+                        signal,
+                        error = %e,
+                        "failed to signal the process group"
+                    );
+                }
+            }
+            ProgramMessage::Suspend => {
+                if self.process.exit_status()?.is_none() {
+                    self.process.suspend()?;
                 }
-                ProgramMessage::Resume => {
-                    if self.process.exit_status()?.is_none() {
-                        self.process.resume()?;
+            }
+            ProgramMessage::Resume => {
+                if self.process.exit_status()?.is_none() {
+                    self.process.resume()?;
+                }
+            }
+            ProgramMessage::CloseStdin => {
+                self.stdin_close_pending = true;
+                self.close_stdin_if_pending();
+            }
+            ProgramMessage::ResetTime => {
+                self.limit_checker.reset_time();
+                // Like a failed peak reset below, the counters are only kept, so the
+                // limits can still be enforced.
+                if self.reset_counters_on_reset_time {
+                    if let Err(e) = group.reset_counters() {
+                        trace_event!(
+                            WARN,
+                            elapsed = ?self.creation_time.elapsed(),
+                            error = %e,
+                            "failed to reset the counters"
+                        );
                     }
                 }
-                ProgramMessage::ResetTime => self.limit_checker.reset_time(),
-                ProgramMessage::StopTimeAccounting => self.limit_checker.stop_time_accounting(),
-                ProgramMessage::ResumeTimeAccounting => self.limit_checker.resume_time_accounting(),
             }
+            ProgramMessage::ResetPeakMemory => {
+                // The usage is still accounted, only the peak is not reset.
+                if let Err(e) = group.reset_peak_memory() {
+                    trace_event!(
+                        WARN,
+                        elapsed = ?self.creation_time.elapsed(),
+                        error = %e,
+                        "failed to reset the peak memory"
+                    );
+                }
+            }
+            ProgramMessage::StopTimeAccounting => self.limit_checker.stop_time_accounting(),
+            ProgramMessage::ResumeTimeAccounting => self.limit_checker.resume_time_accounting(),
         }
         Ok(())
     }
 }
+
+impl<'a> Drop for TerminateOnPanic<'a> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let _ = self.0.terminate_and_wait(TERMINATION_TIMEOUT);
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use crate::process::{GroupMemory, OsLimit};
+use crate::{Error, Result};
+
+use cgroups_fs::{Cgroup, CgroupName};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const CGROUP_MOUNT_POINT: &str = "/sys/fs/cgroup";
+
+// The cgroup that must be created (and delegated to the current user) before running
+// spawner, see create_cgroups.sh.
+const SPAWNER_CGROUP: &str = "sp";
+
+/// The backend that is used to account and limit resources of a process group.
+pub enum ControlGroup {
+    /// Cgroup v1, each controller has its own hierarchy.
+    V1 {
+        memory: Cgroup,
+        cpuacct: Cgroup,
+        pids: Cgroup,
+        freezer: Cgroup,
+    },
+    /// Cgroup v2 (unified hierarchy).
+    V2(Cgroup),
+    /// Cgroups are not mounted or not delegated. Resources are accounted through procfs
+    /// and all limits are checked in software.
+    Procfs(ProcessTree),
+}
+
+/// A process tree that is rooted at the processes added to a group.
+///
+/// Processes that are reparented (e.g. daemons) are lost, and resources used by the dead
+/// processes are accounted only if they were waited for.
+pub struct ProcessTree {
+    roots: Mutex<Vec<Pid>>,
+    max_memory_usage: Mutex<u64>,
+    page_fault_count: Mutex<u64>,
+    // The total user and kernel time.
+    cpu_times: Mutex<(Duration, Duration)>,
+}
+
+impl ControlGroup {
+    pub fn new() -> Result<Self> {
+        let mount_point = Path::new(CGROUP_MOUNT_POINT);
+        if mount_point.join("memory").join(SPAWNER_CGROUP).is_dir() {
+            Ok(ControlGroup::V1 {
+                memory: create_cgroup("memory/sp")?,
+                cpuacct: create_cgroup("cpuacct/sp")?,
+                pids: create_cgroup("pids/sp")?,
+                freezer: create_cgroup("freezer/sp")?,
+            })
+        } else if mount_point.join("cgroup.controllers").is_file()
+            && mount_point.join(SPAWNER_CGROUP).is_dir()
+        {
+            Ok(ControlGroup::V2(create_cgroup(SPAWNER_CGROUP)?))
+        } else {
+            Ok(ControlGroup::Procfs(ProcessTree::new()))
+        }
+    }
+
+    /// Moves the current process to the control group. Called in the child process
+    /// before exec.
+    pub fn add_pid(&self, pid: Pid) -> io::Result<()> {
+        match self {
+            ControlGroup::V1 {
+                memory,
+                cpuacct,
+                pids,
+                freezer,
+            } => memory
+                .set_value("tasks", pid.as_raw())
+                .and(cpuacct.set_value("tasks", pid.as_raw()))
+                .and(pids.set_value("tasks", pid.as_raw()))
+                .and(freezer.set_value("tasks", pid.as_raw())),
+            ControlGroup::V2(cgroup) => cgroup.set_value("cgroup.procs", pid.as_raw()),
+            ControlGroup::Procfs(_) => Ok(()),
+        }
+    }
+
+    /// Registers a process that was spawned into the control group. Called in the parent
+    /// process.
+    pub fn track_spawned(&self, pid: Pid) {
+        if let ControlGroup::Procfs(tree) = self {
+            tree.roots.lock().unwrap().push(pid);
+        }
+    }
+
+    pub fn add(&self, pid: Pid) -> Result<()> {
+        match self {
+            ControlGroup::Procfs(tree) => {
+                tree.roots.lock().unwrap().push(pid);
+                Ok(())
+            }
+            _ => self.add_pid(pid).map_err(Error::from),
+        }
+    }
+
+    pub fn tasks(&self) -> Result<Vec<Pid>> {
+        match self {
+            ControlGroup::V1 { freezer, .. } => read_pids(freezer, "tasks"),
+            ControlGroup::V2(cgroup) => read_pids(cgroup, "cgroup.procs"),
+            ControlGroup::Procfs(tree) => tree.tasks(),
+        }
+    }
+
+    /// Unlike `tasks`, never contains thread ids.
+    pub fn pids(&self) -> Result<Vec<Pid>> {
+        match self {
+            ControlGroup::V1 { freezer, .. } => read_pids(freezer, "cgroup.procs"),
+            ControlGroup::V2(cgroup) => read_pids(cgroup, "cgroup.procs"),
+            ControlGroup::Procfs(tree) => tree.live_pids(),
+        }
+    }
+
+    pub fn memory(&self) -> Result<GroupMemory> {
+        match self {
+            ControlGroup::V1 { memory, .. } => {
+                let stat = read_memory_stat(memory)?;
+                Ok(GroupMemory {
+                    max_usage: memory.get_value::<u64>("memory.max_usage_in_bytes")?
+                        + ignore_missing_kmem(
+                            memory.get_value::<u64>("memory.kmem.max_usage_in_bytes"),
+                        )?,
+                    working_set_bytes: memory
+                        .get_value::<u64>("memory.usage_in_bytes")?
+                        .saturating_sub(stat("total_inactive_file")),
+                    private_bytes: stat("total_rss"),
+                    page_fault_count: stat("total_pgfault"),
+                    // Present only if swap accounting is enabled.
+                    total_swap_bytes: stat("total_swap"),
+                })
+            }
+            ControlGroup::V2(cgroup) => {
+                let stat = read_memory_stat(cgroup)?;
+                let current = cgroup.get_value::<u64>("memory.current")?;
+                Ok(GroupMemory {
+                    // memory.peak is available since Linux 5.19.
+                    max_usage: cgroup.get_value::<u64>("memory.peak").unwrap_or(current),
+                    working_set_bytes: current.saturating_sub(stat("inactive_file")),
+                    private_bytes: stat("anon"),
+                    page_fault_count: stat("pgfault"),
+                    total_swap_bytes: cgroup.get_value::<u64>("memory.swap.current").unwrap_or(0),
+                })
+            }
+            ControlGroup::Procfs(tree) => tree.memory(),
+        }
+    }
+
+    /// Returns the total user and kernel time.
+    pub fn cpu_times(&self) -> Result<(Duration, Duration)> {
+        match self {
+            ControlGroup::V1 { cpuacct, .. } => Ok((
+                Duration::from_nanos(cpuacct.get_value::<u64>("cpuacct.usage_user")?),
+                Duration::from_nanos(cpuacct.get_value::<u64>("cpuacct.usage_sys")?),
+            )),
+            ControlGroup::V2(cgroup) => Ok((
+                Duration::from_micros(read_keyed_value(cgroup, "cpu.stat", "user_usec")?),
+                Duration::from_micros(read_keyed_value(cgroup, "cpu.stat", "system_usec")?),
+            )),
+            ControlGroup::Procfs(tree) => tree.cpu_times(),
+        }
+    }
+
+    /// Same as `tasks`, but on the procfs fallback also samples the usage that is
+    /// accumulated in software, i.e. the peak memory usage and the cpu times, on the same
+    /// walk of the tree. Control groups keep them in the kernel.
+    pub fn sample_tasks(&self) -> Result<Vec<Pid>> {
+        match self {
+            ControlGroup::Procfs(tree) => tree.sample().map(|(_, _, pids)| pids),
+            _ => self.tasks(),
+        }
+    }
+
+    /// Resets the peak memory usage to the current usage. Returns `false` if the peak can't
+    /// be reset: `memory.peak` of cgroup v2 is only reset for the descriptor it is written
+    /// through.
+    pub fn reset_peak_memory(&self) -> Result<bool> {
+        match self {
+            ControlGroup::V1 { memory, .. } => {
+                memory.set_value("memory.max_usage_in_bytes", 0)?;
+                ignore_missing_kmem(memory.set_value("memory.kmem.max_usage_in_bytes", 0))?;
+                Ok(true)
+            }
+            ControlGroup::V2(_) => Ok(false),
+            ControlGroup::Procfs(tree) => {
+                *tree.max_memory_usage.lock().unwrap() = 0;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Returns `false` if the limit can't be enforced by the OS.
+    pub fn set_os_limit(&self, limit: OsLimit, value: u64) -> Result<bool> {
+        match self {
+            ControlGroup::V1 { memory, pids, .. } => {
+                match limit {
+                    OsLimit::Memory => memory.set_value("memory.limit_in_bytes", value)?,
+                    OsLimit::Swap => {
+                        // memory.memsw limits memory and swap together, so the swap
+                        // limit is checked in software.
+                        if memory.get_raw_value("memory.memsw.usage_in_bytes").is_err() {
+                            return Err(swap_accounting_unavailable());
+                        }
+                        return Ok(false);
+                    }
+                    OsLimit::ActiveProcess => pids.set_value("pids.max", value)?,
+                }
+                Ok(true)
+            }
+            ControlGroup::V2(cgroup) => {
+                let key = match limit {
+                    OsLimit::Memory => "memory.max",
+                    OsLimit::Swap => "memory.swap.max",
+                    OsLimit::ActiveProcess => "pids.max",
+                };
+                match cgroup.set_value(key, value) {
+                    Ok(_) => Ok(true),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => match limit {
+                        OsLimit::Swap => Err(swap_accounting_unavailable()),
+                        // The controller is not enabled for the spawner cgroup.
+                        _ => Ok(false),
+                    },
+                    Err(e) => Err(Error::from(e)),
+                }
+            }
+            ControlGroup::Procfs(_) => Ok(false),
+        }
+    }
+
+    pub fn is_os_limit_hit(&self, limit: OsLimit) -> Result<bool> {
+        match self {
+            ControlGroup::V1 { memory, pids, .. } => match limit {
+                OsLimit::Memory => Ok(memory.get_value::<usize>("memory.failcnt")? > 0),
+                OsLimit::Swap => Ok(false),
+                OsLimit::ActiveProcess => Ok(pids.get_raw_value("pids.events")? != "max 0\n"),
+            },
+            ControlGroup::V2(cgroup) => {
+                let file = match limit {
+                    OsLimit::Memory => "memory.events",
+                    OsLimit::Swap => "memory.swap.events",
+                    OsLimit::ActiveProcess => "pids.events",
+                };
+                match read_keyed_value(cgroup, file, "max") {
+                    Ok(v) => Ok(v > 0),
+                    Err(_) => Ok(false),
+                }
+            }
+            ControlGroup::Procfs(_) => Ok(false),
+        }
+    }
+
+    /// Sends the signal to every process in the control group.
+    pub fn signal(&self, signal: Signal) -> Result<()> {
+        for pid in self.pids()? {
+            // The process may have exited in between.
+            kill(pid, signal).ok();
+        }
+        Ok(())
+    }
+
+    pub fn terminate(&self) -> Result<()> {
+        match self {
+            ControlGroup::V1 { freezer, .. } => {
+                freezer.set_raw_value("freezer.state", "FROZEN")?;
+                while freezer.get_raw_value("freezer.state")? == "FREEZING" {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                kill_all(read_pids(freezer, "tasks")?);
+                freezer.set_raw_value("freezer.state", "THAWED")?;
+                Ok(())
+            }
+            ControlGroup::V2(cgroup) => {
+                // cgroup.kill is available since Linux 5.14.
+                if cgroup.set_raw_value("cgroup.kill", "1").is_ok() {
+                    return Ok(());
+                }
+                cgroup.set_raw_value("cgroup.freeze", "1")?;
+                while read_keyed_value(cgroup, "cgroup.events", "frozen")? == 0 {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                kill_all(read_pids(cgroup, "cgroup.procs")?);
+                cgroup.set_raw_value("cgroup.freeze", "0")?;
+                Ok(())
+            }
+            ControlGroup::Procfs(tree) => {
+                kill_all(tree.tasks()?);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for ControlGroup {
+    fn drop(&mut self) {
+        match self {
+            ControlGroup::V1 {
+                memory,
+                cpuacct,
+                pids,
+                freezer,
+            } => {
+                freezer.remove().ok();
+                memory.remove().ok();
+                cpuacct.remove().ok();
+                pids.remove().ok();
+            }
+            ControlGroup::V2(cgroup) => {
+                cgroup.remove().ok();
+            }
+            ControlGroup::Procfs(_) => {}
+        }
+    }
+}
+
+impl ProcessTree {
+    fn new() -> Self {
+        Self {
+            roots: Mutex::new(Vec::new()),
+            max_memory_usage: Mutex::new(0),
+            page_fault_count: Mutex::new(0),
+            cpu_times: Mutex::new((Duration::from_millis(0), Duration::from_millis(0))),
+        }
+    }
+
+    fn processes(&self) -> Result<Vec<procfs::process::Process>> {
+        let mut children = HashMap::new();
+        for ps in procfs::process::all_processes().map_err(|e| Error::from(e.to_string()))? {
+            children
+                .entry(ps.stat.ppid)
+                .or_insert_with(Vec::new)
+                .push(ps.pid());
+        }
+
+        let mut stack = self
+            .roots
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|pid| pid.as_raw())
+            .collect::<Vec<_>>();
+        let mut processes = Vec::new();
+        while let Some(pid) = stack.pop() {
+            if let Ok(ps) = procfs::process::Process::new(pid) {
+                processes.push(ps);
+            }
+            if let Some(pids) = children.remove(&pid) {
+                stack.extend(pids);
+            }
+        }
+        Ok(processes)
+    }
+
+    fn tasks(&self) -> Result<Vec<Pid>> {
+        Ok(self
+            .processes()?
+            .into_iter()
+            .map(|ps| Pid::from_raw(ps.pid()))
+            .collect())
+    }
+
+    // Zombies are left in the tree until they are reaped, unlike in a control group.
+    fn live_pids(&self) -> Result<Vec<Pid>> {
+        Ok(self
+            .processes()?
+            .into_iter()
+            .filter(|ps| ps.stat.state != 'Z')
+            .map(|ps| Pid::from_raw(ps.pid()))
+            .collect())
+    }
+
+    /// Walks the tree once and updates the peak memory usage, the page faults and the cpu
+    /// times, which are only kept by the kernel for a control group. Also returns the pids
+    /// of the tree.
+    fn sample(&self) -> Result<(GroupMemory, (Duration, Duration), Vec<Pid>)> {
+        let page_size = procfs::page_size()? as u64;
+        let ticks_per_second = procfs::ticks_per_second()? as f64;
+        let mut memory = GroupMemory::default();
+        let (mut user_ticks, mut kernel_ticks) = (0, 0);
+        let mut pids = Vec::new();
+        for ps in self.processes()? {
+            pids.push(Pid::from_raw(ps.pid()));
+            memory.page_fault_count +=
+                ps.stat.minflt + ps.stat.majflt + ps.stat.cminflt + ps.stat.cmajflt;
+            user_ticks += ps.stat.utime + ps.stat.cutime as u64;
+            kernel_ticks += ps.stat.stime + ps.stat.cstime as u64;
+            // The process may exit in between. The shared pages, e.g. of the libraries, are
+            // resident in every process that maps them, while a control group charges them
+            // once, to the group that touched them first. So only the private pages count.
+            if let Ok(statm) = ps.statm() {
+                let private = statm.resident.saturating_sub(statm.shared) * page_size;
+                memory.working_set_bytes += private;
+                memory.private_bytes += private;
+            }
+            if let Ok(status) = ps.status() {
+                memory.total_swap_bytes += status.vmswap.unwrap_or(0) * 1024;
+            }
+        }
+        let mut max_memory_usage = self.max_memory_usage.lock().unwrap();
+        *max_memory_usage = std::cmp::max(*max_memory_usage, memory.working_set_bytes);
+        memory.max_usage = *max_memory_usage;
+        // The faults and the times of the processes that exited are lost, so the sums can
+        // decrease.
+        let mut page_fault_count = self.page_fault_count.lock().unwrap();
+        *page_fault_count = std::cmp::max(*page_fault_count, memory.page_fault_count);
+        memory.page_fault_count = *page_fault_count;
+        let to_duration = |ticks: u64| Duration::from_secs_f64(ticks as f64 / ticks_per_second);
+        let mut cpu_times = self.cpu_times.lock().unwrap();
+        cpu_times.0 = std::cmp::max(cpu_times.0, to_duration(user_ticks));
+        cpu_times.1 = std::cmp::max(cpu_times.1, to_duration(kernel_ticks));
+        Ok((memory, *cpu_times, pids))
+    }
+
+    fn memory(&self) -> Result<GroupMemory> {
+        self.sample().map(|(memory, _, _)| memory)
+    }
+
+    fn cpu_times(&self) -> Result<(Duration, Duration)> {
+        self.sample().map(|(_, cpu_times, _)| cpu_times)
+    }
+}
+
+/// Reads `memory.stat` of the cgroup. Missing keys are treated as zeroes.
+fn read_memory_stat(cgroup: &Cgroup) -> Result<impl Fn(&str) -> u64> {
+    let stat = cgroup
+        .get_raw_value("memory.stat")?
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let key = parts.next()?.to_string();
+            let value = parts.next()?.parse::<u64>().ok()?;
+            Some((key, value))
+        })
+        .collect::<HashMap<_, _>>();
+    Ok(move |key: &str| stat.get(key).cloned().unwrap_or(0))
+}
+
+fn create_cgroup(subsystem: &'static str) -> Result<Cgroup> {
+    let mut rng = thread_rng();
+    let name = format!(
+        "task_{}",
+        (0..7).map(|_| rng.sample(Alphanumeric)).collect::<String>()
+    );
+    let cgroup = Cgroup::new(&CgroupName::new(&name), subsystem);
+    cgroup.create().map_err(|e| {
+        Error::from(format!(
+            "Cannot create cgroup /{}/{}: {}",
+            subsystem, name, e
+        ))
+    })?;
+    Ok(cgroup)
+}
+
+fn read_pids(cgroup: &Cgroup, file: &str) -> Result<Vec<Pid>> {
+    Ok(cgroup
+        .get_raw_value(file)?
+        .split_whitespace()
+        .filter_map(|pid| pid.parse().ok())
+        .map(Pid::from_raw)
+        .collect())
+}
+
+/// Reads the value of `key` from a flat keyed file, e.g. `cpu.stat`.
+fn read_keyed_value(cgroup: &Cgroup, file: &str, key: &str) -> Result<u64> {
+    cgroup
+        .get_raw_value(file)?
+        .lines()
+        .filter_map(|line| {
+            let mut kv = line.split_whitespace();
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k == key => v.parse().ok(),
+                _ => None,
+            }
+        })
+        .next()
+        .ok_or_else(|| Error::from(format!("Cannot find '{}' in {}", key, file)))
+}
+
+// The `memory.kmem.*` files are missing if the kernel doesn't account kernel memory
+// separately.
+fn ignore_missing_kmem<T: Default>(result: io::Result<T>) -> io::Result<T> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        result => result,
+    }
+}
+
+fn swap_accounting_unavailable() -> Error {
+    Error::from("Cannot limit swap usage: swap accounting is not enabled for the memory cgroup")
+}
+
+fn kill_all(pids: Vec<Pid>) {
+    for pid in pids {
+        kill(pid, Signal::SIGKILL).ok();
+    }
+}
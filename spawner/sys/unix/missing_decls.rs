@@ -1,6 +1,7 @@
 use nix::libc::{__u16, __u32, __u64, __u8, c_int, c_ushort};
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct sock_filter {
     pub code: __u16,
     pub jt: __u8,
@@ -22,6 +23,24 @@ pub struct seccomp_data {
     pub args: [__u64; 6],
 }
 
+pub const _LINUX_CAPABILITY_VERSION_3: __u32 = 0x2008_0522;
+
+pub const CAP_SYS_ADMIN: __u32 = 21;
+
+#[repr(C)]
+pub struct __user_cap_header_struct {
+    pub version: __u32,
+    pub pid: c_int,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct __user_cap_data_struct {
+    pub effective: __u32,
+    pub permitted: __u32,
+    pub inheritable: __u32,
+}
+
 pub const AUDIT_ARCH_I386: __u32 = 0x4000_0003;
 pub const AUDIT_ARCH_X86_64: __u32 = 0xC000_003E;
 
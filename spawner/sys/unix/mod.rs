@@ -1,3 +1,4 @@
+mod cgroup;
 pub mod error;
 pub mod pipe;
 pub mod process;
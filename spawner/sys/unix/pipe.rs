@@ -2,12 +2,22 @@ use crate::sys::IntoInner;
 use crate::{Error, Result};
 
 use nix::fcntl::{fcntl, open, FcntlArg, FdFlag, OFlag};
-use nix::sys::stat::Mode;
-use nix::unistd::{close, pipe, read, write};
+use nix::libc::STDOUT_FILENO;
+use nix::sys::stat::{fstat, Mode, SFlag};
+use nix::unistd::{close, dup, pipe, read, write};
+
+#[cfg(feature = "tokio")]
+use tokio::io::unix::AsyncFd;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 use std::io::{self, Read, Write};
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::Path;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
 
 #[derive(Debug)]
 pub struct PipeFd(RawFd);
@@ -18,23 +28,83 @@ pub struct ReadPipe(PipeFd);
 #[derive(Debug)]
 pub struct WritePipe(PipeFd);
 
+/// Registers the descriptor in the tokio reactor and switches it to non-blocking mode
+/// for as long as it stays registered.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+struct AsyncPipeFd {
+    fd: Option<AsyncFd<PipeFd>>,
+    was_nonblocking: bool,
+}
+
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncReadPipe(AsyncPipeFd);
+
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncWritePipe(AsyncPipeFd);
+
 pub fn create() -> Result<(ReadPipe, WritePipe)> {
     let (read_fd, write_fd) = pipe()?;
-    Ok((
-        ReadPipe(PipeFd::new(read_fd)?),
-        WritePipe(PipeFd::new(write_fd)?),
-    ))
+    // Both ends are wrapped before either error is returned, so neither leaks.
+    let read_fd = PipeFd::new(read_fd);
+    let write_fd = PipeFd::new(write_fd);
+    Ok((ReadPipe(read_fd?), WritePipe(write_fd?)))
 }
 
 impl PipeFd {
+    /// Takes ownership of `fd` and sets `FD_CLOEXEC` on it. The descriptor is closed if
+    /// that fails.
     fn new(fd: RawFd) -> Result<Self> {
-        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
-        Ok(Self(fd))
+        let fd = Self(fd);
+        fcntl(fd.0, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        Ok(fd)
+    }
+
+    /// Takes ownership of a descriptor opened elsewhere. Unlike `new`, never fails: the
+    /// descriptor may not support `FD_CLOEXEC`, and is kept open in that case.
+    fn adopt(fd: RawFd) -> Self {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).ok();
+        Self(fd)
     }
 
     pub fn raw(&self) -> RawFd {
         self.0
     }
+
+    fn file_type(&self) -> Option<SFlag> {
+        fstat(self.0)
+            .ok()
+            .map(|stat| SFlag::from_bits_truncate(stat.st_mode & SFlag::S_IFMT.bits()))
+    }
+
+    fn is_file(&self) -> bool {
+        self.file_type() == Some(SFlag::S_IFREG)
+    }
+
+    fn is_pipe(&self) -> bool {
+        self.file_type() == Some(SFlag::S_IFIFO)
+    }
+
+    /// Sets `O_NONBLOCK` on the descriptor, returning whether it was set before.
+    #[cfg(feature = "tokio")]
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<bool> {
+        let flags = OFlag::from_bits_truncate(fcntl(self.0, FcntlArg::F_GETFL)?);
+        let was_nonblocking = flags.contains(OFlag::O_NONBLOCK);
+        if was_nonblocking != nonblocking {
+            let mut new_flags = flags;
+            new_flags.set(OFlag::O_NONBLOCK, nonblocking);
+            fcntl(self.0, FcntlArg::F_SETFL(new_flags))?;
+        }
+        Ok(was_nonblocking)
+    }
+}
+
+impl AsRawFd for PipeFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
 }
 
 impl Drop for PipeFd {
@@ -59,6 +129,18 @@ impl ReadPipe {
         Self::open("/dev/null")
     }
 
+    pub fn try_clone(&self) -> Result<Self> {
+        dup_fd(self.raw()).map(Self)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    pub fn is_pipe(&self) -> bool {
+        self.0.is_pipe()
+    }
+
     fn raw(&self) -> RawFd {
         (self.0).0
     }
@@ -66,7 +148,19 @@ impl ReadPipe {
 
 impl Read for ReadPipe {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        read(self.raw(), buf).map_err(|_| io::Error::last_os_error())
+        read_fd(self.raw(), buf)
+    }
+}
+
+impl AsRawFd for ReadPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.raw()
+    }
+}
+
+impl FromRawFd for ReadPipe {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(PipeFd::adopt(fd))
     }
 }
 
@@ -88,10 +182,37 @@ impl WritePipe {
         .map(Self)
     }
 
+    pub fn open_append<P: AsRef<Path>>(path: P) -> Result<Self> {
+        open(
+            path.as_ref(),
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND | OFlag::O_NOFOLLOW,
+            Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IRGRP,
+        )
+        .map_err(Error::from)
+        .and_then(PipeFd::new)
+        .map(Self)
+    }
+
     pub fn null() -> Result<Self> {
         Self::open("/dev/null")
     }
 
+    pub fn stdout() -> Result<Self> {
+        dup_fd(STDOUT_FILENO).map(Self)
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        dup_fd(self.raw()).map(Self)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.0.is_file()
+    }
+
+    pub fn is_pipe(&self) -> bool {
+        self.0.is_pipe()
+    }
+
     fn raw(&self) -> RawFd {
         (self.0).0
     }
@@ -99,7 +220,7 @@ impl WritePipe {
 
 impl Write for WritePipe {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        write(self.raw(), buf).map_err(|_| io::Error::last_os_error())
+        write_fd(self.raw(), buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -107,8 +228,143 @@ impl Write for WritePipe {
     }
 }
 
+impl AsRawFd for WritePipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.raw()
+    }
+}
+
+impl FromRawFd for WritePipe {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(PipeFd::adopt(fd))
+    }
+}
+
 impl IntoInner<PipeFd> for WritePipe {
     fn into_inner(self) -> PipeFd {
         self.0
     }
 }
+
+fn dup_fd(fd: RawFd) -> Result<PipeFd> {
+    dup(fd).map_err(Error::from).and_then(PipeFd::new)
+}
+
+fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    read(fd, buf).map_err(|_| io::Error::last_os_error())
+}
+
+fn write_fd(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    write(fd, buf).map_err(|_| io::Error::last_os_error())
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncPipeFd {
+    fn new(fd: PipeFd) -> Result<Self> {
+        let was_nonblocking = fd.set_nonblocking(true)?;
+        // Safety: `PipeFd` owns the descriptor and does not close it while registered.
+        match unsafe { AsyncFd::register(fd) } {
+            Ok(fd) => Ok(Self {
+                fd: Some(fd),
+                was_nonblocking,
+            }),
+            Err(e) => {
+                let (fd, e) = e.into_parts();
+                fd.set_nonblocking(was_nonblocking).ok();
+                Err(Error::from(e))
+            }
+        }
+    }
+
+    fn into_inner(mut self) -> PipeFd {
+        let fd = self.fd.take().unwrap().into_inner();
+        fd.set_nonblocking(self.was_nonblocking).ok();
+        fd
+    }
+
+    fn poll_io<F>(&self, cx: &mut Context, read: bool, mut f: F) -> Poll<io::Result<usize>>
+    where
+        F: FnMut(RawFd) -> io::Result<usize>,
+    {
+        let fd = self.fd.as_ref().unwrap();
+        loop {
+            let ready = if read {
+                fd.poll_read_ready(cx)
+            } else {
+                fd.poll_write_ready(cx)
+            };
+            let mut guard = match ready {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Ok(result) = guard.try_io(|fd| f(fd.get_ref().raw())) {
+                return Poll::Ready(result);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for AsyncPipeFd {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd.take() {
+            fd.into_inner().set_nonblocking(self.was_nonblocking).ok();
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncReadPipe {
+    pub fn new(pipe: ReadPipe) -> Result<Self> {
+        AsyncPipeFd::new(pipe.0).map(Self)
+    }
+
+    pub fn into_inner(self) -> ReadPipe {
+        ReadPipe(self.0.into_inner())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRead for AsyncReadPipe {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let unfilled = buf.initialize_unfilled();
+        match self.0.poll_io(cx, true, |fd| read_fd(fd, unfilled)) {
+            Poll::Ready(Ok(n)) => {
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWritePipe {
+    pub fn new(pipe: WritePipe) -> Result<Self> {
+        AsyncPipeFd::new(pipe.0).map(Self)
+    }
+
+    pub fn into_inner(self) -> WritePipe {
+        WritePipe(self.0.into_inner())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWrite for AsyncWritePipe {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.0.poll_io(cx, false, |fd| write_fd(fd, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -1,7 +1,12 @@
 use crate::process::{
-    ExitStatus, GroupIo, GroupMemory, GroupNetwork, GroupPidCounters, GroupTimers, OsLimit,
+    ConsoleMode, EnvMode, ExitStatus, FsSandbox, GroupIo, GroupMemory, GroupNetwork,
+    GroupPidCounters, GroupTimers, OsLimit, Priority, ProcessNode, RestrictOptions,
+};
+use crate::sys::unix::cgroup::ControlGroup;
+use crate::sys::unix::missing_decls::{
+    __user_cap_data_struct, __user_cap_header_struct, sock_fprog, _LINUX_CAPABILITY_VERSION_3,
+    CAP_SYS_ADMIN, SECCOMP_MODE_FILTER,
 };
-use crate::sys::unix::missing_decls::{sock_fprog, SECCOMP_MODE_FILTER};
 use crate::sys::unix::pipe::{PipeFd, ReadPipe, WritePipe};
 use crate::sys::unix::process_ext::SyscallFilter;
 use crate::sys::unix::shared_mem::SharedMem;
@@ -9,33 +14,43 @@ use crate::sys::{AsInnerMut, IntoInner};
 use crate::{Error, Result};
 
 use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
 use nix::libc::{
-    c_ushort, getpwnam, prctl, PR_SET_NO_NEW_PRIVS, PR_SET_SECCOMP, STDERR_FILENO, STDIN_FILENO,
-    STDOUT_FILENO,
+    c_int, c_long, c_ulong, c_ushort, getpwnam, ioctl, prctl, setpriority, syscall, sysconf,
+    SYS_capget, SYS_capset, SYS_pidfd_open, SYS_read, SYS_readv, _SC_NPROCESSORS_ONLN,
+    PRIO_PROCESS, PR_CAPBSET_DROP, PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL, PR_CAP_AMBIENT_LOWER,
+    PR_CAP_AMBIENT_RAISE, PR_SET_NO_NEW_PRIVS, PR_SET_SECCOMP, STDERR_FILENO, STDIN_FILENO,
+    STDOUT_FILENO, TIOCSCTTY,
 };
-use nix::sched::{sched_setaffinity, CpuSet};
+use nix::mount::{mount, MsFlags};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{openpty, Winsize};
+use nix::sched::{sched_setaffinity, unshare, CloneFlags, CpuSet};
+use nix::sys::eventfd::{eventfd, EfdFlags};
 use nix::sys::signal::{kill, raise, Signal};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::{
-    chdir, close, dup2, execve, execvpe, fork, setgroups, setresgid, setresuid, ForkResult, Gid,
-    Pid, Uid,
+    chdir, close, dup, dup2, execve, execvpe, fork, read, setgroups, setresgid, setresuid, setsid,
+    write, ForkResult, Gid, Pid, Uid,
 };
 
-use cgroups_fs::{Cgroup, CgroupName};
-
+use procfs::net::TcpState;
 use procfs::process::FDTarget;
 
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-
 use std::collections::HashMap;
-use std::ffi::{CStr, CString};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Write};
 use std::iter;
 use std::mem;
-use std::os::unix::io::RawFd;
+use std::net::SocketAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::process;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub struct Stdio {
     pub stdin: ReadPipe,
@@ -43,22 +58,33 @@ pub struct Stdio {
     pub stderr: WritePipe,
 }
 
+#[derive(Clone)]
 enum Env {
     Clear,
     Inherit,
+    Set(HashMap<String, String>),
 }
 
+#[derive(Clone)]
 pub struct ProcessInfo {
     app: String,
-    args: Vec<String>,
+    args: Vec<OsString>,
     working_dir: Option<String>,
     suspended: bool,
     search_in_path: bool,
     env: Env,
-    envs: HashMap<String, String>,
+    envs: Vec<(String, String)>,
     username: Option<String>,
     filter: Option<SyscallFilter>,
     cpuset: Option<CpuSet>,
+    cpu_affinity: Option<u64>,
+    priority: Option<Priority>,
+    inherited_fds: Vec<RawFd>,
+    kill_on_drop: bool,
+    pseudo_console: Option<Winsize>,
+    restrict: Option<RestrictOptions>,
+    fs_sandbox: Option<FsSandbox>,
+    deny_network: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -66,7 +92,11 @@ enum InitError {
     Group(Option<nix::Error>),
     Other(nix::Error),
     Impersonate(nix::Error),
+    FsSandbox(nix::Error),
+    DenyNetwork(nix::Error),
+    Capabilities(nix::Error),
     Seccomp(nix::Error),
+    Priority(nix::Error),
     CloseFd,
 }
 
@@ -80,6 +110,17 @@ enum ProcessStatus {
 pub struct Process {
     pid: Pid,
     status: ProcessStatus,
+    pty: Option<PtyForwarding>,
+}
+
+// Stops the threads that copy the stdio of a process to and from its terminal once
+// dropped, see `forward_pty`. The threads are not joined: the input stops right away, and
+// the output is read until every process using the terminal has closed it.
+struct PtyForwarding {
+    // A descriptor of the slave side that keeps the terminal open until the process exits,
+    // so that reading the master does not fail if the process closes its stdio early.
+    slave: Option<WritePipe>,
+    stop: Arc<WakeEvent>,
 }
 
 pub struct ResourceUsage<'a> {
@@ -91,19 +132,32 @@ pub struct ResourceUsage<'a> {
 }
 
 pub struct Group {
-    memory: Cgroup,
-    cpuacct: Cgroup,
-    pids: Cgroup,
-    freezer: Cgroup,
+    cgroup: ControlGroup,
 }
 
+// An eventfd that is readable once woken.
+pub struct WakeEvent(RawFd);
+
 struct DeadTasksInfo {
     num_dead_tasks: usize,
     total_bytes_written: u64,
+    total_bytes_read: u64,
+    total_read_operations: u64,
+    total_write_operations: u64,
+}
+
+#[derive(Copy, Clone, Default)]
+struct TaskIo {
+    bytes_written: u64,
+    bytes_read: u64,
+    read_operations: u64,
+    write_operations: u64,
 }
 
 struct ActiveTasks {
-    wchar_by_pid: HashMap<Pid, u64>,
+    io_by_pid: HashMap<Pid, TaskIo>,
+    num_threads: usize,
+    num_open_fds: usize,
     pid_by_inode: HashMap<u32, Pid>,
 }
 
@@ -113,6 +167,22 @@ struct RawStdio {
     stderr: PipeFd,
 }
 
+// Everything the forked child sets up before `exec`, see `init_child_process`.
+struct ChildInit<'a> {
+    stdio: RawStdio,
+    inherited_fds: &'a [RawFd],
+    working_dir: Option<&'a str>,
+    filter: Option<&'a mut SyscallFilter>,
+    group: Option<&'a Group>,
+    usr: Option<&'a User>,
+    cpuset: Option<&'a CpuSet>,
+    priority: Option<Priority>,
+    controlling_terminal: bool,
+    fs_sandbox: Option<&'a FsSandbox>,
+    deny_network: bool,
+    restrict: Option<&'a RestrictOptions>,
+}
+
 struct User {
     uid: Uid,
     gid: Gid,
@@ -127,20 +197,28 @@ impl ProcessInfo {
             suspended: false,
             search_in_path: true,
             env: Env::Inherit,
-            envs: HashMap::new(),
+            envs: Vec::new(),
             username: None,
             filter: None,
             cpuset: None,
+            cpu_affinity: None,
+            priority: None,
+            inherited_fds: Vec::new(),
+            kill_on_drop: true,
+            pseudo_console: None,
+            restrict: None,
+            fs_sandbox: None,
+            deny_network: false,
         }
     }
 
     pub fn args<T, U>(&mut self, args: T) -> &mut Self
     where
         T: IntoIterator<Item = U>,
-        U: AsRef<str>,
+        U: AsRef<OsStr>,
     {
         self.args
-            .extend(args.into_iter().map(|s| s.as_ref().to_string()));
+            .extend(args.into_iter().map(|s| s.as_ref().to_os_string()));
         self
     }
 
@@ -172,13 +250,12 @@ impl ProcessInfo {
         self
     }
 
-    pub fn env_clear(&mut self) -> &mut Self {
-        self.env = Env::Clear;
-        self
-    }
-
-    pub fn env_inherit(&mut self) -> &mut Self {
-        self.env = Env::Inherit;
+    pub fn env(&mut self, mode: EnvMode) -> &mut Self {
+        self.env = match mode {
+            EnvMode::Clear => Env::Clear,
+            EnvMode::Inherit => Env::Inherit,
+            EnvMode::Set(vars) => Env::Set(vars),
+        };
         self
     }
 
@@ -191,6 +268,72 @@ impl ProcessInfo {
         self
     }
 
+    pub fn cpu_affinity(&mut self, mask: u64) -> &mut Self {
+        self.cpu_affinity = Some(mask);
+        self
+    }
+
+    pub fn priority(&mut self, priority: Priority) -> &mut Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn show_window(&mut self, _show: bool) -> &mut Self {
+        self
+    }
+
+    pub fn interruptible(&mut self, _v: bool) -> &mut Self {
+        self
+    }
+
+    pub fn console(&mut self, _mode: ConsoleMode) -> &mut Self {
+        self
+    }
+
+    pub fn pseudo_console(&mut self, cols: u16, rows: u16) -> &mut Self {
+        self.pseudo_console = Some(Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        });
+        self
+    }
+
+    pub fn restrict_token(&mut self, options: RestrictOptions) -> &mut Self {
+        self.restrict = Some(options);
+        self
+    }
+
+    pub fn filesystem_sandbox(&mut self, sandbox: FsSandbox) -> &mut Self {
+        self.fs_sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn deny_network(&mut self, v: bool) -> &mut Self {
+        self.deny_network = v;
+        self
+    }
+
+    pub fn kill_on_drop(&mut self, v: bool) -> &mut Self {
+        self.kill_on_drop = v;
+        self
+    }
+
+    pub fn is_kill_on_drop(&self) -> bool {
+        self.kill_on_drop
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ref username) = self.username {
+            User::new(username)?;
+        }
+        if let Some(mask) = self.cpu_affinity {
+            affinity_mask_to_cpuset(mask)?;
+        }
+        Ok(())
+    }
+
     pub fn syscall_filter(&mut self, filter: SyscallFilter) -> &mut Self {
         self.filter = Some(filter);
         self
@@ -200,15 +343,109 @@ impl ProcessInfo {
         self.cpuset = Some(cpuset);
         self
     }
+
+    pub fn inherit_fd(&mut self, fd: RawFd) -> &mut Self {
+        self.inherited_fds.push(fd);
+        self
+    }
 }
 
 impl Process {
+    pub fn pid(&self) -> u32 {
+        self.pid.as_raw() as u32
+    }
+
     pub fn exit_status(&mut self) -> Result<Option<ExitStatus>> {
         if let ProcessStatus::Exited(ref status) = self.status {
             return Ok(Some(status.clone()));
         }
 
-        let exit_status = match waitpid(self.pid, Some(WaitPidFlag::WNOHANG))? {
+        let status = waitpid(self.pid, Some(WaitPidFlag::WNOHANG))?;
+        self.on_wait_status(status)
+    }
+
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => loop {
+                if let ProcessStatus::Exited(ref status) = self.status {
+                    return Ok(Some(status.clone()));
+                }
+                match waitpid(self.pid, None) {
+                    Err(nix::Error::Sys(Errno::EINTR)) => {}
+                    status => {
+                        if let Some(status) = self.on_wait_status(status?)? {
+                            return Ok(Some(status));
+                        }
+                    }
+                }
+            },
+        };
+
+        self.wait_with_pidfd(Instant::now() + timeout, None)
+    }
+
+    pub fn wait_or_wake(
+        &mut self,
+        event: &WakeEvent,
+        timeout: Duration,
+    ) -> Result<Option<ExitStatus>> {
+        self.wait_with_pidfd(Instant::now() + timeout, Some(event))
+    }
+
+    fn wait_with_pidfd(
+        &mut self,
+        deadline: Instant,
+        event: Option<&WakeEvent>,
+    ) -> Result<Option<ExitStatus>> {
+        // The pidfd becomes readable once the process exits. Kernels before 5.3 have no
+        // pidfd, and the status is polled instead.
+        let pidfd = unsafe { syscall(SYS_pidfd_open, self.pid.as_raw(), 0) } as RawFd;
+        let result = self.wait_until(deadline, pidfd, event);
+        if pidfd >= 0 {
+            let _ = close(pidfd);
+        }
+        result
+    }
+
+    fn wait_until(
+        &mut self,
+        deadline: Instant,
+        pidfd: RawFd,
+        event: Option<&WakeEvent>,
+    ) -> Result<Option<ExitStatus>> {
+        loop {
+            if let Some(status) = self.exit_status()? {
+                return Ok(Some(status));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            let remaining = if pidfd < 0 {
+                std::cmp::min(deadline - now, Duration::from_millis(1))
+            } else {
+                deadline - now
+            };
+            let mut fds = [pidfd, event.map_or(-1, |e| e.0)]
+                .iter()
+                .filter(|&&fd| fd >= 0)
+                .map(|&fd| PollFd::new(fd, PollFlags::POLLIN))
+                .collect::<Vec<_>>();
+            // Rounded up, otherwise the last millisecond is spent spinning.
+            let ms = std::cmp::min(remaining.as_micros().div_ceil(1000), c_int::MAX as u128);
+            match poll(&mut fds, ms as c_int) {
+                Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Err(e) => return Err(Error::from(e)),
+            }
+            if event.is_some_and(WakeEvent::reset) {
+                return self.exit_status();
+            }
+        }
+    }
+
+    fn on_wait_status(&mut self, status: WaitStatus) -> Result<Option<ExitStatus>> {
+        let exit_status = match status {
             WaitStatus::Exited(pid, code) => {
                 assert_eq!(pid, self.pid);
                 ExitStatus::Finished(code as u32)
@@ -219,6 +456,8 @@ impl Process {
             }
             _ => return Ok(None),
         };
+        // The rest of the output is still read by the forwarding thread.
+        self.pty = None;
 
         // Process has exited. Check initialization result.
         let init_error =
@@ -235,9 +474,20 @@ impl Process {
             InitError::Impersonate(e) => {
                 Err(Error::from(format!("Failed to impersonate user: {}", e)))
             }
+            InitError::FsSandbox(e) => Err(Error::from(format!(
+                "Failed to set up the filesystem sandbox: {}",
+                e
+            ))),
+            InitError::DenyNetwork(e) => {
+                Err(Error::from(format!("Failed to deny network access: {}", e)))
+            }
+            InitError::Capabilities(e) => {
+                Err(Error::from(format!("Failed to drop capabilities: {}", e)))
+            }
             InitError::Seccomp(e) => {
                 Err(Error::from(format!("Failed to initialize seccomp: {}", e)))
             }
+            InitError::Priority(e) => Err(Error::from(format!("Failed to set priority: {}", e))),
             InitError::Group(e) => match e {
                 Some(e) => Err(Error::from(format!(
                     "Failed to add process to cgroup: {}",
@@ -261,17 +511,35 @@ impl Process {
         kill(self.pid, Signal::SIGKILL).map_err(Error::from)
     }
 
+    pub fn is_waiting_on_stdin(&self) -> Result<bool> {
+        // The file holds the number and the arguments of the syscall the process is
+        // blocked in, or "running". It can't be read if the process has already exited
+        // or belongs to another user.
+        let syscall = match fs::read_to_string(format!("/proc/{}/syscall", self.pid)) {
+            Ok(s) => s,
+            Err(_) => return Ok(false),
+        };
+        let mut fields = syscall.split_whitespace();
+        let nr = fields.next().and_then(|s| s.parse::<c_long>().ok());
+        let fd = fields
+            .next()
+            .and_then(|s| c_long::from_str_radix(s.trim_start_matches("0x"), 16).ok());
+        Ok((nr == Some(SYS_read) || nr == Some(SYS_readv)) && fd == Some(STDIN_FILENO as c_long))
+    }
+
     pub fn spawn(info: &mut ProcessInfo, stdio: Stdio) -> Result<Self> {
-        create_process(info, stdio, None).map(|(pid, init_result)| Self {
+        create_process(info, stdio, None).map(|(pid, init_result, pty)| Self {
             pid,
             status: ProcessStatus::Alive(init_result),
+            pty,
         })
     }
 
-    pub fn spawn_in_group(info: &mut ProcessInfo, stdio: Stdio, group: &mut Group) -> Result<Self> {
-        create_process(info, stdio, Some(group)).map(|(pid, init_result)| Self {
+    pub fn spawn_in_group(info: &mut ProcessInfo, stdio: Stdio, group: &Group) -> Result<Self> {
+        create_process(info, stdio, Some(group)).map(|(pid, init_result, pty)| Self {
             pid,
             status: ProcessStatus::Alive(init_result),
+            pty,
         })
     }
 }
@@ -286,24 +554,31 @@ impl<'a> ResourceUsage<'a> {
     }
 
     pub fn update(&mut self) -> Result<()> {
-        let dead_tasks_info = self.active_tasks.update(&self.group.freezer)?;
+        let dead_tasks_info = self
+            .active_tasks
+            .update(self.group.cgroup.sample_tasks()?)?;
         self.dead_tasks_info.num_dead_tasks += dead_tasks_info.num_dead_tasks;
         self.dead_tasks_info.total_bytes_written += dead_tasks_info.total_bytes_written;
+        self.dead_tasks_info.total_bytes_read += dead_tasks_info.total_bytes_read;
+        self.dead_tasks_info.total_read_operations += dead_tasks_info.total_read_operations;
+        self.dead_tasks_info.total_write_operations += dead_tasks_info.total_write_operations;
         Ok(())
     }
 
     pub fn memory(&self) -> Result<Option<GroupMemory>> {
-        let mem = &self.group.memory;
-        Ok(Some(GroupMemory {
-            max_usage: mem.get_value::<u64>("memory.max_usage_in_bytes")?
-                + mem.get_value::<u64>("memory.kmem.max_usage_in_bytes")?,
-        }))
+        self.group.cgroup.memory().map(Some)
     }
 
     pub fn io(&self) -> Result<Option<GroupIo>> {
         Ok(Some(GroupIo {
             total_bytes_written: self.active_tasks.total_bytes_written()
                 + self.dead_tasks_info.total_bytes_written,
+            total_bytes_read: self.active_tasks.total_bytes_read()
+                + self.dead_tasks_info.total_bytes_read,
+            read_operations: self.active_tasks.total_read_operations()
+                + self.dead_tasks_info.total_read_operations,
+            write_operations: self.active_tasks.total_write_operations()
+                + self.dead_tasks_info.total_write_operations,
         }))
     }
 
@@ -311,6 +586,7 @@ impl<'a> ResourceUsage<'a> {
         let active_processes = self.active_tasks.count();
         Ok(Some(GroupPidCounters {
             active_processes,
+            active_threads: self.active_tasks.count_threads(),
             total_processes: self.dead_tasks_info.num_dead_tasks + active_processes,
         }))
     }
@@ -321,84 +597,149 @@ impl<'a> ResourceUsage<'a> {
                 .active_tasks
                 .count_network_connections()
                 .map_err(|e| Error::from(e.to_string()))?,
+            // Accumulated by `process::ResourceUsage`.
+            distinct_remote_endpoints: 0,
         }))
     }
 
+    pub fn remote_endpoints(&self) -> Result<Vec<SocketAddr>> {
+        self.active_tasks
+            .remote_endpoints()
+            .map_err(|e| Error::from(e.to_string()))
+    }
+
+    pub fn open_handles(&self) -> Result<Option<usize>> {
+        Ok(Some(self.active_tasks.count_open_fds()))
+    }
+
     pub fn timers(&self) -> Result<Option<GroupTimers>> {
-        let cpuacct = &self.group.cpuacct;
+        let (total_user_time, total_kernel_time) = self.group.cgroup.cpu_times()?;
         Ok(Some(GroupTimers {
-            total_user_time: Duration::from_nanos(cpuacct.get_value::<u64>("cpuacct.usage_user")?),
-            total_kernel_time: Duration::from_nanos(cpuacct.get_value::<u64>("cpuacct.usage_sys")?),
+            total_user_time,
+            total_kernel_time,
         }))
     }
 }
 
+impl WakeEvent {
+    pub fn new() -> Result<Self> {
+        eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)
+            .map(Self)
+            .map_err(Error::from)
+    }
+
+    pub fn wake(&self) -> Result<()> {
+        write(self.0, &1u64.to_ne_bytes())
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    // Returns whether the event has been woken since the last reset.
+    fn reset(&self) -> bool {
+        let mut counter = [0; 8];
+        read(self.0, &mut counter).is_ok()
+    }
+}
+
+impl Drop for WakeEvent {
+    fn drop(&mut self) {
+        let _ = close(self.0);
+    }
+}
+
+impl Drop for PtyForwarding {
+    fn drop(&mut self) {
+        let _ = self.stop.wake();
+        // Reading and writing the master fail with EIO once the other processes using the
+        // terminal close it too, so neither thread can block forever.
+        self.slave = None;
+    }
+}
+
 impl Group {
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            memory: create_cgroup("memory/sp")?,
-            cpuacct: create_cgroup("cpuacct/sp")?,
-            pids: create_cgroup("pids/sp")?,
-            freezer: create_cgroup("freezer/sp")?,
-        })
+        ControlGroup::new().map(|cgroup| Self { cgroup })
     }
 
-    fn add_pid(&mut self, pid: Pid) -> std::io::Result<()> {
-        self.memory
-            .add_task(pid)
-            .and(self.cpuacct.add_task(pid))
-            .and(self.pids.add_task(pid))
-            .and(self.freezer.add_task(pid))
+    fn add_pid(&self, pid: Pid) -> std::io::Result<()> {
+        self.cgroup.add_pid(pid)
     }
 
-    pub fn add(&mut self, ps: &Process) -> Result<()> {
-        self.add_pid(ps.pid).map_err(Error::from)
+    pub fn add(&self, ps: &Process) -> Result<()> {
+        self.cgroup.add(ps.pid)
     }
 
-    pub fn set_os_limit(&mut self, limit: OsLimit, value: u64) -> Result<bool> {
-        match limit {
-            OsLimit::Memory => {
-                self.memory.set_value("memory.limit_in_bytes", value)?;
-            }
-            OsLimit::ActiveProcess => {
-                self.pids.set_value("pids.max", value)?;
-            }
-        }
-        Ok(true)
+    pub fn set_os_limit(&self, limit: OsLimit, value: u64) -> Result<bool> {
+        self.cgroup.set_os_limit(limit, value)
     }
 
     pub fn is_os_limit_hit(&self, limit: OsLimit) -> Result<bool> {
-        match limit {
-            OsLimit::Memory => Ok(self.memory.get_value::<usize>("memory.failcnt")? > 0),
-            OsLimit::ActiveProcess => Ok(self.pids.get_raw_value("pids.events")? != "max 0\n"),
-        }
+        self.cgroup.is_os_limit_hit(limit)
+    }
+
+    pub fn pids(&self) -> Result<Vec<u32>> {
+        self.cgroup
+            .pids()
+            .map(|pids| pids.into_iter().map(|pid| pid.as_raw() as u32).collect())
+    }
+
+    pub fn process_nodes(&self) -> Result<Vec<ProcessNode>> {
+        Ok(self
+            .cgroup
+            .pids()?
+            .into_iter()
+            // The process may exit in between.
+            .filter_map(|pid| procfs::process::Process::new(pid.as_raw()).ok())
+            .map(|ps| ProcessNode {
+                pid: ps.pid() as u32,
+                parent_pid: ps.stat.ppid as u32,
+                start_time: ps.stat.starttime,
+                image_name: ps.stat.comm.clone(),
+                exit_code: match ps.stat.exit_code {
+                    // The wait status is only set for zombies, and is only an exit code if
+                    // the process was not killed by a signal.
+                    Some(status) if ps.stat.state == 'Z' && status & 0x7f == 0 => {
+                        Some(((status >> 8) & 0xff) as u32)
+                    }
+                    _ => None,
+                },
+            })
+            .collect())
+    }
+
+    pub fn interrupt(&self) -> Result<()> {
+        self.cgroup.signal(Signal::SIGTERM)
+    }
+
+    pub fn signal(&self, signal: i32) -> Result<()> {
+        self.cgroup.signal(to_signal(signal)?)
+    }
+
+    pub fn validate_signal(signal: i32) -> Result<()> {
+        to_signal(signal).map(|_| ())
     }
 
     pub fn terminate(&self) -> Result<()> {
-        self.freezer.set_raw_value("freezer.state", "FROZEN")?;
-        while self.freezer.get_raw_value("freezer.state")? == "FREEZING" {
-            thread::sleep(Duration::from_millis(1));
-        }
-        self.freezer.send_signal_to_all_tasks(Signal::SIGKILL)?;
-        self.freezer.set_raw_value("freezer.state", "THAWED")?;
-        Ok(())
+        self.cgroup.terminate()
     }
-}
 
-impl Drop for Group {
-    fn drop(&mut self) {
-        self.freezer.remove().ok();
-        self.memory.remove().ok();
-        self.cpuacct.remove().ok();
-        self.pids.remove().ok();
+    pub fn reset_peak_memory(&self) -> Result<bool> {
+        self.cgroup.reset_peak_memory()
     }
 }
 
+fn to_signal(signal: i32) -> Result<Signal> {
+    Signal::try_from(signal).map_err(|_| Error::from(format!("Invalid signal {}", signal)))
+}
+
 impl DeadTasksInfo {
     fn new() -> Self {
         Self {
             num_dead_tasks: 0,
             total_bytes_written: 0,
+            total_bytes_read: 0,
+            total_read_operations: 0,
+            total_write_operations: 0,
         }
     }
 }
@@ -406,17 +747,39 @@ impl DeadTasksInfo {
 impl ActiveTasks {
     fn new() -> Self {
         Self {
-            wchar_by_pid: HashMap::new(),
+            io_by_pid: HashMap::new(),
+            num_threads: 0,
+            num_open_fds: 0,
             pid_by_inode: HashMap::new(),
         }
     }
 
     fn count(&self) -> usize {
-        self.wchar_by_pid.len()
+        self.io_by_pid.len()
+    }
+
+    fn count_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    fn count_open_fds(&self) -> usize {
+        self.num_open_fds
     }
 
     fn total_bytes_written(&self) -> u64 {
-        self.wchar_by_pid.values().sum()
+        self.io_by_pid.values().map(|io| io.bytes_written).sum()
+    }
+
+    fn total_bytes_read(&self) -> u64 {
+        self.io_by_pid.values().map(|io| io.bytes_read).sum()
+    }
+
+    fn total_read_operations(&self) -> u64 {
+        self.io_by_pid.values().map(|io| io.read_operations).sum()
+    }
+
+    fn total_write_operations(&self) -> u64 {
+        self.io_by_pid.values().map(|io| io.write_operations).sum()
     }
 
     fn count_network_connections(&self) -> procfs::ProcResult<usize> {
@@ -436,16 +799,32 @@ impl ActiveTasks {
             .count())
     }
 
-    fn update(&mut self, freezer: &Cgroup) -> Result<DeadTasksInfo> {
+    fn remote_endpoints(&self) -> procfs::ProcResult<Vec<SocketAddr>> {
+        Ok(procfs::net::tcp()?
+            .into_iter()
+            .chain(procfs::net::tcp6()?)
+            .filter(|tcp_entry| {
+                self.pid_by_inode.contains_key(&tcp_entry.inode)
+                    && tcp_entry.state != TcpState::Listen
+                    && !tcp_entry.remote_address.ip().is_unspecified()
+            })
+            .map(|tcp_entry| tcp_entry.remote_address)
+            .collect())
+    }
+
+    fn update(&mut self, tasks: Vec<Pid>) -> Result<DeadTasksInfo> {
         self.pid_by_inode.clear();
-        let new_wchar_by_pid = freezer
-            .get_tasks()?
+        self.num_threads = 0;
+        self.num_open_fds = 0;
+        let new_io_by_pid = tasks
             .into_iter()
             .filter_map(|pid| procfs::process::Process::new(pid.as_raw()).ok())
             .map(|ps| {
                 let pid = Pid::from_raw(ps.pid());
+                self.num_threads += ps.stat.num_threads as usize;
 
                 if let Ok(fds) = ps.fd() {
+                    self.num_open_fds += fds.len();
                     self.pid_by_inode
                         .extend(fds.into_iter().filter_map(|fd| match fd.target {
                             FDTarget::Socket(inode) => Some((inode, pid)),
@@ -453,35 +832,51 @@ impl ActiveTasks {
                         }));
                 }
 
-                (pid, ps.io().ok().map(|io| io.wchar))
+                let io = ps
+                    .io()
+                    .map(|io| TaskIo {
+                        bytes_written: io.wchar,
+                        bytes_read: io.read_bytes,
+                        read_operations: io.syscr,
+                        write_operations: io.syscw,
+                    })
+                    .unwrap_or_default();
+                (pid, io)
             })
-            .collect::<HashMap<Pid, Option<u64>>>();
+            .collect::<HashMap<Pid, TaskIo>>();
 
-        let old_wchar_by_pid = &mut self.wchar_by_pid;
-        let dead_tasks = old_wchar_by_pid
+        let old_io_by_pid = &mut self.io_by_pid;
+        let dead_tasks = old_io_by_pid
             .iter_mut()
-            .filter_map(|(pid, wchar)| match new_wchar_by_pid.get(pid) {
-                Some(new_wchar) => {
-                    *wchar = std::cmp::max(*wchar, new_wchar.unwrap_or(0));
+            .filter_map(|(pid, io)| match new_io_by_pid.get(pid) {
+                Some(new_io) => {
+                    io.bytes_written = std::cmp::max(io.bytes_written, new_io.bytes_written);
+                    io.bytes_read = std::cmp::max(io.bytes_read, new_io.bytes_read);
+                    io.read_operations = std::cmp::max(io.read_operations, new_io.read_operations);
+                    io.write_operations =
+                        std::cmp::max(io.write_operations, new_io.write_operations);
                     None
                 }
                 None => Some(*pid),
             })
             .collect::<Vec<Pid>>();
 
-        for (pid, wchar) in new_wchar_by_pid.iter() {
-            if old_wchar_by_pid.get(pid).is_none() {
-                old_wchar_by_pid.insert(*pid, wchar.unwrap_or(0));
+        for (pid, io) in new_io_by_pid.iter() {
+            if old_io_by_pid.get(pid).is_none() {
+                old_io_by_pid.insert(*pid, *io);
             }
         }
 
-        Ok(DeadTasksInfo {
-            num_dead_tasks: dead_tasks.len(),
-            total_bytes_written: dead_tasks
-                .into_iter()
-                .map(|pid| old_wchar_by_pid.remove(&pid).unwrap())
-                .sum(),
-        })
+        let mut dead_tasks_info = DeadTasksInfo::new();
+        for pid in dead_tasks {
+            let io = old_io_by_pid.remove(&pid).unwrap();
+            dead_tasks_info.num_dead_tasks += 1;
+            dead_tasks_info.total_bytes_written += io.bytes_written;
+            dead_tasks_info.total_bytes_read += io.bytes_read;
+            dead_tasks_info.total_read_operations += io.read_operations;
+            dead_tasks_info.total_write_operations += io.write_operations;
+        }
+        Ok(dead_tasks_info)
     }
 }
 
@@ -507,22 +902,6 @@ impl User {
     }
 }
 
-fn create_cgroup(subsystem: &'static str) -> Result<Cgroup> {
-    let mut rng = thread_rng();
-    let name = format!(
-        "task_{}",
-        (0..7).map(|_| rng.sample(Alphanumeric)).collect::<String>()
-    );
-    let cgroup = Cgroup::new(&CgroupName::new(&name), subsystem);
-    cgroup.create().map_err(|e| {
-        Error::from(format!(
-            "Cannot create cgroup /{}/{}: {}",
-            subsystem, name, e
-        ))
-    })?;
-    Ok(cgroup)
-}
-
 fn to_cstr<S: Into<Vec<u8>>>(s: S) -> Result<CString> {
     CString::new(s).map_err(|e| Error::from(e.to_string()))
 }
@@ -531,8 +910,9 @@ fn create_env(info: &ProcessInfo) -> Result<Vec<CString>> {
     let mut env = match info.env {
         Env::Clear => HashMap::new(),
         Env::Inherit => std::env::vars().collect(),
+        Env::Set(ref vars) => vars.clone(),
     };
-    env.extend(info.envs.iter().map(|(k, v)| (k.clone(), v.clone())));
+    env.extend(info.envs.iter().cloned());
 
     env.into_iter()
         .map(|(k, v)| to_cstr(format!("{}={}", k, v)))
@@ -540,8 +920,8 @@ fn create_env(info: &ProcessInfo) -> Result<Vec<CString>> {
 }
 
 fn create_args(info: &ProcessInfo) -> Result<Vec<CString>> {
-    iter::once(info.app.as_str())
-        .chain(info.args.iter().map(|s| s.as_str()))
+    iter::once(info.app.as_bytes())
+        .chain(info.args.iter().map(|s| s.as_bytes()))
         .map(to_cstr)
         .collect()
 }
@@ -566,6 +946,261 @@ fn init_stdio(stdio: RawStdio) -> nix::Result<()> {
     Ok(())
 }
 
+// Makes the terminal on stdin the controlling terminal of the process, which has to lead a
+// new session for that.
+fn init_controlling_terminal() -> nix::Result<()> {
+    setsid()?;
+    if unsafe { ioctl(STDIN_FILENO, TIOCSCTTY, 0) } == -1 {
+        return Err(nix::Error::last());
+    }
+    Ok(())
+}
+
+// Opens a pseudo terminal, and returns the stdio of its slave side and its master side.
+fn open_pty(winsize: &Winsize) -> Result<(Stdio, ReadPipe)> {
+    let pty = openpty(winsize, None)?;
+    let master = unsafe { ReadPipe::from_raw_fd(pty.master) };
+    let stdin = unsafe { ReadPipe::from_raw_fd(pty.slave) };
+    let stdout = unsafe { WritePipe::from_raw_fd(dup(pty.slave)?) };
+    let stderr = unsafe { WritePipe::from_raw_fd(dup(pty.slave)?) };
+    let stdio = Stdio {
+        stdin,
+        stdout,
+        stderr,
+    };
+    Ok((stdio, master))
+}
+
+// Copies the data from stdin to the terminal until the returned value is dropped, and from
+// the terminal to stdout until the terminal is closed. `slave` is the slave side of the
+// terminal, which is kept open meanwhile.
+fn forward_pty(master: ReadPipe, slave: &ReadPipe, stdio: Stdio) -> Result<PtyForwarding> {
+    let mut input = unsafe { WritePipe::from_raw_fd(dup(master.as_raw_fd())?) };
+    let slave = unsafe { WritePipe::from_raw_fd(dup(slave.as_raw_fd())?) };
+    let stop = Arc::new(WakeEvent::new()?);
+    let Stdio {
+        mut stdin, stdout, ..
+    } = stdio;
+
+    let input_stop = stop.clone();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while wait_readable(&stdin, &input_stop) {
+            match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if input.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    });
+    thread::spawn(move || copy_pty_output(master, stdout));
+
+    Ok(PtyForwarding {
+        slave: Some(slave),
+        stop,
+    })
+}
+
+// Copies the output of the terminal until reading the master fails with EIO, which happens
+// once every descriptor of the slave side is closed. The output written before is read
+// first.
+fn copy_pty_output(mut master: ReadPipe, mut stdout: WritePipe) {
+    let mut buf = [0; 4096];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if stdout.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+}
+
+// Waits until `fd` is readable. Returns `false` if `stop` is woken first.
+fn wait_readable<T: AsRawFd>(fd: &T, stop: &WakeEvent) -> bool {
+    let mut fds = [
+        PollFd::new(fd.as_raw_fd(), PollFlags::POLLIN),
+        PollFd::new(stop.0, PollFlags::POLLIN),
+    ];
+    loop {
+        match poll(&mut fds, -1) {
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(_) => return false,
+            Ok(_) => {}
+        }
+        let stopped = fds[1]
+            .revents()
+            .is_some_and(|r| r.contains(PollFlags::POLLIN));
+        // Hangups and errors are reported by the read.
+        return !stopped;
+    }
+}
+
+// Confines the writes to the scratch directory, see `ProcessInfo::filesystem_sandbox`.
+fn init_fs_sandbox(sandbox: &FsSandbox) -> nix::Result<()> {
+    unshare(CloneFlags::CLONE_NEWNS)?;
+    // Keeps the mounts below from propagating to the namespace of the spawner.
+    let private = MsFlags::MS_REC | MsFlags::MS_PRIVATE;
+    mount(None::<&str>, "/", None::<&str>, private, None::<&str>)?;
+
+    let mounts = procfs::process::Process::myself()
+        .and_then(|ps| ps.mountinfo())
+        .map_err(|_| nix::Error::from_errno(Errno::EIO))?;
+    for m in mounts {
+        // The flags that are not given are cleared by the remount.
+        let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+        for (option, flag) in [
+            ("nosuid", MsFlags::MS_NOSUID),
+            ("nodev", MsFlags::MS_NODEV),
+            ("noexec", MsFlags::MS_NOEXEC),
+        ] {
+            if m.mount_options.contains_key(option) {
+                flags |= flag;
+            }
+        }
+        let target = m.mount_point.as_path();
+        match mount(None::<&str>, target, None::<&str>, flags, None::<&str>) {
+            // The mount point is hidden by another mount.
+            Err(nix::Error::Sys(Errno::ENOENT)) => {}
+            result => result?,
+        }
+    }
+
+    let size = sandbox.scratch_size.map(|size| format!("size={}", size));
+    mount(
+        Some("tmpfs"),
+        sandbox.scratch_dir.as_str(),
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        size.as_deref(),
+    )
+}
+
+// Moves the process to a new network namespace, see `ProcessInfo::deny_network`.
+fn init_network_namespace() -> nix::Result<()> {
+    match unshare(CloneFlags::CLONE_NEWNET) {
+        // An unprivileged spawner can still create the network namespace if it is owned by
+        // a new user namespace, in which the process keeps its user and group.
+        Err(nix::Error::Sys(Errno::EPERM)) => {
+            let (uid, gid) = (Uid::current(), Gid::current());
+            unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNET)?;
+            let write_map = |path: &str, map: String| {
+                fs::write(path, map).map_err(|e| {
+                    nix::Error::from_errno(e.raw_os_error().map_or(Errno::EIO, Errno::from_i32))
+                })
+            };
+            write_map("/proc/self/uid_map", format!("{} {} 1", uid, uid))?;
+            // Writing the group map is not allowed until setgroups is denied.
+            write_map("/proc/self/setgroups", "deny".to_string())?;
+            write_map("/proc/self/gid_map", format!("{} {} 1", gid, gid))
+        }
+        result => result,
+    }
+}
+
+// Drops `CAP_SYS_ADMIN`, which the namespaces of the sandbox are created with, from every
+// capability set. The process could otherwise undo the mounts of the sandbox, or join the
+// network namespace of the spawner with `setns`.
+fn drop_sys_admin() -> nix::Result<()> {
+    let cap = CAP_SYS_ADMIN as c_ulong;
+    if unsafe { prctl(PR_CAPBSET_DROP, cap, 0, 0, 0) } == -1 {
+        // Without `CAP_SETPCAP` the bounding set can't be changed, so `exec` is kept from
+        // granting the capability back instead.
+        if Errno::last() != Errno::EPERM || unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } == -1
+        {
+            return Err(nix::Error::last());
+        }
+    }
+
+    let mut header = __user_cap_header_struct {
+        version: _LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [__user_cap_data_struct::default(); 2];
+    if unsafe { syscall(SYS_capget, &mut header, data.as_mut_ptr()) } == -1 {
+        return Err(nix::Error::last());
+    }
+    let d = &mut data[CAP_SYS_ADMIN as usize / 32];
+    let mask = !(1 << (CAP_SYS_ADMIN % 32));
+    d.permitted &= mask;
+    d.effective &= mask;
+    d.inheritable &= mask;
+    if unsafe { syscall(SYS_capset, &mut header, data.as_ptr()) } == -1 {
+        return Err(nix::Error::last());
+    }
+
+    // Fails with EINVAL before Linux 4.3, which has no ambient capabilities.
+    let lower = PR_CAP_AMBIENT_LOWER as c_ulong;
+    if unsafe { prctl(PR_CAP_AMBIENT, lower, cap, 0, 0) } == -1 && Errno::last() != Errno::EINVAL {
+        return Err(nix::Error::last());
+    }
+    Ok(())
+}
+
+// Drops every capability but the ones in `keep`, see `ProcessInfo::restrict_token`.
+fn drop_capabilities(keep: &[u32]) -> nix::Result<()> {
+    if unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } == -1 {
+        return Err(nix::Error::last());
+    }
+    for cap in (0..64).filter(|cap| !keep.contains(cap)) {
+        if unsafe { prctl(PR_CAPBSET_DROP, cap as c_ulong, 0, 0, 0) } == -1 {
+            match Errno::last() {
+                // Past the last capability, or the bounding set can't be changed.
+                Errno::EINVAL | Errno::EPERM => break,
+                _ => return Err(nix::Error::last()),
+            }
+        }
+    }
+
+    let mut header = __user_cap_header_struct {
+        version: _LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [__user_cap_data_struct::default(); 2];
+    if unsafe { syscall(SYS_capget, &mut header, data.as_mut_ptr()) } == -1 {
+        return Err(nix::Error::last());
+    }
+    for (i, d) in data.iter_mut().enumerate() {
+        let mask = keep
+            .iter()
+            .filter(|&&cap| cap / 32 == i as u32)
+            .fold(0, |mask, cap| mask | 1 << (cap % 32));
+        d.permitted &= mask;
+        d.effective &= mask;
+        d.inheritable = d.permitted;
+    }
+    if unsafe { syscall(SYS_capset, &mut header, data.as_ptr()) } == -1 {
+        return Err(nix::Error::last());
+    }
+
+    // Ambient capabilities are kept across `exec` by an unprivileged process. Clearing them
+    // fails with EINVAL before Linux 4.3, which has none.
+    let clear_all = PR_CAP_AMBIENT_CLEAR_ALL as c_ulong;
+    if unsafe { prctl(PR_CAP_AMBIENT, clear_all, 0, 0, 0) } == -1 && Errno::last() != Errno::EINVAL
+    {
+        return Err(nix::Error::last());
+    }
+    for &cap in keep.iter().filter(|&&cap| cap < 64) {
+        if data[cap as usize / 32].permitted & 1 << (cap % 32) == 0 {
+            continue;
+        }
+        let raise = PR_CAP_AMBIENT_RAISE as c_ulong;
+        if unsafe { prctl(PR_CAP_AMBIENT, raise, cap as c_ulong, 0, 0) } == -1 {
+            return Err(nix::Error::last());
+        }
+    }
+    Ok(())
+}
+
 fn init_seccomp(filter: &mut SyscallFilter) -> nix::Result<()> {
     if unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } == -1 {
         return Err(nix::Error::last());
@@ -581,14 +1216,21 @@ fn init_seccomp(filter: &mut SyscallFilter) -> nix::Result<()> {
     Ok(())
 }
 
-fn init_child_process(
-    stdio: RawStdio,
-    working_dir: Option<&str>,
-    filter: Option<&mut SyscallFilter>,
-    group: Option<&mut Group>,
-    usr: Option<&User>,
-    cpuset: Option<&CpuSet>,
-) -> InitResult {
+fn init_child_process(init: ChildInit) -> InitResult {
+    let ChildInit {
+        stdio,
+        inherited_fds,
+        working_dir,
+        filter,
+        group,
+        usr,
+        cpuset,
+        priority,
+        controlling_terminal,
+        fs_sandbox,
+        deny_network,
+        restrict,
+    } = init;
     group
         .map(|g| g.add_pid(Pid::this()))
         .transpose()
@@ -602,9 +1244,37 @@ fn init_child_process(
     // Even though we set FD_CLOEXEC flag on all pipes, some child processes
     // still inherit pipes of their siblings.
     // Close all open file descriptors to fix this.
-    close_all_fds(&[stdio.stdin.raw(), stdio.stdout.raw(), stdio.stderr.raw()])?;
+    let mut keep_fds = vec![stdio.stdin.raw(), stdio.stdout.raw(), stdio.stderr.raw()];
+    keep_fds.extend_from_slice(inherited_fds);
+    close_all_fds(&keep_fds)?;
+    for &fd in inherited_fds {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty())).map_err(InitError::Other)?;
+    }
+
+    // Must be done before changing the working directory, which may be the scratch one.
+    fs_sandbox
+        .map(init_fs_sandbox)
+        .transpose()
+        .map_err(InitError::FsSandbox)?;
+    if deny_network {
+        init_network_namespace().map_err(InitError::DenyNetwork)?;
+    }
+    let keep_sys_admin = restrict.is_some_and(|r| r.keep_capabilities.contains(&CAP_SYS_ADMIN));
+    if (fs_sandbox.is_some() || deny_network) && !keep_sys_admin {
+        drop_sys_admin().map_err(|e| match fs_sandbox {
+            Some(_) => InitError::FsSandbox(e),
+            None => InitError::DenyNetwork(e),
+        })?;
+    }
 
     init_stdio(stdio)
+        .and_then(|_| {
+            if controlling_terminal {
+                init_controlling_terminal()
+            } else {
+                Ok(())
+            }
+        })
         .and_then(|_| working_dir.map(chdir).transpose())
         .and_then(|_| {
             cpuset
@@ -613,10 +1283,21 @@ fn init_child_process(
         })
         .map_err(InitError::Other)?;
 
+    // Must be done before impersonation, since raising the priority needs privileges.
+    priority
+        .map(set_priority)
+        .transpose()
+        .map_err(InitError::Priority)?;
+
     usr.map(User::impersonate)
         .transpose()
         .map_err(InitError::Impersonate)?;
 
+    restrict
+        .map(|r| drop_capabilities(&r.keep_capabilities))
+        .transpose()
+        .map_err(InitError::Capabilities)?;
+
     filter
         .map(init_seccomp)
         .transpose()
@@ -625,6 +1306,38 @@ fn init_child_process(
     Ok(())
 }
 
+fn set_priority(priority: Priority) -> nix::Result<()> {
+    let nice = match priority {
+        Priority::Idle => 19,
+        Priority::BelowNormal => 10,
+        Priority::Normal => 0,
+        Priority::AboveNormal => -5,
+        Priority::High => -10,
+    };
+    if unsafe { setpriority(PRIO_PROCESS, 0, nice) } == -1 {
+        return Err(nix::Error::last());
+    }
+    Ok(())
+}
+
+fn affinity_mask_to_cpuset(mask: u64) -> Result<CpuSet> {
+    if mask == 0 {
+        return Err(Error::from("CPU affinity mask is empty"));
+    }
+    let num_cpus = unsafe { sysconf(_SC_NPROCESSORS_ONLN) };
+    if num_cpus > 0 && num_cpus < 64 && mask >> num_cpus != 0 {
+        return Err(Error::from(format!(
+            "CPU affinity mask {:#x} refers to missing cores, only {} are available",
+            mask, num_cpus
+        )));
+    }
+    let mut cpuset = CpuSet::new();
+    for cpu in (0..64).filter(|cpu| mask & (1 << cpu) != 0) {
+        cpuset.set(cpu)?;
+    }
+    Ok(cpuset)
+}
+
 fn exec_app(app: &CStr, args: &[&CStr], env: &[&CStr], search_in_path: bool) -> nix::Result<()> {
     raise(Signal::SIGSTOP)?;
     if search_in_path {
@@ -638,8 +1351,8 @@ fn exec_app(app: &CStr, args: &[&CStr], env: &[&CStr], search_in_path: bool) ->
 fn create_process(
     info: &mut ProcessInfo,
     stdio: Stdio,
-    group: Option<&mut Group>,
-) -> Result<(Pid, SharedMem<InitResult>)> {
+    group: Option<&Group>,
+) -> Result<(Pid, SharedMem<InitResult>, Option<PtyForwarding>)> {
     let usr = info
         .username
         .as_ref()
@@ -655,28 +1368,57 @@ fn create_process(
     let env_ref = (0..env.len())
         .map(|i| env[i].as_c_str())
         .collect::<Vec<_>>();
+    let cpuset = match info.cpu_affinity {
+        Some(mask) => Some(affinity_mask_to_cpuset(mask)?),
+        None => info.cpuset,
+    };
+    // The child gets the slave side of the terminal, and the given stdio is forwarded to
+    // the master side once it is spawned.
+    let (stdio, pty) = match info.pseudo_console {
+        Some(ref winsize) => {
+            let (slave_stdio, master) = open_pty(winsize)?;
+            (slave_stdio, Some((master, stdio)))
+        }
+        None => (stdio, None),
+    };
 
     if let ForkResult::Parent { child, .. } = fork()? {
+        let pty = match pty {
+            Some((master, parent_stdio)) => Some(forward_pty(master, &stdio.stdin, parent_stdio)?),
+            None => None,
+        };
+        if let Some(g) = group {
+            g.cgroup.track_spawned(child);
+        }
         // Wait for initialization to complete.
         waitpid(child, Some(WaitPidFlag::WSTOPPED))?;
         if !info.suspended {
             kill(child, Signal::SIGCONT)?;
         }
-        return Ok((child, init_result));
+        return Ok((child, init_result, pty));
     }
 
-    *init_result.lock().unwrap() = init_child_process(
-        RawStdio {
+    *init_result.lock().unwrap() = init_child_process(ChildInit {
+        stdio: RawStdio {
             stdin: stdio.stdin.into_inner(),
             stdout: stdio.stdout.into_inner(),
             stderr: stdio.stderr.into_inner(),
         },
-        info.working_dir.as_deref(),
-        info.filter.as_mut(),
+        inherited_fds: &info.inherited_fds,
+        working_dir: info
+            .working_dir
+            .as_deref()
+            .or(info.fs_sandbox.as_ref().map(|s| s.scratch_dir.as_str())),
+        filter: info.filter.as_mut(),
         group,
-        usr.as_ref(),
-        info.cpuset.as_ref(),
-    )
+        usr: usr.as_ref(),
+        cpuset: cpuset.as_ref(),
+        priority: info.priority,
+        controlling_terminal: info.pseudo_console.is_some(),
+        fs_sandbox: info.fs_sandbox.as_ref(),
+        deny_network: info.deny_network,
+        restrict: info.restrict.as_ref(),
+    })
     .and_then(|_| {
         exec_app(&app, &args_ref, &env_ref, info.search_in_path).map_err(InitError::Other)
     });
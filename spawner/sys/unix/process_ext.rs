@@ -8,7 +8,10 @@ use crate::sys::AsInnerMut;
 use nix::libc::{__u16, __u32, __u8};
 pub use nix::sched::CpuSet;
 
+use std::os::unix::io::RawFd;
+
 // https://outflux.net/teach-seccomp
+#[derive(Clone)]
 pub struct SyscallFilter(Vec<sock_filter>);
 
 pub struct SyscallFilterBuilder(Vec<sock_filter>);
@@ -16,6 +19,10 @@ pub struct SyscallFilterBuilder(Vec<sock_filter>);
 pub trait ProcessInfoExt {
     fn syscall_filter(&mut self, filter: SyscallFilter) -> &mut Self;
     fn cpuset(&mut self, cpuset: CpuSet) -> &mut Self;
+    /// Keeps `fd` open in the child, under the same number, in addition to stdio. The
+    /// close-on-exec flag is cleared in the child only. The child has to learn the number
+    /// out of band, e.g. from its arguments. Numbers 0-2 are replaced by stdio.
+    fn inherit_fd(&mut self, fd: RawFd) -> &mut Self;
 }
 
 #[cfg(target_arch = "x86")]
@@ -68,6 +75,11 @@ impl ProcessInfoExt for ProcessInfo {
         self.as_inner_mut().cpuset(cpuset);
         self
     }
+
+    fn inherit_fd(&mut self, fd: RawFd) -> &mut Self {
+        self.as_inner_mut().inherit_fd(fd);
+        self
+    }
 }
 
 fn bpf_stmt(code: __u16, k: __u32) -> sock_filter {
@@ -1,34 +1,49 @@
+use crate::process::RestrictOptions;
 use crate::sys::windows::error::SysError;
 use crate::sys::windows::missing_decls::{
-    GetExtendedTcpTable, GetExtendedUdpTable, MIB_TCP6ROW_OWNER_PID, MIB_TCP6TABLE_OWNER_PID,
-    MIB_TCPROW_OWNER_PID, MIB_TCPTABLE_OWNER_PID, MIB_UDP6ROW_OWNER_PID, MIB_UDP6TABLE_OWNER_PID,
-    MIB_UDPROW_OWNER_PID, MIB_UDPTABLE_OWNER_PID, PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
+    ClosePseudoConsole, CreatePseudoConsole, GetExtendedTcpTable, GetExtendedUdpTable, HPCON,
+    MIB_TCP6ROW_OWNER_PID, MIB_TCP6TABLE_OWNER_PID, MIB_TCPROW_OWNER_PID, MIB_TCPTABLE_OWNER_PID,
+    MIB_TCP_STATE_LISTEN, MIB_UDP6ROW_OWNER_PID, MIB_UDP6TABLE_OWNER_PID, MIB_UDPROW_OWNER_PID,
+    MIB_UDPTABLE_OWNER_PID, PROC_THREAD_ATTRIBUTE_HANDLE_LIST, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
     TCP_TABLE_OWNER_PID_ALL, UDP_TABLE_OWNER_PID,
 };
+use crate::sys::windows::process_ext::UserOptions;
 use crate::{Error, Result};
 
 use winapi::shared::basetsd::{DWORD_PTR, SIZE_T, ULONG_PTR};
-use winapi::shared::minwindef::{DWORD, FALSE, HWINSTA, LPVOID, TRUE, ULONG, WORD};
+use winapi::shared::minwindef::{DWORD, FALSE, FILETIME, HWINSTA, LPVOID, TRUE, ULONG, WORD};
 use winapi::shared::windef::HDESK;
-use winapi::shared::winerror::{ERROR_INSUFFICIENT_BUFFER, ERROR_MORE_DATA, NO_ERROR};
+use winapi::shared::winerror::{ERROR_INSUFFICIENT_BUFFER, ERROR_MORE_DATA, NO_ERROR, S_OK};
 use winapi::shared::ws2def::{AF_INET, AF_INET6};
 use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
 use winapi::um::ioapiset::{CreateIoCompletionPort, GetQueuedCompletionStatus};
 use winapi::um::jobapi2::{QueryInformationJobObject, SetInformationJobObject};
 use winapi::um::processthreadsapi::{
-    DeleteProcThreadAttributeList, InitializeProcThreadAttributeList, UpdateProcThreadAttribute,
+    DeleteProcThreadAttributeList, GetCurrentProcess, GetProcessHandleCount, GetProcessTimes,
+    InitializeProcThreadAttributeList, OpenProcess, OpenProcessToken, UpdateProcThreadAttribute,
     LPSTARTUPINFOW, PROC_THREAD_ATTRIBUTE_LIST,
 };
-use winapi::um::securitybaseapi::{ImpersonateLoggedOnUser, RevertToSelf};
+use winapi::um::psapi::{
+    GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS, PROCESS_MEMORY_COUNTERS_EX,
+};
+use winapi::um::sddl::ConvertStringSidToSidW;
+use winapi::um::securitybaseapi::{CreateRestrictedToken, GetTokenInformation};
+use winapi::um::tlhelp32::{
+    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, Thread32First, Thread32Next,
+    PROCESSENTRY32W, TH32CS_SNAPPROCESS, TH32CS_SNAPTHREAD, THREADENTRY32,
+};
 use winapi::um::userenv::{CreateEnvironmentBlock, DestroyEnvironmentBlock};
 use winapi::um::winbase::{
-    LogonUserW, LOGON32_LOGON_INTERACTIVE, LOGON32_PROVIDER_DEFAULT, STARTF_USESHOWWINDOW,
-    STARTF_USESTDHANDLES, STARTUPINFOEXW,
+    LocalFree, LogonUserW, LookupPrivilegeValueW, LOGON32_LOGON_INTERACTIVE,
+    LOGON32_PROVIDER_DEFAULT, STARTF_USESHOWWINDOW, STARTF_USESTDHANDLES, STARTUPINFOEXW,
 };
+use winapi::um::wincontypes::COORD;
 use winapi::um::winnt::{
-    JobObjectAssociateCompletionPortInformation, JobObjectBasicProcessIdList, DELETE, HANDLE,
-    JOBOBJECT_ASSOCIATE_COMPLETION_PORT, JOBOBJECT_BASIC_PROCESS_ID_LIST,
-    JOB_OBJECT_MSG_ACTIVE_PROCESS_LIMIT, JOB_OBJECT_MSG_JOB_MEMORY_LIMIT, PVOID, READ_CONTROL,
+    JobObjectAssociateCompletionPortInformation, JobObjectBasicProcessIdList, TokenPrivileges,
+    DELETE, HANDLE, JOBOBJECT_ASSOCIATE_COMPLETION_PORT, JOBOBJECT_BASIC_PROCESS_ID_LIST,
+    JOB_OBJECT_MSG_ACTIVE_PROCESS_LIMIT, JOB_OBJECT_MSG_JOB_MEMORY_LIMIT, LPWSTR, LUID,
+    LUID_AND_ATTRIBUTES, PROCESS_QUERY_LIMITED_INFORMATION, PSID, PVOID, READ_CONTROL,
+    SID_AND_ATTRIBUTES, TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE, TOKEN_PRIVILEGES, TOKEN_QUERY,
     WCHAR, WRITE_DAC, WRITE_OWNER,
 };
 use winapi::um::winuser::{
@@ -39,10 +54,14 @@ use winapi::um::winuser::{
     DESKTOP_WRITEOBJECTS, SW_HIDE, SW_SHOW, UOI_NAME, WINSTA_ALL_ACCESS,
 };
 
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
 use std::alloc::{alloc_zeroed, dealloc, Layout};
 use std::ffi::OsStr;
 use std::marker::PhantomData;
-use std::mem::{size_of, size_of_val, zeroed};
+use std::mem::{self, size_of, size_of_val, zeroed};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::os::raw::c_int;
 use std::os::windows::ffi::OsStrExt;
 use std::ptr;
@@ -62,24 +81,33 @@ pub struct RawStdio {
 
 pub struct User {
     token: Handle,
-    winsta: HWINSTA,
-    desktop: HDESK,
+    // The desktop must be closed before its window station.
+    desktop: Desktop,
+    winsta: WindowStation,
     desktop_name: Vec<u16>,
 }
 
-pub struct UserContext<'a>(&'a Option<User>);
+struct WindowStation(HWINSTA);
+
+// A SID allocated by `ConvertStringSidToSidW`.
+struct LocalSid(PSID);
+
+struct Desktop(HDESK);
 
 pub struct EnvBlock {
     block: *mut u16,
     len: usize,
 }
 
+pub struct PseudoConsole(HPCON);
+
 pub struct StartupInfo<'a, 'b, 'c> {
     base: STARTUPINFOEXW,
     _att_list: AttList,
     stdio: PhantomData<&'a RawStdio>,
     inherited_handles: PhantomData<&'b mut [HANDLE]>,
-    user: PhantomData<&'c mut User>,
+    user: PhantomData<&'c User>,
+    pseudo_console: PhantomData<&'c PseudoConsole>,
 }
 
 struct AttList {
@@ -152,6 +180,144 @@ pub fn to_utf16<S: AsRef<OsStr>>(s: S) -> Vec<u16> {
     s.as_ref().encode_wide().chain(std::iter::once(0)).collect()
 }
 
+/// Same as `to_utf16`, but fails if the string contains a nul character, which would make
+/// Windows silently truncate it. Must be used for any string that comes from the user.
+/// The error names the string as `what` and never contains the string itself, which may
+/// be a password.
+pub fn to_utf16_checked<S: AsRef<OsStr>>(s: S, what: &str) -> Result<Vec<u16>> {
+    let wide = to_utf16(s);
+    match wide.iter().position(|&c| c == 0) {
+        Some(pos) if pos + 1 != wide.len() => Err(Error::from(format!(
+            "Nul character found in the {} at position {}",
+            what, pos
+        ))),
+        _ => Ok(wide),
+    }
+}
+
+/// Counts the threads that belong to the given processes.
+pub fn count_threads(pids: &[ULONG_PTR]) -> Result<usize> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return Err(Error::last_os_error());
+    }
+    let snapshot = Handle::new(snapshot);
+
+    let mut entry: THREADENTRY32 = unsafe { zeroed() };
+    entry.dwSize = size_of::<THREADENTRY32>() as DWORD;
+    let mut count = 0;
+    let mut has_entry = unsafe { Thread32First(snapshot.0, &mut entry) };
+    while has_entry == TRUE {
+        if pids.contains(&(entry.th32OwnerProcessID as ULONG_PTR)) {
+            count += 1;
+        }
+        has_entry = unsafe { Thread32Next(snapshot.0, &mut entry) };
+    }
+    Ok(count)
+}
+
+/// Returns the parent pid and the executable name of each of the given processes that
+/// still exist.
+pub fn process_entries(pids: &[ULONG_PTR]) -> Result<Vec<(u32, u32, String)>> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return Err(Error::last_os_error());
+    }
+    let snapshot = Handle::new(snapshot);
+
+    let mut entry: PROCESSENTRY32W = unsafe { zeroed() };
+    entry.dwSize = size_of::<PROCESSENTRY32W>() as DWORD;
+    let mut entries = Vec::new();
+    let mut has_entry = unsafe { Process32FirstW(snapshot.0, &mut entry) };
+    while has_entry == TRUE {
+        if pids.contains(&(entry.th32ProcessID as ULONG_PTR)) {
+            let name = &entry.szExeFile;
+            let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+            entries.push((
+                entry.th32ProcessID,
+                entry.th32ParentProcessID,
+                String::from_utf16_lossy(&name[..len]),
+            ));
+        }
+        has_entry = unsafe { Process32NextW(snapshot.0, &mut entry) };
+    }
+    Ok(entries)
+}
+
+/// Returns the total working set and private commit of the given processes, in bytes.
+/// Processes that have exited or cannot be opened are skipped.
+pub fn sum_memory_counters(pids: &[ULONG_PTR]) -> (u64, u64) {
+    let mut working_set = 0;
+    let mut private_bytes = 0;
+    for &pid in pids {
+        let process =
+            unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid as DWORD) };
+        if process.is_null() {
+            continue;
+        }
+        let process = Handle::new(process);
+        let mut counters: PROCESS_MEMORY_COUNTERS_EX = unsafe { zeroed() };
+        let result = unsafe {
+            GetProcessMemoryInfo(
+                process.raw(),
+                &mut counters as *mut _ as *mut PROCESS_MEMORY_COUNTERS,
+                size_of_val(&counters) as DWORD,
+            )
+        };
+        if result != FALSE {
+            working_set += counters.WorkingSetSize as u64;
+            private_bytes += counters.PrivateUsage as u64;
+        }
+    }
+    (working_set, private_bytes)
+}
+
+/// Returns the creation time of the process, or 0 if the process has exited or cannot be
+/// opened.
+pub fn process_start_time(pid: DWORD) -> u64 {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) };
+    if process.is_null() {
+        return 0;
+    }
+    let process = Handle::new(process);
+    let mut creation_time: FILETIME = unsafe { zeroed() };
+    let mut exit_time: FILETIME = unsafe { zeroed() };
+    let mut kernel_time: FILETIME = unsafe { zeroed() };
+    let mut user_time: FILETIME = unsafe { zeroed() };
+    let result = unsafe {
+        GetProcessTimes(
+            process.raw(),
+            &mut creation_time,
+            &mut exit_time,
+            &mut kernel_time,
+            &mut user_time,
+        )
+    };
+    if result == FALSE {
+        return 0;
+    }
+    (creation_time.dwHighDateTime as u64) << 32 | creation_time.dwLowDateTime as u64
+}
+
+/// Returns the total number of open handles of the given processes. Processes that have
+/// exited or cannot be opened are skipped.
+pub fn count_handles(pids: &[ULONG_PTR]) -> usize {
+    let mut count = 0;
+    for &pid in pids {
+        let process =
+            unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid as DWORD) };
+        if process.is_null() {
+            continue;
+        }
+        let process = Handle::new(process);
+        let mut handle_count: DWORD = 0;
+        if unsafe { GetProcessHandleCount(process.raw(), &mut handle_count) } != FALSE {
+            count += handle_count as usize;
+        }
+    }
+    count
+}
+
 impl Handle {
     pub fn new(handle: HANDLE) -> Self {
         Self(handle)
@@ -171,54 +337,100 @@ impl Drop for Handle {
 }
 
 impl User {
+    /// Logs on a local user account.
     pub fn create<T, U>(user: T, password: Option<U>) -> Result<Self>
     where
         T: AsRef<str>,
         U: AsRef<str>,
     {
+        Self::create_with_domain(user, ".", password)
+    }
+
+    pub fn create_with_domain<T, D, U>(user: T, domain: D, password: Option<U>) -> Result<Self>
+    where
+        T: AsRef<str>,
+        D: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let options = UserOptions {
+            domain: Some(domain.as_ref().to_string()),
+            password: password.map(|p| p.as_ref().to_string()),
+            desktop_name: Some("desktop".to_string()),
+        };
+        Self::create_with_options(user.as_ref(), &options)
+    }
+
+    pub fn create_with_options(user: &str, options: &UserOptions) -> Result<Self> {
         let mut token = INVALID_HANDLE_VALUE;
-        let pwd = match password {
-            Some(p) => to_utf16(p.as_ref()),
+        let domain = options.domain.as_deref().unwrap_or(".");
+        let pwd = match options.password {
+            Some(ref p) => to_utf16_checked(p, "password")?,
             None => to_utf16(""),
         };
+        let user_name = to_utf16_checked(user, "user name")?;
+        let domain_name = to_utf16_checked(domain, "domain name")?;
+        let desktop_name = match options.desktop_name {
+            Some(ref name) => name.clone(),
+            None => {
+                let mut rng = thread_rng();
+                format!(
+                    "desktop_{}",
+                    (0..7).map(|_| rng.sample(Alphanumeric)).collect::<String>()
+                )
+            }
+        };
+        let desktop_name_wide = to_utf16_checked(&desktop_name, "desktop name")?;
 
         unsafe {
-            cvt(LogonUserW(
-                /*lpUsername=*/ to_utf16(user.as_ref()).as_ptr(),
-                /*lpDomain=*/ to_utf16(".").as_ptr(),
-                /*lpPassword=*/ pwd.as_ptr(),
-                /*dwLogonType=*/ LOGON32_LOGON_INTERACTIVE,
-                /*dwLogonProvider=*/ LOGON32_PROVIDER_DEFAULT,
-                /*phToken=*/ &mut token,
-            ))?;
-
             // Create separate desktop and window station for this user account, so it can get access to them.
             // Otherwise, window applications may crash since they don't have access to current desktop\winstation.
-            let new_winsta = cvt(CreateWindowStationW(
+            // Handles are wrapped right away, so they are closed if any of the next steps fails,
+            // the logon included.
+            let winsta = WindowStation(cvt(CreateWindowStationW(
                 /*lpwinsta=*/ ptr::null(),
                 /*dwFlags=*/ 0,
                 /*dwDesiredAccess=*/ WINSTA_ALL_ACCESS,
                 /*lpsa=*/ ptr::null_mut(),
-            ))?;
+            ))?);
 
             let old_winsta = cvt(GetProcessWindowStation())?;
-            cvt(SetProcessWindowStation(new_winsta))?;
-            let desktop_name = "desktop";
+            cvt(SetProcessWindowStation(winsta.0))?;
             let desktop = CreateDesktopW(
-                /*lpszDesktop=*/ to_utf16(desktop_name).as_ptr(),
+                /*lpszDesktop=*/ desktop_name_wide.as_ptr(),
                 /*lpszDevice=*/ ptr::null(),
                 /*pDevmode=*/ ptr::null_mut(),
                 /*dwFlags=*/ 0,
                 /*dwDesiredAccess=*/ DESKTOP_ALL,
                 /*lpsa=*/ ptr::null_mut(),
             );
+            let desktop = cvt(desktop).map(Desktop);
             cvt(SetProcessWindowStation(old_winsta))?;
-            cvt(desktop)?;
+            let desktop = desktop?;
+
+            cvt(LogonUserW(
+                /*lpUsername=*/ user_name.as_ptr(),
+                /*lpDomain=*/ domain_name.as_ptr(),
+                /*lpPassword=*/ pwd.as_ptr(),
+                /*dwLogonType=*/ LOGON32_LOGON_INTERACTIVE,
+                /*dwLogonProvider=*/ LOGON32_PROVIDER_DEFAULT,
+                /*phToken=*/ &mut token,
+            ))
+            .map_err(|e| {
+                // Keep the code, e.g. to tell bad credentials (1326) from missing privileges (1314).
+                Error::from(format!(
+                    "Failed to log on user '{}\\{}' (error {}): {}",
+                    domain,
+                    user,
+                    e.raw(),
+                    e
+                ))
+            })?;
+            let token = Handle(token);
 
             let mut winsta_name_bytes = 0;
             let mut winsta_name_buf = [0 as WCHAR; 128];
             cvt(GetUserObjectInformationW(
-                /*hObj=*/ new_winsta as HANDLE,
+                /*hObj=*/ winsta.0 as HANDLE,
                 /*nIndex=*/ UOI_NAME as c_int,
                 /*pvInfo=*/ winsta_name_buf.as_mut_ptr() as PVOID,
                 /*nLength=*/ (size_of::<WCHAR>() * winsta_name_buf.len()) as DWORD,
@@ -229,9 +441,9 @@ impl User {
             let winsta_name = &winsta_name_buf[..winsta_name_len];
 
             Ok(Self {
-                token: Handle(token),
-                winsta: new_winsta,
+                token,
                 desktop,
+                winsta,
                 desktop_name: to_utf16(format!(
                     "{}\\{}",
                     String::from_utf16(winsta_name).map_err(|e| Error::from(e.to_string()))?,
@@ -244,38 +456,202 @@ impl User {
     pub fn token(&self) -> &Handle {
         &self.token
     }
+
+    /// Returns the full name of the desktop, in the form `winsta\desktop`.
+    pub fn desktop_name(&self) -> String {
+        String::from_utf16_lossy(&self.desktop_name[..self.desktop_name.len() - 1])
+    }
+
+    /// Closes the desktop and the window station of the user, reporting failures that
+    /// are ignored on drop.
+    pub fn close(self) -> Result<()> {
+        let User {
+            desktop, winsta, ..
+        } = self;
+        let result = desktop.close();
+        result.and(winsta.close())
+    }
 }
 
-impl Drop for User {
+impl WindowStation {
+    fn close(self) -> Result<()> {
+        let winsta = self.0;
+        mem::forget(self);
+        unsafe { cvt(CloseWindowStation(winsta)) }
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+impl Drop for WindowStation {
     fn drop(&mut self) {
         unsafe {
-            CloseDesktop(self.desktop);
-            CloseWindowStation(self.winsta);
+            CloseWindowStation(self.0);
         }
     }
 }
 
-impl<'a> UserContext<'a> {
-    pub fn enter(user: &'a Option<User>) -> Result<Self> {
-        if let Some(u) = user {
-            unsafe {
-                cvt(ImpersonateLoggedOnUser(u.token.0))?;
-            }
+impl Desktop {
+    fn close(self) -> Result<()> {
+        let desktop = self.0;
+        mem::forget(self);
+        unsafe { cvt(CloseDesktop(desktop)) }
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+impl Drop for Desktop {
+    fn drop(&mut self) {
+        unsafe {
+            CloseDesktop(self.0);
         }
-        Ok(Self(user))
     }
 }
 
-impl<'a> Drop for UserContext<'a> {
+impl Drop for LocalSid {
     fn drop(&mut self) {
-        if self.0.is_some() {
+        unsafe {
+            LocalFree(self.0);
+        }
+    }
+}
+
+/// Creates a restricted version of `token`, or of the token of the current process if it's
+/// `None`, see `ProcessInfo::restrict_token`.
+pub fn create_restricted_token(
+    token: Option<&Handle>,
+    options: &RestrictOptions,
+) -> Result<Handle> {
+    let own_token;
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let mut raw = INVALID_HANDLE_VALUE;
             unsafe {
-                RevertToSelf();
+                cvt(OpenProcessToken(
+                    /*ProcessHandle=*/ GetCurrentProcess(),
+                    /*DesiredAccess=*/ TOKEN_DUPLICATE | TOKEN_QUERY | TOKEN_ASSIGN_PRIMARY,
+                    /*TokenHandle=*/ &mut raw,
+                ))?;
             }
+            own_token = Handle(raw);
+            &own_token
         }
+    };
+
+    let sids = options
+        .deny_only_sids
+        .iter()
+        .map(|sid| {
+            let wide = to_utf16_checked(sid, "SID")?;
+            let mut raw: PSID = ptr::null_mut();
+            unsafe { cvt(ConvertStringSidToSidW(wide.as_ptr(), &mut raw)) }
+                .map(|_| LocalSid(raw))
+                .map_err(|_| Error::from(format!("Invalid SID '{}'", sid)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut sids_to_disable = sids
+        .iter()
+        .map(|sid| SID_AND_ATTRIBUTES {
+            Sid: sid.0,
+            Attributes: 0,
+        })
+        .collect::<Vec<_>>();
+
+    let kept_privileges = options
+        .keep_privileges
+        .iter()
+        .map(|name| {
+            let wide = to_utf16_checked(name, "privilege name")?;
+            let mut luid: LUID = unsafe { zeroed() };
+            unsafe { cvt(LookupPrivilegeValueW(ptr::null(), wide.as_ptr(), &mut luid)) }
+                .map(|_| luid)
+                .map_err(|_| Error::from(format!("Unknown privilege '{}'", name)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut privileges_to_delete = token_privileges(token)?
+        .into_iter()
+        .filter(|p| {
+            !kept_privileges
+                .iter()
+                .any(|luid| luid.LowPart == p.Luid.LowPart && luid.HighPart == p.Luid.HighPart)
+        })
+        .collect::<Vec<_>>();
+
+    let mut restricted = INVALID_HANDLE_VALUE;
+    unsafe {
+        cvt(CreateRestrictedToken(
+            /*ExistingTokenHandle=*/ token.0,
+            /*Flags=*/ 0,
+            /*DisableSidCount=*/ sids_to_disable.len() as DWORD,
+            /*SidsToDisable=*/ sids_to_disable.as_mut_ptr(),
+            /*DeletePrivilegeCount=*/ privileges_to_delete.len() as DWORD,
+            /*PrivilegesToDelete=*/ privileges_to_delete.as_mut_ptr(),
+            /*RestrictedSidCount=*/ 0,
+            /*SidsToRestrict=*/ ptr::null_mut(),
+            /*NewTokenHandle=*/ &mut restricted,
+        ))?;
     }
+    Ok(Handle(restricted))
 }
 
+fn token_privileges(token: &Handle) -> Result<Vec<LUID_AND_ATTRIBUTES>> {
+    unsafe {
+        let mut len: DWORD = 0;
+        // Fails with ERROR_INSUFFICIENT_BUFFER, returning the size.
+        GetTokenInformation(token.0, TokenPrivileges, ptr::null_mut(), 0, &mut len);
+        // `u64` keeps the buffer aligned for `TOKEN_PRIVILEGES`.
+        let mut buf = vec![0u64; (len as usize + 7) / 8];
+        cvt(GetTokenInformation(
+            /*TokenHandle=*/ token.0,
+            /*TokenInformationClass=*/ TokenPrivileges,
+            /*TokenInformation=*/ buf.as_mut_ptr() as LPVOID,
+            /*TokenInformationLength=*/ len,
+            /*ReturnLength=*/ &mut len,
+        ))?;
+        let privileges = &*(buf.as_ptr() as *const TOKEN_PRIVILEGES);
+        Ok(slice::from_raw_parts(
+            privileges.Privileges.as_ptr(),
+            privileges.PrivilegeCount as usize,
+        )
+        .to_vec())
+    }
+}
+
+impl PseudoConsole {
+    pub fn create(cols: u16, rows: u16, input: &Handle, output: &Handle) -> Result<Self> {
+        let size = COORD {
+            X: cols as i16,
+            Y: rows as i16,
+        };
+        let mut hpc: HPCON = ptr::null_mut();
+        let result = unsafe { CreatePseudoConsole(size, input.0, output.0, 0, &mut hpc) };
+        if result != S_OK {
+            return Err(Error::from(format!(
+                "CreatePseudoConsole failed with HRESULT {:#x}",
+                result
+            )));
+        }
+        Ok(Self(hpc))
+    }
+}
+
+impl Drop for PseudoConsole {
+    fn drop(&mut self) {
+        unsafe {
+            ClosePseudoConsole(self.0);
+        }
+    }
+}
+
+// The pseudo console handle may be closed from any thread.
+unsafe impl Send for PseudoConsole {}
+
+// Window station and desktop handles may be used from any thread.
+unsafe impl Send for User {}
+unsafe impl Sync for User {}
+
 impl EnvBlock {
     pub fn create(user: Option<&User>) -> Result<Self> {
         // https://docs.microsoft.com/en-us/windows/desktop/api/processthreadsapi/nf-processthreadsapi-createprocessa
@@ -314,10 +690,50 @@ impl EnvBlock {
     }
 
     pub fn iter<'a>(&'a self) -> impl Iterator<Item = String> + 'a {
-        self.as_slice()
-            .split(|c| *c == 0)
+        self.vars().map(String::from_utf16_lossy)
+    }
+
+    /// Returns the value of the variable `name`. Names are case-insensitive.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.vars()
+            .map(split_env_var)
+            .find(|(n, _)| to_upper(n).eq(name.chars().flat_map(char::to_uppercase)))
+            .map(|(_, value)| String::from_utf16_lossy(value))
+    }
+
+    /// Returns the `name=value` strings of the variables whose names start with `prefix`,
+    /// ignoring case.
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.vars()
+            .filter(move |var| {
+                let mut name = to_upper(split_env_var(var).0);
+                prefix
+                    .chars()
+                    .flat_map(char::to_uppercase)
+                    .all(|c| name.next() == Some(c))
+            })
             .map(String::from_utf16_lossy)
     }
+
+    fn vars<'a>(&'a self) -> impl Iterator<Item = &'a [u16]> + 'a {
+        self.as_slice().split(|c| *c == 0)
+    }
+}
+
+// Splits `name=value` into the name and the value. The name may start with `=`, like the
+// variables that keep the current directory of every drive, e.g. `=C:=C:\dir`.
+fn split_env_var(var: &[u16]) -> (&[u16], &[u16]) {
+    let eq = '=' as u16;
+    match var.iter().skip(1).position(|&c| c == eq) {
+        Some(idx) => (&var[..idx + 1], &var[idx + 2..]),
+        None => (var, &[]),
+    }
+}
+
+fn to_upper<'a>(s: &'a [u16]) -> impl Iterator<Item = char> + 'a {
+    std::char::decode_utf16(s.iter().cloned())
+        .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+        .flat_map(char::to_uppercase)
 }
 
 impl Drop for EnvBlock {
@@ -332,26 +748,40 @@ impl<'a, 'b, 'c> StartupInfo<'a, 'b, 'c> {
     pub fn create(
         stdio: &'a RawStdio,
         inherited_handles: &'b mut [HANDLE],
-        user: Option<&'c mut User>,
+        user: Option<&'c User>,
+        pseudo_console: Option<&'c PseudoConsole>,
         show_window: bool,
     ) -> Result<Self> {
-        let mut att_list = AttList::allocate(1)?;
+        let mut att_list = AttList::allocate(if pseudo_console.is_some() { 2 } else { 1 })?;
         att_list.update(
             PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
             inherited_handles.as_mut_ptr() as PVOID,
             inherited_handles.len() * size_of::<HANDLE>(),
         )?;
+        if let Some(console) = pseudo_console {
+            // Unlike other attributes, the value is the HPCON itself rather than a pointer to it.
+            att_list.update(
+                PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
+                console.0,
+                size_of::<HPCON>(),
+            )?;
+        }
 
         let mut info: STARTUPINFOEXW = unsafe { zeroed() };
         info.lpAttributeList = att_list.ptr;
         info.StartupInfo.cb = size_of_val(&info) as DWORD;
-        info.StartupInfo.dwFlags = STARTF_USESTDHANDLES | STARTF_USESHOWWINDOW;
+        info.StartupInfo.dwFlags = STARTF_USESHOWWINDOW;
         info.StartupInfo.wShowWindow = if show_window { SW_SHOW } else { SW_HIDE } as WORD;
-        info.StartupInfo.hStdInput = stdio.stdin.0;
-        info.StartupInfo.hStdOutput = stdio.stdout.0;
-        info.StartupInfo.hStdError = stdio.stderr.0;
+        // The pseudo console provides standard handles itself.
+        if pseudo_console.is_none() {
+            info.StartupInfo.dwFlags |= STARTF_USESTDHANDLES;
+            info.StartupInfo.hStdInput = stdio.stdin.0;
+            info.StartupInfo.hStdOutput = stdio.stdout.0;
+            info.StartupInfo.hStdError = stdio.stderr.0;
+        }
+        // The desktop name is not modified, even though the pointer is mutable.
         info.StartupInfo.lpDesktop = user
-            .map(|u| u.desktop_name.as_mut_ptr())
+            .map(|u| u.desktop_name.as_ptr() as LPWSTR)
             .unwrap_or(ptr::null_mut());
 
         Ok(StartupInfo {
@@ -360,6 +790,7 @@ impl<'a, 'b, 'c> StartupInfo<'a, 'b, 'c> {
             stdio: PhantomData,
             inherited_handles: PhantomData,
             user: PhantomData,
+            pseudo_console: PhantomData,
         })
     }
 
@@ -504,6 +935,36 @@ impl Endpoints {
             })
     }
 
+    /// Returns the remote endpoints of the TCP connections owned by the given processes.
+    /// The addresses and ports are stored in network byte order.
+    pub fn tcp_remote_endpoints(&mut self, pids: &[ULONG_PTR]) -> Result<Vec<SocketAddr>> {
+        let owned = |owner: DWORD| pids.iter().any(|&pid| pid as DWORD == owner);
+        let mut endpoints = self
+            .load_tcpv4()?
+            .iter()
+            .filter(|row| owned(row.dwOwningPid) && row.dwState != MIB_TCP_STATE_LISTEN)
+            .map(|row| {
+                SocketAddr::from((
+                    Ipv4Addr::from(row.dwRemoteAddr.to_ne_bytes()),
+                    u16::from_be(row.dwRemotePort as u16),
+                ))
+            })
+            .collect::<Vec<_>>();
+        endpoints.extend(
+            self.load_tcpv6()?
+                .iter()
+                .filter(|row| owned(row.dwOwningPid) && row.dwState != MIB_TCP_STATE_LISTEN)
+                .map(|row| {
+                    SocketAddr::from((
+                        Ipv6Addr::from(row.ucRemoteAddr),
+                        u16::from_be(row.dwRemotePort as u16),
+                    ))
+                }),
+        );
+        endpoints.retain(|addr| !addr.ip().is_unspecified());
+        Ok(endpoints)
+    }
+
     fn load_udp<T>(&mut self, af: ULONG) -> Result<&T> {
         self.load(|buf| unsafe {
             let mut size = buf.len() as DWORD;
@@ -588,18 +1049,21 @@ impl JobNotifications {
     }
 
     pub fn is_memory_limit_hit(&mut self) -> Result<bool> {
-        self.recv_message().map(|_| self.is_memory_limit_hit)
+        self.recv_messages().map(|_| self.is_memory_limit_hit)
     }
     pub fn is_active_process_limit_hit(&mut self) -> Result<bool> {
-        self.recv_message()
+        self.recv_messages()
             .map(|_| self.is_active_process_limit_hit)
     }
 
-    fn recv_message(&mut self) -> Result<()> {
+    // Reads every queued message, so that a limit message is not left behind the messages
+    // about new and exited processes. The flags stay set once read, so every supervisor of
+    // a shared group sees a hit limit, whichever of them has read the message.
+    fn recv_messages(&mut self) -> Result<()> {
         let mut num_bytes = 0;
         let mut _key = 0;
         let mut _overlapped = ptr::null_mut();
-        if unsafe {
+        while unsafe {
             GetQueuedCompletionStatus(
                 /*CompletionPort=*/ self.completion_port.raw(),
                 /*lpNumberOfBytes=*/ &mut num_bytes,
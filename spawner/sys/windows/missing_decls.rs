@@ -1,9 +1,16 @@
 use winapi::shared::basetsd::DWORD_PTR;
 use winapi::shared::minwindef::{BOOL, DWORD, PDWORD, UCHAR, ULONG};
-use winapi::um::winnt::PVOID;
+use winapi::shared::ntdef::HRESULT;
+use winapi::um::wincontypes::COORD;
+use winapi::um::winnt::{HANDLE, PVOID};
 use winapi::{ENUM, STRUCT};
 
 pub const PROC_THREAD_ATTRIBUTE_HANDLE_LIST: DWORD_PTR = 131_074;
+pub const PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE: DWORD_PTR = 131_094;
+
+pub type HPCON = PVOID;
+
+pub const MIB_TCP_STATE_LISTEN: DWORD = 2;
 
 ENUM! {
     enum TCP_TABLE_CLASS {
@@ -116,3 +123,16 @@ extern "system" {
         Reserved: ULONG,
     ) -> DWORD;
 }
+
+#[link(name = "kernel32")]
+extern "system" {
+    pub fn CreatePseudoConsole(
+        size: COORD,
+        hInput: HANDLE,
+        hOutput: HANDLE,
+        dwFlags: DWORD,
+        phPC: *mut HPCON,
+    ) -> HRESULT;
+
+    pub fn ClosePseudoConsole(hPC: HPCON);
+}
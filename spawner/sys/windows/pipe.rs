@@ -1,22 +1,33 @@
-use crate::sys::windows::helpers::{cvt, to_utf16, Handle};
+use crate::sys::windows::helpers::{cvt, to_utf16_checked, Handle};
+use crate::sys::windows::pipe_ext::ShareMode;
 use crate::sys::IntoInner;
 use crate::{Error, Result};
 
 use winapi::shared::minwindef::{DWORD, LPVOID, TRUE};
-use winapi::um::fileapi::{CreateFileW, ReadFile, WriteFile, CREATE_ALWAYS, OPEN_EXISTING};
-use winapi::um::handleapi::{SetHandleInformation, INVALID_HANDLE_VALUE};
+use winapi::um::fileapi::{
+    CreateFileW, GetFileType, ReadFile, WriteFile, CREATE_ALWAYS, OPEN_ALWAYS, OPEN_EXISTING,
+};
+use winapi::um::handleapi::{DuplicateHandle, SetHandleInformation, INVALID_HANDLE_VALUE};
 use winapi::um::minwinbase::SECURITY_ATTRIBUTES;
 use winapi::um::namedpipeapi::CreatePipe;
-use winapi::um::winbase::HANDLE_FLAG_INHERIT;
+use winapi::um::processenv::GetStdHandle;
+use winapi::um::processthreadsapi::GetCurrentProcess;
+use winapi::um::winbase::{FILE_TYPE_DISK, FILE_TYPE_PIPE, HANDLE_FLAG_INHERIT, STD_OUTPUT_HANDLE};
 use winapi::um::winnt::{
-    FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, GENERIC_WRITE,
+    DUPLICATE_SAME_ACCESS, FILE_ATTRIBUTE_NORMAL, FILE_GENERIC_WRITE, FILE_SHARE_READ,
+    FILE_SHARE_WRITE, FILE_WRITE_DATA, GENERIC_READ, GENERIC_WRITE, HANDLE,
 };
 
 use std::io::{self, Read, Write};
 use std::mem::size_of;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
 use std::path::Path;
 use std::ptr;
 
+// Write access without FILE_WRITE_DATA, so that every write is appended to the end of the
+// file.
+const APPEND_ACCESS: DWORD = FILE_GENERIC_WRITE & !FILE_WRITE_DATA;
+
 #[derive(Debug)]
 pub struct ReadPipe(Handle);
 
@@ -49,16 +60,44 @@ pub fn create() -> Result<(ReadPipe, WritePipe)> {
 
 impl ReadPipe {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        open(path, GENERIC_READ, OPEN_EXISTING, false).map(Self)
+        open(path, GENERIC_READ, OPEN_EXISTING, ShareMode::Shared).map(Self)
     }
 
     pub fn lock<P: AsRef<Path>>(path: P) -> Result<Self> {
-        open(path, GENERIC_READ, OPEN_EXISTING, true).map(Self)
+        open(path, GENERIC_READ, OPEN_EXISTING, ShareMode::Exclusive).map(Self)
+    }
+
+    pub fn open_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self> {
+        open(path, GENERIC_READ, OPEN_EXISTING, mode).map(Self)
     }
 
     pub fn null() -> Result<Self> {
         Self::open("nul")
     }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        duplicate(self.0.raw()).map(Self)
+    }
+
+    pub fn is_file(&self) -> bool {
+        file_type(&self.0) == FILE_TYPE_DISK
+    }
+
+    pub fn is_pipe(&self) -> bool {
+        file_type(&self.0) == FILE_TYPE_PIPE
+    }
+}
+
+impl AsRawHandle for ReadPipe {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.raw() as RawHandle
+    }
+}
+
+impl FromRawHandle for ReadPipe {
+    unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+        Self(Handle::new(handle as HANDLE))
+    }
 }
 
 impl IntoInner<Handle> for ReadPipe {
@@ -86,15 +125,59 @@ impl Read for ReadPipe {
 
 impl WritePipe {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        open(path, GENERIC_WRITE, CREATE_ALWAYS, false).map(Self)
+        open(path, GENERIC_WRITE, CREATE_ALWAYS, ShareMode::Shared).map(Self)
     }
 
     pub fn lock<P: AsRef<Path>>(path: P) -> Result<Self> {
-        open(path, GENERIC_WRITE, CREATE_ALWAYS, true).map(Self)
+        open(path, GENERIC_WRITE, CREATE_ALWAYS, ShareMode::Exclusive).map(Self)
+    }
+
+    pub fn open_append<P: AsRef<Path>>(path: P) -> Result<Self> {
+        open(path, APPEND_ACCESS, OPEN_ALWAYS, ShareMode::Shared).map(Self)
+    }
+
+    pub fn lock_append<P: AsRef<Path>>(path: P) -> Result<Self> {
+        open(path, APPEND_ACCESS, OPEN_ALWAYS, ShareMode::Exclusive).map(Self)
+    }
+
+    pub fn open_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self> {
+        open(path, GENERIC_WRITE, CREATE_ALWAYS, mode).map(Self)
+    }
+
+    pub fn open_append_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self> {
+        open(path, APPEND_ACCESS, OPEN_ALWAYS, mode).map(Self)
     }
 
     pub fn null() -> Result<Self> {
-        open("nul", GENERIC_WRITE, OPEN_EXISTING, false).map(Self)
+        open("nul", GENERIC_WRITE, OPEN_EXISTING, ShareMode::Shared).map(Self)
+    }
+
+    pub fn stdout() -> Result<Self> {
+        duplicate(unsafe { GetStdHandle(STD_OUTPUT_HANDLE) }).map(Self)
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        duplicate(self.0.raw()).map(Self)
+    }
+
+    pub fn is_file(&self) -> bool {
+        file_type(&self.0) == FILE_TYPE_DISK
+    }
+
+    pub fn is_pipe(&self) -> bool {
+        file_type(&self.0) == FILE_TYPE_PIPE
+    }
+}
+
+impl AsRawHandle for WritePipe {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.raw() as RawHandle
+    }
+}
+
+impl FromRawHandle for WritePipe {
+    unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+        Self(Handle::new(handle as HANDLE))
     }
 }
 
@@ -129,17 +212,18 @@ fn open<P: AsRef<Path>>(
     path: P,
     access: DWORD,
     creation_disposition: DWORD,
-    exclusive: bool,
+    share_mode: ShareMode,
 ) -> Result<Handle> {
+    let mut path = to_utf16_checked(path.as_ref(), "path")?;
     let handle = unsafe {
         Handle::new(CreateFileW(
-            /*lpFileName=*/ to_utf16(path.as_ref()).as_mut_ptr(),
+            /*lpFileName=*/ path.as_mut_ptr(),
             /*dwDesiredAccess=*/ access,
             /*dwShareMode=*/
-            if exclusive {
-                0
-            } else {
-                FILE_SHARE_READ | FILE_SHARE_WRITE
+            match share_mode {
+                ShareMode::Shared => FILE_SHARE_READ | FILE_SHARE_WRITE,
+                ShareMode::ReadShared => FILE_SHARE_READ,
+                ShareMode::Exclusive => 0,
             },
             /*lpSecurityAttributes=*/ ptr::null_mut(),
             /*dwCreationDisposition=*/ creation_disposition,
@@ -162,3 +246,24 @@ fn open<P: AsRef<Path>>(
         .map(|_| handle)
     }
 }
+
+fn file_type(handle: &Handle) -> DWORD {
+    unsafe { GetFileType(handle.raw()) }
+}
+
+fn duplicate(src: HANDLE) -> Result<Handle> {
+    let mut handle = INVALID_HANDLE_VALUE;
+    unsafe {
+        let process = GetCurrentProcess();
+        cvt(DuplicateHandle(
+            process,
+            src,
+            process,
+            &mut handle,
+            0,
+            TRUE,
+            DUPLICATE_SAME_ACCESS,
+        ))?;
+    }
+    Ok(Handle::new(handle))
+}
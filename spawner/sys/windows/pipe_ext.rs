@@ -5,22 +5,75 @@ use crate::Result;
 
 use std::path::Path;
 
+/// Which access other handles may open a file with while the pipe keeps it open, see
+/// `dwShareMode` of `CreateFileW`. The plain `open` functions of the pipes use `Shared`,
+/// and the `lock` functions use `Exclusive`.
+///
+/// There is no counterpart on Linux, where a file can always be opened by others: the
+/// pipes there open files shared, and no advisory locks are taken.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShareMode {
+    /// Others may both read and write the file.
+    Shared,
+    /// Others may read the file but not write it, e.g. to follow a log while the child
+    /// writes it.
+    ReadShared,
+    /// Others can not open the file at all.
+    Exclusive,
+}
+
 pub trait ReadPipeExt: Sized {
     fn lock<P: AsRef<Path>>(path: P) -> Result<Self>;
+
+    /// Version of [`ReadPipe::open`] with the given share mode.
+    ///
+    /// [`ReadPipe::open`]: ../../pipe/struct.ReadPipe.html#method.open
+    fn open_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self>;
 }
 
 pub trait WritePipeExt: Sized {
     fn lock<P: AsRef<Path>>(path: P) -> Result<Self>;
+
+    /// Exclusive version of [`WritePipe::open_append`].
+    ///
+    /// [`WritePipe::open_append`]: ../../pipe/struct.WritePipe.html#method.open_append
+    fn lock_append<P: AsRef<Path>>(path: P) -> Result<Self>;
+
+    /// Version of [`WritePipe::open`] with the given share mode.
+    ///
+    /// [`WritePipe::open`]: ../../pipe/struct.WritePipe.html#method.open
+    fn open_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self>;
+
+    /// Version of [`WritePipe::open_append`] with the given share mode.
+    ///
+    /// [`WritePipe::open_append`]: ../../pipe/struct.WritePipe.html#method.open_append
+    fn open_append_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self>;
 }
 
 impl ReadPipeExt for ReadPipe {
     fn lock<P: AsRef<Path>>(path: P) -> Result<Self> {
         imp::ReadPipe::lock(path).map(Self::from_inner)
     }
+
+    fn open_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self> {
+        imp::ReadPipe::open_shared(path, mode).map(Self::from_inner)
+    }
 }
 
 impl WritePipeExt for WritePipe {
     fn lock<P: AsRef<Path>>(path: P) -> Result<Self> {
         imp::WritePipe::lock(path).map(Self::from_inner)
     }
+
+    fn lock_append<P: AsRef<Path>>(path: P) -> Result<Self> {
+        imp::WritePipe::lock_append(path).map(Self::from_inner)
+    }
+
+    fn open_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self> {
+        imp::WritePipe::open_shared(path, mode).map(Self::from_inner)
+    }
+
+    fn open_append_shared<P: AsRef<Path>>(path: P, mode: ShareMode) -> Result<Self> {
+        imp::WritePipe::open_append_shared(path, mode).map(Self::from_inner)
+    }
 }
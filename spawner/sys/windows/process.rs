@@ -1,16 +1,20 @@
+use crate::cmdline;
 use crate::process::{
-    ExitStatus, GroupIo, GroupMemory, GroupNetwork, GroupPidCounters, GroupTimers, OsLimit,
+    ConsoleMode, EnvMode, ExitStatus, FsSandbox, GroupIo, GroupMemory, GroupNetwork,
+    GroupPidCounters, GroupTimers, OsLimit, Priority, ProcessNode, RestrictOptions,
 };
 use crate::sys::windows::helpers::{
-    cvt, to_utf16, Endpoints, EnvBlock, Handle, JobNotifications, PidList, RawStdio, StartupInfo,
-    User, UserContext,
+    count_handles, count_threads, create_restricted_token, cvt, process_entries,
+    process_start_time, sum_memory_counters, to_utf16_checked, Endpoints, EnvBlock, Handle,
+    JobNotifications, PidList, PseudoConsole, RawStdio, StartupInfo, User,
 };
 use crate::sys::windows::pipe::{ReadPipe, WritePipe};
 use crate::sys::windows::process_ext::UiRestrictions;
 use crate::sys::IntoInner;
 use crate::{Error, Result};
 
-use winapi::shared::minwindef::{DWORD, LPVOID, TRUE};
+use winapi::shared::basetsd::DWORD_PTR;
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPVOID, TRUE};
 use winapi::um::errhandlingapi::SetErrorMode;
 use winapi::um::jobapi2::{
     AssignProcessToJobObject, CreateJobObjectW, QueryInformationJobObject, SetInformationJobObject,
@@ -18,42 +22,56 @@ use winapi::um::jobapi2::{
 };
 use winapi::um::minwinbase::STILL_ACTIVE;
 use winapi::um::processthreadsapi::{
-    CreateProcessAsUserW, CreateProcessW, GetExitCodeProcess, ResumeThread, SuspendThread,
-    TerminateProcess, PROCESS_INFORMATION,
+    CreateProcessAsUserW, CreateProcessW, GetCurrentProcess, GetExitCodeProcess, GetProcessId,
+    GetThreadIOPendingFlag, ResumeThread, SuspendThread, TerminateProcess, PROCESS_INFORMATION,
 };
+use winapi::um::synchapi::{CreateEventW, SetEvent, WaitForMultipleObjects, WaitForSingleObject};
 use winapi::um::winbase::{
+    GetProcessAffinityMask, SetProcessAffinityMask, ABOVE_NORMAL_PRIORITY_CLASS,
+    BELOW_NORMAL_PRIORITY_CLASS, CREATE_NEW_CONSOLE, CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW,
     CREATE_SUSPENDED, CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT,
-    SEM_FAILCRITICALERRORS, SEM_NOGPFAULTERRORBOX,
+    HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, INFINITE, NORMAL_PRIORITY_CLASS,
+    SEM_FAILCRITICALERRORS, SEM_NOGPFAULTERRORBOX, WAIT_FAILED,
 };
+use winapi::um::wincon::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
 use winapi::um::winnt::{
     JobObjectBasicAccountingInformation, JobObjectBasicAndIoAccountingInformation,
-    JobObjectBasicUIRestrictions, JobObjectExtendedLimitInformation, JOBOBJECTINFOCLASS,
+    JobObjectBasicUIRestrictions, JobObjectExtendedLimitInformation, HANDLE, JOBOBJECTINFOCLASS,
     JOBOBJECT_BASIC_ACCOUNTING_INFORMATION, JOBOBJECT_BASIC_AND_IO_ACCOUNTING_INFORMATION,
     JOBOBJECT_BASIC_UI_RESTRICTIONS, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
-    JOB_OBJECT_LIMIT_ACTIVE_PROCESS, JOB_OBJECT_LIMIT_JOB_MEMORY, STATUS_ACCESS_VIOLATION,
-    STATUS_ARRAY_BOUNDS_EXCEEDED, STATUS_BREAKPOINT, STATUS_CONTROL_C_EXIT,
-    STATUS_DATATYPE_MISALIGNMENT, STATUS_FLOAT_DENORMAL_OPERAND, STATUS_FLOAT_INEXACT_RESULT,
-    STATUS_FLOAT_INVALID_OPERATION, STATUS_FLOAT_MULTIPLE_FAULTS, STATUS_FLOAT_MULTIPLE_TRAPS,
-    STATUS_FLOAT_OVERFLOW, STATUS_FLOAT_STACK_CHECK, STATUS_FLOAT_UNDERFLOW,
-    STATUS_GUARD_PAGE_VIOLATION, STATUS_ILLEGAL_INSTRUCTION, STATUS_INTEGER_DIVIDE_BY_ZERO,
-    STATUS_INTEGER_OVERFLOW, STATUS_INVALID_DISPOSITION, STATUS_IN_PAGE_ERROR,
-    STATUS_NONCONTINUABLE_EXCEPTION, STATUS_PRIVILEGED_INSTRUCTION, STATUS_REG_NAT_CONSUMPTION,
-    STATUS_SINGLE_STEP, STATUS_STACK_OVERFLOW,
+    JOB_OBJECT_LIMIT_ACTIVE_PROCESS, JOB_OBJECT_LIMIT_JOB_MEMORY,
+    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, STATUS_ACCESS_VIOLATION, STATUS_ARRAY_BOUNDS_EXCEEDED,
+    STATUS_BREAKPOINT, STATUS_CONTROL_C_EXIT, STATUS_DATATYPE_MISALIGNMENT,
+    STATUS_FLOAT_DENORMAL_OPERAND, STATUS_FLOAT_INEXACT_RESULT, STATUS_FLOAT_INVALID_OPERATION,
+    STATUS_FLOAT_MULTIPLE_FAULTS, STATUS_FLOAT_MULTIPLE_TRAPS, STATUS_FLOAT_OVERFLOW,
+    STATUS_FLOAT_STACK_CHECK, STATUS_FLOAT_UNDERFLOW, STATUS_GUARD_PAGE_VIOLATION,
+    STATUS_ILLEGAL_INSTRUCTION, STATUS_INTEGER_DIVIDE_BY_ZERO, STATUS_INTEGER_OVERFLOW,
+    STATUS_INVALID_DISPOSITION, STATUS_IN_PAGE_ERROR, STATUS_NONCONTINUABLE_EXCEPTION,
+    STATUS_PRIVILEGED_INSTRUCTION, STATUS_REG_NAT_CONSUMPTION, STATUS_SINGLE_STEP,
+    STATUS_STACK_OVERFLOW,
 };
 
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fmt::{self, Write};
+use std::ffi::{OsStr, OsString};
 use std::fs::canonicalize;
 use std::mem::{size_of_val, zeroed};
+use std::net::SocketAddr;
 use std::ptr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::u32;
 
+/// Variables that are kept in a cleared environment, since many programs
+/// (and some system DLLs) fail to initialize without them.
+const REQUIRED_ENV_VARS: [&str; 1] = ["SystemRoot"];
+
+#[derive(Clone)]
 enum Env {
     Clear,
     Inherit,
     User,
+    Set(HashMap<String, String>),
 }
 
 pub struct Stdio {
@@ -62,26 +80,49 @@ pub struct Stdio {
     pub stderr: WritePipe,
 }
 
+#[derive(Clone)]
 pub struct ProcessInfo {
     app: String,
-    args: Vec<String>,
+    args: Vec<OsString>,
     working_dir: Option<String>,
     show_window: bool,
     suspended: bool,
     search_in_path: bool,
     env: Env,
-    envs: HashMap<String, String>,
+    envs: Vec<(String, String)>,
     user_creds: Option<(String, Option<String>)>,
+    user_domain: Option<String>,
+    run_as: Option<Arc<User>>,
+    cpu_affinity: Option<u64>,
+    priority: Option<Priority>,
+    console: ConsoleMode,
+    interruptible: bool,
+    pseudo_console: Option<(u16, u16)>,
+    restrict: Option<RestrictOptions>,
+    fs_sandbox: Option<FsSandbox>,
+    deny_network: bool,
+    inherited_handles: Vec<InheritedHandle>,
+    kill_on_drop: bool,
 }
 
+// A handle that is inherited by the child. It is neither owned nor used, only passed to
+// `CreateProcess`.
+#[derive(Copy, Clone)]
+struct InheritedHandle(HANDLE);
+
+// Safety: the handle is never dereferenced or closed, and handle values are valid in every
+// thread of the process, so the value can be moved to another thread.
+unsafe impl Send for InheritedHandle {}
+
 pub struct Process {
     handle: Handle,
     main_thread: Handle,
-    user: Option<User>,
+    // Keeps the window station and the desktop of the user alive.
+    _user: Option<Arc<User>>,
+    // Closing the pseudo console terminates its output, see `ProcessInfo::pseudo_console`.
+    _pseudo_console: Option<PseudoConsole>,
 }
 
-unsafe impl Send for Process {}
-
 pub struct ResourceUsage<'a> {
     group: &'a Group,
     pid_list: RefCell<PidList>,
@@ -90,31 +131,52 @@ pub struct ResourceUsage<'a> {
 
 pub struct Group {
     job: Handle,
-    notifications: RefCell<JobNotifications>,
+    notifications: Mutex<JobNotifications>,
 }
 
+// The job handle may be used from any thread, and the rest is synchronized.
+unsafe impl Sync for Group {}
+
+// An auto-reset event, so that a wait satisfied by it also resets it.
+pub struct WakeEvent(Handle);
+
+// Events can be signaled from any thread.
+unsafe impl Sync for WakeEvent {}
+
 impl ProcessInfo {
     pub fn new<T: AsRef<str>>(app: T) -> Self {
         Self {
             app: app.as_ref().to_string(),
             args: Vec::new(),
             working_dir: None,
-            show_window: true,
+            show_window: false,
             search_in_path: true,
             suspended: false,
             env: Env::Inherit,
-            envs: HashMap::new(),
+            envs: Vec::new(),
             user_creds: None,
+            user_domain: None,
+            run_as: None,
+            cpu_affinity: None,
+            priority: None,
+            console: ConsoleMode::Inherit,
+            interruptible: false,
+            pseudo_console: None,
+            restrict: None,
+            fs_sandbox: None,
+            deny_network: false,
+            inherited_handles: Vec::new(),
+            kill_on_drop: true,
         }
     }
 
     pub fn args<T, U>(&mut self, args: T) -> &mut Self
     where
         T: IntoIterator<Item = U>,
-        U: AsRef<str>,
+        U: AsRef<OsStr>,
     {
         self.args
-            .extend(args.into_iter().map(|s| s.as_ref().to_string()));
+            .extend(args.into_iter().map(|s| s.as_ref().to_os_string()));
         self
     }
 
@@ -146,13 +208,12 @@ impl ProcessInfo {
         self
     }
 
-    pub fn env_clear(&mut self) -> &mut Self {
-        self.env = Env::Clear;
-        self
-    }
-
-    pub fn env_inherit(&mut self) -> &mut Self {
-        self.env = Env::Inherit;
+    pub fn env(&mut self, mode: EnvMode) -> &mut Self {
+        self.env = match mode {
+            EnvMode::Clear => Env::Clear,
+            EnvMode::Inherit => Env::Inherit,
+            EnvMode::Set(vars) => Env::Set(vars),
+        };
         self
     }
 
@@ -173,10 +234,111 @@ impl ProcessInfo {
         self
     }
 
+    pub fn user_domain<T: AsRef<str>>(&mut self, domain: T) -> &mut Self {
+        self.user_domain = Some(domain.as_ref().to_string());
+        self
+    }
+
+    pub fn run_as(&mut self, user: Arc<User>) -> &mut Self {
+        self.run_as = Some(user);
+        self
+    }
+
+    pub fn cpu_affinity(&mut self, mask: u64) -> &mut Self {
+        self.cpu_affinity = Some(mask);
+        self
+    }
+
+    pub fn priority(&mut self, priority: Priority) -> &mut Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.validate_isolation()?;
+        self.create_user()?;
+        if let Some(mask) = self.cpu_affinity {
+            validate_affinity_mask(mask)?;
+        }
+        Ok(())
+    }
+
     pub fn show_window(&mut self, show: bool) -> &mut Self {
         self.show_window = show;
         self
     }
+
+    pub fn interruptible(&mut self, v: bool) -> &mut Self {
+        self.interruptible = v;
+        self
+    }
+
+    pub fn console(&mut self, mode: ConsoleMode) -> &mut Self {
+        self.console = mode;
+        self
+    }
+
+    pub fn pseudo_console(&mut self, cols: u16, rows: u16) -> &mut Self {
+        self.pseudo_console = Some((cols, rows));
+        self
+    }
+
+    pub fn restrict_token(&mut self, options: RestrictOptions) -> &mut Self {
+        self.restrict = Some(options);
+        self
+    }
+
+    pub fn filesystem_sandbox(&mut self, sandbox: FsSandbox) -> &mut Self {
+        self.fs_sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn deny_network(&mut self, v: bool) -> &mut Self {
+        self.deny_network = v;
+        self
+    }
+
+    pub fn kill_on_drop(&mut self, v: bool) -> &mut Self {
+        self.kill_on_drop = v;
+        self
+    }
+
+    pub fn is_kill_on_drop(&self) -> bool {
+        self.kill_on_drop
+    }
+
+    pub fn inherit_handle(&mut self, handle: HANDLE) -> &mut Self {
+        self.inherited_handles.push(InheritedHandle(handle));
+        self
+    }
+
+    fn validate_isolation(&self) -> Result<()> {
+        if self.fs_sandbox.is_some() {
+            return Err(Error::from(
+                "Filesystem sandbox is not supported on Windows",
+            ));
+        }
+        if self.deny_network {
+            return Err(Error::from(
+                "Denying network access is not supported on Windows",
+            ));
+        }
+        Ok(())
+    }
+
+    fn create_user(&self) -> Result<Option<Arc<User>>> {
+        if let Some(ref user) = self.run_as {
+            return Ok(Some(user.clone()));
+        }
+        self.user_creds
+            .as_ref()
+            .map(|(name, password)| match self.user_domain {
+                Some(ref domain) => User::create_with_domain(name, domain, password.as_ref()),
+                None => User::create(name, password.as_ref()),
+            })
+            .transpose()
+            .map(|user| user.map(Arc::new))
+    }
 }
 
 impl AsRef<ProcessInfo> for ProcessInfo {
@@ -186,6 +348,10 @@ impl AsRef<ProcessInfo> for ProcessInfo {
 }
 
 impl Process {
+    pub fn pid(&self) -> u32 {
+        unsafe { GetProcessId(self.handle.raw()) }
+    }
+
     pub fn exit_status(&self) -> Result<Option<ExitStatus>> {
         let mut exit_code: DWORD = 0;
         unsafe {
@@ -226,8 +392,44 @@ impl Process {
         })
     }
 
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+        // The exit code can be set before the handle is signaled, see `exit_status`.
+        if let Some(status) = self.exit_status()? {
+            return Ok(Some(status));
+        }
+        let ms = match timeout {
+            // Rounded up, so that the process is not reported as running just before the
+            // timeout. INFINITE itself is out of range.
+            Some(t) => std::cmp::min(t.as_micros().div_ceil(1000), (INFINITE - 1) as u128),
+            None => INFINITE as u128,
+        };
+        if unsafe { WaitForSingleObject(self.handle.raw(), ms as DWORD) } == WAIT_FAILED {
+            return Err(Error::last_os_error());
+        }
+        self.exit_status()
+    }
+
+    pub fn wait_or_wake(&self, event: &WakeEvent, timeout: Duration) -> Result<Option<ExitStatus>> {
+        if let Some(status) = self.exit_status()? {
+            return Ok(Some(status));
+        }
+        let handles = [self.handle.raw(), event.0.raw()];
+        let ms = std::cmp::min(timeout.as_micros().div_ceil(1000), (INFINITE - 1) as u128);
+        let result = unsafe {
+            WaitForMultipleObjects(
+                handles.len() as DWORD,
+                handles.as_ptr(),
+                /*bWaitAll=*/ FALSE,
+                ms as DWORD,
+            )
+        };
+        if result == WAIT_FAILED {
+            return Err(Error::last_os_error());
+        }
+        self.exit_status()
+    }
+
     pub fn suspend(&self) -> Result<()> {
-        let _ctx = UserContext::enter(&self.user);
         unsafe {
             match SuspendThread(self.main_thread.raw()) {
                 u32::MAX => Err(Error::last_os_error()),
@@ -237,7 +439,6 @@ impl Process {
     }
 
     pub fn resume(&self) -> Result<()> {
-        let _ctx = UserContext::enter(&self.user);
         unsafe {
             match ResumeThread(self.main_thread.raw()) {
                 u32::MAX => Err(Error::last_os_error()),
@@ -253,6 +454,19 @@ impl Process {
         Ok(())
     }
 
+    pub fn is_waiting_on_stdin(&self) -> Result<bool> {
+        // There is no cheap way to find out which handle a thread waits on, so any
+        // pending I/O request of the main thread is treated as a blocking stdin read.
+        let mut is_pending: BOOL = FALSE;
+        unsafe {
+            cvt(GetThreadIOPendingFlag(
+                self.main_thread.raw(),
+                &mut is_pending,
+            ))?;
+        }
+        Ok(is_pending != FALSE)
+    }
+
     pub fn spawn(info: &mut ProcessInfo, stdio: Stdio) -> Result<Self> {
         let ps = Self::suspended(info, stdio)?;
         if !info.suspended {
@@ -261,7 +475,10 @@ impl Process {
         Ok(ps)
     }
 
-    pub fn spawn_in_group(info: &mut ProcessInfo, stdio: Stdio, group: &mut Group) -> Result<Self> {
+    pub fn spawn_in_group(info: &mut ProcessInfo, stdio: Stdio, group: &Group) -> Result<Self> {
+        if !info.kill_on_drop {
+            group.set_kill_on_close(false)?;
+        }
         let ps = Self::suspended(info, stdio)?;
         group.add(&ps)?;
         if !info.suspended {
@@ -271,17 +488,34 @@ impl Process {
     }
 
     fn suspended(info: &mut ProcessInfo, stdio: Stdio) -> Result<Self> {
+        info.validate_isolation()?;
+        if let Some(mask) = info.cpu_affinity {
+            validate_affinity_mask(mask)?;
+        }
         let stdio = RawStdio {
             stdin: stdio.stdin.into_inner(),
             stdout: stdio.stdout.into_inner(),
             stderr: stdio.stderr.into_inner(),
         };
-        let mut user = info
-            .user_creds
+        let user = info.create_user()?;
+        let restricted_token = info
+            .restrict
             .as_ref()
-            .map(|(name, password)| User::create(name, password.as_ref()))
+            .map(|options| create_restricted_token(user.as_ref().map(|u| u.token()), options))
             .transpose()?;
-        let user_token = user.as_ref().map(|u| u.token().raw());
+        let user_token = restricted_token
+            .as_ref()
+            .or_else(|| user.as_ref().map(|u| u.token()))
+            .map(|token| token.raw());
+        let pseudo_console = info
+            .pseudo_console
+            .map(|(cols, rows)| PseudoConsole::create(cols, rows, &stdio.stdin, &stdio.stdout))
+            .transpose()?;
+        let console_mode = if pseudo_console.is_some() {
+            0
+        } else {
+            console_flags(info.console)
+        };
 
         let app = if info.search_in_path {
             None
@@ -289,26 +523,51 @@ impl Process {
             Some(
                 canonicalize(&info.app)
                     .map_err(|_| Error::last_os_error())
-                    .map(to_utf16)?,
+                    .and_then(|app| to_utf16_checked(app, "application path"))?,
             )
         };
         let app_ptr = app.as_ref().map_or(ptr::null(), |v| v.as_ptr());
 
-        let mut cmd = argv_to_cmd(std::iter::once(&info.app).chain(info.args.iter()));
-        let mut env = create_env(info, user.as_ref())?;
-        let creation_flags =
-            CREATE_UNICODE_ENVIRONMENT | EXTENDED_STARTUPINFO_PRESENT | CREATE_SUSPENDED;
+        let mut cmd = to_utf16_checked(
+            cmdline::join_windows_os(
+                std::iter::once(OsStr::new(&info.app))
+                    .chain(info.args.iter().map(|s| s.as_os_str())),
+            ),
+            "command line",
+        )?;
+        let mut env = create_env(info, user.as_deref())?;
+        // Makes the process a leader of a console process group, see `Group::interrupt`.
+        let process_group = if info.interruptible {
+            CREATE_NEW_PROCESS_GROUP
+        } else {
+            0
+        };
+        let creation_flags = CREATE_UNICODE_ENVIRONMENT
+            | EXTENDED_STARTUPINFO_PRESENT
+            | CREATE_SUSPENDED
+            | process_group
+            | console_mode
+            | info.priority.map_or(0, priority_class);
         let working_dir = info
             .working_dir
             .as_ref()
-            .map_or(ptr::null(), |dir| to_utf16(dir).as_ptr());
-
-        // Allow child process to inherit only stdio handles.
-        let mut inherited_handles = [stdio.stdin.raw(), stdio.stdout.raw(), stdio.stderr.raw()];
+            .map(|dir| to_utf16_checked(dir, "working directory"))
+            .transpose()?;
+        let working_dir = working_dir.as_ref().map_or(ptr::null(), |dir| dir.as_ptr());
+
+        // Allow child process to inherit only stdio and explicitly listed handles. The list
+        // must not contain duplicates.
+        let mut inherited_handles = vec![stdio.stdin.raw(), stdio.stdout.raw(), stdio.stderr.raw()];
+        for &InheritedHandle(handle) in info.inherited_handles.iter() {
+            if !inherited_handles.contains(&handle) {
+                inherited_handles.push(handle);
+            }
+        }
         let mut startup_info = StartupInfo::create(
             &stdio,
             &mut inherited_handles,
-            user.as_mut(),
+            user.as_deref(),
+            pseudo_console.as_ref(),
             info.show_window,
         )?;
 
@@ -352,11 +611,21 @@ impl Process {
             cvt(result)?;
         }
 
-        Ok(Self {
+        let ps = Self {
             handle: Handle::new(process_info.hProcess),
             main_thread: Handle::new(process_info.hThread),
-            user,
-        })
+            _user: user,
+            _pseudo_console: pseudo_console,
+        };
+        if let Some(mask) = info.cpu_affinity {
+            // Child processes inherit the affinity of their parent.
+            let result = unsafe { cvt(SetProcessAffinityMask(ps.handle.raw(), mask as DWORD_PTR)) };
+            if let Err(e) = result {
+                let _ = ps.terminate();
+                return Err(Error::from(e));
+            }
+        }
+        Ok(ps)
     }
 }
 
@@ -402,28 +671,40 @@ impl<'a> ResourceUsage<'a> {
     }
 
     pub fn memory(&self) -> Result<Option<GroupMemory>> {
-        self.group.ext_limit_info().map(|info| {
-            Some(GroupMemory {
-                max_usage: info.PeakJobMemoryUsed as u64,
-            })
-        })
+        let ext_info = self.group.ext_limit_info()?;
+        let basic_info = self.group.basic_info()?;
+        let mut pid_list = self.pid_list.borrow_mut();
+        let (working_set_bytes, private_bytes) =
+            sum_memory_counters(pid_list.update(&self.group.job)?);
+        Ok(Some(GroupMemory {
+            max_usage: ext_info.PeakJobMemoryUsed as u64,
+            working_set_bytes,
+            private_bytes,
+            page_fault_count: basic_info.TotalPageFaultCount as u64,
+            total_swap_bytes: private_bytes,
+        }))
     }
 
     pub fn io(&self) -> Result<Option<GroupIo>> {
         self.group.basic_and_io_info().map(|info| {
             Some(GroupIo {
                 total_bytes_written: info.IoInfo.WriteTransferCount,
+                total_bytes_read: info.IoInfo.ReadTransferCount,
+                read_operations: info.IoInfo.ReadOperationCount,
+                write_operations: info.IoInfo.WriteOperationCount,
             })
         })
     }
 
     pub fn pid_counters(&self) -> Result<Option<GroupPidCounters>> {
-        self.group.basic_and_io_info().map(|info| {
-            Some(GroupPidCounters {
-                total_processes: info.BasicInfo.TotalProcesses as usize,
-                active_processes: info.BasicInfo.ActiveProcesses as usize,
-            })
-        })
+        let info = self.group.basic_and_io_info()?;
+        let mut pid_list = self.pid_list.borrow_mut();
+        let pids = pid_list.update(&self.group.job)?;
+        Ok(Some(GroupPidCounters {
+            total_processes: info.BasicInfo.TotalProcesses as usize,
+            active_processes: info.BasicInfo.ActiveProcesses as usize,
+            active_threads: count_threads(pids)?,
+        }))
     }
 
     pub fn network(&self) -> Result<Option<GroupNetwork>> {
@@ -436,8 +717,36 @@ impl<'a> ResourceUsage<'a> {
                 + count_endpoints!(pids, endpoints.load_tcpv6()?)
                 + count_endpoints!(pids, endpoints.load_udpv4()?)
                 + count_endpoints!(pids, endpoints.load_udpv6()?),
+            // Accumulated by `process::ResourceUsage`.
+            distinct_remote_endpoints: 0,
         }))
     }
+
+    pub fn remote_endpoints(&self) -> Result<Vec<SocketAddr>> {
+        let mut pid_list = self.pid_list.borrow_mut();
+        let pids = pid_list.update(&self.group.job)?;
+        self.endpoints.borrow_mut().tcp_remote_endpoints(pids)
+    }
+
+    pub fn open_handles(&self) -> Result<Option<usize>> {
+        let mut pid_list = self.pid_list.borrow_mut();
+        let pids = pid_list.update(&self.group.job)?;
+        Ok(Some(count_handles(pids)))
+    }
+}
+
+impl WakeEvent {
+    pub fn new() -> Result<Self> {
+        unsafe { cvt(CreateEventW(ptr::null_mut(), FALSE, FALSE, ptr::null())) }
+            .map(|event| Self(Handle::new(event)))
+            .map_err(Error::from)
+    }
+
+    pub fn wake(&self) -> Result<()> {
+        unsafe { cvt(SetEvent(self.0.raw())) }
+            .map(|_| ())
+            .map_err(Error::from)
+    }
 }
 
 impl Group {
@@ -448,9 +757,15 @@ impl Group {
             .and_then(|job| {
                 JobNotifications::new(&job).map(|notifications| Self {
                     job,
-                    notifications: RefCell::new(notifications),
+                    notifications: Mutex::new(notifications),
                 })
             })
+            .and_then(|group| {
+                // The processes must not outlive the group, even if the supervisor dies
+                // without terminating them.
+                group.set_kill_on_close(true)?;
+                Ok(group)
+            })
     }
 
     pub fn set_ui_restrictions<T>(&mut self, restrictions: T) -> Result<()>
@@ -477,45 +792,90 @@ impl Group {
         Ok(())
     }
 
-    pub fn set_os_limit(&mut self, limit: OsLimit, value: u64) -> Result<bool> {
-        let mut ext_limit_info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { zeroed() };
-
+    pub fn set_os_limit(&self, limit: OsLimit, value: u64) -> Result<bool> {
         match limit {
-            OsLimit::Memory => {
-                ext_limit_info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
-                ext_limit_info.JobMemoryLimit = value as usize;
-            }
-            OsLimit::ActiveProcess => {
-                ext_limit_info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
-                ext_limit_info.BasicLimitInformation.ActiveProcessLimit = value as DWORD;
-            }
-        }
-
-        unsafe {
-            cvt(SetInformationJobObject(
-                /*hJob=*/ self.job.raw(),
-                /*JobObjectInformationClass=*/ JobObjectExtendedLimitInformation,
-                /*lpJobObjectInformation=*/ &mut ext_limit_info as *mut _ as LPVOID,
-                /*cbJobObjectInformationLength=*/ size_of_val(&ext_limit_info) as DWORD,
-            ))?;
+            OsLimit::Memory => self.set_ext_limit_info(|info| {
+                info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+                info.JobMemoryLimit = value as usize;
+            })?,
+            // The committed memory is always accounted, the limit is checked in software.
+            OsLimit::Swap => return Ok(false),
+            OsLimit::ActiveProcess => self.set_ext_limit_info(|info| {
+                info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
+                info.BasicLimitInformation.ActiveProcessLimit = value as DWORD;
+            })?,
         }
-
         Ok(true)
     }
 
     pub fn is_os_limit_hit(&self, limit: OsLimit) -> Result<bool> {
-        let mut notifications = self.notifications.borrow_mut();
+        let mut notifications = self.notifications.lock().unwrap();
         match limit {
             OsLimit::Memory => notifications.is_memory_limit_hit(),
+            OsLimit::Swap => Ok(false),
             OsLimit::ActiveProcess => notifications.is_active_process_limit_hit(),
         }
     }
 
+    pub fn pids(&self) -> Result<Vec<u32>> {
+        let mut pid_list = PidList::new();
+        pid_list
+            .update(&self.job)
+            .map(|pids| pids.iter().map(|&pid| pid as u32).collect())
+    }
+
+    pub fn process_nodes(&self) -> Result<Vec<ProcessNode>> {
+        let mut pid_list = PidList::new();
+        let pids = pid_list.update(&self.job)?;
+        Ok(process_entries(pids)?
+            .into_iter()
+            .map(|(pid, parent_pid, image_name)| ProcessNode {
+                pid,
+                parent_pid,
+                start_time: process_start_time(pid),
+                image_name,
+                // Processes leave the job once they exit.
+                exit_code: None,
+            })
+            .collect())
+    }
+
+    pub fn interrupt(&self) -> Result<()> {
+        let mut pid_list = PidList::new();
+        for &pid in pid_list.update(&self.job)? {
+            // Fails for the processes that are not process group leaders.
+            unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid as DWORD) };
+        }
+        Ok(())
+    }
+
+    pub fn signal(&self, signal: i32) -> Result<()> {
+        Self::validate_signal(signal)?;
+        self.interrupt()
+    }
+
+    pub fn validate_signal(signal: i32) -> Result<()> {
+        if signal as DWORD == CTRL_BREAK_EVENT {
+            Ok(())
+        } else {
+            Err(Error::from(format!(
+                "Invalid console control event {}, only CTRL_BREAK_EVENT ({}) can be sent to a process group",
+                signal, CTRL_BREAK_EVENT
+            )))
+        }
+    }
+
     pub fn terminate(&self) -> Result<()> {
         cvt(unsafe { TerminateJobObject(self.job.raw(), 0) })?;
         Ok(())
     }
 
+    /// The peak memory usage of a job object can't be reset, and a process can't be moved
+    /// out of its job, so the peak is kept.
+    pub fn reset_peak_memory(&self) -> Result<bool> {
+        Ok(false)
+    }
+
     fn query_info<T>(&self, class: JOBOBJECTINFOCLASS) -> Result<T> {
         unsafe {
             let mut info = zeroed::<T>();
@@ -542,41 +902,85 @@ impl Group {
     fn ext_limit_info(&self) -> Result<JOBOBJECT_EXTENDED_LIMIT_INFORMATION> {
         self.query_info(JobObjectExtendedLimitInformation)
     }
-}
 
-fn argv_to_cmd<T, U>(argv: T) -> Vec<u16>
-where
-    T: IntoIterator<Item = U>,
-    U: AsRef<str>,
-{
-    let mut cmd = String::new();
-    for (idx, arg) in argv.into_iter().enumerate() {
-        if idx != 0 {
-            cmd.write_char(' ').unwrap();
+    fn set_kill_on_close(&self, kill: bool) -> Result<()> {
+        self.set_ext_limit_info(|info| {
+            if kill {
+                info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            } else {
+                info.BasicLimitInformation.LimitFlags &= !JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            }
+        })
+    }
+
+    /// Updates the limits of the job, keeping the ones that are already set.
+    fn set_ext_limit_info<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut JOBOBJECT_EXTENDED_LIMIT_INFORMATION),
+    {
+        let mut info = self.ext_limit_info()?;
+        f(&mut info);
+        unsafe {
+            cvt(SetInformationJobObject(
+                /*hJob=*/ self.job.raw(),
+                /*JobObjectInformationClass=*/ JobObjectExtendedLimitInformation,
+                /*lpJobObjectInformation=*/ &mut info as *mut _ as LPVOID,
+                /*cbJobObjectInformationLength=*/ size_of_val(&info) as DWORD,
+            ))?;
         }
-        write_quoted(&mut cmd, arg.as_ref());
+        Ok(())
     }
-    to_utf16(cmd)
 }
 
-fn write_quoted<W, S>(w: &mut W, s: S)
-where
-    W: fmt::Write,
-    S: AsRef<str>,
-{
-    let escaped = s.as_ref().replace("\"", "\\\"");
-    if escaped.find(' ').is_some() {
-        write!(w, "\"{}\"", escaped)
-    } else {
-        w.write_str(escaped.as_str())
+fn priority_class(priority: Priority) -> DWORD {
+    match priority {
+        Priority::Idle => IDLE_PRIORITY_CLASS,
+        Priority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+        Priority::Normal => NORMAL_PRIORITY_CLASS,
+        Priority::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+        Priority::High => HIGH_PRIORITY_CLASS,
     }
-    .unwrap();
+}
+
+fn console_flags(mode: ConsoleMode) -> DWORD {
+    match mode {
+        ConsoleMode::Inherit => 0,
+        ConsoleMode::New => CREATE_NEW_CONSOLE,
+        ConsoleMode::None => CREATE_NO_WINDOW,
+    }
+}
+
+fn validate_affinity_mask(mask: u64) -> Result<()> {
+    if mask == 0 {
+        return Err(Error::from("CPU affinity mask is empty"));
+    }
+    let mut process_mask: DWORD_PTR = 0;
+    let mut system_mask: DWORD_PTR = 0;
+    unsafe {
+        cvt(GetProcessAffinityMask(
+            GetCurrentProcess(),
+            &mut process_mask,
+            &mut system_mask,
+        ))?;
+    }
+    if mask & !(system_mask as u64) != 0 {
+        return Err(Error::from(format!(
+            "CPU affinity mask {:#x} refers to missing cores, the system mask is {:#x}",
+            mask, system_mask
+        )));
+    }
+    Ok(())
 }
 
 fn create_env(info: &ProcessInfo, user: Option<&User>) -> Result<Vec<u16>> {
     let mut env = match info.env {
-        Env::Clear => HashMap::new(),
+        Env::Clear => required_env(),
         Env::Inherit => std::env::vars().collect(),
+        Env::Set(ref vars) => {
+            let mut env = required_env();
+            override_env_vars(&mut env, vars.iter());
+            env
+        }
         Env::User => EnvBlock::create(user)?
             .iter()
             .map(|var| {
@@ -585,11 +989,14 @@ fn create_env(info: &ProcessInfo, user: Option<&User>) -> Result<Vec<u16>> {
             })
             .collect(),
     };
-    env.extend(info.envs.iter().map(|(k, v)| (k.clone(), v.clone())));
+    override_env_vars(&mut env, info.envs.iter().map(|(k, v)| (k, v)));
 
-    let mut result = env
+    let vars = env
+        .into_iter()
+        .map(|(k, v)| to_utf16_checked(format!("{}={}", k, v), "environment block"))
+        .collect::<Result<Vec<_>>>()?;
+    let mut result = vars
         .into_iter()
-        .map(|(k, v)| to_utf16(format!("{}={}", k, v)))
         .flatten()
         .chain(std::iter::once(0))
         .collect::<Vec<u16>>();
@@ -601,6 +1008,24 @@ fn create_env(info: &ProcessInfo, user: Option<&User>) -> Result<Vec<u16>> {
     Ok(result)
 }
 
+fn override_env_vars<'a, I>(env: &mut HashMap<String, String>, vars: I)
+where
+    I: IntoIterator<Item = (&'a String, &'a String)>,
+{
+    for (name, value) in vars {
+        // Variable names are case-insensitive on Windows.
+        env.retain(|k, _| !k.eq_ignore_ascii_case(name));
+        env.insert(name.clone(), value.clone());
+    }
+}
+
+fn required_env() -> HashMap<String, String> {
+    REQUIRED_ENV_VARS
+        .iter()
+        .filter_map(|name| std::env::var(name).ok().map(|v| (name.to_string(), v)))
+        .collect()
+}
+
 fn crash_cause(exit_code: DWORD) -> Option<&'static str> {
     match exit_code {
         STATUS_ACCESS_VIOLATION => Some("AccessViolation"),
@@ -1,18 +1,45 @@
 use crate::process::{Group, ProcessInfo};
+use crate::sys::windows::helpers;
 use crate::sys::{AsInnerMut, IntoInner};
 use crate::Result;
 
 use winapi::shared::minwindef::DWORD;
 use winapi::um::winnt::{
-    JOB_OBJECT_UILIMIT_DESKTOP, JOB_OBJECT_UILIMIT_DISPLAYSETTINGS, JOB_OBJECT_UILIMIT_EXITWINDOWS,
-    JOB_OBJECT_UILIMIT_GLOBALATOMS, JOB_OBJECT_UILIMIT_HANDLES, JOB_OBJECT_UILIMIT_READCLIPBOARD,
-    JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS, JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
+    HANDLE, JOB_OBJECT_UILIMIT_DESKTOP, JOB_OBJECT_UILIMIT_DISPLAYSETTINGS,
+    JOB_OBJECT_UILIMIT_EXITWINDOWS, JOB_OBJECT_UILIMIT_GLOBALATOMS, JOB_OBJECT_UILIMIT_HANDLES,
+    JOB_OBJECT_UILIMIT_READCLIPBOARD, JOB_OBJECT_UILIMIT_SYSTEMPARAMETERS,
+    JOB_OBJECT_UILIMIT_WRITECLIPBOARD,
 };
 
+use std::os::windows::io::RawHandle;
+use std::sync::Arc;
+
 /// https://docs.microsoft.com/en-us/windows/desktop/api/winnt/ns-winnt-_jobobject_basic_ui_restrictions
 #[derive(Default)]
 pub struct UiRestrictions(DWORD);
 
+/// A logged on user account with its own window station and desktop. Can be shared by
+/// several processes.
+#[derive(Clone)]
+pub struct User(Arc<helpers::User>);
+
+/// Options of [`User::create_with_options`].
+///
+/// [`User::create_with_options`]: struct.User.html#method.create_with_options
+#[derive(Clone, Default)]
+pub struct UserOptions {
+    /// The domain of the user. Defaults to the local computer.
+    pub domain: Option<String>,
+    pub password: Option<String>,
+    /// The name of the desktop that is created for the user. Defaults to a unique name, so
+    /// that concurrent users do not share a desktop name.
+    pub desktop_name: Option<String>,
+}
+
+/// The environment that `CreateEnvironmentBlock` builds for the processes of a user, e.g.
+/// to see the `PATH` that a process spawned with `ProcessInfoExt::env_user` gets.
+pub struct EnvBlock(helpers::EnvBlock);
+
 pub trait GroupExt {
     fn set_ui_restrictions<T>(&mut self, r: T) -> Result<()>
     where
@@ -20,8 +47,86 @@ pub trait GroupExt {
 }
 
 pub trait ProcessInfoExt {
-    fn show_window(&mut self, show: bool) -> &mut Self;
     fn env_user(&mut self) -> &mut Self;
+    /// Sets the domain of the user set by `ProcessInfo::user`. Defaults to the local
+    /// computer.
+    fn user_domain<T: AsRef<str>>(&mut self, domain: T) -> &mut Self;
+    /// Spawns the process with the token of `user` through `CreateProcessAsUserW`, on the
+    /// desktop of `user`. Overrides `ProcessInfo::user`.
+    ///
+    /// The spawning process needs the `SE_ASSIGNPRIMARYTOKEN_NAME` and
+    /// `SE_INCREASE_QUOTA_NAME` privileges.
+    fn run_as(&mut self, user: &User) -> &mut Self;
+    /// Lets the child inherit `handle` in addition to stdio, by adding it to the
+    /// `PROC_THREAD_ATTRIBUTE_HANDLE_LIST`. The handle must be created inheritable, and the
+    /// child has to learn its value out of band, e.g. from its arguments.
+    fn inherit_handle(&mut self, handle: RawHandle) -> &mut Self;
+}
+
+impl User {
+    /// Logs on a local user account.
+    pub fn create<T, U>(user: T, password: Option<U>) -> Result<Self>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        helpers::User::create(user, password).map(|u| Self(Arc::new(u)))
+    }
+
+    pub fn create_with_domain<T, D, U>(user: T, domain: D, password: Option<U>) -> Result<Self>
+    where
+        T: AsRef<str>,
+        D: AsRef<str>,
+        U: AsRef<str>,
+    {
+        helpers::User::create_with_domain(user, domain, password).map(|u| Self(Arc::new(u)))
+    }
+
+    /// Logs on a user account with the given options. Unlike [`create`], the desktop of
+    /// the user gets a unique name unless [`UserOptions::desktop_name`] is set.
+    ///
+    /// [`create`]: struct.User.html#method.create
+    /// [`UserOptions::desktop_name`]: struct.UserOptions.html#structfield.desktop_name
+    pub fn create_with_options<T: AsRef<str>>(user: T, options: &UserOptions) -> Result<Self> {
+        helpers::User::create_with_options(user.as_ref(), options).map(|u| Self(Arc::new(u)))
+    }
+
+    /// Returns the full name of the desktop of the user, in the form `winsta\desktop`.
+    pub fn desktop_name(&self) -> String {
+        self.0.desktop_name()
+    }
+
+    /// Closes the window station and the desktop of the user. If they are still used by
+    /// spawned processes, they are closed once the last of these processes is dropped.
+    pub fn close(self) -> Result<()> {
+        match Arc::try_unwrap(self.0) {
+            Ok(user) => user.close(),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl EnvBlock {
+    /// Creates the environment block of `user`, or of the current user if it is `None`.
+    pub fn create(user: Option<&User>) -> Result<Self> {
+        helpers::EnvBlock::create(user.map(|u| &*u.0)).map(Self)
+    }
+
+    /// Returns the `name=value` strings of all variables.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = String> + 'a {
+        self.0.iter()
+    }
+
+    /// Returns the value of the variable `name`. Names are case-insensitive.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.0.get(name)
+    }
+
+    /// Returns the `name=value` strings of the variables whose names start with `prefix`,
+    /// ignoring case.
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.0.iter_prefix(prefix)
+    }
 }
 
 impl UiRestrictions {
@@ -77,13 +182,23 @@ impl IntoInner<DWORD> for UiRestrictions {
 }
 
 impl ProcessInfoExt for ProcessInfo {
-    fn show_window(&mut self, show: bool) -> &mut Self {
-        self.as_inner_mut().show_window(show);
+    fn env_user(&mut self) -> &mut Self {
+        self.as_inner_mut().env_user();
         self
     }
 
-    fn env_user(&mut self) -> &mut Self {
-        self.as_inner_mut().env_user();
+    fn user_domain<T: AsRef<str>>(&mut self, domain: T) -> &mut Self {
+        self.as_inner_mut().user_domain(domain);
+        self
+    }
+
+    fn run_as(&mut self, user: &User) -> &mut Self {
+        self.as_inner_mut().run_as(user.0.clone());
+        self
+    }
+
+    fn inherit_handle(&mut self, handle: RawHandle) -> &mut Self {
+        self.as_inner_mut().inherit_handle(handle as HANDLE);
         self
     }
 }
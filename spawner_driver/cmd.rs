@@ -5,7 +5,7 @@ use crate::value_parser::{
 
 use spawner_opts::{CmdLineOptions, OptionValueParser};
 
-use spawner::VERSION;
+use spawner::{cmdline, DEFAULT_MONITOR_INTERVAL, VERSION};
 
 use std::f64;
 use std::fmt::{self, Display, Formatter};
@@ -54,7 +54,8 @@ pub type StderrRedirectList = RedirectList;
 #[optcont(
     delimeters = "=:",
     usage = "sp [options] executable [arguments]",
-    default_parser = "DefaultValueParser"
+    default_parser = "DefaultValueParser",
+    response_files = "split_response_file"
 )]
 pub struct Command {
     #[opt(
@@ -99,6 +100,15 @@ pub struct Command {
     )]
     pub write_limit: Option<f64>,
 
+    #[opt(
+        name = "-rl",
+        env = "SP_READ_LIMIT",
+        desc = "Set the read limit for an executable",
+        value_desc = "<number>[unit]",
+        parser = "MemValueParser"
+    )]
+    pub read_limit: Option<f64>,
+
     #[opt(
         name = "-lr",
         env = "SP_LOAD_RATIO",
@@ -108,6 +118,13 @@ pub struct Command {
     )]
     pub load_ratio: f64,
 
+    #[opt(
+        name = "-load-window",
+        desc = "The number of samples the processor load is averaged over (default 20)",
+        value_desc = "<number>"
+    )]
+    pub load_window: usize,
+
     #[opt(
         name = "-process-count",
         desc = "The maximum allowed number of processes created",
@@ -122,6 +139,13 @@ pub struct Command {
     )]
     pub active_process_count: Option<usize>,
 
+    #[opt(
+        name = "-active-thread-count",
+        desc = "The maximum allowed number of active threads",
+        value_desc = "<number>[unit]"
+    )]
+    pub active_thread_count: Option<usize>,
+
     #[opt(
         name = "-active-connection-count",
         desc = "The maximum allowed number of internet connections",
@@ -306,6 +330,9 @@ pub struct Command {
     )]
     pub wait_for_children: bool,
 
+    #[flag(name = "--merge-stderr", desc = "Redirect stderr to stdout")]
+    pub merge_stderr: bool,
+
     pub argv: Vec<String>,
 }
 
@@ -317,11 +344,14 @@ impl Default for Command {
             idle_time_limit: None,
             memory_limit: None,
             write_limit: None,
+            read_limit: None,
             load_ratio: 5.0,
+            load_window: 20,
             process_count: None,
             active_process_count: None,
+            active_thread_count: None,
             active_connection_count: None,
-            monitor_interval: Duration::from_millis(1),
+            monitor_interval: DEFAULT_MONITOR_INTERVAL,
             secure: false,
             show_window: false,
             debug: false,
@@ -343,6 +373,7 @@ impl Default for Command {
             shared_memory: None,
             use_json: false,
             wait_for_children: false,
+            merge_stderr: false,
             argv: Vec::new(),
         }
     }
@@ -399,6 +430,20 @@ impl Command {
     }
 }
 
+// Splits the response file like the command line. The Windows rules don't treat line breaks
+// as whitespace, so they are replaced there.
+fn split_response_file(contents: &str) -> Result<Vec<String>, String> {
+    let contents = if cfg!(windows) {
+        contents.replace(['\r', '\n'], " ")
+    } else {
+        contents.to_string()
+    };
+    // The first word is split by the rules for the program name, so a placeholder takes it.
+    cmdline::split(&format!("sp {}", contents))
+        .map(|mut args| args.split_off(1))
+        .map_err(|e| e.to_string())
+}
+
 impl Default for RedirectList {
     fn default() -> Self {
         Self {
@@ -448,15 +448,23 @@ fn create_program(
                     idle_time: cmd.idle_time_limit.map(|limit| IdleTimeLimit {
                         total_idle_time: limit,
                         cpu_load_threshold: cmd.load_ratio / 100.0,
+                        cpu_load_window_length: cmd.load_window,
                     }),
                     total_user_time: cmd.time_limit,
                     max_memory_usage: cmd.memory_limit.map(mb2b),
+                    max_swap_usage: None,
                     total_bytes_written: cmd.write_limit.map(mb2b),
+                    total_bytes_read: cmd.read_limit.map(mb2b),
                     total_processes_created: cmd.process_count,
+                    max_process_creation_rate: None,
                     active_processes: cmd.active_process_count,
+                    active_threads: cmd.active_thread_count,
                     active_network_connections: cmd.active_connection_count,
+                    max_open_handles: None,
+                    max_distinct_endpoints: None,
                 })
                 .wait_for_children(cmd.wait_for_children)
+                .merge_stderr(cmd.merge_stderr)
                 .msg_receiver(receiver);
         })
     })
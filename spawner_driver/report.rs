@@ -2,7 +2,7 @@ use crate::cmd::{Command, RedirectList};
 use crate::misc::{b2mb, mb2b};
 
 use spawner::process::ExitStatus;
-use spawner::{Error, ProgramResult, TerminationReason};
+use spawner::{Error, LegacyReport, ProgramResult, TerminationReason};
 
 use json::{array, object, JsonValue};
 
@@ -69,44 +69,16 @@ pub enum TerminateReason {
     TimeLimitExceeded,
     IdleTimeLimitExceeded,
     WriteLimitExceeded,
+    ReadLimitExceeded,
     MemoryLimitExceeded,
     ProcessesCountLimitExceeded,
     ActiveProcessesCountLimitExceeded,
+    ActiveThreadsCountLimitExceeded,
     ActiveConnectionCountLimitExceeded,
+    HandlesCountLimitExceeded,
     TerminatedByController,
 }
 
-#[derive(Debug)]
-pub struct LegacyReport<'a> {
-    pub application: &'a String,
-    pub parameters: &'a Vec<String>,
-    pub security_level: Option<u32>,
-    pub create_process_method: &'a String,
-    pub username: &'a Option<String>,
-    pub user_time_limit: Option<f64>,
-    pub deadline: Option<f64>,
-    pub memory_limit: Option<f64>,
-    pub write_limit: Option<f64>,
-    pub user_time: f64,
-    pub peak_memory_used: f64,
-    pub written: f64,
-    pub terminate_reason: TerminateReason,
-    pub exit_code: u32,
-    pub exit_status: &'a String,
-    pub spawner_error: &'a Vec<Error>,
-    pub debug: bool,
-}
-
-struct NoneOrJoin<T, U>(T)
-where
-    T: IntoIterator<Item = U> + Clone,
-    U: AsRef<str>;
-
-struct MbOrInf(Option<f64>);
-struct FltSecsOrInf(Option<f64>);
-struct Mb(f64);
-struct FltSecs(f64);
-
 impl Report {
     pub fn new(cmd: &Command, result: ProgramResult) -> Self {
         let mut report = Report::from(cmd);
@@ -122,10 +94,14 @@ impl Report {
                         report.terminate_reason = TerminateReason::AbnormalExitProcess;
                         report.exit_status = cause;
                     }
+                    ExitStatus::Running => {}
                 }
                 if let Some(tr) = runner_report.termination_reason {
                     report.terminate_reason = TerminateReason::from(tr);
                 }
+                if let Some(e) = runner_report.error {
+                    report.spawner_error.push(Error::from(e));
+                }
             }
             Err(e) => report.spawner_error = e.errors,
         }
@@ -183,11 +159,11 @@ impl Report {
 
     fn as_legacy(&self) -> LegacyReport {
         LegacyReport {
-            application: &self.application,
-            parameters: &self.arguments,
+            application: self.application.clone(),
+            parameters: self.arguments.clone(),
             security_level: self.limit.security_level,
-            create_process_method: &self.create_process_method,
-            username: &self.username,
+            create_process_method: self.create_process_method.clone(),
+            username: self.username.clone(),
             user_time_limit: self.limit.time,
             deadline: self.limit.wall_clock_time,
             memory_limit: self.limit.memory.map(b2mb),
@@ -195,11 +171,20 @@ impl Report {
             user_time: self.result.time,
             peak_memory_used: b2mb(self.result.memory),
             written: b2mb(self.result.bytes_written),
-            terminate_reason: self.terminate_reason,
+            terminate_reason: self.terminate_reason.to_string(),
             exit_code: self.exit_code,
-            exit_status: &self.exit_status,
-            spawner_error: &self.spawner_error,
-            debug: self.options.debug,
+            exit_status: self.exit_status.clone(),
+            spawner_errors: self
+                .spawner_error
+                .iter()
+                .map(|e| {
+                    if self.options.debug {
+                        format!("{:?}", e)
+                    } else {
+                        format!("{}", e)
+                    }
+                })
+                .collect(),
         }
     }
 }
@@ -324,14 +309,17 @@ impl Display for TerminateReason {
             TerminateReason::TimeLimitExceeded => "TimeLimitExceeded",
             TerminateReason::IdleTimeLimitExceeded => "IdleTimeLimitExceeded",
             TerminateReason::WriteLimitExceeded => "WriteLimitExceeded",
+            TerminateReason::ReadLimitExceeded => "ReadLimitExceeded",
             TerminateReason::MemoryLimitExceeded => "MemoryLimitExceeded",
             TerminateReason::ProcessesCountLimitExceeded => "ProcessesCountLimitExceeded",
             TerminateReason::ActiveProcessesCountLimitExceeded => {
                 "ActiveProcessesCountLimitExceeded"
             }
+            TerminateReason::ActiveThreadsCountLimitExceeded => "ActiveThreadsCountLimitExceeded",
             TerminateReason::ActiveConnectionCountLimitExceeded => {
                 "ActiveConnectionCountLimitExceeded"
             }
+            TerminateReason::HandlesCountLimitExceeded => "HandlesCountLimitExceeded",
             TerminateReason::TerminatedByController => "TerminatedByController",
         })
     }
@@ -339,117 +327,28 @@ impl Display for TerminateReason {
 
 impl From<TerminationReason> for TerminateReason {
     fn from(reason: TerminationReason) -> Self {
-        match reason {
-            TerminationReason::WallClockTimeLimitExceeded => TerminateReason::TimeLimitExceeded,
-            TerminationReason::IdleTimeLimitExceeded => TerminateReason::IdleTimeLimitExceeded,
-            TerminationReason::UserTimeLimitExceeded => TerminateReason::TimeLimitExceeded,
-            TerminationReason::WriteLimitExceeded => TerminateReason::WriteLimitExceeded,
-            TerminationReason::MemoryLimitExceeded => TerminateReason::MemoryLimitExceeded,
-            TerminationReason::ProcessLimitExceeded => TerminateReason::ProcessesCountLimitExceeded,
-            TerminationReason::ActiveProcessLimitExceeded => {
+        match reason.kind().legacy_name() {
+            "TimeLimitExceeded" => TerminateReason::TimeLimitExceeded,
+            "IdleTimeLimitExceeded" => TerminateReason::IdleTimeLimitExceeded,
+            "WriteLimitExceeded" => TerminateReason::WriteLimitExceeded,
+            "ReadLimitExceeded" => TerminateReason::ReadLimitExceeded,
+            "MemoryLimitExceeded" => TerminateReason::MemoryLimitExceeded,
+            "ProcessesCountLimitExceeded" => TerminateReason::ProcessesCountLimitExceeded,
+            "ActiveProcessesCountLimitExceeded" => {
                 TerminateReason::ActiveProcessesCountLimitExceeded
             }
-            TerminationReason::ActiveNetworkConnectionLimitExceeded => {
+            "ActiveThreadsCountLimitExceeded" => TerminateReason::ActiveThreadsCountLimitExceeded,
+            "ActiveConnectionCountLimitExceeded" => {
                 TerminateReason::ActiveConnectionCountLimitExceeded
             }
-            TerminationReason::TerminatedByRunner => TerminateReason::TerminatedByController,
+            "HandlesCountLimitExceeded" => TerminateReason::HandlesCountLimitExceeded,
+            _ => TerminateReason::TerminatedByController,
         }
     }
 }
 
-macro_rules! line {
-    ($f:expr, $name:expr, $val:expr) => {
-        write!($f, "{0: <27}{1}\n", $name, $val)
-    };
-}
-
-impl<'a> Display for LegacyReport<'a> {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        writeln!(f)?;
-        writeln!(f, "--------------- Spawner report ---------------")?;
-        line!(f, "Application:", self.application)?;
-        line!(f, "Parameters:", NoneOrJoin(self.parameters.iter()))?;
-        line!(f, "SecurityLevel:", self.security_level.unwrap_or(0))?;
-        line!(f, "CreateProcessMethod:", self.create_process_method)?;
-        line!(
-            f,
-            "UserName:",
-            self.username.as_ref().unwrap_or(&String::new())
-        )?;
-        line!(f, "UserTimeLimit:", FltSecsOrInf(self.user_time_limit))?;
-        line!(f, "DeadLine:", FltSecsOrInf(self.deadline))?;
-        line!(f, "MemoryLimit:", MbOrInf(self.memory_limit))?;
-        line!(f, "WriteLimit:", MbOrInf(self.write_limit))?;
-        writeln!(f, "----------------------------------------------")?;
-        line!(f, "UserTime:", FltSecs(self.user_time))?;
-        line!(f, "PeakMemoryUsed:", Mb(self.peak_memory_used))?;
-        line!(f, "Written:", Mb(self.written))?;
-        line!(f, "TerminateReason:", self.terminate_reason)?;
-        line!(f, "ExitCode:", self.exit_code)?;
-        line!(f, "ExitStatus:", self.exit_status)?;
-        writeln!(f, "----------------------------------------------")?;
-        line!(
-            f,
-            "SpawnerError:",
-            NoneOrJoin(self.spawner_error.iter().map(|e| if self.debug {
-                format!("{:?}", e)
-            } else {
-                format!("{}", e)
-            }))
-        )
-    }
-}
-
 impl From<&RedirectList> for Vec<String> {
     fn from(list: &RedirectList) -> Vec<String> {
         list.items.iter().map(|x| x.to_string()).collect()
     }
 }
-
-impl<T, U> Display for NoneOrJoin<T, U>
-where
-    T: IntoIterator<Item = U> + Clone,
-    U: AsRef<str>,
-{
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let mut empty = true;
-        for i in self.0.clone().into_iter() {
-            empty = false;
-            write!(f, "{} ", i.as_ref())?;
-        }
-        if empty {
-            f.write_str("<none>")?;
-        }
-        Ok(())
-    }
-}
-
-impl Display for Mb {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{:.6} (Mb)", self.0)
-    }
-}
-
-impl Display for FltSecs {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{:.6} (sec)", self.0)
-    }
-}
-
-impl Display for MbOrInf {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self.0 {
-            Some(v) => write!(f, "{}", Mb(v)),
-            None => write!(f, "Infinity"),
-        }
-    }
-}
-
-impl Display for FltSecsOrInf {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self.0 {
-            Some(v) => write!(f, "{}", FltSecs(v)),
-            None => write!(f, "Infinity"),
-        }
-    }
-}
@@ -1,8 +1,11 @@
 use crate::cmd::*;
-use crate::value_parser::StdinRedirectParser;
+use crate::report::Report;
+use crate::value_parser::{DefaultValueParser, StdinRedirectParser};
 
 use spawner_opts::{CmdLineOptions, OptionValueParser};
 
+use std::fs;
+use std::process;
 use std::time::Duration;
 
 fn fsec2dur(s: f64) -> Duration {
@@ -17,6 +20,53 @@ macro_rules! check_opt {
     }};
 }
 
+#[test]
+fn parse_end_of_options() {
+    let mut cmd = Command::default();
+    assert_eq!(cmd.parse_argv(["-tl=10", "--", "-d=10"]), Ok(2));
+    assert_eq!(cmd.time_limit, Some(fsec2dur(10.0)));
+    assert_eq!(cmd.wall_clock_time_limit, None);
+}
+
+#[test]
+fn parse_response_file() {
+    let dir = std::env::temp_dir();
+    let opts_file = dir.join(format!("sp_opts_{}.rsp", process::id()));
+    let nested_file = dir.join(format!("sp_nested_{}.rsp", process::id()));
+    let argv = [
+        format!("@{}", opts_file.display()),
+        "-ml=10".into(),
+        "app".into(),
+    ];
+    let opts = format!("-tl=10 \"-wd=some dir\"\n@{}", nested_file.display());
+    fs::write(&opts_file, opts).unwrap();
+    fs::write(&nested_file, "-d 5").unwrap();
+
+    let mut cmd = Command::default();
+    assert_eq!(cmd.parse_argv(&argv), Ok(2));
+    assert_eq!(cmd.time_limit, Some(fsec2dur(10.0)));
+    assert_eq!(cmd.working_directory, Some("some dir".to_string()));
+    assert_eq!(cmd.wall_clock_time_limit, Some(fsec2dur(5.0)));
+    assert_eq!(cmd.memory_limit, Some(10.0));
+
+    fs::write(&nested_file, format!("@{}", opts_file.display())).unwrap();
+    assert!(Command::default().parse_argv(&argv).is_err());
+
+    fs::write(&nested_file, "app").unwrap();
+    assert!(Command::default().parse_argv(&argv).is_err());
+
+    // The value of the last option must not be taken from the argv.
+    fs::write(&nested_file, "-d").unwrap();
+    let argv = [argv[0].clone(), "5".into(), "app".into()];
+    assert_eq!(
+        Command::default().parse_argv(&argv),
+        Err("Option '-d' has no value at the end of response file".to_string())
+    );
+
+    let _ = fs::remove_file(opts_file);
+    let _ = fs::remove_file(nested_file);
+}
+
 #[test]
 fn parse_opt_delimeters() {
     check_opt!(&["-tl=10"], time_limit, Some(fsec2dur(10.0)));
@@ -30,10 +80,12 @@ fn parse_basic_opts() {
     check_opt!(&["-d=10"], wall_clock_time_limit, Some(fsec2dur(10.0)));
     check_opt!(&["-ml=10"], memory_limit, Some(10.0));
     check_opt!(&["-wl=10"], write_limit, Some(10.0));
+    check_opt!(&["-rl=10"], read_limit, Some(10.0));
     check_opt!(&["-s=1"], secure, true);
     check_opt!(&["-y=10"], idle_time_limit, Some(fsec2dur(10.0)));
     check_opt!(&["-lr=10"], load_ratio, 10.0);
     check_opt!(&["-lr=10%"], load_ratio, 10.0);
+    check_opt!(&["-load-window=10"], load_window, 10);
     check_opt!(&["-sw=1"], show_window, true);
     check_opt!(&["--debug=1"], debug, true);
     check_opt!(&["-mi=0.1"], monitor_interval, fsec2dur(0.1));
@@ -54,6 +106,7 @@ fn parse_basic_opts() {
         active_process_count,
         Some(10)
     );
+    check_opt!(&["-active-thread-count=10"], active_thread_count, Some(10));
     check_opt!(
         &["-active-connection-count=10"],
         active_connection_count,
@@ -63,6 +116,7 @@ fn parse_basic_opts() {
     check_opt!(&["-j"], use_json, true);
     check_opt!(&["--json"], use_json, true);
     check_opt!(&["--wait-for-children"], wait_for_children, true);
+    check_opt!(&["--merge-stderr"], merge_stderr, true);
 }
 
 #[test]
@@ -284,3 +338,321 @@ fn parse_pipe_redirect() {
     check_redirect!(("*fe:", "*:", "*:std"), "*-f-e:std");
     check_redirect!(("*fe:", "*:", "*std"), "*f-e:std");
 }
+
+fn check_port(port: &Option<usize>) -> Result<(), String> {
+    match *port {
+        Some(p) if p == 0 || p > 65535 => Err(format!("Invalid port '{}'", p)),
+        _ => Ok(()),
+    }
+}
+
+fn check_not_reserved(port: &Option<usize>) -> Result<(), String> {
+    match *port {
+        Some(p) if p < 1024 => Err(format!("Port '{}' is reserved", p)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(CmdLineOptions, Default)]
+#[optcont(delimeters = "=", default_parser = "DefaultValueParser")]
+struct ValidatedOpts {
+    #[opt(
+        name = "--port",
+        validate = "check_port",
+        validate = "check_not_reserved"
+    )]
+    port: Option<usize>,
+}
+
+#[test]
+fn parse_validated_opt() {
+    let mut opts = ValidatedOpts::default();
+    assert_eq!(opts.parse_argv(["--port=8080"]), Ok(1));
+    assert_eq!(opts.port, Some(8080));
+
+    let mut opts = ValidatedOpts::default();
+    assert_eq!(
+        opts.parse_argv(["--port=70000"]),
+        Err("Invalid port '70000'".to_string())
+    );
+    // The validators run in order.
+    let mut opts = ValidatedOpts::default();
+    assert_eq!(
+        opts.parse_argv(["--port=0"]),
+        Err("Invalid port '0'".to_string())
+    );
+    let mut opts = ValidatedOpts::default();
+    assert_eq!(
+        opts.parse_argv(["--port=80"]),
+        Err("Port '80' is reserved".to_string())
+    );
+}
+
+struct RangeParser;
+
+impl OptionValueParser<Option<(usize, usize)>> for RangeParser {
+    fn parse(_: &mut Option<(usize, usize)>, _: &str) -> Result<(), String> {
+        Err("Expected two values".to_string())
+    }
+
+    fn parse_values(opt: &mut Option<(usize, usize)>, vals: &[String]) -> Result<(), String> {
+        let parse = |v: &String| v.parse().map_err(|_| format!("Invalid value '{}'", v));
+        *opt = Some((parse(&vals[0])?, parse(&vals[1])?));
+        Ok(())
+    }
+}
+
+#[derive(CmdLineOptions, Default)]
+#[optcont(delimeters = "=", default_parser = "DefaultValueParser")]
+struct MultiValueOpts {
+    #[opt(name = "--range", arity = 2, parser = "RangeParser")]
+    range: Option<(usize, usize)>,
+    #[flag(name = "-f")]
+    flag: bool,
+}
+
+#[test]
+fn parse_multi_value_opt() {
+    let mut opts = MultiValueOpts::default();
+    assert_eq!(opts.parse_argv(["--range", "1", "5", "-f", "app"]), Ok(2));
+    assert_eq!(opts.range, Some((1, 5)));
+    assert!(opts.flag);
+
+    let mut opts = MultiValueOpts::default();
+    assert_eq!(opts.parse_argv(["--range=2", "3"]), Ok(1));
+    assert_eq!(opts.range, Some((2, 3)));
+
+    let mut opts = MultiValueOpts::default();
+    assert_eq!(
+        opts.parse_argv(["--range", "1"]),
+        Err("Option '--range' expects 2 values, got 1".to_string())
+    );
+}
+
+#[test]
+fn parser_case_insensitive_prefers_first_registered() {
+    use spawner_opts::parser::Parser;
+
+    for _ in 0..10 {
+        let mut parser = Parser::new(["--PATH=a", "--path=b"], "=");
+        parser
+            .case_insensitive(true)
+            .opt(&["--Path"])
+            .opt(&["--path"]);
+        assert_eq!(parser.try_parse(), Ok(2));
+        assert_eq!(parser.get_opt("--Path"), Some(&vec!["a".to_string()]));
+        assert_eq!(parser.get_opt("--path"), Some(&vec!["b".to_string()]));
+    }
+}
+
+#[test]
+fn parse_args_reports_env_error_with_help() {
+    // The environment is parsed first, so the error does not depend on the arguments of
+    // the test binary.
+    std::env::set_var("SP_MEMORY_LIMIT", "invalid");
+    let env_error = Command::default().parse_env().unwrap_err();
+    let result = Command::default().parse_args();
+    std::env::remove_var("SP_MEMORY_LIMIT");
+
+    assert_eq!(result, Err(format!("{}\n\n{}", env_error, Command::help())));
+}
+
+#[test]
+fn parser_parse_stops_at_invalid_argument() {
+    use spawner_opts::parser::Parser;
+
+    let argv = ["-f", "--range", "1"];
+    let mut parser = Parser::new(argv, "=");
+    parser.flag(&["-f"]).multi_opt(&["--range"], 2);
+    assert_eq!(parser.parse(), 1);
+    assert!(parser.has_flag("-f"));
+
+    let mut parser = Parser::new(argv, "=");
+    parser.flag(&["-f"]).multi_opt(&["--range"], 2);
+    assert_eq!(
+        parser.try_parse(),
+        Err("Option '--range' expects 2 values, got 1".to_string())
+    );
+}
+
+#[derive(CmdLineOptions, Default)]
+#[optcont(delimeters = "=", default_parser = "DefaultValueParser")]
+struct PositionalOpts {
+    #[flag(name = "-f")]
+    flag: bool,
+    #[positional]
+    files: Vec<String>,
+}
+
+#[derive(CmdLineOptions, Default)]
+#[optcont(delimeters = "=", default_parser = "DefaultValueParser")]
+struct ParsedPositionalOpts {
+    #[positional]
+    numbers: Vec<usize>,
+}
+
+#[test]
+fn parse_positionals() {
+    let mut opts = PositionalOpts::default();
+    assert_eq!(opts.parse_argv(["a", "-f", "b"]), Ok(1));
+    assert!(opts.flag);
+    assert_eq!(opts.files, vec!["a".to_string(), "b".to_string()]);
+
+    // The arguments after `--` are positional.
+    let mut opts = PositionalOpts::default();
+    assert_eq!(opts.parse_argv(["a", "--", "-f", "--"]), Ok(1));
+    assert!(!opts.flag);
+    assert_eq!(
+        opts.files,
+        vec!["a".to_string(), "-f".to_string(), "--".to_string()]
+    );
+
+    let mut opts = ParsedPositionalOpts::default();
+    assert_eq!(opts.parse_argv(["1", "2"]), Ok(0));
+    assert_eq!(opts.numbers, vec![1, 2]);
+    assert_eq!(
+        opts.parse_argv(["x"]),
+        Err("Invalid argument 'x'".to_string())
+    );
+}
+
+#[derive(CmdLineOptions, Default)]
+#[optcont(delimeters = "=", default_parser = "DefaultValueParser")]
+struct ShortOpts {
+    #[flag(name = "-a")]
+    a: bool,
+    #[flag(name = "-b")]
+    b: bool,
+    #[flag(name = "-ab")]
+    ab: bool,
+    #[opt(name = "-o")]
+    o: Option<String>,
+}
+
+#[test]
+fn parse_short_group() {
+    let mut opts = ShortOpts::default();
+    assert_eq!(opts.parse_argv(["-ba", "app"]), Ok(1));
+    assert!(opts.a && opts.b && !opts.ab);
+
+    // A registered name takes precedence over the group.
+    let mut opts = ShortOpts::default();
+    assert_eq!(opts.parse_argv(["-ab"]), Ok(1));
+    assert!(!opts.a && !opts.b && opts.ab);
+
+    let mut opts = ShortOpts::default();
+    assert_eq!(opts.parse_argv(["-boval"]), Ok(1));
+    assert!(opts.b);
+    assert_eq!(opts.o, Some("val".to_string()));
+
+    let mut opts = ShortOpts::default();
+    assert_eq!(opts.parse_argv(["-bo=val"]), Ok(1));
+    assert_eq!(opts.o, Some("val".to_string()));
+
+    let mut opts = ShortOpts::default();
+    assert_eq!(opts.parse_argv(["-bo", "val"]), Ok(1));
+    assert_eq!(opts.o, Some("val".to_string()));
+
+    // Not a group if any letter is unknown.
+    let mut opts = ShortOpts::default();
+    assert_eq!(opts.parse_argv(["-abx"]), Ok(0));
+    assert!(!opts.a && !opts.b);
+}
+
+#[derive(CmdLineOptions, Default)]
+#[optcont(delimeters = "=", default_parser = "DefaultValueParser", strict)]
+struct StrictOpts {
+    #[opt(name = "--timeout")]
+    timeout: Option<usize>,
+    #[positional]
+    args: Vec<String>,
+}
+
+#[test]
+fn parse_strict() {
+    let mut opts = StrictOpts::default();
+    assert_eq!(
+        opts.parse_argv(["--tmeout=10"]),
+        Err("Unknown option '--tmeout', did you mean '--timeout'?".to_string())
+    );
+    assert_eq!(
+        opts.parse_argv(["--verbose"]),
+        Err("Unknown option '--verbose'".to_string())
+    );
+
+    let mut opts = StrictOpts::default();
+    assert_eq!(opts.parse_argv(["--timeout=10", "a", "--", "-x"]), Ok(2));
+    assert_eq!(opts.timeout, Some(10));
+    assert_eq!(opts.args, vec!["a".to_string(), "-x".to_string()]);
+
+    // Lenient by default.
+    let mut opts = PositionalOpts::default();
+    assert_eq!(opts.parse_argv(["--verbose"]), Ok(0));
+    assert_eq!(opts.files, vec!["--verbose".to_string()]);
+}
+
+#[derive(CmdLineOptions, Default)]
+#[optcont(
+    delimeters = "=",
+    default_parser = "DefaultValueParser",
+    case_insensitive
+)]
+struct CaseInsensitiveOpts {
+    #[opt(name = "--Path")]
+    path: Option<String>,
+    #[flag(name = "-v")]
+    v: bool,
+}
+
+#[test]
+fn parse_case_insensitive() {
+    let mut opts = CaseInsensitiveOpts::default();
+    assert_eq!(opts.parse_argv(["--PATH=Dir"]), Ok(1));
+    assert_eq!(opts.path, Some("Dir".to_string()));
+    assert_eq!(CaseInsensitiveOpts::help().options[0].names, vec!["--Path"]);
+
+    // Single-letter names are case-sensitive.
+    let mut opts = CaseInsensitiveOpts::default();
+    assert_eq!(opts.parse_argv(["-V"]), Ok(0));
+    assert!(!opts.v);
+
+    // Case-sensitive by default.
+    let mut opts = StrictOpts::default();
+    assert!(opts.parse_argv(["--TIMEOUT=1"]).is_err());
+}
+
+fn runner_report() -> spawner::Report {
+    spawner::Report {
+        wall_clock_time: Duration::from_millis(100),
+        memory: None,
+        io: None,
+        timers: None,
+        pid_counters: None,
+        network: None,
+        exit_status: spawner::process::ExitStatus::Running,
+        termination_reason: None,
+        error: None,
+        restarts: 0,
+        samples_taken: 0,
+        sampling_time: Duration::from_millis(0),
+        peak_open_handles: None,
+        idle_time: Duration::from_millis(0),
+        cpu_load: 0.0,
+        security_violation: None,
+        process_tree: None,
+        remote_endpoints: None,
+        natural_exit_status: None,
+        counters_reset_at: None,
+    }
+}
+
+#[test]
+fn report_monitoring_error() {
+    let mut runner_report = runner_report();
+    runner_report.error = Some("Monitoring failed".to_string());
+    let mut cmd = Command::default();
+    cmd.argv.push("app".to_string());
+    let report = Report::new(&cmd, Ok(runner_report));
+    assert_eq!(report.spawner_error.len(), 1);
+    assert_eq!(report.spawner_error[0].to_string(), "Monitoring failed");
+}
@@ -26,6 +26,17 @@ impl OptionValueParser<Option<usize>> for DefaultValueParser {
     }
 }
 
+impl OptionValueParser<usize> for DefaultValueParser {
+    fn parse(opt: &mut usize, v: &str) -> Result<(), String> {
+        if let Ok(v) = v.parse::<usize>() {
+            *opt = v;
+            Ok(())
+        } else {
+            Err(format!("Invalid value '{}'", v))
+        }
+    }
+}
+
 impl OptionValueParser<bool> for DefaultValueParser {
     fn parse(opt: &mut bool, v: &str) -> Result<(), String> {
         if v.len() == 1 {
@@ -68,10 +68,29 @@ pub trait CmdLineOptions: Sized {
         U: AsRef<str>;
 
     fn parse_env(&mut self) -> Result<(), String>;
+
+    /// Parses the environment variables and then the arguments of the current process,
+    /// without the program name, so that the arguments take precedence. The error is
+    /// followed by the help message.
+    fn parse_args(&mut self) -> Result<(), String> {
+        self.parse_env()
+            .and_then(|_| self.parse_argv(std::env::args().skip(1)))
+            .map(|_| ())
+            .map_err(|e| format!("{}\n\n{}", e, Self::help()))
+    }
 }
 
 pub trait OptionValueParser<T> {
     fn parse(opt: &mut T, val: &str) -> Result<(), String>;
+
+    /// Parses the values of an option with `arity` greater than one. By default they are
+    /// parsed one by one.
+    fn parse_values(opt: &mut T, vals: &[String]) -> Result<(), String> {
+        for v in vals {
+            Self::parse(opt, v)?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for Help {
@@ -1,8 +1,15 @@
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Splits the contents of a response file into arguments.
+pub type SplitFn = fn(&str) -> Result<Vec<String>, String>;
 
 pub enum Entries {
     Flag(Vec<String>),
     Opt(Vec<String>),
+    /// An option that takes a fixed number of values, with the values of every occurrence.
+    MultiOpt(usize, Vec<Vec<String>>),
 }
 
 pub struct Parser<T, U>
@@ -13,7 +20,18 @@ where
     pos: std::iter::Peekable<<T as IntoIterator>::IntoIter>,
     entries: Vec<Entries>,
     optmap: HashMap<&'static str, usize>,
+    // The names in lowercase, for the case-insensitive lookup. A name that differs from an
+    // earlier one only in case refers to the earlier one.
+    lowercase_optmap: HashMap<String, usize>,
     delims: &'static str,
+    // The arguments that are not options, if they are collected.
+    positionals: Option<Vec<String>>,
+    strict: bool,
+    case_insensitive: bool,
+    split_response_file: Option<SplitFn>,
+    // The arguments of the response file being parsed, in reverse order.
+    pending: Vec<String>,
+    response_files: usize,
 }
 
 impl<T, U> Parser<T, U>
@@ -26,7 +44,14 @@ where
             pos: argv.into_iter().peekable(),
             entries: Vec::new(),
             optmap: HashMap::new(),
+            lowercase_optmap: HashMap::new(),
             delims,
+            positionals: None,
+            strict: false,
+            case_insensitive: false,
+            split_response_file: None,
+            pending: Vec::new(),
+            response_files: 0,
         }
     }
 
@@ -34,6 +59,9 @@ where
         let idx = self.entries.len() - 1;
         for name in names {
             self.optmap.insert(name, idx);
+            self.lowercase_optmap
+                .entry(name.to_ascii_lowercase())
+                .or_insert(idx);
         }
     }
 
@@ -43,12 +71,62 @@ where
         self
     }
 
+    /// Registers an option that takes `arity` values. The first value may follow the name
+    /// after a delimiter, the rest are the next arguments.
+    pub fn multi_opt(&mut self, names: &[&'static str], arity: usize) -> &mut Self {
+        self.entries.push(Entries::MultiOpt(arity, Vec::new()));
+        self.add_names(names);
+        self
+    }
+
     pub fn flag(&mut self, names: &[&'static str]) -> &mut Self {
         self.entries.push(Entries::Flag(Vec::new()));
         self.add_names(names);
         self
     }
 
+    /// Makes the parser collect the arguments that are not options instead of stopping at
+    /// the first of them.
+    pub fn positional(&mut self) -> &mut Self {
+        self.positionals = Some(Vec::new());
+        self
+    }
+
+    /// Makes the parser fail on an argument that starts with `-` but is not a registered
+    /// option, suggesting the closest registered name. Such positional arguments must follow
+    /// `--` then.
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Makes the names longer than a single letter match regardless of their case, e.g.
+    /// `--Timeout` matches `--timeout`. The single-letter names, and so the grouped ones
+    /// like `-abc`, are still case-sensitive, so that `-v` and `-V` can differ. The values
+    /// are kept as they are. An exact match is preferred, otherwise of the names that
+    /// differ only in case the one registered first matches.
+    pub fn case_insensitive(&mut self, case_insensitive: bool) -> &mut Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Makes the parser replace an argument of the form `@path` with the arguments read from
+    /// the file at `path`, split by `split`. The files may refer to other response files,
+    /// which are resolved relative to the current directory; a file that refers to itself,
+    /// directly or not, is an error. Only the arguments in place of an option are expanded,
+    /// not the values that follow an option name. The response file counts as a single
+    /// parsed option, and every argument in it must be an option or its value unless the
+    /// positional arguments are collected. The values of an option in a response file must
+    /// be in the same file.
+    pub fn response_files(&mut self, split: SplitFn) -> &mut Self {
+        self.split_response_file = Some(split);
+        self
+    }
+
+    pub fn get_positionals(&self) -> &[String] {
+        self.positionals.as_ref().map_or(&[], |p| &p[..])
+    }
+
     pub fn has_flag(&self, flag: &str) -> bool {
         self.optmap.get(flag).map_or(false, |i| {
             if let Entries::Flag(ref e) = self.entries[*i] {
@@ -69,45 +147,287 @@ where
         })
     }
 
-    fn parse_opt(&mut self, arg: &str) -> bool {
+    pub fn get_multi_opt(&self, opt: &str) -> Option<&Vec<Vec<String>>> {
+        self.optmap.get(opt).and_then(|i| {
+            if let Entries::MultiOpt(_, ref e) = self.entries[*i] {
+                Some(e)
+            } else {
+                None
+            }
+        })
+    }
+
+    // `from_file` tells whether the option is read from a response file.
+    fn parse_opt(&mut self, arg: &str, from_file: bool) -> Result<bool, String> {
         let (name, val) = match arg.find(|x| self.delims.find(x).is_some()) {
             Some(pos) => (&arg[0..pos], Some(&arg[pos + 1..arg.len()])),
             None => (&arg[0..arg.len()], None),
         };
-        if let Some(opt_idx) = self.optmap.get(name) {
-            let entries = &mut self.entries[*opt_idx];
-            match (entries, val) {
-                (Entries::Flag(e), None) => {
-                    e.push(name.to_string());
+        match self.find_opt(name) {
+            Some(opt_idx) => self.parse_entry(opt_idx, name, val, from_file),
+            None => self.parse_short_group(arg, from_file),
+        }
+    }
+
+    fn parse_entry(
+        &mut self,
+        opt_idx: usize,
+        name: &str,
+        val: Option<&str>,
+        from_file: bool,
+    ) -> Result<bool, String> {
+        let entries = &mut self.entries[opt_idx];
+        let pending = &mut self.pending;
+        let pos = &mut self.pos;
+        let mut next_value = || match pending.pop() {
+            Some(next) => Some(next),
+            None if from_file => None,
+            None => pos.next().map(|next| next.as_ref().to_string()),
+        };
+        Ok(match (entries, val) {
+            (Entries::Flag(e), None) => {
+                e.push(name.to_string());
+                true
+            }
+            (Entries::Opt(e), Some(v)) => {
+                e.push(v.to_string());
+                true
+            }
+            (Entries::Opt(e), None) => match next_value() {
+                Some(next) => {
+                    e.push(next);
                     true
                 }
-                (Entries::Opt(e), Some(v)) => {
-                    e.push(v.to_string());
-                    true
+                None if from_file => {
+                    return Err(format!(
+                        "Option '{}' has no value at the end of response file",
+                        name
+                    ));
                 }
-                (Entries::Opt(e), None) => {
-                    if let Some(next) = self.pos.next() {
-                        e.push(next.as_ref().to_string());
-                        true
-                    } else {
-                        false
+                None => false,
+            },
+            (Entries::MultiOpt(arity, e), val) => {
+                let mut values: Vec<String> = val.iter().map(|v| v.to_string()).collect();
+                while values.len() < *arity {
+                    match next_value() {
+                        Some(next) => values.push(next),
+                        None => {
+                            return Err(format!(
+                                "Option '{}' expects {} values, got {}",
+                                name,
+                                arity,
+                                values.len()
+                            ));
+                        }
                     }
                 }
-                _ => false,
+                e.push(values);
+                true
+            }
+            _ => false,
+        })
+    }
+
+    /// Parses grouped single-letter options, e.g. `-abc` as `-a -b -c`. Only used if the
+    /// name is not registered as a whole, so a registered `-ab` takes precedence. Every
+    /// letter but the last one must be a flag. The option that takes a value ends the group,
+    /// and its value is the rest of the argument with a leading delimiter removed, e.g.
+    /// `-avalue` or `-a=value`, or the next argument if the rest is empty.
+    fn parse_short_group(&mut self, arg: &str, from_file: bool) -> Result<bool, String> {
+        if !arg.starts_with('-') || arg.starts_with("--") || arg.chars().count() < 3 {
+            return Ok(false);
+        }
+
+        let mut group: Vec<(usize, String, Option<&str>)> = Vec::new();
+        for (pos, c) in arg.char_indices().skip(1) {
+            let name = format!("-{}", c);
+            let opt_idx = match self.optmap.get(name.as_str()) {
+                Some(&idx) => idx,
+                None => return Ok(false),
+            };
+            if let Entries::Flag(_) = self.entries[opt_idx] {
+                group.push((opt_idx, name, None));
+                continue;
+            }
+            let rest = &arg[pos + c.len_utf8()..];
+            let rest = match rest.chars().next() {
+                Some(d) if self.delims.contains(d) => &rest[d.len_utf8()..],
+                _ => rest,
+            };
+            group.push((opt_idx, name, Some(rest).filter(|r| !r.is_empty())));
+            break;
+        }
+
+        for (opt_idx, name, val) in group {
+            if !self.parse_entry(opt_idx, &name, val, from_file)? {
+                return Ok(false);
             }
-        } else {
-            false
         }
+        Ok(true)
     }
 
+    // Returns the index of the registered entry of the option named `name`.
+    fn find_opt(&self, name: &str) -> Option<usize> {
+        if let Some(&idx) = self.optmap.get(name) {
+            return Some(idx);
+        }
+        if !self.case_insensitive || name.chars().count() <= 2 {
+            return None;
+        }
+        self.lowercase_optmap
+            .get(&name.to_ascii_lowercase())
+            .cloned()
+    }
+
+    fn opt_name<'b>(&self, arg: &'b str) -> &'b str {
+        match arg.find(|x| self.delims.find(x).is_some()) {
+            Some(pos) => &arg[0..pos],
+            None => arg,
+        }
+    }
+
+    fn is_unknown_opt(&self, arg: &str) -> bool {
+        arg.starts_with('-') && arg != "-" && self.find_opt(self.opt_name(arg)).is_none()
+    }
+
+    fn unknown_opt_error(&self, arg: &str) -> String {
+        let name = self.opt_name(arg);
+        let closest = self
+            .optmap
+            .keys()
+            .map(|known| (edit_distance(name, known), *known))
+            .min();
+        match closest {
+            Some((distance, known)) if distance <= std::cmp::max(name.len() / 3, 1) => {
+                format!("Unknown option '{}', did you mean '{}'?", name, known)
+            }
+            _ => format!("Unknown option '{}'", name),
+        }
+    }
+
+    // Returns the next argument in place of an option, and whether it is read from a response
+    // file.
+    fn next_arg(&mut self) -> Result<Option<(String, bool)>, String> {
+        if let Some(arg) = self.pending.pop() {
+            return Ok(Some((arg, true)));
+        }
+        let arg = match self.pos.next() {
+            Some(arg) => arg.as_ref().to_string(),
+            None => return Ok(None),
+        };
+        match self.split_response_file {
+            Some(split) if arg.len() > 1 && arg.starts_with('@') => {
+                let mut args = Vec::new();
+                expand_response_file(&arg[1..], split, &mut Vec::new(), &mut args)?;
+                args.reverse();
+                self.pending = args;
+                self.response_files += 1;
+                self.next_arg()
+            }
+            _ => Ok(Some((arg, false))),
+        }
+    }
+
+    /// Parses the options up to the first argument that is not an option, and returns the
+    /// number of options parsed. If the positional arguments are collected, the whole
+    /// argv is parsed instead. A bare `--` ends the options: it is counted as parsed, and
+    /// the arguments after it are positional even if they look like options.
+    pub fn try_parse(&mut self) -> Result<usize, String> {
+        let mut parsed_opts = 0;
+        self.parse_args(&mut parsed_opts)?;
+        Ok(parsed_opts + self.response_files)
+    }
+
+    /// Same as [`try_parse`], but stops at the first invalid argument instead of returning
+    /// an error, e.g. at an option with fewer values than it takes.
+    ///
+    /// [`try_parse`]: #method.try_parse
     pub fn parse(&mut self) -> usize {
         let mut parsed_opts = 0;
-        while let Some(arg) = self.pos.next() {
-            if !self.parse_opt(arg.as_ref()) {
+        let _ = self.parse_args(&mut parsed_opts);
+        parsed_opts + self.response_files
+    }
+
+    // Counts the parsed options in `parsed_opts`, so that the count is known on an error.
+    fn parse_args(&mut self, parsed_opts: &mut usize) -> Result<(), String> {
+        while let Some((arg, from_file)) = self.next_arg()? {
+            if arg == "--" {
+                if !from_file {
+                    *parsed_opts += 1;
+                }
+                if let Some(ref mut positionals) = self.positionals {
+                    positionals.extend(self.pending.drain(..).rev());
+                    positionals.extend(self.pos.by_ref().map(|a| a.as_ref().to_string()));
+                } else if let Some(arg) = self.pending.pop() {
+                    return Err(unexpected_arg_error(&arg));
+                }
                 break;
             }
-            parsed_opts += 1;
+            if self.parse_opt(&arg, from_file)? {
+                if !from_file {
+                    *parsed_opts += 1;
+                }
+            } else if self.strict && self.is_unknown_opt(&arg) {
+                return Err(self.unknown_opt_error(&arg));
+            } else if let Some(ref mut positionals) = self.positionals {
+                positionals.push(arg);
+            } else if from_file {
+                return Err(unexpected_arg_error(&arg));
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Appends the arguments of the response file to `args`, expanding the nested response files.
+// `stack` holds the files being expanded, to catch the ones that refer to themselves.
+fn expand_response_file(
+    path: &str,
+    split: SplitFn,
+    stack: &mut Vec<PathBuf>,
+    args: &mut Vec<String>,
+) -> Result<(), String> {
+    let read_err = |e| format!("Unable to read response file '{}': {}", path, e);
+    let canonical = fs::canonicalize(path).map_err(read_err)?;
+    if stack.contains(&canonical) {
+        return Err(format!("Response file '{}' refers to itself", path));
+    }
+    let contents = fs::read_to_string(&canonical).map_err(read_err)?;
+    let file_args =
+        split(&contents).map_err(|e| format!("Invalid response file '{}': {}", path, e))?;
+
+    stack.push(canonical);
+    for arg in file_args {
+        if arg.len() > 1 && arg.starts_with('@') {
+            expand_response_file(&arg[1..], split, stack, args)?;
+        } else {
+            args.push(arg);
+        }
+    }
+    stack.pop();
+    Ok(())
+}
+
+fn unexpected_arg_error(arg: &str) -> String {
+    format!("Unexpected argument '{}' in response file", arg)
+}
+
+// The Levenshtein distance between the strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            cur.push(std::cmp::min(
+                substitution,
+                std::cmp::min(prev[j + 1], cur[j]) + 1,
+            ));
         }
-        parsed_opts
+        prev = cur;
     }
+    prev[b.len()]
 }
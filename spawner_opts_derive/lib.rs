@@ -3,17 +3,29 @@
 //! use `spawner_opts` library.
 //!
 //! # Container attributes
-//! `#[optcont(delimeters = "...", usage = "...", default_parser = "...")]`
+//! `#[optcont(delimeters = "...", usage = "...", default_parser = "...", strict,
+//! case_insensitive, response_files = "...")]`
 //! - `delimeters` - This tells parser on what character the incoming string should be split
 //!   into the name\value pair.
 //! - `usage` - This attribute helps to build proper help message.
 //! - `default_parser` - If some field doesn't have the `parser` attribute the parser specified
 //!   by `default_parser` will be used.
+//! - `strict` - Makes parsing fail on an argument that looks like an unknown option, i.e.
+//!   starts with `-`, with a suggestion of the closest known name. By default the parsing
+//!   stops at such an argument, or collects it as a positional one.
+//! - `case_insensitive` - Makes the names longer than a single letter match regardless of
+//!   their case. The help keeps the declared spelling. The single-letter names, including the
+//!   grouped ones like `-abc`, stay case-sensitive.
+//! - `response_files = "split_args"` - Makes an argument of the form `@path` in place of an
+//!   option expand into the arguments read from the file, split by a function
+//!   `fn(&str) -> Result<Vec<String>, String>`. The files may refer to other response files,
+//!   but not to themselves. See `Parser::response_files`.
 //!
 //! # Field attributes
-//! There are two kinds of field attributes:
+//! There are three kinds of field attributes:
 //! - `#[opt(...)]`
 //! - `#[flag(...)]`
+//! - `#[positional]`
 //!
 //! The main difference is that the fields marked by the `#[flag(...)]` macro must have `bool`
 //! type, and the macro must not contain `value_desc` and `parser` attributes.
@@ -28,7 +40,20 @@
 //! - `parser = "IntValueParser"` - This attribute tells what parser should be used on the value.
 //! The parser must implement `OptionValueParser` trait.
 //! - `value_desc = "<int>"` - The description of the option's value.
+//! - `validate = "check_port"` - A function `fn(&T) -> Result<(), String>` that is called
+//!   with the field once the option is parsed, its error is returned from the parsing. The
+//!   attribute can be repeated, the validators run in the order they are declared.
 //! - `env = "..."` - Corresponding environment variable for this option.
+//! - `arity = 2` - The number of values the option takes. The first value may follow the
+//!   name after a delimiter, and the rest are the next arguments. The values are passed to
+//!   `OptionValueParser::parse_values`, and the value of the environment variable is split
+//!   on whitespace.
+//!
+//! # `#[positional]`
+//! Marks a `Vec<T>` field, where `T: FromStr`, that collects the arguments that are not
+//! options, in order. Without such a field `parse_argv` stops at the first of them. At most
+//! one field can be marked. The arguments after a bare `--` are positional even if they look
+//! like options.
 #![recursion_limit = "128"]
 
 extern crate proc_macro;
@@ -44,7 +69,7 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, DeriveInput, Error};
 
-#[proc_macro_derive(CmdLineOptions, attributes(optcont, opt, flag))]
+#[proc_macro_derive(CmdLineOptions, attributes(optcont, opt, flag, positional))]
 pub fn derive_cmd_line_options(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     expand_derive_cmd_line_options(&input)
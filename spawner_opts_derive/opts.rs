@@ -7,12 +7,16 @@ use syn::{
 struct OptKindOpt {
     value_desc: Option<String>,
     parser: Option<TokenStream>,
+    validators: Vec<TokenStream>,
+    // The number of values the option takes, if it takes more than one.
+    arity: Option<usize>,
 }
 
 enum OptKind {
     Invalid,
     Opt(OptKindOpt),
     Flag,
+    Positional,
 }
 
 struct Opt<'a> {
@@ -29,6 +33,8 @@ enum OptAttribute<'a> {
     Desc(&'a MetaNameValue, String),
     ValueDesc(&'a MetaNameValue, String),
     Parser(&'a MetaNameValue, String),
+    Validate(&'a MetaNameValue, String),
+    Arity(&'a MetaNameValue, usize),
     Env(&'a MetaNameValue, String),
 }
 
@@ -37,6 +43,9 @@ enum OptContainerAttribute {
     Delimeters(String),
     Usage(String),
     DefaultParser(String),
+    Strict,
+    CaseInsensitive,
+    ResponseFiles(String),
 }
 
 struct OptContainer<'a> {
@@ -44,6 +53,9 @@ struct OptContainer<'a> {
     usage: Option<String>,
     overview: Option<String>,
     default_parser: Option<TokenStream>,
+    strict: bool,
+    case_insensitive: bool,
+    split_response_file: Option<TokenStream>,
     opts: Vec<Opt<'a>>,
     ast: &'a DeriveInput,
 }
@@ -53,6 +65,8 @@ impl Default for OptKindOpt {
         Self {
             value_desc: None,
             parser: None,
+            validators: Vec::new(),
+            arity: None,
         }
     }
 }
@@ -75,7 +89,7 @@ impl<'a> OptAttribute<'a> {
         Error::new_spanned(
             v,
             "Expected one of: name = \"...\", names(...), desc = \"...\", \
-             value_desc = \"...\" parser = \"...\" env = \"...\"",
+             value_desc = \"...\" parser = \"...\" validate = \"...\" env = \"...\" arity = N",
         )
     }
 
@@ -86,6 +100,8 @@ impl<'a> OptAttribute<'a> {
             "desc" => Ok(OptAttribute::Desc(nameval, expect_str(lit)?)),
             "value_desc" => Ok(OptAttribute::ValueDesc(nameval, expect_str(lit)?)),
             "parser" => Ok(OptAttribute::Parser(nameval, expect_str(lit)?)),
+            "validate" => Ok(OptAttribute::Validate(nameval, expect_str(lit)?)),
+            "arity" => Ok(OptAttribute::Arity(nameval, expect_arity(lit)?)),
             "env" => Ok(OptAttribute::Env(nameval, expect_str(lit)?)),
             _ => Err(OptAttribute::expected_one_of_err(nameval)),
         }
@@ -156,6 +172,21 @@ impl<'a> Opt<'a> {
                         ));
                     }
                 },
+                OptAttribute::Validate(nameval, s) => match opt.kind {
+                    OptKind::Opt(ref mut v) => v.validators.push(s.parse().unwrap()),
+                    _ => {
+                        return Err(Error::new_spanned(
+                            nameval,
+                            "Validator allowed on options only",
+                        ));
+                    }
+                },
+                OptAttribute::Arity(nameval, n) => match opt.kind {
+                    OptKind::Opt(ref mut v) => v.arity = Some(n).filter(|&n| n > 1),
+                    _ => {
+                        return Err(Error::new_spanned(nameval, "Arity allowed on options only"));
+                    }
+                },
                 OptAttribute::Env(_, s) => opt.env = Some(s),
             }
         }
@@ -186,6 +217,11 @@ impl<'a> Opt<'a> {
                 let ident = &attr.path.segments[0].ident;
                 if ident == "opt" || ident == "flag" {
                     opts.push(Opt::from_meta(field, attr, attr.interpret_meta())?);
+                } else if ident == "positional" {
+                    match attr.interpret_meta() {
+                        Some(Meta::Word(_)) => opts.push(Opt::new(OptKind::Positional, field)),
+                        _ => return Err(Error::new_spanned(attr, "Expected #[positional]")),
+                    }
                 }
             }
         }
@@ -201,11 +237,19 @@ impl OptContainerAttribute {
         Error::new_spanned(
             v,
             "Expected one of: delimeters = \"...\", usage = \"...\", overview = \"...\" \
-             default_parser = \"...\"",
+             default_parser = \"...\", strict, case_insensitive, response_files = \"...\"",
         )
     }
 
     fn from_meta(meta: &Meta) -> Result<Self, Error> {
+        if let Meta::Word(ident) = meta {
+            if ident == "strict" {
+                return Ok(OptContainerAttribute::Strict);
+            }
+            if ident == "case_insensitive" {
+                return Ok(OptContainerAttribute::CaseInsensitive);
+            }
+        }
         if let Meta::NameValue(nameval) = meta {
             match nameval.ident.to_string().as_ref() {
                 "overview" => Ok(OptContainerAttribute::Overview(expect_str(&nameval.lit)?)),
@@ -214,6 +258,9 @@ impl OptContainerAttribute {
                 "default_parser" => Ok(OptContainerAttribute::DefaultParser(expect_str(
                     &nameval.lit,
                 )?)),
+                "response_files" => Ok(OptContainerAttribute::ResponseFiles(expect_str(
+                    &nameval.lit,
+                )?)),
                 _ => Err(OptContainerAttribute::expected_one_of_err(meta)),
             }
         } else {
@@ -290,6 +337,16 @@ impl<'a> OptContainer<'a> {
                 Err(e) => errors.push(e),
             }
         }
+        let positionals = self
+            .opts
+            .iter()
+            .filter(|opt| matches!(opt.kind, OptKind::Positional));
+        for opt in positionals.skip(1) {
+            errors.push(Error::new_spanned(
+                opt.field,
+                "Only one field can collect positional arguments",
+            ));
+        }
         match errors.len() {
             0 => Ok(()),
             _ => Err(errors),
@@ -305,6 +362,11 @@ impl<'a> OptContainer<'a> {
                 OptContainerAttribute::DefaultParser(p) => {
                     self.default_parser = Some(p.parse().unwrap())
                 }
+                OptContainerAttribute::Strict => self.strict = true,
+                OptContainerAttribute::CaseInsensitive => self.case_insensitive = true,
+                OptContainerAttribute::ResponseFiles(f) => {
+                    self.split_response_file = Some(f.parse().unwrap())
+                }
             }
         }
         Ok(())
@@ -316,6 +378,9 @@ impl<'a> OptContainer<'a> {
             overview: None,
             usage: None,
             default_parser: None,
+            strict: false,
+            case_insensitive: false,
+            split_response_file: None,
             opts: Vec::new(),
             ast,
         };
@@ -344,7 +409,7 @@ impl<'a> OptContainer<'a> {
                 let desc = self.build_str_opt(&opt.desc);
                 let env = self.build_str_opt(&opt.env);
                 match opt.kind {
-                    OptKind::Invalid => None,
+                    OptKind::Invalid | OptKind::Positional => None,
                     OptKind::Flag => Some(quote! {
                         spawner_opts::OptionHelp {
                             names: vec![#(#names),*],
@@ -383,19 +448,20 @@ impl<'a> OptContainer<'a> {
         self.opts
             .iter()
             .filter_map(|opt| {
-                let member_func = match &opt.kind {
-                    OptKind::Flag => quote!(flag),
-                    OptKind::Opt(_) => quote!(opt),
-                    _ => return None,
-                };
                 let names: Vec<Lit> = opt
                     .names
                     .iter()
                     .map(|name| Lit::new(Literal::string(name)))
                     .collect();
-                Some(quote! {
-                    parser.#member_func(&[#(#names),*]);
-                })
+                match &opt.kind {
+                    OptKind::Flag => Some(quote!(parser.flag(&[#(#names),*]);)),
+                    OptKind::Opt(OptKindOpt {
+                        arity: Some(arity), ..
+                    }) => Some(quote!(parser.multi_opt(&[#(#names),*], #arity);)),
+                    OptKind::Opt(_) => Some(quote!(parser.opt(&[#(#names),*]);)),
+                    OptKind::Positional => Some(quote!(parser.positional();)),
+                    _ => None,
+                }
             })
             .collect()
     }
@@ -440,16 +506,38 @@ impl<'a> OptContainer<'a> {
                         self.#field = true;
                     }
                 }),
-                OptKind::Opt(_) => match self.opt_parser(opt) {
-                    Ok(parser) => set_opts.push(quote! {
-                        if let Some(entries) = parser.get_opt(#name) {
-                            for e in entries {
-                                #parser::parse(&mut self.#field, e)?;
-                            }
-                        }
-                    }),
+                OptKind::Opt(ref v) => match self.opt_parser(opt) {
+                    Ok(parser) => {
+                        let validate = build_validate(&v.validators, opt.field);
+                        set_opts.push(match v.arity {
+                            Some(_) => quote! {
+                                if let Some(entries) = parser.get_multi_opt(#name) {
+                                    for e in entries {
+                                        #parser::parse_values(&mut self.#field, e)?;
+                                    }
+                                    #validate
+                                }
+                            },
+                            None => quote! {
+                                if let Some(entries) = parser.get_opt(#name) {
+                                    for e in entries {
+                                        #parser::parse(&mut self.#field, e)?;
+                                    }
+                                    #validate
+                                }
+                            },
+                        })
+                    }
                     Err(e) => errors.push(e),
                 },
+                OptKind::Positional => set_opts.push(quote! {
+                    for p in parser.get_positionals() {
+                        let val = p
+                            .parse()
+                            .map_err(|_| format!("Invalid argument '{}'", p))?;
+                        self.#field.push(val);
+                    }
+                }),
                 _ => {}
             }
         }
@@ -476,12 +564,36 @@ impl<'a> OptContainer<'a> {
             };
 
             let field = &opt.field.ident;
+            let (validate, arity) = match opt.kind {
+                OptKind::Opt(ref v) => (build_validate(&v.validators, opt.field), v.arity),
+                _ => (TokenStream::new(), None),
+            };
             match parser {
-                Ok(parser) => result.push(quote! {
-                    if let Some(val) = std::env::var(#env).ok() {
-                        #parser::parse(&mut self.#field, val.as_str())?;
-                    }
-                }),
+                Ok(parser) => {
+                    let parse = match arity {
+                        // The values are separated by whitespace.
+                        Some(arity) => quote! {
+                            let vals: Vec<String> =
+                                val.split_whitespace().map(String::from).collect();
+                            if vals.len() != #arity {
+                                return Err(format!(
+                                    "Environment variable '{}' expects {} values, got {}",
+                                    #env,
+                                    #arity,
+                                    vals.len()
+                                ));
+                            }
+                            #parser::parse_values(&mut self.#field, &vals)?;
+                        },
+                        None => quote!(#parser::parse(&mut self.#field, val.as_str())?;),
+                    };
+                    result.push(quote! {
+                        if let Some(val) = std::env::var(#env).ok() {
+                            #parse
+                            #validate
+                        }
+                    })
+                }
                 Err(e) => errors.push(e),
             }
         }
@@ -503,6 +615,12 @@ impl<'a> OptContainer<'a> {
 
     fn build_parse_argv_fn(&self) -> Result<TokenStream, Vec<Error>> {
         let delimeters = &self.delimeters;
+        let strict = self.strict;
+        let case_insensitive = self.case_insensitive;
+        let response_files = self
+            .split_response_file
+            .as_ref()
+            .map(|split| quote!(parser.response_files(#split);));
         let register_opts = self.build_register_opts();
         let set_opts = self.build_set_opts()?;
 
@@ -516,8 +634,10 @@ impl<'a> OptContainer<'a> {
                 fn assert_flag_type_is_bool(v: &bool) {}
 
                 let mut parser = Parser::new(argv, #delimeters);
+                parser.strict(#strict).case_insensitive(#case_insensitive);
+                #response_files
                 #(#register_opts)*
-                let parsed_opts = parser.parse();
+                let parsed_opts = parser.try_parse()?;
                 #(#set_opts)*
                 Ok(parsed_opts)
             }
@@ -525,6 +645,20 @@ impl<'a> OptContainer<'a> {
     }
 }
 
+// Calls every validator of the field in order, returning the first error.
+fn build_validate(validators: &[TokenStream], field: &Field) -> TokenStream {
+    let field = &field.ident;
+    let calls = validators.iter().map(|v| quote!(#v(&self.#field)?;));
+    quote!(#(#calls)*)
+}
+
+fn expect_arity(lit: &Lit) -> Result<usize, Error> {
+    match lit {
+        Lit::Int(i) if i.value() > 0 => Ok(i.value() as usize),
+        _ => Err(Error::new_spanned(lit, "Expected positive integer literal")),
+    }
+}
+
 fn expect_str(lit: &Lit) -> Result<String, Error> {
     match lit {
         Lit::Str(s) => Ok(s.value()),
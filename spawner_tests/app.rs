@@ -2,7 +2,7 @@ use std::alloc::{alloc, Layout};
 use std::env;
 use std::fs;
 use std::io::*;
-use std::net::{TcpListener, UdpSocket};
+use std::net::{TcpListener, TcpStream, UdpSocket};
 use std::process;
 use std::ptr;
 use std::str;
@@ -62,6 +62,12 @@ fn fwrite(filename: String, kb: usize) {
     }
 }
 
+fn fread(filename: String) {
+    let mut file = fs::File::open(&filename).unwrap();
+    let mut chunk = [0; 1024];
+    while file.read(&mut chunk).unwrap() != 0 {}
+}
+
 fn pipe_loop() {
     let mut chunk = [0 as u8; 128];
     while let Ok(bytes) = stdin().read(&mut chunk) {
@@ -105,12 +111,81 @@ fn create_udp_sockets(n: usize, ip: &'static str) {
     thread::sleep(Duration::from_secs(1));
 }
 
+fn connect_tcp(ports: String, dur: Duration) {
+    let _streams = ports
+        .split(',')
+        .map(|port| TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap())
+        .collect::<Vec<_>>();
+    thread::sleep(dur);
+}
+
+fn spawn_threads(n: usize, dur: Duration) {
+    let threads = (0..n)
+        .map(|_| thread::spawn(move || thread::sleep(dur)))
+        .collect::<Vec<_>>();
+    for t in threads {
+        let _ = t.join();
+    }
+}
+
+fn open_files(n: usize, dur: Duration) {
+    let exe = env::current_exe().unwrap();
+    let _files = (0..n)
+        .map(|_| fs::File::open(&exe).unwrap())
+        .collect::<Vec<_>>();
+    thread::sleep(dur);
+}
+
+fn spawn_processes(n: usize, dur: Duration) {
+    let exe = env::current_exe().unwrap();
+    let secs = dur.as_secs_f64().to_string();
+    let children = (0..n)
+        .map(|_| {
+            process::Command::new(&exe)
+                .args(["sleep", &secs])
+                .spawn()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for mut child in children {
+        let _ = child.wait();
+    }
+}
+
+#[cfg(unix)]
+fn ignore_interrupt() {
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+    const SIGTERM: i32 = 15;
+    const SIG_IGN: usize = 1;
+    unsafe { signal(SIGTERM, SIG_IGN) };
+}
+
+#[cfg(windows)]
+fn ignore_interrupt() {
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+    extern "system" fn ignore(_event: u32) -> i32 {
+        1
+    }
+    unsafe { SetConsoleCtrlHandler(Some(ignore), 1) };
+}
+
 fn try_write(file: String, text: String) {
     if let Ok(mut f) = fs::File::open(file) {
         let _ = f.write_all(text.as_bytes());
     }
 }
 
+#[cfg(unix)]
+fn write_fd(fd: i32, text: String) {
+    use std::os::unix::io::FromRawFd;
+    let mut f = unsafe { fs::File::from_raw_fd(fd) };
+    let _ = f.write_all(text.as_bytes());
+}
+
 fn main() {
     let mut p = Parser(std::env::args().skip(1));
     while let Some(arg) = p.0.next() {
@@ -124,6 +199,7 @@ fn main() {
             "sleep" => thread::sleep(p.parse_flt_secs()),
             "alloc" => alloc_((p.parse::<f64>() * 1024.0 * 1024.0) as usize),
             "fwrite" => fwrite(p.next(), p.parse()),
+            "fread" => fread(p.next()),
             "pipe_loop" => pipe_loop(),
             "print_n" => {
                 let s = p.next();
@@ -131,6 +207,9 @@ fn main() {
             }
             "wake_controller" => wake_controller(),
             "try_write" => try_write(p.next(), p.next()),
+            "ignore_interrupt" => ignore_interrupt(),
+            #[cfg(unix)]
+            "write_fd" => write_fd(p.parse(), p.next()),
             "exec_rest" => {
                 let _ = process::Command::new(p.next()).args(p.0).spawn();
                 return;
@@ -144,6 +223,14 @@ fn main() {
             "create_tcpv6_sockets" => create_tcp_sockets(p.parse(), "[::1]"),
             "create_udpv4_sockets" => create_udp_sockets(p.parse(), "127.0.0.1"),
             "create_udpv6_sockets" => create_udp_sockets(p.parse(), "[::1]"),
+            "is_tty" => match (stdin().is_terminal(), stdout().is_terminal()) {
+                (true, true) => print!("tty"),
+                _ => print!("notty"),
+            },
+            "connect_tcp" => connect_tcp(p.next(), p.parse_flt_secs()),
+            "spawn_threads" => spawn_threads(p.parse(), p.parse_flt_secs()),
+            "open_files" => open_files(p.parse(), p.parse_flt_secs()),
+            "spawn_processes" => spawn_processes(p.parse(), p.parse_flt_secs()),
             _ => print!("{}", arg),
         }
     }
@@ -0,0 +1,179 @@
+use crate::common::APP;
+
+use spawner::cmdline::{join_windows, join_windows_os, split_posix, split_windows};
+use spawner::process::ProcessInfo;
+use spawner::{run_capture, ResourceLimits};
+
+use std::ffi::OsString;
+
+const TRICKY_ARGS: [&str; 8] = [
+    "a b",
+    "\"quoted\"",
+    "a\\b",
+    "trailing\\",
+    "trailing space\\",
+    "\\\"",
+    "tab\there",
+    "",
+];
+
+#[test]
+fn join_plain_args() {
+    assert_eq!(join_windows(["app.exe", "a", "b\\c"]), "app.exe a b\\c");
+}
+
+#[test]
+fn join_args_with_spaces() {
+    assert_eq!(join_windows(["a b", "c\td"]), "\"a b\" \"c\td\"");
+}
+
+#[test]
+fn join_empty_arg() {
+    assert_eq!(join_windows(["a", ""]), "a \"\"");
+}
+
+#[test]
+fn join_args_with_quotes() {
+    assert_eq!(join_windows(["\"a\""]), "\"\\\"a\\\"\"");
+    assert_eq!(join_windows(["a\\\"b"]), "\"a\\\\\\\"b\"");
+}
+
+#[test]
+fn join_args_with_backslashes() {
+    // Backslashes are only doubled before a quote.
+    assert_eq!(join_windows(["a\\\\b"]), "a\\\\b");
+    assert_eq!(join_windows(["a b\\"]), "\"a b\\\\\"");
+    assert_eq!(join_windows(["a b\\\\"]), "\"a b\\\\\\\\\"");
+}
+
+#[test]
+fn args_are_passed_as_is() {
+    for arg in TRICKY_ARGS.iter() {
+        let mut info = ProcessInfo::new(APP);
+        info.arg("print_n").arg(arg).arg("1");
+        let (_, stdout, _) = run_capture(info, None, ResourceLimits::default()).unwrap();
+        assert_eq!(&stdout, arg);
+    }
+}
+
+#[test]
+fn join_os_matches_join() {
+    assert_eq!(
+        join_windows_os(TRICKY_ARGS.iter()),
+        OsString::from(join_windows(TRICKY_ARGS.iter()))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn join_non_unicode_args() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let args = [OsStr::from_bytes(b"a\xff"), OsStr::from_bytes(b"b \xfe\"")];
+    assert_eq!(join_windows_os(args).as_bytes(), b"a\xff \"b \xfe\\\"\"");
+}
+
+#[cfg(windows)]
+#[test]
+fn join_non_unicode_args() {
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+    // An unpaired surrogate.
+    let arg = OsString::from_wide(&[b'a' as u16, b' ' as u16, 0xd800]);
+    let expected = [b'"' as u16, b'a' as u16, b' ' as u16, 0xd800, b'"' as u16];
+    assert_eq!(
+        join_windows_os([arg]).encode_wide().collect::<Vec<_>>(),
+        expected
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn non_unicode_args_are_passed_as_is() {
+    use spawner::process::{ExitStatus, Process, Stdio};
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut info = ProcessInfo::new("sh");
+    info.args(["-c", "test \"$1\" = \"$(printf 'a\\377b')\"", "sh"])
+        .arg_os(OsStr::from_bytes(b"a\xffb"));
+    let mut ps = Process::spawn(&mut info, Stdio::null().unwrap()).unwrap();
+    assert_eq!(ps.wait(None).unwrap(), Some(ExitStatus::Finished(0)));
+}
+
+fn check_split<F>(split: F, cmd: &str, expected: &[&str])
+where
+    F: Fn(&str) -> spawner::Result<Vec<String>>,
+{
+    assert_eq!(split(cmd).unwrap(), expected, "command line: {}", cmd);
+}
+
+#[test]
+fn split_windows_command_lines() {
+    let cases: &[(&str, &[&str])] = &[
+        ("app a  b\tc", &["app", "a", "b", "c"]),
+        ("  app a", &["app", "a"]),
+        (
+            r#""C:\Program Files\app.exe" a"#,
+            &[r"C:\Program Files\app.exe", "a"],
+        ),
+        (r#"app "a b" c"d e"f"#, &["app", "a b", "cd ef"]),
+        (r#"app a\b a\\b"#, &["app", r"a\b", r"a\\b"]),
+        (
+            r#"app a\"b a\\"b c" a\\\"b"#,
+            &["app", r#"a"b"#, r"a\b c", r#"a\"b"#],
+        ),
+        (r#"app "a""b """#, &["app", r#"a"b"#, ""]),
+        (r#"app "a\\""#, &["app", r"a\"]),
+    ];
+    for &(cmd, expected) in cases {
+        check_split(split_windows, cmd, expected);
+    }
+}
+
+#[test]
+fn split_windows_is_inverse_of_join() {
+    let args = std::iter::once("app").chain(TRICKY_ARGS.iter().cloned());
+    let cmd = join_windows(args.clone());
+    assert_eq!(split_windows(&cmd).unwrap(), args.collect::<Vec<_>>());
+}
+
+#[test]
+fn split_posix_command_lines() {
+    let cases: &[(&str, &[&str])] = &[
+        ("app a  b\tc\nd", &["app", "a", "b", "c", "d"]),
+        ("app 'a b' \"c d\" e\\ f", &["app", "a b", "c d", "e f"]),
+        ("app 'a\\b' \"a\\b\" a\\b", &["app", "a\\b", "a\\b", "ab"]),
+        ("app \"\\$x \\\" \\\\\" '$x'", &["app", "$x \" \\", "$x"]),
+        ("app a'b'\"c\" '' \"\"", &["app", "abc", "", ""]),
+        ("app a\\\nb", &["app", "ab"]),
+    ];
+    for &(cmd, expected) in cases {
+        check_split(split_posix, cmd, expected);
+    }
+}
+
+#[test]
+fn unbalanced_quotes() {
+    assert!(split_windows("\"app").is_err());
+    assert!(split_windows("app \"a").is_err());
+    assert!(split_posix("app 'a").is_err());
+    assert!(split_posix("app \"a").is_err());
+    assert!(split_posix("app a\\").is_err());
+}
+
+#[test]
+fn empty_command_line() {
+    assert!(split_windows(" ").is_err());
+    assert!(split_posix(" ").is_err());
+    assert!(ProcessInfo::from_command_line("").is_err());
+}
+
+#[test]
+fn spawn_from_command_line() {
+    let cmd = format!("\"{}\" print_n \"a b\" 2", APP);
+    let info = ProcessInfo::from_command_line(cmd).unwrap();
+    let (_, stdout, _) = run_capture(info, None, ResourceLimits::default()).unwrap();
+    assert_eq!(stdout, "a ba b");
+}
@@ -19,10 +19,10 @@ pub const TIME_ERR: f64 = 0.15; // 150 ms
 
 macro_rules! target_dir {
     ($s:expr) => {
-        concat!(env!("CARGO_MANIFEST_DIR"), "/../target/debug/", $s);
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../target/debug/", $s)
     };
     () => {
-        target_dir!("");
+        target_dir!("")
     };
 }
 
@@ -36,6 +36,13 @@ impl Env {
     }
 
     fn vars<'a>(&'a self) -> Vec<(&'a str, &'a str)> {
+        self.all_vars()
+            .into_iter()
+            .filter(|(name, _)| !is_required_var(name))
+            .collect()
+    }
+
+    fn all_vars(&self) -> Vec<(&str, &str)> {
         self.data
             .lines()
             .map(|line| {
@@ -46,12 +53,27 @@ impl Env {
     }
 }
 
+/// Variables that are kept in a cleared environment.
+fn is_required_var(name: &str) -> bool {
+    cfg!(windows) && name.eq_ignore_ascii_case("SystemRoot")
+}
+
 #[test]
 fn clear_env() {
     let env = Env::with_argv(&["-env=clear"]);
     assert_eq!(env.vars(), Vec::new());
 }
 
+#[cfg(windows)]
+#[test]
+fn clear_env_keeps_system_root() {
+    let env = Env::with_argv(&["-env=clear"]);
+    assert!(env
+        .all_vars()
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("SystemRoot")));
+}
+
 #[test]
 fn define_var() {
     let env = Env::with_argv(&["-env=clear", "-D:NAME=VAR"]);
@@ -72,6 +94,18 @@ fn overwrite_var() {
     assert_eq!(env.vars(), vec![("NAME", "VAR1")]);
 }
 
+#[cfg(windows)]
+#[test]
+fn overwrite_var_ignores_case() {
+    let env = Env::with_argv(&["-env=inherit", "-D:path=VAR"]);
+    let path_vars = env
+        .vars()
+        .into_iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("PATH"))
+        .collect::<Vec<_>>();
+    assert_eq!(path_vars, vec![("path", "VAR")]);
+}
+
 #[test]
 fn default_env() {
     let env = Env::new();
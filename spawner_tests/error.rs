@@ -66,3 +66,48 @@ fn invalid_controller_command_2() {
     let r = run_single_controller_cmd("A\n");
     ensure_error(&r[0], "Missing '#' in controller message");
 }
+
+#[cfg(windows)]
+#[test]
+fn failed_user_creation_does_not_leak_handles() {
+    use spawner::windows::process::User;
+    use std::ffi::c_void;
+
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn GetProcessHandleCount(process: *mut c_void, count: *mut u32) -> i32;
+    }
+    let handle_count = || {
+        let mut count = 0;
+        assert_ne!(
+            unsafe { GetProcessHandleCount(GetCurrentProcess(), &mut count) },
+            0
+        );
+        count
+    };
+
+    // The logon fails after the window station and the desktop are created, so each
+    // iteration would leak both of them. Other tests may open a few handles meanwhile.
+    let before = handle_count();
+    for _ in 0..100 {
+        let err = User::create("spawner_tests_missing_user", Some("password"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("(error 1326)"));
+    }
+    assert!(handle_count() < before + 50);
+}
+
+#[test]
+fn invalid_signal() {
+    use spawner::ProgramMessage;
+
+    let err = ProgramMessage::signal(12345).unwrap_err();
+    if cfg!(windows) {
+        assert!(err
+            .to_string()
+            .starts_with("Invalid console control event 12345"));
+    } else {
+        assert_eq!(err.to_string(), "Invalid signal 12345");
+    }
+}
@@ -10,6 +10,8 @@ cfg_if! {
         extern crate rand;
         extern crate spawner;
         extern crate spawner_driver;
+        #[cfg(unix)]
+        extern crate tokio;
 
         mod term_reason;
         mod redirect;
@@ -18,5 +20,6 @@ cfg_if! {
         mod other;
         mod error;
         mod resource_usage;
+        mod cmdline;
     }
 }
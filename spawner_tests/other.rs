@@ -1,11 +1,18 @@
 use crate::term_reason::{ensure_ok, ensure_wall_clock_time_limit_exceeded};
 
-use crate::common::APP;
 #[cfg(windows)]
-use crate::common::{read_all, write_all, TmpDir};
+use crate::common::{read_all, write_all};
+use crate::common::{TmpDir, APP};
 
+use spawner::process::{ExitStatus, Group, OsLimit, Process, ProcessInfo, Stdio};
+use spawner::{
+    run_capture, IdleTimeLimit, Program, ResourceLimits, RestartPolicy, Session, SessionBuilder,
+    TerminationKind, TerminationReason,
+};
 use spawner_driver::run;
 
+use std::time::{Duration, Instant};
+
 #[cfg(windows)]
 #[test]
 fn exclusive_read() {
@@ -50,6 +57,54 @@ fn exclusive_write() {
     assert_eq!("A", read_all(file));
 }
 
+#[cfg(windows)]
+#[test]
+fn read_shared_write() {
+    use spawner::pipe::WritePipe;
+    use spawner::windows::pipe::{ShareMode, WritePipeExt};
+    use std::io::Write;
+
+    let tmp = TmpDir::new();
+    let file = tmp.file("file.txt");
+    let mut pipe = WritePipe::open_shared(&file, ShareMode::ReadShared).unwrap();
+    pipe.write_all(b"data").unwrap();
+    assert_eq!("data", read_all(&file));
+    assert!(WritePipe::open(&file).is_err());
+}
+
+// Logs on the account given by the `SPAWNER_TEST_USER` and `SPAWNER_TEST_PASSWORD`
+// environment variables, which is why it is not run by default.
+#[cfg(windows)]
+#[ignore]
+#[test]
+fn user_desktop_name() {
+    use spawner::windows::process::{User, UserOptions};
+    use std::env;
+
+    let user = env::var("SPAWNER_TEST_USER").expect("SPAWNER_TEST_USER is not set");
+    let options = UserOptions {
+        password: env::var("SPAWNER_TEST_PASSWORD").ok(),
+        ..Default::default()
+    };
+    let desktop = |user: &User| user.desktop_name().rsplit('\\').next().unwrap().to_string();
+
+    // The desktops of the users that are logged on at the same time get unique names.
+    let first = User::create_with_options(&user, &options).unwrap();
+    let second = User::create_with_options(&user, &options).unwrap();
+    assert!(desktop(&first).starts_with("desktop_"));
+    assert_ne!(desktop(&first), desktop(&second));
+
+    let named = User::create_with_options(
+        &user,
+        &UserOptions {
+            desktop_name: Some("spawner_desktop".to_string()),
+            ..options.clone()
+        },
+    )
+    .unwrap();
+    assert_eq!(desktop(&named), "spawner_desktop");
+}
+
 #[cfg(windows)]
 #[test]
 fn exclusive_write_2() {
@@ -172,3 +227,1518 @@ fn search_in_path_disabled() {
     let r = run(&["sh", "-c", "exit"]).unwrap();
     assert!(!r[0].spawner_error.is_empty());
 }
+
+pub fn run_program<F>(args: &[&str], f: F) -> spawner::Report
+where
+    F: FnOnce(&mut Program),
+{
+    let mut info = ProcessInfo::new(APP);
+    info.args(args);
+    let mut sess = Session::new();
+    sess.add_program(Program::new_with(info, f)).unwrap();
+    sess.run().unwrap().wait().pop().unwrap().unwrap()
+}
+
+fn spawn_and_wait(info: &mut ProcessInfo) -> ExitStatus {
+    let mut ps = Process::spawn(info, Stdio::null().unwrap()).unwrap();
+    ps.wait(None).unwrap().unwrap()
+}
+
+#[test]
+fn session_defaults() {
+    let mut sess = SessionBuilder::new()
+        .resource_limits(ResourceLimits {
+            wall_clock_time: Some(Duration::from_millis(100)),
+            ..Default::default()
+        })
+        .build();
+    for limits in [None, Some(ResourceLimits::default())] {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["sleep", "0.5"]);
+        sess.add_program(Program::new_with(info, |p| {
+            if let Some(limits) = limits {
+                p.resource_limits(limits);
+            }
+        }))
+        .unwrap();
+    }
+    let reports = sess
+        .run()
+        .unwrap()
+        .wait()
+        .into_iter()
+        .map(|r| r.unwrap().termination_reason.map(|r| r.kind()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        reports,
+        [Some(TerminationKind::WallClockTimeLimitExceeded), None]
+    );
+}
+
+#[test]
+fn restart_on_failure() {
+    let report = run_program(&["abnormal_exit"], |p| {
+        p.restart_policy(RestartPolicy::OnFailure { max: 2 });
+    });
+    assert_eq!(report.restarts, 2);
+    assert!(matches!(report.exit_status, ExitStatus::Crashed(_)));
+}
+
+#[test]
+fn no_restart_on_success() {
+    let report = run_program(&[], |p| {
+        p.restart_policy(RestartPolicy::OnFailure { max: 2 });
+    });
+    assert_eq!(report.restarts, 0);
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+}
+
+#[test]
+fn limits_span_restarts() {
+    let report = run_program(&["sleep", "0.4"], |p| {
+        p.restart_policy(RestartPolicy::Always { max: 10 })
+            .resource_limits(ResourceLimits {
+                wall_clock_time: Some(Duration::from_secs(1)),
+                ..Default::default()
+            });
+    });
+    assert!(report.restarts >= 1 && report.restarts < 10);
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+}
+
+#[test]
+fn sampling_stats() {
+    let report = run_program(&["sleep", "0.2"], |p| {
+        p.monitor_interval(Duration::from_millis(10));
+    });
+    assert!(report.samples_taken > 1);
+    assert!(report.sampling_time > Duration::from_millis(0));
+    assert!(report.sampling_time < report.wall_clock_time);
+}
+
+#[test]
+fn zero_monitor_interval() {
+    let report = run_program(&["sleep", "10"], |p| {
+        p.monitor_interval(Duration::from_millis(0))
+            .resource_limits(ResourceLimits {
+                wall_clock_time: Some(Duration::from_millis(100)),
+                ..Default::default()
+            });
+    });
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+    // The monitor sleeps between the samples instead of spinning.
+    assert!(report.samples_taken as u128 <= report.wall_clock_time.as_millis() + 1);
+}
+
+#[test]
+fn idle_time_in_report() {
+    let report = run_program(&["sleep", "10"], |p| {
+        p.resource_limits(ResourceLimits {
+            idle_time: Some(IdleTimeLimit {
+                total_idle_time: Duration::from_millis(300),
+                cpu_load_threshold: 0.05,
+                cpu_load_window_length: 1,
+            }),
+            ..Default::default()
+        });
+    });
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::IdleTimeLimitExceeded)
+    );
+    assert!(report.idle_time > Duration::from_millis(300));
+    assert!(report.cpu_load < 0.05);
+}
+
+#[test]
+fn memory_limit_applies_from_start() {
+    const LIMIT: u64 = 100 * 1024 * 1024;
+    let group = Group::new().unwrap();
+    assert!(group.set_os_limit(OsLimit::Memory, LIMIT).unwrap());
+    let report = run_program(&["alloc", "1024"], |p| {
+        p.group(group).resource_limits(ResourceLimits {
+            max_memory_usage: Some(LIMIT),
+            ..Default::default()
+        });
+    });
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::MemoryLimitExceeded)
+    );
+    assert!(report.memory.unwrap().max_usage <= LIMIT);
+}
+
+#[test]
+fn capture_output() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["pipe_loop"]);
+    let (report, stdout, stderr) =
+        run_capture(info, Some(b"data"), ResourceLimits::default()).unwrap();
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+    assert_eq!(stdout, "data");
+    assert_eq!(stderr, "data");
+}
+
+#[test]
+fn capture_output_without_stdin() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["print_n", "a", "100000"]);
+    let (_, stdout, stderr) = run_capture(info, None, ResourceLimits::default()).unwrap();
+    assert_eq!(stdout.len(), 100000);
+    assert!(stderr.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn inherit_fd() {
+    use spawner::pipe;
+    use std::os::unix::net::UnixStream;
+
+    let (r, w) = UnixStream::pair().unwrap();
+    check_inherited_fd(r, w);
+    // Pipe ends expose their descriptors through `AsRawFd` as well.
+    let (r, w) = pipe::create().unwrap();
+    check_inherited_fd(r, w);
+}
+
+#[cfg(unix)]
+fn check_inherited_fd<R, W>(mut r: R, w: W)
+where
+    R: std::io::Read,
+    W: std::os::unix::io::AsRawFd,
+{
+    use spawner::unix::process::ProcessInfoExt;
+
+    let fd = w.as_raw_fd().to_string();
+    let mut info = ProcessInfo::new(APP);
+    info.args(["write_fd", fd.as_str(), "data"])
+        .inherit_fd(w.as_raw_fd());
+    assert_eq!(spawn_and_wait(&mut info), ExitStatus::Finished(0));
+
+    drop(w);
+    let mut data = String::new();
+    r.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "data");
+}
+
+#[cfg(unix)]
+#[test]
+fn async_pipe() {
+    use spawner::pipe;
+    use std::io::{Read, Write};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::runtime::{Builder, Runtime};
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_io().build().unwrap()
+    }
+
+    // Larger than the pipe buffer, so both ends have to wait for each other.
+    let data = vec![7u8; 1 << 20];
+    let (r, w) = pipe::create().unwrap();
+    let writer = std::thread::spawn({
+        let data = data.clone();
+        move || {
+            let rt = runtime();
+            let mut w = {
+                let _guard = rt.enter();
+                w.into_async().unwrap()
+            };
+            rt.block_on(w.write_all(&data)).unwrap();
+            w.into_sync()
+        }
+    });
+    let rt = runtime();
+    let mut r = {
+        let _guard = rt.enter();
+        r.into_async().unwrap()
+    };
+    let mut received = vec![0u8; data.len()];
+    rt.block_on(r.read_exact(&mut received)).unwrap();
+    assert!(received == data);
+    let mut r = r.into_sync();
+    let mut w = writer.join().unwrap();
+
+    // The blocking mode is restored, so reading an empty pipe waits for the writer.
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        w.write_all(b"data").unwrap();
+    });
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"data");
+    writer.join().unwrap();
+}
+
+#[test]
+fn terminate_and_wait() {
+    let group = Group::new().unwrap();
+    for _ in 0..3 {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["loop"]);
+        Process::spawn_in_group(&mut info, Stdio::null().unwrap(), &group).unwrap();
+    }
+    assert_eq!(group.pids().unwrap().len(), 3);
+    assert!(group.terminate_and_wait(Duration::from_secs(5)).unwrap());
+    assert!(group.pids().unwrap().is_empty());
+}
+
+#[test]
+fn inspect_output() {
+    use spawner::pipe::{self, WritePipe};
+    use spawner::process::StdioBuilder;
+    use std::io::Read;
+    use std::sync::mpsc::channel;
+
+    let (mut r, w) = pipe::create().unwrap();
+    let (sender, receiver) = channel();
+    let stdout = WritePipe::inspect(w, move |data| sender.send(data.to_vec()).unwrap()).unwrap();
+    let mut info = ProcessInfo::new(APP);
+    info.args(["READY"]);
+    let stdio = StdioBuilder::new().stdout(stdout).build().unwrap();
+    let _ps = Process::spawn(&mut info, stdio).unwrap();
+
+    let mut data = String::new();
+    r.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "READY");
+    assert_eq!(receiver.iter().flatten().collect::<Vec<u8>>(), b"READY");
+}
+
+#[test]
+fn for_each_line() {
+    use spawner::pipe;
+    use spawner::process::StdioBuilder;
+    use std::sync::{Arc, Mutex};
+
+    let (r, w) = pipe::create().unwrap();
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let reader = {
+        let lines = lines.clone();
+        r.for_each_line(move |line| lines.lock().unwrap().push(line.to_string()))
+    };
+    let mut info = ProcessInfo::new(APP);
+    info.args(["one\r\ntwo\n\n", "three"]);
+    let stdio = StdioBuilder::new().stdout(w).build().unwrap();
+    let _ps = Process::spawn(&mut info, stdio).unwrap();
+
+    reader.join().unwrap().unwrap();
+    assert_eq!(*lines.lock().unwrap(), ["one", "two", "", "three"]);
+}
+
+#[test]
+fn feed_paced() {
+    use spawner::pipe;
+    use std::io::Read;
+
+    let data = vec![b'x'; 300];
+    let (mut r, w) = pipe::create().unwrap();
+    let start = Instant::now();
+    let feeder = w.feed_paced(std::io::Cursor::new(data.clone()), 1000);
+    let mut received = Vec::new();
+    r.read_to_end(&mut received).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(250));
+    assert_eq!(received, data);
+    assert_eq!(feeder.join().unwrap().unwrap(), 300);
+
+    let (r, w) = pipe::create().unwrap();
+    drop(r);
+    let feeder = w.feed_paced(std::io::repeat(b'x'), 1000);
+    assert_eq!(feeder.join().unwrap().unwrap(), 0);
+}
+
+#[test]
+fn open_append() {
+    use spawner::pipe::WritePipe;
+    use std::io::Write;
+
+    let tmp = TmpDir::new();
+    let file = tmp.file("file.txt");
+    for data in ["first", "second"].iter() {
+        let mut pipe = WritePipe::open_append(&file).unwrap();
+        pipe.write_all(data.as_bytes()).unwrap();
+    }
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "firstsecond");
+}
+
+#[test]
+fn pipe_kind() {
+    use spawner::pipe::{self, ReadPipe, WritePipe};
+
+    let tmp = TmpDir::new();
+    let file = tmp.file("file.txt");
+    let w = WritePipe::open(&file).unwrap();
+    assert!(w.is_file() && !w.is_pipe());
+    let r = ReadPipe::open(&file).unwrap();
+    assert!(r.is_file() && !r.is_pipe());
+
+    let (r, w) = pipe::create().unwrap();
+    assert!(r.is_pipe() && !r.is_file());
+    assert!(w.is_pipe() && !w.is_file());
+
+    // A device is neither a file nor a pipe.
+    let w = WritePipe::null().unwrap();
+    assert!(!w.is_file() && !w.is_pipe());
+    #[cfg(unix)]
+    {
+        let tty = ReadPipe::open("/dev/ptmx").unwrap();
+        assert!(!tty.is_file() && !tty.is_pipe());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn socket_as_stdout() {
+    use spawner::pipe::WritePipe;
+    use spawner::process::StdioBuilder;
+    use std::io::Read;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+
+    let (mut r, w) = UnixStream::pair().unwrap();
+    let stdout = unsafe { WritePipe::from_raw_fd(w.into_raw_fd()) };
+    assert!(!stdout.is_file() && !stdout.is_pipe());
+    let mut info = ProcessInfo::new(APP);
+    info.args(["data"]);
+    let stdio = StdioBuilder::new().stdout(stdout).build().unwrap();
+    let _ps = Process::spawn(&mut info, stdio).unwrap();
+
+    let mut data = String::new();
+    r.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "data");
+}
+
+#[cfg(unix)]
+#[test]
+fn restrict_token() {
+    use spawner::process::RestrictOptions;
+
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "1"])
+        .restrict_token(RestrictOptions::default());
+    let ps = Process::spawn(&mut info, Stdio::null().unwrap()).unwrap();
+    let status = std::fs::read_to_string(format!("/proc/{}/status", ps.pid())).unwrap();
+    ps.terminate().unwrap();
+    assert!(status.contains("NoNewPrivs:\t1"));
+    assert!(status.contains("CapEff:\t0000000000000000"));
+    assert!(status.contains("CapPrm:\t0000000000000000"));
+}
+
+#[cfg(unix)]
+#[test]
+fn filesystem_sandbox() {
+    use spawner::process::FsSandbox;
+    use std::fs;
+    use std::path::Path;
+
+    let tmp = TmpDir::new();
+    let scratch = tmp.file("scratch");
+    fs::remove_file(&scratch).unwrap();
+    fs::create_dir(&scratch).unwrap();
+    let outside = tmp.file("outside.txt");
+    let run_sandboxed = |file: &str| {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["fwrite", file, "1"])
+            .filesystem_sandbox(FsSandbox::new(&scratch));
+        spawn_and_wait(&mut info)
+    };
+
+    assert_eq!(run_sandboxed("file.txt"), ExitStatus::Finished(0));
+    assert!(!Path::new(&scratch).join("file.txt").exists());
+    assert_ne!(run_sandboxed(&outside), ExitStatus::Finished(0));
+    assert_eq!(fs::metadata(&outside).unwrap().len(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn filesystem_sandbox_drops_sys_admin() {
+    use spawner::process::FsSandbox;
+    use std::fs;
+
+    let tmp = TmpDir::new();
+    let scratch = tmp.file("scratch");
+    fs::remove_file(&scratch).unwrap();
+    fs::create_dir(&scratch).unwrap();
+    // The capability is not in the bounding set, so it can't be regained by `exec`.
+    // Bit 21 is CAP_SYS_ADMIN.
+    let script =
+        r#"cap=$(sed -n 's/^CapBnd:\s*//p' /proc/self/status); [ $((0x$cap >> 21 & 1)) = 0 ]"#;
+    let mut info = ProcessInfo::new("/bin/sh");
+    info.args(["-c", script])
+        .filesystem_sandbox(FsSandbox::new(&scratch));
+    assert_eq!(spawn_and_wait(&mut info), ExitStatus::Finished(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn deny_network() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port().to_string();
+    let connect = |deny: bool| {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["connect_tcp", &port, "0"]).deny_network(deny);
+        spawn_and_wait(&mut info)
+    };
+
+    assert_eq!(connect(false), ExitStatus::Finished(0));
+    assert_ne!(connect(true), ExitStatus::Finished(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn deny_network_drops_sys_admin() {
+    // Bit 21 is CAP_SYS_ADMIN, see `filesystem_sandbox_drops_sys_admin`.
+    let script =
+        r#"cap=$(sed -n 's/^CapBnd:\s*//p' /proc/self/status); [ $((0x$cap >> 21 & 1)) = 0 ]"#;
+    let mut info = ProcessInfo::new("/bin/sh");
+    info.args(["-c", script]).deny_network(true);
+    assert_eq!(spawn_and_wait(&mut info), ExitStatus::Finished(0));
+}
+
+#[test]
+fn program_template() {
+    use spawner::{ProgramMessage, ProgramTemplate};
+
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "10"]);
+    let template = ProgramTemplate::new(info, |p| {
+        p.resource_limits(ResourceLimits {
+            wall_clock_time: Some(Duration::from_millis(500)),
+            ..Default::default()
+        });
+    });
+    let mut sess = Session::new();
+    let mut senders = Vec::new();
+    for _ in 0..2 {
+        let (prog, sender) = template.instantiate();
+        sess.add_program(prog).unwrap();
+        senders.push(sender);
+    }
+    let run = sess.run().unwrap();
+    senders[1].send(ProgramMessage::Terminate).unwrap();
+    let reasons = run
+        .wait()
+        .into_iter()
+        .map(|r| r.unwrap().termination_reason.map(|r| r.kind()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        reasons,
+        [
+            Some(TerminationKind::WallClockTimeLimitExceeded),
+            Some(TerminationKind::TerminatedByRunner)
+        ]
+    );
+}
+
+#[test]
+fn wait_each_in_completion_order() {
+    let mut sess = Session::new();
+    for secs in ["1", "0"].iter() {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["sleep", secs]);
+        sess.add_program(Program::new(info)).unwrap();
+    }
+    let mut order = Vec::new();
+    sess.run().unwrap().wait_each(|idx, result| {
+        assert_eq!(result.unwrap().exit_status, ExitStatus::Finished(0));
+        order.push(idx);
+    });
+    assert_eq!(order, [1, 0]);
+}
+
+#[test]
+fn on_threshold() {
+    use std::sync::{Arc, Mutex};
+
+    let crossed = Arc::new(Mutex::new(Vec::new()));
+    let crossed_clone = crossed.clone();
+    let report = run_program(&["sleep", "10"], |p| {
+        p.resource_limits(ResourceLimits {
+            wall_clock_time: Some(Duration::from_secs(1)),
+            total_user_time: Some(Duration::from_secs(10)),
+            ..Default::default()
+        })
+        .on_threshold(0.5, move |report: &spawner::Report, reason| {
+            crossed_clone
+                .lock()
+                .unwrap()
+                .push((report.wall_clock_time, reason));
+        });
+    });
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+    let crossed = crossed.lock().unwrap();
+    assert_eq!(crossed.len(), 1);
+    assert!(crossed[0].0 >= Duration::from_millis(500));
+    assert_eq!(crossed[0].1, TerminationKind::WallClockTimeLimitExceeded);
+}
+
+#[cfg(windows)]
+#[test]
+fn env_block_lookup() {
+    use spawner::windows::process::EnvBlock;
+
+    let env = EnvBlock::create(None).unwrap();
+    let path = env.get("PATH");
+    assert!(path.is_some());
+    assert_eq!(env.get("path"), path);
+    assert_eq!(env.get("NO_SUCH_VARIABLE"), None);
+    let path_vars = env.iter_prefix("path").collect::<Vec<_>>();
+    assert!(path_vars
+        .iter()
+        .all(|v| v.to_uppercase().starts_with("PATH")));
+    assert!(path_vars
+        .iter()
+        .any(|v| v[5..] == path.as_ref().unwrap()[..] && v[..5].eq_ignore_ascii_case("PATH=")));
+}
+
+#[cfg(windows)]
+#[test]
+fn nul_in_arguments() {
+    let spawn_err = |info: &mut ProcessInfo| {
+        Process::spawn(info, Stdio::null().unwrap())
+            .err()
+            .unwrap()
+            .to_string()
+    };
+
+    // The strings may be secrets, so only their position is reported.
+    let mut info = ProcessInfo::new(APP);
+    info.args(["secret\0b"]);
+    let e = spawn_err(&mut info);
+    assert!(
+        e.contains("Nul character found in the command line"),
+        "{}",
+        e
+    );
+    assert!(!e.contains("secret"), "{}", e);
+
+    let mut info = ProcessInfo::new(APP);
+    info.envs([("VAR", "secret\0b")]);
+    let e = spawn_err(&mut info);
+    assert!(
+        e.contains("Nul character found in the environment block"),
+        "{}",
+        e
+    );
+    assert!(!e.contains("secret"), "{}", e);
+}
+
+#[test]
+fn process_tree() {
+    let report = run_program(&["exec_rest_and_sleep", APP, "sleep", "10"], |p| {
+        p.resource_limits(ResourceLimits {
+            wall_clock_time: Some(Duration::from_secs(1)),
+            ..Default::default()
+        })
+        .record_process_tree(true);
+    });
+    let tree = report.process_tree.unwrap();
+    assert_eq!(tree.len(), 2);
+    assert!(tree.iter().all(|n| n.image_name.starts_with("app")));
+    assert_eq!(tree[1].parent_pid, tree[0].pid);
+    assert!(tree[0].start_time > 0);
+    assert!(tree[1].start_time >= tree[0].start_time);
+
+    let report = run_program(&["sleep", "0.1"], |p| {
+        p.record_process_tree(true);
+    });
+    let tree = report.process_tree.unwrap();
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree[0].exit_code, Some(0));
+
+    assert!(run_program(&["sleep", "0.1"], |_| {})
+        .process_tree
+        .is_none());
+}
+
+#[test]
+fn legacy_report() {
+    let report = run_program(&["sleep", "0"], |_| {});
+    let mut legacy = report.to_legacy();
+    legacy.application = APP.to_string();
+    let s = legacy.to_string();
+    assert!(s.contains("--------------- Spawner report ---------------\n"));
+    assert!(s.contains(&format!("Application:               {}\n", APP)));
+    assert!(s.contains("DeadLine:                  Infinity\n"));
+    assert!(s.contains("TerminateReason:           ExitProcess\n"));
+    assert!(s.contains("ExitCode:                  0\n"));
+    assert!(s.contains("ExitStatus:                0\n"));
+    assert!(s.contains("UserTime:                  "));
+    assert!(s.contains(" (Mb)\n"));
+
+    let report = run_program(&["loop", "10"], |p| {
+        p.resource_limits(ResourceLimits {
+            wall_clock_time: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+    });
+    assert!(report
+        .to_legacy_string()
+        .contains("TerminateReason:           TimeLimitExceeded\n"));
+}
+
+#[test]
+fn compare_reports() {
+    use spawner::{ReportDiff, ReportTolerances};
+
+    let expected = run_program(&["sleep", "0"], |_| {});
+    assert_eq!(expected.compare(&expected, ReportTolerances::default()), []);
+
+    let mut report = expected.clone();
+    report.wall_clock_time += Duration::from_millis(40);
+    assert_eq!(report.compare(&expected, ReportTolerances::default()), []);
+
+    report.wall_clock_time += Duration::from_millis(20);
+    report.exit_status = ExitStatus::Finished(1);
+    report.termination_reason = Some(TerminationReason::TerminatedByRunner);
+    assert_eq!(
+        report.compare(&expected, ReportTolerances::default()),
+        [
+            ReportDiff::WallClockTime {
+                expected: expected.wall_clock_time,
+                actual: report.wall_clock_time,
+            },
+            ReportDiff::ExitStatus {
+                expected: ExitStatus::Finished(0),
+                actual: ExitStatus::Finished(1),
+            },
+            ReportDiff::TerminationReason {
+                expected: None,
+                actual: Some(TerminationKind::TerminatedByRunner),
+            },
+        ]
+    );
+}
+
+#[test]
+fn reset_counters() {
+    use spawner::ProgramMessage;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    let run_with_reset = |reset: bool| {
+        let (sender, receiver) = channel();
+        let mut info = ProcessInfo::new(APP);
+        info.args(["loop", "0.5", "sleep", "0.5"]);
+        let mut sess = Session::new();
+        sess.add_program(Program::new_with(info, move |p| {
+            p.msg_receiver(receiver).reset_counters_on_reset_time(reset);
+        }))
+        .unwrap();
+        let run = sess.run().unwrap();
+        thread::sleep(Duration::from_millis(700));
+        sender.send(ProgramMessage::ResetTime).unwrap();
+        run.wait().pop().unwrap().unwrap()
+    };
+
+    // The loop runs before the reset, and sleeping after it takes no cpu time.
+    let report = run_with_reset(false);
+    assert!(report.timers.unwrap().total_user_time >= Duration::from_millis(100));
+    assert_eq!(report.counters_reset_at, None);
+
+    let report = run_with_reset(true);
+    assert!(report.timers.unwrap().total_user_time < Duration::from_millis(100));
+    assert!(report.counters_reset_at.unwrap() >= Duration::from_millis(600));
+}
+
+#[test]
+fn signal_message() {
+    use spawner::ProgramMessage;
+    use std::thread;
+
+    #[cfg(unix)]
+    const SIGNAL: i32 = 15; // SIGTERM
+    #[cfg(windows)]
+    const SIGNAL: i32 = 1; // CTRL_BREAK_EVENT
+
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "10"]).interruptible(true);
+    let mut sess = Session::new();
+    sess.add_program(Program::new(info)).unwrap();
+    let run = sess.run().unwrap();
+    thread::sleep(Duration::from_millis(200));
+    run.broadcast(ProgramMessage::signal(SIGNAL).unwrap());
+    let report = run.wait().pop().unwrap().unwrap();
+    assert_eq!(report.error, None);
+    // The process is killed by the signal, not by the supervisor.
+    assert_eq!(report.termination_reason, None);
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+    #[cfg(unix)]
+    let expected = "Process terminated by the 'SIGTERM' signal";
+    #[cfg(windows)]
+    let expected = "Control_C_Exit";
+    assert_eq!(
+        report.exit_status,
+        ExitStatus::Crashed(expected.to_string())
+    );
+}
+
+#[test]
+fn invalid_signal_message() {
+    use spawner::ProgramMessage;
+    use std::thread;
+
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "0.5"]);
+    let mut sess = Session::new();
+    sess.add_program(Program::new(info)).unwrap();
+    let run = sess.run().unwrap();
+    thread::sleep(Duration::from_millis(100));
+    run.broadcast(ProgramMessage::Signal(12345));
+    let report = run.wait().pop().unwrap().unwrap();
+    assert_eq!(report.error, None);
+    assert_eq!(report.termination_reason, None);
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+}
+
+#[test]
+fn shared_group_memory_limit() {
+    use std::sync::Arc;
+
+    // Each program stays under the limit, but the two of them exceed it together.
+    const LIMIT: u64 = 48 * 1024 * 1024;
+    let group = Arc::new(Group::new().unwrap());
+    let mut sess = Session::new();
+    for _ in 0..2 {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["alloc", "32", "sleep", "3"]);
+        sess.add_program(Program::new_with(info, |p| {
+            p.shared_group(group.clone())
+                .resource_limits(ResourceLimits {
+                    max_memory_usage: Some(LIMIT),
+                    ..Default::default()
+                });
+        }))
+        .unwrap();
+    }
+    let start = Instant::now();
+    let reports = sess.run().unwrap().wait();
+    assert!(start.elapsed() < Duration::from_secs(3));
+    for report in reports {
+        let report = report.unwrap();
+        assert!(report.memory.unwrap().max_usage > LIMIT);
+        assert!(matches!(
+            report.termination_reason,
+            Some(TerminationReason::MemoryLimitExceeded { .. })
+        ));
+    }
+}
+
+#[test]
+fn reset_peak_memory() {
+    use spawner::ProgramMessage;
+    use std::thread;
+
+    let run_with_reset = |reset: bool| {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["exec_rest_and_sleep", APP, "alloc", "64"]);
+        let mut sess = Session::new();
+        sess.add_program(Program::new(info)).unwrap();
+        let run = sess.run().unwrap();
+        // Touching 64MB takes a while in a debug build.
+        thread::sleep(Duration::from_millis(2000));
+        if reset {
+            run.broadcast(ProgramMessage::ResetPeakMemory);
+        }
+        thread::sleep(Duration::from_millis(300));
+        run.terminate_all();
+        run.wait().pop().unwrap().unwrap()
+    };
+
+    // The child allocates the memory and exits before the reset. Without cgroups the peak
+    // is sampled, and the pages touched right before the exit may be missed.
+    let report = run_with_reset(false);
+    assert!(report.memory.unwrap().max_usage >= 48 * 1024 * 1024);
+
+    // Where the OS can't reset its peak, the peak sampled since the reset is reported, see
+    // `Group::reset_peak_memory`, so the reset takes effect with every kind of group.
+    let report = run_with_reset(true);
+    assert!(report.memory.unwrap().max_usage < 32 * 1024 * 1024);
+}
+
+#[test]
+fn capture_natural_exit() {
+    let run = |dur: &str| {
+        run_program(&["sleep", dur], |p| {
+            p.resource_limits(ResourceLimits {
+                wall_clock_time: Some(Duration::from_millis(100)),
+                ..Default::default()
+            })
+            .capture_natural_exit(Duration::from_millis(500));
+        })
+    };
+
+    let report = run("0.3");
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+    assert_eq!(report.natural_exit_status, Some(ExitStatus::Finished(0)));
+
+    let report = run("5");
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+    assert_eq!(report.natural_exit_status, None);
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+}
+
+#[test]
+fn process_wait() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "0.5"]);
+    let mut ps = Process::spawn(&mut info, Stdio::null().unwrap()).unwrap();
+    let start = std::time::Instant::now();
+    assert_eq!(ps.wait(Some(Duration::from_millis(100))).unwrap(), None);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(
+        ps.wait(Some(Duration::from_secs(5))).unwrap(),
+        Some(ExitStatus::Finished(0))
+    );
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(ps.wait(None).unwrap(), Some(ExitStatus::Finished(0)));
+
+    let mut ps = Process::spawn(&mut info, Stdio::null().unwrap()).unwrap();
+    assert_eq!(ps.wait(None).unwrap(), Some(ExitStatus::Finished(0)));
+}
+
+#[test]
+fn event_driven_monitor() {
+    let run = |args: &[&str], limit: Option<Duration>| {
+        let mut info = ProcessInfo::new(APP);
+        info.args(args);
+        let mut sess = Session::new();
+        sess.add_program(Program::new_with(info, |p| {
+            p.monitor_interval(Duration::from_secs(10))
+                .event_driven_monitor(true)
+                .resource_limits(ResourceLimits {
+                    wall_clock_time: limit,
+                    ..Default::default()
+                });
+        }))
+        .unwrap();
+        sess.run().unwrap()
+    };
+
+    // Woken up by the exit of the process.
+    let report = run(&["sleep", "0.2"], None).wait().pop().unwrap().unwrap();
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+
+    // Woken up by a message.
+    let r = run(&["sleep", "10"], None);
+    std::thread::sleep(Duration::from_millis(200));
+    r.terminate_all();
+    let report = r.wait().pop().unwrap().unwrap();
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::TerminatedByRunner)
+    );
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+
+    // Woken up for the limit check.
+    let r = run(&["sleep", "10"], Some(Duration::from_millis(300)));
+    let report = r.wait().pop().unwrap().unwrap();
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+}
+
+// ConPTY adds escape sequences to the output on Windows.
+#[cfg(unix)]
+#[test]
+fn pseudo_console() {
+    use spawner::process::StdioBuilder;
+    use std::io::Read;
+
+    let mut info = ProcessInfo::new(APP);
+    info.args(["is_tty"]);
+    let (_, stdout, _) = run_capture(info.clone(), None, ResourceLimits::default()).unwrap();
+    assert_eq!(stdout, "notty");
+
+    info.pseudo_console(80, 24);
+    let (report, stdout, stderr) = run_capture(info, None, ResourceLimits::default()).unwrap();
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+    assert_eq!(stdout, "tty");
+    assert_eq!(stderr, "");
+
+    // The output is read until the end after the process exits.
+    let mut info = ProcessInfo::new(APP);
+    info.args(["print_n", "A", "100000"]).pseudo_console(80, 24);
+    let (_, stdout, _) = run_capture(info, None, ResourceLimits::default()).unwrap();
+    assert_eq!(stdout.len(), 100000);
+
+    // So is the output of a child that writes to the terminal well after the process exits.
+    // The child ignores the SIGHUP sent once the session leader exits.
+    let (mut r, w) = spawner::pipe::create().unwrap();
+    let stdio = StdioBuilder::new().stdout(w).build().unwrap();
+    let mut info = ProcessInfo::new("/bin/sh");
+    info.args(["-c", "trap '' HUP; (sleep 1; echo done) &"])
+        .pseudo_console(80, 24);
+    let mut ps = Process::spawn(&mut info, stdio).unwrap();
+    assert_eq!(ps.wait(None).unwrap(), Some(ExitStatus::Finished(0)));
+    let mut stdout = String::new();
+    r.read_to_string(&mut stdout).unwrap();
+    assert_eq!(stdout, "done\r\n");
+}
+
+#[test]
+fn remaining_limits() {
+    use std::thread;
+
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "5"]);
+    let mut sess = Session::new();
+    sess.add_program(Program::new_with(info, |p| {
+        p.resource_limits(ResourceLimits {
+            wall_clock_time: Some(Duration::from_secs(10)),
+            active_processes: Some(5),
+            ..Default::default()
+        });
+    }))
+    .unwrap();
+    let run = sess.run().unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let remaining = run.remaining(0).unwrap();
+    run.terminate_all();
+    run.wait();
+
+    let wall_clock_time = remaining.wall_clock_time.unwrap();
+    assert!(wall_clock_time > Duration::from_secs(5));
+    assert!(wall_clock_time < Duration::from_secs(10));
+    assert_eq!(remaining.active_processes, Some(4));
+    assert_eq!(remaining.max_memory_usage, None);
+}
+
+// Only the pages that are swapped out are counted on Linux, and the process doesn't touch
+// enough memory to be swapped. On Windows the private bytes are counted instead, see
+// `swap_limit_exceeded`.
+#[cfg(unix)]
+#[test]
+fn swap_limit() {
+    use std::thread;
+
+    const LIMIT: u64 = 1024 * 1024;
+    let mut info = ProcessInfo::new(APP);
+    info.args(["alloc", "16", "sleep", "1"]);
+    let mut sess = Session::new();
+    sess.add_program(Program::new_with(info, |p| {
+        p.resource_limits(ResourceLimits {
+            max_swap_usage: Some(LIMIT),
+            ..Default::default()
+        });
+    }))
+    .unwrap();
+    let run = sess.run().unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let remaining = run.remaining(0);
+    let result = run.wait().pop().unwrap();
+    let report = match result {
+        Ok(report) => report,
+        // Setting the limit fails if swap accounting is not available.
+        Err(e) => {
+            assert!(e.to_string().contains("swap accounting"), "{}", e);
+            return;
+        }
+    };
+    assert_eq!(report.termination_reason, None);
+    assert!(report.memory.unwrap().total_swap_bytes <= LIMIT);
+    assert!(remaining.unwrap().max_swap_usage.unwrap() <= LIMIT);
+}
+
+#[cfg(windows)]
+#[test]
+fn swap_limit_exceeded() {
+    const LIMIT: u64 = 1024 * 1024;
+    let report = run_program(&["alloc", "16", "sleep", "1"], |p| {
+        p.resource_limits(ResourceLimits {
+            max_swap_usage: Some(LIMIT),
+            ..Default::default()
+        });
+    });
+    match report.termination_reason {
+        Some(TerminationReason::SwapLimitExceeded { used, limit }) => {
+            assert_eq!(limit, LIMIT);
+            assert!(used > LIMIT);
+        }
+        reason => panic!("unexpected termination reason {:?}", reason),
+    }
+}
+
+#[test]
+fn max_messages_per_tick() {
+    use spawner::ProgramMessage;
+    use std::thread;
+
+    let time_to_terminate = |max: usize| {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["sleep", "5"]);
+        let mut sess = Session::new();
+        sess.add_program(Program::new_with(info, |p| {
+            p.monitor_interval(Duration::from_millis(200))
+                .event_driven_monitor(true)
+                .max_messages_per_tick(max);
+        }))
+        .unwrap();
+        let run = sess.run().unwrap();
+        thread::sleep(Duration::from_millis(300));
+
+        // Resuming the time accounting that is not stopped does nothing, so only the last
+        // message has an effect. Unlike `Terminate`, it waits in the queue.
+        let start = Instant::now();
+        for _ in 0..3 {
+            run.broadcast(ProgramMessage::ResumeTimeAccounting);
+        }
+        run.broadcast(ProgramMessage::GracefulTerminate {
+            timeout: Duration::from_millis(0),
+        });
+        run.wait();
+        start.elapsed()
+    };
+
+    // The messages sent at once wake the monitor up once, and the ones left over wait
+    // for the next ticks.
+    assert!(time_to_terminate(1) >= Duration::from_millis(600));
+    assert!(time_to_terminate(10) < Duration::from_millis(150));
+    // Otherwise no message would ever be handled and the program would sleep to the end.
+    let elapsed = time_to_terminate(0);
+    assert!(elapsed >= Duration::from_millis(600));
+    assert!(elapsed < Duration::from_secs(3));
+}
+
+#[test]
+fn program_message_eq() {
+    use spawner::ProgramMessage;
+
+    assert_eq!(ProgramMessage::Terminate, ProgramMessage::Terminate);
+    assert_eq!(ProgramMessage::Signal(15), ProgramMessage::Signal(15));
+    assert_ne!(ProgramMessage::Signal(15), ProgramMessage::Signal(9));
+    assert_ne!(ProgramMessage::Suspend, ProgramMessage::Resume);
+    assert_eq!(
+        ProgramMessage::GracefulTerminate {
+            timeout: Duration::from_secs(1)
+        },
+        ProgramMessage::GracefulTerminate {
+            timeout: Duration::from_secs(1)
+        }
+    );
+}
+
+#[test]
+fn spawn_detached() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "0.1"]);
+    let mut ps = spawner::spawn_detached(info, Stdio::null().unwrap(), Default::default()).unwrap();
+    assert_eq!(ps.wait(None).unwrap(), Some(ExitStatus::Finished(0)));
+
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "5"]);
+    let mut ps = spawner::spawn_detached(info, Stdio::null().unwrap(), Default::default()).unwrap();
+    assert_eq!(ps.wait(Some(Duration::from_millis(100))).unwrap(), None);
+    ps.terminate().unwrap();
+    let status = ps.wait(Some(Duration::from_secs(5))).unwrap();
+    assert!(status.is_some());
+    assert_ne!(status, Some(ExitStatus::Finished(0)));
+}
+
+#[test]
+fn spawn_detached_memory_limit() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["alloc", "64", "sleep", "1"]);
+    let limits = spawner::ResourceLimits {
+        max_memory_usage: Some(16 * 1024 * 1024),
+        ..Default::default()
+    };
+    let mut ps = match spawner::spawn_detached(info, Stdio::null().unwrap(), limits) {
+        Ok(ps) => ps,
+        // Without control groups the limit is only checked by a supervisor.
+        Err(e) if e.to_string().contains("can't be enforced by the OS") => return,
+        Err(e) => panic!("{}", e),
+    };
+    let status = ps.wait(Some(Duration::from_secs(5))).unwrap();
+    assert!(status.is_some());
+    assert_ne!(status, Some(ExitStatus::Finished(0)));
+}
+
+#[test]
+fn on_tick() {
+    use std::sync::{Arc, Mutex};
+
+    let ticks = Arc::new(Mutex::new(Vec::new()));
+    let ticks_clone = ticks.clone();
+    let report = run_program(&["sleep", "0.3"], |p| {
+        p.monitor_interval(Duration::from_millis(50))
+            .on_tick(move |report: &spawner::Report| {
+                let mut ticks = ticks_clone.lock().unwrap();
+                ticks.push((report.wall_clock_time, report.exit_status.clone()));
+            });
+    });
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+
+    let ticks = ticks.lock().unwrap();
+    assert!(ticks.len() > 2);
+    assert!(ticks
+        .iter()
+        .all(|(_, status)| *status == ExitStatus::Running));
+    assert!(ticks.windows(2).all(|w| w[0].0 <= w[1].0));
+}
+
+/// Returns the size of the file the program writes a second after the supervisor panics.
+fn bytes_written_after_supervisor_panic(kill_on_drop: bool) -> u64 {
+    struct PanicOnDrop;
+
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self) {
+            panic!("drop");
+        }
+    }
+
+    let tmp = TmpDir::new();
+    let file = tmp.file("file.txt");
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "1", "fwrite", file.as_str(), "1"])
+        .kill_on_drop(kill_on_drop);
+    let mut sess = Session::new();
+    sess.add_program(Program::new_with(info, |p| {
+        // The callback is dropped by the supervisor once it panics, and the drop is not
+        // guarded.
+        let guard = PanicOnDrop;
+        p.on_tick(move |_: &spawner::Report| {
+            let _ = &guard;
+            panic!("on_tick");
+        });
+    }))
+    .unwrap();
+    let result = sess.run().unwrap().wait().pop().unwrap();
+    assert!(result.is_err());
+
+    std::thread::sleep(Duration::from_millis(1500));
+    std::fs::metadata(&file).unwrap().len()
+}
+
+#[test]
+fn supervisor_panic_terminates_group() {
+    assert_eq!(bytes_written_after_supervisor_panic(true), 0);
+}
+
+#[test]
+fn kill_on_drop() {
+    assert_eq!(bytes_written_after_supervisor_panic(false), 1024);
+}
+
+#[test]
+fn terminate_all() {
+    let mut sess = Session::new();
+    for secs in ["0", "10"].iter() {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["sleep", secs]);
+        sess.add_program(Program::new(info)).unwrap();
+    }
+    let run = sess.run().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    // The first program has finished and does not receive the message.
+    assert_eq!(run.terminate_all(), [0]);
+    let reasons = run
+        .wait()
+        .into_iter()
+        .map(|r| r.unwrap().termination_reason)
+        .collect::<Vec<_>>();
+    assert_eq!(reasons, [None, Some(TerminationReason::TerminatedByRunner)]);
+}
+
+#[test]
+fn pause_time_on_stdin_wait() {
+    let run_reader = |pause: bool| {
+        // Closes the stdin of the reader after a while without writing to it.
+        let mut writer = ProcessInfo::new(APP);
+        writer.args(["sleep", "1"]);
+        let mut reader = ProcessInfo::new(APP);
+        reader.args(["pipe_loop"]);
+        let mut sess = Session::new();
+        sess.pipeline(vec![
+            Program::new(writer),
+            Program::new_with(reader, |p| {
+                p.pause_time_on_stdin_wait(pause)
+                    .resource_limits(ResourceLimits {
+                        wall_clock_time: Some(Duration::from_millis(500)),
+                        ..Default::default()
+                    });
+            }),
+        ])
+        .unwrap();
+        sess.run().unwrap().wait().pop().unwrap().unwrap()
+    };
+
+    let report = run_reader(true);
+    assert_eq!(report.termination_reason, None);
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+
+    let report = run_reader(false);
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+}
+
+#[test]
+fn try_report() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "0.3"]);
+    let mut sess = Session::new();
+    sess.add_program(Program::new(info)).unwrap();
+    let mut run = sess.run().unwrap();
+    assert!(run.try_report(0).is_none());
+
+    let start = Instant::now();
+    while run.try_report(0).is_none() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let exit_status = |run: &mut spawner::Run| {
+        run.try_report(0)
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .exit_status
+            .clone()
+    };
+    assert_eq!(exit_status(&mut run), ExitStatus::Finished(0));
+    // The result stays available.
+    assert_eq!(exit_status(&mut run), ExitStatus::Finished(0));
+}
+
+#[test]
+fn wait_timeout() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "0.5"]);
+    let mut sess = Session::new();
+    sess.add_program(Program::new(info)).unwrap();
+    let run = sess.run().unwrap();
+
+    let start = Instant::now();
+    let run = match run.wait_timeout(Duration::from_millis(100)) {
+        Ok(_) => panic!("the program has not finished yet"),
+        Err(run) => run,
+    };
+    assert!(start.elapsed() < Duration::from_millis(400));
+
+    let results = match run.wait_timeout(Duration::from_secs(5)) {
+        Ok(results) => results,
+        Err(_) => panic!("the program has not finished in time"),
+    };
+    assert_eq!(
+        results[0].as_ref().unwrap().exit_status,
+        ExitStatus::Finished(0)
+    );
+}
+
+#[test]
+fn wait_each_after_wait_timeout() {
+    let mut sess = Session::new();
+    for secs in ["0", "1"].iter() {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["sleep", secs]);
+        sess.add_program(Program::new(info)).unwrap();
+    }
+    let run = match sess.run().unwrap().wait_timeout(Duration::from_millis(500)) {
+        Ok(_) => panic!("the second program has not finished yet"),
+        Err(run) => run,
+    };
+    // The first program has finished while waiting, and is still passed to `wait_each`.
+    let mut order = Vec::new();
+    run.wait_each(|idx, result| {
+        assert_eq!(result.unwrap().exit_status, ExitStatus::Finished(0));
+        order.push(idx);
+    });
+    assert_eq!(order, [0, 1]);
+}
+
+#[test]
+fn cpu_affinity() {
+    let mut info = ProcessInfo::new(APP);
+    info.cpu_affinity(0);
+    assert!(Process::spawn(&mut info, Stdio::null().unwrap()).is_err());
+
+    let mut info = ProcessInfo::new(APP);
+    info.cpu_affinity(1);
+    assert_eq!(spawn_and_wait(&mut info), ExitStatus::Finished(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn cpu_affinity_is_applied() {
+    let mut info = ProcessInfo::new("sh");
+    info.args(["-c", "grep Cpus_allowed_list /proc/self/status"])
+        .cpu_affinity(1);
+    let (_, stdout, _) = run_capture(info, None, ResourceLimits::default()).unwrap();
+    assert_eq!(stdout.trim_end(), "Cpus_allowed_list:\t0");
+}
+
+#[cfg(unix)]
+#[test]
+fn priority() {
+    use spawner::process::Priority;
+
+    let nice = |priority: Priority| {
+        let mut info = ProcessInfo::new("sh");
+        info.args(["-c", "cut -d ' ' -f 19 /proc/self/stat"])
+            .priority(priority);
+        let (_, stdout, _) = run_capture(info, None, ResourceLimits::default()).unwrap();
+        stdout.trim_end().to_string()
+    };
+    assert_eq!(nice(Priority::Idle), "19");
+    assert_eq!(nice(Priority::BelowNormal), "10");
+}
+
+#[test]
+fn validate_program() {
+    let program = |mask: u64| {
+        let mut info = ProcessInfo::new(APP);
+        info.cpu_affinity(mask);
+        Program::new(info)
+    };
+    assert!(program(1).validate().is_ok());
+    assert!(program(0).validate().is_err());
+
+    let mut info = ProcessInfo::new(APP);
+    info.user("spawner_missing_user", None::<&str>);
+    assert!(Program::new(info).validate().is_err());
+}
+
+#[test]
+fn validate_session() {
+    let mut sess = Session::new();
+    let mapping = sess
+        .add_program(Program::new(ProcessInfo::new(APP)))
+        .unwrap();
+    sess.add_program(Program::new_with(ProcessInfo::new(APP), |p| {
+        p.merge_stderr(true);
+    }))
+    .unwrap();
+    sess.graph_mut().connect(mapping.stdout, mapping.stdin);
+    assert!(sess.validate().is_ok());
+
+    let mut info = ProcessInfo::new(APP);
+    info.cpu_affinity(0);
+    sess.add_program(Program::new(info)).unwrap();
+    assert!(sess.validate().is_err());
+}
+
+/// Drives `f` to completion on the current thread, parking between polls.
+fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::Thread;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut f = Box::pin(f);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+fn finished_future() {
+    let mut sess = Session::new();
+    for t in ["0.1", "0.5"] {
+        let mut info = ProcessInfo::new(APP);
+        info.args(["sleep", t]);
+        sess.add_program(Program::new(info)).unwrap();
+    }
+    let mut run = sess.run().unwrap();
+
+    let start = Instant::now();
+    block_on(run.finished(0));
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert_eq!(
+        run.try_report(0).unwrap().as_ref().unwrap().exit_status,
+        ExitStatus::Finished(0)
+    );
+    assert!(run.try_report(1).is_none());
+
+    let results = block_on(run.wait_async());
+    assert!(start.elapsed() >= Duration::from_millis(500));
+    for result in results {
+        assert_eq!(result.unwrap().exit_status, ExitStatus::Finished(0));
+    }
+}
+
+// Assumes that a pipe holds less than the output, which is the case on Linux.
+#[cfg(unix)]
+#[test]
+fn wait_async_does_not_block() {
+    use std::future::Future;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::task::{Context, Wake, Waker};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // The output doesn't fit in the pipe it is copied to, so it is still being copied
+    // after the program exits, until the pipe is read.
+    let mut sess = Session::new();
+    let mut info = ProcessInfo::new(APP);
+    info.args(["print_n", "A", "100000"]);
+    let mapping = sess.add_program(Program::new(info)).unwrap();
+    let (mut r, w) = spawner::pipe::create().unwrap();
+    let dst = sess.graph_mut().add_destination(w);
+    sess.graph_mut().connect(mapping.stdout, dst);
+    // Otherwise the program writes to the pipe directly and never exits.
+    sess.disable_source_optimization(mapping.stdout);
+    let run = sess.run().unwrap();
+    block_on(run.finished(0));
+
+    let start = Instant::now();
+    let mut wait = Box::pin(run.wait_async());
+    let waker = Waker::from(Arc::new(NoopWaker));
+    assert!(wait
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker))
+        .is_pending());
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    let reader = std::thread::spawn(move || {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        data.len()
+    });
+    let results = block_on(wait);
+    assert_eq!(reader.join().unwrap(), 100000);
+    assert_eq!(
+        results[0].as_ref().unwrap().exit_status,
+        ExitStatus::Finished(0)
+    );
+}
@@ -107,6 +107,27 @@ fn stdout_to_file() {
     assert_eq!("AAA".repeat(20), read_all(output));
 }
 
+#[test]
+fn merge_stderr_into_stdout() {
+    let tmp = TmpDir::new();
+    let input = tmp.file("in.txt");
+    let output = tmp.file("out.txt");
+    let error = tmp.file("err.txt");
+
+    write_all(&input, "1".repeat(30));
+    run(&[
+        "--merge-stderr",
+        format!("--in={}", input).as_str(),
+        format!("--out={}", output).as_str(),
+        format!("--err={}", error).as_str(),
+        APP,
+        "pipe_loop",
+    ])
+    .unwrap();
+    assert_eq!("1".repeat(60), read_all(output));
+    assert_eq!("", read_all(error));
+}
+
 #[test]
 fn stdout_to_2_files() {
     let tmp = TmpDir::new();
@@ -1,8 +1,12 @@
 use crate::assert_approx_eq;
 use crate::common::{TmpDir, APP, MEM_ERR, TIME_ERR};
+use crate::other::run_program;
 
+use spawner::process::{Group, Process, ProcessInfo, Stdio};
 use spawner_driver::run;
 
+use std::time::Duration;
+
 fn total_user_time(arg: &str) {
     let r = run(&[
         "--wait-for-children",
@@ -163,3 +167,68 @@ fn memory_usage_mi_1ms() {
 fn memory_usage_mi_1s() {
     memory_usage("-mi=1s");
 }
+
+fn io_operations(mi: Duration) {
+    let tmp = TmpDir::new();
+    let file = tmp.file("file.txt");
+    // Writes and reads 1 kb at a time, and sleeps so that the counters are sampled before
+    // the exit.
+    let report = run_program(
+        &["fwrite", &file, "1000", "fread", &file, "sleep", "1.5"],
+        |p| {
+            p.monitor_interval(mi);
+        },
+    );
+    let io = report.io.unwrap();
+    assert!(io.write_operations >= 1000);
+    assert!(io.total_bytes_written >= 1000 * 1024);
+    assert!(io.read_operations >= 1000);
+}
+
+#[test]
+fn io_operations_mi_1ms() {
+    io_operations(Duration::from_millis(1));
+}
+
+#[test]
+fn io_operations_mi_1s() {
+    io_operations(Duration::from_secs(1));
+}
+
+#[test]
+fn group_memory() {
+    use spawner::process::ResourceUsage;
+
+    const ALLOC: u64 = 32 * 1024 * 1024;
+    let group = Group::new().unwrap();
+    let mut info = ProcessInfo::new(APP);
+    info.args(["alloc", "32", "sleep", "3"]);
+    let ps = Process::spawn_in_group(&mut info, Stdio::null().unwrap(), &group).unwrap();
+    std::thread::sleep(Duration::from_millis(1500));
+
+    let mut usage = ResourceUsage::new(&group);
+    usage.update().unwrap();
+    let memory = usage.memory().unwrap().unwrap();
+    assert!(memory.working_set_bytes >= ALLOC);
+    assert!(memory.private_bytes >= ALLOC);
+    assert!(memory.max_usage >= memory.working_set_bytes);
+    // Touching every page of the allocation faults it in.
+    assert!(memory.page_fault_count >= ALLOC / 64 / 1024);
+
+    ps.terminate().unwrap();
+}
+
+#[test]
+fn group_cpu_load() {
+    let group = Group::new().unwrap();
+    let mut info = ProcessInfo::new(APP);
+    info.args(["loop", "10"]);
+    Process::spawn_in_group(&mut info, Stdio::null().unwrap(), &group).unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(group.cpu_load().unwrap() > 0.2);
+
+    assert!(group.terminate_and_wait(Duration::from_secs(5)).unwrap());
+    group.cpu_load().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(group.cpu_load().unwrap() < 0.1);
+}
@@ -1,8 +1,18 @@
 use crate::assert_approx_eq;
 use crate::common::{TmpDir, APP, MEM_ERR, TIME_ERR};
+use crate::other::run_program;
 
+use spawner::process::{ExitStatus, ProcessInfo};
+use spawner::{
+    BrokenPipe, Program, ProgramMessage, ResourceLimits, Session, TerminationKind,
+    TerminationReason,
+};
 use spawner_driver::{run, Report, TerminateReason};
 
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
 pub fn check_tr(report: &Report, tr: TerminateReason) {
     assert!(report.spawner_error.is_empty());
     assert_eq!(report.terminate_reason, tr);
@@ -35,6 +45,10 @@ pub fn ensure_active_process_count_limit_exceeded(report: &Report) {
     check_tr(report, TerminateReason::ActiveProcessesCountLimitExceeded);
 }
 
+pub fn ensure_active_thread_count_limit_exceeded(report: &Report) {
+    check_tr(report, TerminateReason::ActiveThreadsCountLimitExceeded);
+}
+
 pub fn ensure_idle_time_limit_exceeded(report: &Report) {
     check_tr(report, TerminateReason::IdleTimeLimitExceeded);
 }
@@ -58,7 +72,8 @@ pub fn ensure_active_connection_count_limit_exceeded(report: &Report) {
 
 #[test]
 fn mem_limit() {
-    let r = run(&["-d=3", "-ml=10", APP, "alloc", "10"]).unwrap();
+    // Without cgroups the limit is checked in software, so the memory is held for a while.
+    let r = run(&["-d=3", "-ml=10", APP, "alloc", "10", "sleep", "1"]).unwrap();
     ensure_mem_limit_exceeded(&r[0]);
 }
 
@@ -130,6 +145,12 @@ fn single_active_process() {
     ensure_ok(&r[0]);
 }
 
+#[test]
+fn active_thread_limit() {
+    let r = run(&["-active-thread-count=4", APP, "spawn_threads", "8", "1"]).unwrap();
+    ensure_active_thread_count_limit_exceeded(&r[0]);
+}
+
 #[test]
 fn idle_time_limit() {
     let r = run(&["-y=0.2", APP, "sleep", "1"]).unwrap();
@@ -148,6 +169,14 @@ fn wall_clock_time_limit_using_loop() {
     ensure_wall_clock_time_limit_exceeded(&r[0]);
 }
 
+#[test]
+fn wall_clock_time_limit_with_long_monitor_interval() {
+    let r = run(&["-d=0.1", "-mi=1", APP, "sleep", "2"]).unwrap();
+    ensure_wall_clock_time_limit_exceeded(&r[0]);
+    // The limit is checked at the deadline instead of the next tick of the monitor.
+    assert!(r[0].result.wall_clock_time < 0.5);
+}
+
 #[test]
 fn abnormal_exit() {
     let r = run(&["-d=2", APP, "abnormal_exit"]).unwrap();
@@ -230,3 +259,318 @@ fn active_connections_limit_ok() {
     connection_limit_ok("create_udpv4_sockets");
     connection_limit_ok("create_udpv6_sockets");
 }
+
+#[test]
+fn terminate_is_not_delayed_by_queued_messages() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "10"]);
+    let mut sess = Session::new();
+    sess.add_program(Program::new_with(info, |p| {
+        p.adaptive_interval(Duration::from_millis(1), Duration::from_secs(5));
+    }))
+    .unwrap();
+    let run = sess.run().unwrap();
+    // Let the interval back off.
+    thread::sleep(Duration::from_secs(3));
+
+    let start = Instant::now();
+    for _ in 0..1000 {
+        run.broadcast(ProgramMessage::Suspend);
+        run.broadcast(ProgramMessage::Resume);
+    }
+    run.terminate_all();
+    let report = run.wait().pop().unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::TerminatedByRunner)
+    );
+}
+
+#[test]
+fn terminate_through_msg_receiver_is_not_delayed() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "10"]);
+    let (sender, receiver) = channel();
+    let mut sess = Session::new();
+    sess.add_program(Program::new_with(info, move |p| {
+        p.adaptive_interval(Duration::from_millis(1), Duration::from_secs(5))
+            .msg_receiver(receiver);
+    }))
+    .unwrap();
+    let run = sess.run().unwrap();
+    // Let the interval back off.
+    thread::sleep(Duration::from_secs(3));
+
+    let start = Instant::now();
+    sender.send(ProgramMessage::Terminate).unwrap();
+    let report = run.wait().pop().unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::TerminatedByRunner)
+    );
+}
+
+#[test]
+fn msg_receiver_is_dropped_after_run() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "0"]);
+    let (sender, receiver) = channel();
+    let mut sess = Session::new();
+    sess.add_program(Program::new_with(info, move |p| {
+        p.msg_receiver(receiver);
+    }))
+    .unwrap();
+    sess.run().unwrap().wait();
+
+    // The sender is kept, so the receiver is only dropped once the forwarding thread sees
+    // that the supervisor has finished.
+    thread::sleep(Duration::from_millis(500));
+    assert!(sender.send(ProgramMessage::Terminate).is_err());
+}
+
+#[test]
+fn graceful_termination() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["sleep", "10"]).interruptible(true);
+    let mut sess = Session::new();
+    sess.add_program(Program::new(info)).unwrap();
+    let run = sess.run().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    run.broadcast(ProgramMessage::GracefulTerminate {
+        timeout: Duration::from_secs(5),
+    });
+    let report = run.wait().pop().unwrap().unwrap();
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::GracefullyTerminatedByRunner)
+    );
+}
+
+#[test]
+fn graceful_termination_timeout() {
+    let mut info = ProcessInfo::new(APP);
+    info.args(["ignore_interrupt", "sleep", "10"])
+        .interruptible(true);
+    let mut sess = Session::new();
+    sess.add_program(Program::new(info)).unwrap();
+    let run = sess.run().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    run.broadcast(ProgramMessage::GracefulTerminate {
+        timeout: Duration::from_millis(500),
+    });
+    let report = run.wait().pop().unwrap().unwrap();
+    assert!(report.wall_clock_time >= Duration::from_millis(700));
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::TerminatedByRunner)
+    );
+}
+
+#[test]
+fn close_stdin() {
+    let mut writer = ProcessInfo::new(APP);
+    writer.args(["sleep", "10"]);
+    let mut reader = ProcessInfo::new(APP);
+    reader.args(["pipe_loop"]);
+    let mut sess = Session::new();
+    let mappings = sess
+        .pipeline(vec![Program::new(writer), Program::new(reader)])
+        .unwrap();
+    // Keep the writer's stdout in the graph, otherwise stdin is not closable.
+    sess.disable_destination_optimization(mappings[1].stdin);
+    let run = sess.run().unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    run.broadcast(ProgramMessage::CloseStdin);
+    thread::sleep(Duration::from_secs(1));
+    run.terminate_all();
+    let mut reports = run.wait();
+    let reader_report = reports.pop().unwrap().unwrap();
+    assert!(reader_report.wall_clock_time < Duration::from_secs(1));
+    assert_eq!(reader_report.termination_reason, None);
+}
+
+fn run_with_gone_consumer(n: &str, policy: BrokenPipe) -> spawner::Report {
+    let mut producer = ProcessInfo::new(APP);
+    producer.args(["print_n", "0123456789", n]);
+    // Exits without reading its stdin.
+    let consumer = ProcessInfo::new(APP);
+    let mut sess = Session::new();
+    sess.pipeline(vec![
+        Program::new_with(producer, |p| {
+            p.on_broken_pipe(policy).resource_limits(ResourceLimits {
+                wall_clock_time: Some(Duration::from_secs(10)),
+                ..Default::default()
+            });
+        }),
+        Program::new(consumer),
+    ])
+    .unwrap();
+    sess.run().unwrap().wait().remove(0).unwrap()
+}
+
+#[test]
+fn output_consumer_gone() {
+    let report = run_with_gone_consumer("1000000000", BrokenPipe::Terminate);
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::OutputConsumerGone)
+    );
+}
+
+#[test]
+fn discard_output_of_gone_consumer() {
+    let report = run_with_gone_consumer("100000", BrokenPipe::Discard);
+    assert_eq!(report.termination_reason, None);
+    assert_eq!(report.exit_status, ExitStatus::Finished(0));
+}
+
+#[test]
+fn open_handle_limit() {
+    let report = run_program(&["open_files", "200", "1"], |p| {
+        p.resource_limits(ResourceLimits {
+            max_open_handles: Some(100),
+            ..Default::default()
+        });
+    });
+    match report.termination_reason {
+        Some(TerminationReason::HandleLimitExceeded { used, limit }) => {
+            assert_eq!(limit, 100);
+            assert!(used > limit);
+            assert_eq!(Some(used), report.peak_open_handles);
+        }
+        reason => panic!("unexpected termination reason {:?}", reason),
+    }
+    assert_eq!(
+        report.to_legacy().terminate_reason,
+        "HandlesCountLimitExceeded"
+    );
+}
+
+#[test]
+fn open_handle_limit_ok() {
+    let report = run_program(&["open_files", "10", "0.2"], |p| {
+        p.resource_limits(ResourceLimits {
+            max_open_handles: Some(100),
+            ..Default::default()
+        });
+    });
+    assert_eq!(report.termination_reason, None);
+    assert!(report.peak_open_handles.is_some());
+}
+
+#[test]
+fn security_policy_violation() {
+    let report = run_program(&["sleep", "10"], |p| {
+        p.security_policy(|r: &spawner::Report| {
+            if r.wall_clock_time > Duration::from_millis(200) {
+                Some("Too slow".to_string())
+            } else {
+                None
+            }
+        });
+    });
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::SecurityViolation)
+    );
+    assert_eq!(report.security_violation.as_deref(), Some("Too slow"));
+}
+
+#[test]
+fn panicking_security_policy() {
+    let report = run_program(&["sleep", "10"], |p| {
+        p.security_policy(|_: &spawner::Report| -> Option<String> { panic!() });
+    });
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::SecurityViolation)
+    );
+}
+
+#[test]
+fn process_creation_rate_limit() {
+    let limits = ResourceLimits {
+        max_process_creation_rate: Some((5, Duration::from_secs(1))),
+        ..Default::default()
+    };
+    let report = run_program(&["spawn_processes", "10", "0.2"], |p| {
+        p.resource_limits(limits);
+    });
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::ProcessCreationRateExceeded)
+    );
+
+    let report = run_program(&["spawn_processes", "2", "0.2"], |p| {
+        p.resource_limits(limits);
+    });
+    assert_eq!(report.termination_reason, None);
+}
+
+#[test]
+fn distinct_endpoint_limit() {
+    let listeners = (0..3)
+        .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect::<Vec<_>>();
+    let mut addrs = listeners
+        .iter()
+        .map(|l| l.local_addr().unwrap())
+        .collect::<Vec<_>>();
+    addrs.sort();
+    let ports = addrs
+        .iter()
+        .map(|a| a.port().to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let report = run_program(&["connect_tcp", &ports, "0.5"], |p| {
+        p.record_remote_endpoints(true);
+    });
+    assert_eq!(report.termination_reason, None);
+    assert_eq!(report.remote_endpoints, Some(addrs));
+
+    let report = run_program(&["connect_tcp", &ports, "5"], |p| {
+        p.resource_limits(ResourceLimits {
+            max_distinct_endpoints: Some(2),
+            ..Default::default()
+        });
+    });
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::NetworkEndpointLimitExceeded { used: 3, limit: 2 })
+    );
+}
+
+#[test]
+fn max_runtime() {
+    let report = run_program(&["sleep", "5"], |p| {
+        p.max_runtime(Duration::from_millis(200));
+    });
+    assert_eq!(
+        report.termination_reason,
+        Some(TerminationReason::DeadlineExceeded)
+    );
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+
+    // The deadline is enforced while the termination for the limit is delayed.
+    let report = run_program(&["sleep", "5"], |p| {
+        p.resource_limits(ResourceLimits {
+            wall_clock_time: Some(Duration::from_millis(100)),
+            ..Default::default()
+        })
+        .capture_natural_exit(Duration::from_secs(10))
+        .absolute_deadline(Instant::now() + Duration::from_millis(300));
+    });
+    assert_eq!(
+        report.termination_reason.map(|r| r.kind()),
+        Some(TerminationKind::WallClockTimeLimitExceeded)
+    );
+    assert!(report.wall_clock_time < Duration::from_secs(2));
+}